@@ -0,0 +1,586 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use openssl::derive::Deriver;
+use openssl::hash::MessageDigest;
+use openssl::md::Md;
+use openssl::pkey::{Id, PKey};
+use openssl::pkey_ctx::PkeyCtx;
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// First line of every age file
+const VERSION_LINE: &str = "age-encryption.org/v1";
+
+/// Size of a plaintext payload chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of a Poly1305 tag
+const TAG_SIZE: usize = 16;
+
+/// Width at which stanza bodies are wrapped
+const COLUMNS: usize = 64;
+
+/// Highest scrypt work factor accepted, as age itself refuses more
+const MAX_SCRYPT_LOG_N: u8 = 22;
+
+/// Bech32 prefix of native X25519 identities
+const IDENTITY_HRP: &str = "age-secret-key-";
+
+/// Bech32 alphabet
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Keys an age file can be decrypted with
+pub struct AgeKeys {
+    /// X25519 secret keys read from the identity file
+    identities: Vec<[u8; 32]>,
+
+    /// Passphrase for files encrypted with `age --passphrase`
+    passphrase: Option<String>,
+}
+
+impl AgeKeys {
+    /// Reads an identity file (as written by `age-keygen`) and/or a passphrase file
+    pub fn load(identity_file: Option<&str>, passphrase_file: Option<&str>) -> Result<Self> {
+        if identity_file.is_none() && passphrase_file.is_none() {
+            bail!("age-encrypted archives need decryption.identity_file or decryption.passphrase_file");
+        }
+
+        let identities = match identity_file {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read age identity file {}", path))?;
+                parse_identities(&content)
+                    .with_context(|| format!("Invalid age identity file {}", path))?
+            }
+            None => Vec::new(),
+        };
+
+        let passphrase = passphrase_file
+            .map(|path| {
+                fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read passphrase file {}", path))
+                    .map(|content| content.trim_end_matches(['\r', '\n']).to_string())
+            })
+            .transpose()?;
+
+        Ok(AgeKeys {
+            identities,
+            passphrase,
+        })
+    }
+}
+
+/// Parses the `AGE-SECRET-KEY-1...` lines of an identity file
+fn parse_identities(content: &str) -> Result<Vec<[u8; 32]>> {
+    let mut identities = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with("AGE-SECRET-KEY-1") {
+            bail!("Unsupported identity (only native X25519 identities are supported)");
+        }
+
+        let (hrp, key) = bech32_decode(line)?;
+        if hrp != IDENTITY_HRP {
+            bail!("Unexpected identity prefix {}", hrp);
+        }
+        let key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| anyhow!("Identity is not a 32-byte X25519 key"))?;
+        identities.push(key);
+    }
+
+    if identities.is_empty() {
+        bail!("No identities found");
+    }
+    Ok(identities)
+}
+
+/// A recipient stanza of the header, e.g. `-> X25519 <share>`
+struct Stanza {
+    kind: String,
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+/// Parses an age header and returns a reader over the decrypted payload
+///
+/// The payload is authenticated chunk by chunk, so a tampered or truncated
+/// file fails the read at the first bad chunk.
+pub fn decrypt<R: Read>(reader: R, keys: &AgeKeys) -> Result<AgeReader<R>> {
+    let mut reader = BufReader::new(reader);
+    let mut header = Vec::new();
+
+    if read_line(&mut reader, &mut header)? != VERSION_LINE {
+        bail!("Not an age file or unsupported age version");
+    }
+
+    let mut stanzas = Vec::new();
+    let (mac, mac_input_len) = loop {
+        let start = header.len();
+        let line = read_line(&mut reader, &mut header)?;
+        if let Some(mac) = line.strip_prefix("--- ") {
+            // The MAC covers the header up to and including the `---`
+            break (decode(mac)?, start + 3);
+        }
+
+        let args = line
+            .strip_prefix("-> ")
+            .ok_or_else(|| anyhow!("Malformed age header line: {}", line))?;
+        let mut args = args.split(' ').map(str::to_string);
+        let kind = args.next().unwrap_or_default();
+        let args = args.collect();
+
+        let mut body = Vec::new();
+        loop {
+            let line = read_line(&mut reader, &mut header)?;
+            body.extend(decode(&line)?);
+            if line.len() < COLUMNS {
+                break;
+            }
+        }
+        stanzas.push(Stanza { kind, args, body });
+    };
+
+    let file_key = unwrap_file_key(&stanzas, keys)?;
+
+    let mac_key = hkdf(&file_key, &[], b"header")?;
+    let expected = hmac_sha256(&mac_key, &header[..mac_input_len])?;
+    if mac.len() != expected.len() || !openssl::memcmp::eq(&mac, &expected) {
+        bail!("age header MAC mismatch; the file is corrupt or was tampered with");
+    }
+
+    let mut nonce = [0u8; 16];
+    reader
+        .read_exact(&mut nonce)
+        .context("age file is truncated before its payload")?;
+    let key = hkdf(&file_key, &nonce, b"payload")?;
+
+    Ok(AgeReader {
+        inner: reader,
+        key,
+        counter: 0,
+        chunk: Vec::new(),
+        pos: 0,
+        done: false,
+    })
+}
+
+/// Recovers the file key from the first stanza one of the configured keys opens
+fn unwrap_file_key(stanzas: &[Stanza], keys: &AgeKeys) -> Result<[u8; 16]> {
+    for stanza in stanzas {
+        match stanza.kind.as_str() {
+            "X25519" => {
+                for identity in &keys.identities {
+                    if let Some(file_key) = unwrap_x25519(stanza, identity)? {
+                        return Ok(file_key);
+                    }
+                }
+            }
+            "scrypt" => {
+                if stanzas.len() != 1 {
+                    bail!("age scrypt stanza must be the only recipient");
+                }
+                let passphrase = keys.passphrase.as_deref().ok_or_else(|| {
+                    anyhow!("Archive is passphrase-encrypted; set decryption.passphrase_file")
+                })?;
+                return unwrap_scrypt(stanza, passphrase);
+            }
+            _ => {}
+        }
+    }
+
+    bail!("None of the configured age identities can decrypt this archive")
+}
+
+/// Opens an X25519 stanza, or returns `None` when it is for another identity
+fn unwrap_x25519(stanza: &Stanza, identity: &[u8; 32]) -> Result<Option<[u8; 16]>> {
+    let [share] = stanza.args.as_slice() else {
+        bail!("Malformed age X25519 stanza");
+    };
+    let share = decode(share)?;
+    if share.len() != 32 {
+        bail!("Malformed age X25519 stanza");
+    }
+
+    let private = PKey::private_key_from_raw_bytes(identity, Id::X25519)?;
+    let peer = PKey::public_key_from_raw_bytes(&share, Id::X25519)?;
+    let mut deriver = Deriver::new(&private)?;
+    deriver.set_peer(&peer)?;
+    let shared = deriver.derive_to_vec()?;
+    if shared.iter().all(|&b| b == 0) {
+        bail!("age X25519 stanza has a low-order share");
+    }
+
+    let mut salt = share;
+    salt.extend(private.raw_public_key()?);
+    let wrap_key = hkdf(&shared, &salt, b"age-encryption.org/v1/X25519")?;
+
+    Ok(open_file_key(&wrap_key, &stanza.body))
+}
+
+/// Opens a passphrase stanza
+fn unwrap_scrypt(stanza: &Stanza, passphrase: &str) -> Result<[u8; 16]> {
+    let [salt, log_n] = stanza.args.as_slice() else {
+        bail!("Malformed age scrypt stanza");
+    };
+    let salt = decode(salt)?;
+    let log_n: u8 = log_n
+        .parse()
+        .map_err(|_| anyhow!("Malformed age scrypt work factor {}", log_n))?;
+    if salt.len() != 16 || log_n == 0 {
+        bail!("Malformed age scrypt stanza");
+    }
+    if log_n > MAX_SCRYPT_LOG_N {
+        bail!("age scrypt work factor 2^{} is too high", log_n);
+    }
+
+    let mut full_salt = b"age-encryption.org/v1/scrypt".to_vec();
+    full_salt.extend(salt);
+    let n = 1u64 << log_n;
+    let mut wrap_key = [0u8; 32];
+    openssl::pkcs5::scrypt(
+        passphrase.as_bytes(),
+        &full_salt,
+        n,
+        8,
+        1,
+        // scrypt needs 128 * r * N bytes, plus some headroom
+        2 * 128 * 8 * n,
+        &mut wrap_key,
+    )
+    .context("scrypt key derivation failed")?;
+
+    open_file_key(&wrap_key, &stanza.body).ok_or_else(|| anyhow!("Wrong age passphrase"))
+}
+
+/// Decrypts a wrapped file key, or returns `None` when the key does not fit
+fn open_file_key(wrap_key: &[u8], body: &[u8]) -> Option<[u8; 16]> {
+    if body.len() != 16 + TAG_SIZE {
+        return None;
+    }
+    open(wrap_key, &[0; 12], body)?.try_into().ok()
+}
+
+/// ChaCha20-Poly1305 decryption of `sealed` (ciphertext followed by its tag)
+fn open(key: &[u8], nonce: &[u8; 12], sealed: &[u8]) -> Option<Vec<u8>> {
+    let (data, tag) = sealed.split_at(sealed.len().checked_sub(TAG_SIZE)?);
+    symm::decrypt_aead(
+        Cipher::chacha20_poly1305(),
+        key,
+        Some(nonce),
+        &[],
+        data,
+        tag,
+    )
+    .ok()
+}
+
+/// HKDF-SHA-256 with a 32-byte output
+fn hkdf(key: &[u8], salt: &[u8], info: &[u8]) -> Result<Vec<u8>> {
+    let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+    ctx.derive_init()?;
+    ctx.set_hkdf_md(Md::sha256())?;
+    ctx.set_hkdf_key(key)?;
+    // An absent salt is the same as an empty one
+    if !salt.is_empty() {
+        ctx.set_hkdf_salt(salt)?;
+    }
+    ctx.add_hkdf_info(info)?;
+    let mut out = vec![0; 32];
+    ctx.derive(Some(&mut out))?;
+    Ok(out)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    Ok(signer.sign_oneshot_to_vec(data)?)
+}
+
+/// Unpadded base64 as used throughout the age header
+fn decode(text: &str) -> Result<Vec<u8>> {
+    STANDARD_NO_PAD
+        .decode(text)
+        .map_err(|e| anyhow!("Malformed base64 in age header: {}", e))
+}
+
+/// Reads one `\n`-terminated header line, appending its raw bytes to `header`
+fn read_line<R: BufRead>(reader: &mut R, header: &mut Vec<u8>) -> Result<String> {
+    let start = header.len();
+    reader
+        .take(1024)
+        .read_until(b'\n', header)
+        .context("Failed to read age header")?;
+    if header.last() != Some(&b'\n') {
+        bail!("age header is truncated or malformed");
+    }
+    String::from_utf8(header[start..header.len() - 1].to_vec())
+        .map_err(|_| anyhow!("age header is not valid text"))
+}
+
+/// Decodes a bech32 string into its human-readable part and data
+fn bech32_decode(text: &str) -> Result<(String, Vec<u8>)> {
+    let text = text.to_ascii_lowercase();
+    let separator = text
+        .rfind('1')
+        .ok_or_else(|| anyhow!("Malformed bech32 string"))?;
+    let (hrp, data) = (&text[..separator], &text[separator + 1..]);
+
+    let values = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|&x| x == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| anyhow!("Invalid bech32 character"))?;
+    if values.len() < 6 {
+        bail!("Malformed bech32 string");
+    }
+
+    let mut checked: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    checked.push(0);
+    checked.extend(hrp.bytes().map(|c| c & 31));
+    checked.extend(&values);
+    if bech32_polymod(&checked) != 1 {
+        bail!("Invalid bech32 checksum");
+    }
+
+    // Regroup the 5-bit values into bytes
+    let mut bytes = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for &value in &values[..values.len() - 6] {
+        acc = (acc << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || acc & ((1 << bits) - 1) != 0 {
+        bail!("Invalid bech32 padding");
+    }
+
+    Ok((hrp.to_string(), bytes))
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for &value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Plaintext of an age file, decrypted one 64 KiB chunk at a time
+pub struct AgeReader<R> {
+    inner: BufReader<R>,
+    key: Vec<u8>,
+    counter: u64,
+
+    /// Decrypted chunk being handed out
+    chunk: Vec<u8>,
+    pos: usize,
+
+    /// Whether the final chunk has been decrypted
+    done: bool,
+}
+
+impl<R: Read> AgeReader<R> {
+    /// Reads and authenticates the next chunk
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut sealed = Vec::with_capacity(CHUNK_SIZE + TAG_SIZE);
+        (&mut self.inner)
+            .take((CHUNK_SIZE + TAG_SIZE) as u64)
+            .read_to_end(&mut sealed)?;
+
+        // A full chunk is the last one only when nothing follows it
+        let last = sealed.len() < CHUNK_SIZE + TAG_SIZE || self.inner.fill_buf()?.is_empty();
+        if sealed.len() < TAG_SIZE || (sealed.len() == TAG_SIZE && (self.counter > 0 || !last)) {
+            return Err(invalid_data("age payload is truncated"));
+        }
+
+        let mut nonce = [0u8; 12];
+        nonce[3..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = last as u8;
+        self.chunk = open(&self.key, &nonce, &sealed).ok_or_else(|| {
+            invalid_data("age payload failed authentication; the file is corrupt or truncated")
+        })?;
+        self.pos = 0;
+        self.counter += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for AgeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.pos);
+        buf[..read].copy_from_slice(&self.chunk[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    //! Files in `testdata/age` come from `generate.py` there, an encoder
+    //! written from the age specification independently of this decoder.
+
+    use super::*;
+
+    const X25519: &[u8] = include_bytes!("../testdata/age/x25519.age");
+    const EMPTY: &[u8] = include_bytes!("../testdata/age/empty.age");
+    const EXACT_CHUNK: &[u8] = include_bytes!("../testdata/age/exact-chunk.age");
+    const PASSPHRASE: &[u8] = include_bytes!("../testdata/age/passphrase.age");
+
+    fn identity(file: &str) -> AgeKeys {
+        AgeKeys {
+            identities: parse_identities(file).unwrap(),
+            passphrase: None,
+        }
+    }
+
+    fn keys() -> AgeKeys {
+        identity(include_str!("../testdata/age/identity.txt"))
+    }
+
+    /// Test payloads count up modulo 251, so chunks differ from each other
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn decrypt_all(file: &[u8], keys: &AgeKeys) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        decrypt(file, keys)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    /// Offset of the payload nonce, right after the header's MAC line
+    fn payload_start(file: &[u8]) -> usize {
+        let mac_line = file.windows(4).position(|w| w == b"--- ").unwrap();
+        mac_line + file[mac_line..].iter().position(|&b| b == b'\n').unwrap() + 1
+    }
+
+    #[test]
+    fn decrypts_with_x25519_identity() {
+        assert_eq!(decrypt_all(X25519, &keys()).unwrap(), payload(100_000));
+    }
+
+    #[test]
+    fn decrypts_with_passphrase() {
+        let keys = AgeKeys {
+            identities: Vec::new(),
+            passphrase: Some("correct horse battery staple".to_string()),
+        };
+        assert_eq!(
+            decrypt_all(PASSPHRASE, &keys).unwrap(),
+            b"snapshot payload\n"
+        );
+    }
+
+    #[test]
+    fn refuses_wrong_passphrase() {
+        let keys = AgeKeys {
+            identities: Vec::new(),
+            passphrase: Some("wrong".to_string()),
+        };
+        assert!(decrypt(PASSPHRASE, &keys).is_err());
+    }
+
+    #[test]
+    fn refuses_wrong_identity() {
+        let keys = identity(include_str!("../testdata/age/other-identity.txt"));
+        let error = decrypt(X25519, &keys).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("None of the configured age identities"));
+    }
+
+    #[test]
+    fn refuses_tampered_header_mac() {
+        let mut file = X25519.to_vec();
+        let mac = file.windows(4).position(|w| w == b"--- ").unwrap() + 4;
+        file[mac] = if file[mac] == b'A' { b'B' } else { b'A' };
+        let error = decrypt(&file[..], &keys()).err().unwrap();
+        assert!(error.to_string().contains("MAC mismatch"));
+    }
+
+    #[test]
+    fn refuses_tampered_payload() {
+        let mut file = X25519.to_vec();
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        assert!(decrypt_all(&file, &keys()).is_err());
+    }
+
+    #[test]
+    fn refuses_truncated_final_chunk() {
+        assert!(decrypt_all(&X25519[..X25519.len() - 10], &keys()).is_err());
+    }
+
+    #[test]
+    fn refuses_missing_final_chunk() {
+        // The first chunk then ends the file without being flagged as the last
+        let end = payload_start(X25519) + 16 + CHUNK_SIZE + TAG_SIZE;
+        assert!(decrypt_all(&X25519[..end], &keys()).is_err());
+    }
+
+    #[test]
+    fn refuses_missing_payload() {
+        assert!(decrypt_all(&X25519[..payload_start(X25519) + 16], &keys()).is_err());
+    }
+
+    #[test]
+    fn decrypts_empty_payload() {
+        assert_eq!(decrypt_all(EMPTY, &keys()).unwrap(), b"");
+    }
+
+    #[test]
+    fn decrypts_payload_of_exactly_one_chunk() {
+        assert_eq!(
+            decrypt_all(EXACT_CHUNK, &keys()).unwrap(),
+            payload(CHUNK_SIZE)
+        );
+    }
+
+    #[test]
+    fn refuses_data_after_final_chunk() {
+        let mut file = EXACT_CHUNK.to_vec();
+        file.extend([0; TAG_SIZE]);
+        assert!(decrypt_all(&file, &keys()).is_err());
+    }
+
+    #[test]
+    fn decodes_bech32_test_vectors() {
+        // Valid strings from BIP 173
+        assert_eq!(
+            bech32_decode("A12UEL5L").unwrap(),
+            ("a".to_string(), vec![])
+        );
+        let (hrp, _) = bech32_decode("abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw").unwrap();
+        assert_eq!(hrp, "abcdef");
+        // Invalid checksum
+        assert!(bech32_decode("A12UEL5M").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, instrument};
+
+use crate::config::BuildConfig;
+
+/// Builds the node binary from a source checkout
+pub struct SourceBuilder {
+    /// Directory the repository is cloned into
+    checkout_dir: PathBuf,
+}
+
+impl SourceBuilder {
+    /// Creates a new builder that checks out sources into `checkout_dir`
+    pub fn new(checkout_dir: &Path) -> Self {
+        SourceBuilder {
+            checkout_dir: checkout_dir.to_path_buf(),
+        }
+    }
+
+    /// Clones the configured tag, runs the build command and returns the binary path
+    ///
+    /// # Arguments
+    /// * `build` - Repository, tag and build command to use
+    /// * `bin` - Configured binary path, whose file name is the default build output
+    #[instrument(skip(self, build), fields(repo = %build.repo, tag = %build.tag))]
+    pub fn build(&self, build: &BuildConfig, bin: &str) -> Result<PathBuf> {
+        self.checkout(build)?;
+
+        info!("Running build command: {}", build.command);
+        // GOBIN must be absolute because the build runs from inside the checkout
+        let output_dir = std::path::absolute(self.checkout_dir.join("build"))
+            .context("Failed to resolve build output directory")?;
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&build.command)
+            .current_dir(&self.checkout_dir)
+            .env("GOBIN", &output_dir)
+            .status()
+            .context("Failed to execute build command")?;
+
+        if !status.success() {
+            return Err(anyhow!("Build command failed with {}", status));
+        }
+
+        let binary = match &build.output {
+            Some(output) => self.checkout_dir.join(output),
+            None => {
+                let name = Path::new(bin)
+                    .file_name()
+                    .context("Failed to determine binary name from cosmos.bin")?;
+                output_dir.join(name)
+            }
+        };
+
+        if !binary.is_file() {
+            return Err(anyhow!(
+                "Build succeeded but no binary was found at {}",
+                binary.display()
+            ));
+        }
+
+        info!("Built binary at {}", binary.display());
+        Ok(binary)
+    }
+
+    /// Performs a fresh shallow clone of the configured tag
+    fn checkout(&self, build: &BuildConfig) -> Result<()> {
+        if self.checkout_dir.exists() {
+            info!(
+                "Removing previous checkout at {}",
+                self.checkout_dir.display()
+            );
+            fs::remove_dir_all(&self.checkout_dir)
+                .context("Failed to remove previous source checkout")?;
+        }
+
+        info!("Cloning {} at {}", build.repo, build.tag);
+        let status = Command::new("git")
+            .args(["clone", "--depth", "1", "--branch", &build.tag, &build.repo])
+            .arg(&self.checkout_dir)
+            .status()
+            .context("Failed to run git; is it installed?")?;
+
+        if !status.success() {
+            return Err(anyhow!("git clone failed with {}", status));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+use anyhow::{Context, Result};
+use std::cell::Cell;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+use tracing::warn;
+
+/// Magic number opening every LZ4 frame
+const LZ4_MAGIC: u32 = 0x184d2204;
+
+/// Magic number of skippable frames, with any value in the low nibble
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+
+/// Shortest LZ4 frame header: magic number, flags, block descriptor and header checksum
+const MIN_HEADER_SIZE: usize = 7;
+
+/// Frame flags marking a content size, content checksum, block checksums and a dictionary ID
+const FLAG_CONTENT_SIZE: u8 = 0x08;
+const FLAG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLAG_DICT_ID: u8 = 0x01;
+
+/// A point an interrupted extraction can restart from
+///
+/// Decoding restarts at the start of an LZ4 frame, then skips ahead to the
+/// tar header of the first entry not yet extracted.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint {
+    /// Where the frame starts
+    pub frame: FrameStart,
+
+    /// Offset of the entry's tar header in the decompressed stream
+    pub entry: u64,
+
+    /// Entries extracted before it
+    pub entries: u64,
+}
+
+/// Extraction checkpoints of an archive
+///
+/// Kept next to the archive as `<archive>.checkpoint`: a header line with the
+/// archive's size and modification time, the strip-components setting and
+/// the output directory, then the latest checkpoint. A checkpoint written for
+/// another archive or extraction is ignored.
+pub struct CheckpointFile {
+    /// Path of the checkpoint file
+    path: PathBuf,
+
+    /// First line of the file, identifying the extraction it belongs to
+    header: String,
+}
+
+impl CheckpointFile {
+    /// Checkpoints of extracting `archive` into `output_dir`
+    pub fn new(archive: &Path, output_dir: &Path, strip_components: usize) -> Result<Self> {
+        let metadata = fs::metadata(archive)
+            .with_context(|| format!("Failed to read metadata of {}", archive.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let output_dir = std::path::absolute(output_dir)?;
+
+        Ok(CheckpointFile {
+            path: checkpoint_path(archive),
+            header: format!(
+                "{} {} {} {}",
+                metadata.len(),
+                modified,
+                strip_components,
+                output_dir.display()
+            ),
+        })
+    }
+
+    /// The latest checkpoint, if one was recorded for this extraction
+    pub fn load(&self) -> Option<Checkpoint> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(self.header.as_str()) {
+            warn!(
+                "Extraction checkpoint {} doesn't match the archive, extracting from the start",
+                self.path.display()
+            );
+            return None;
+        }
+
+        let values = lines
+            .next()?
+            .split_whitespace()
+            .map(|value| value.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let [compressed, uncompressed, entry, entries] = values[..] else {
+            return None;
+        };
+        Some(Checkpoint {
+            frame: FrameStart {
+                compressed,
+                uncompressed,
+            },
+            entry,
+            entries,
+        })
+    }
+
+    /// Records a checkpoint, replacing the previous one
+    pub fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        // Written aside and renamed, so an interruption never leaves half a checkpoint
+        let mut temp = self.path.as_os_str().to_os_string();
+        temp.push(".tmp");
+        fs::write(
+            &temp,
+            format!(
+                "{}\n{} {} {} {}\n",
+                self.header,
+                checkpoint.frame.compressed,
+                checkpoint.frame.uncompressed,
+                checkpoint.entry,
+                checkpoint.entries
+            ),
+        )
+        .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        fs::rename(&temp, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Removes the checkpoint once the extraction is complete
+    pub fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Path of the checkpoint file kept next to an archive
+pub fn checkpoint_path(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_os_string();
+    name.push(".checkpoint");
+    PathBuf::from(name)
+}
+
+/// Where an LZ4 frame starts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStart {
+    /// Offset of the frame in the archive file
+    pub compressed: u64,
+
+    /// Offset of its first decompressed byte in the tar stream
+    pub uncompressed: u64,
+}
+
+/// Decodes a sequence of LZ4 frames, tracking where the current one started
+///
+/// Each frame is decoded on its own, so an archive written as several
+/// frames (e.g. by a parallel compressor) can be decoded from any frame
+/// boundary. Skippable frames are passed over.
+pub struct Lz4Frames<R> {
+    state: FrameState<R>,
+
+    /// Decompressed bytes produced so far, counting from the start of the stream
+    position: u64,
+
+    /// Start of the frame being decoded, shared with whoever records checkpoints
+    frame_start: Rc<Cell<FrameStart>>,
+}
+
+enum FrameState<R> {
+    /// Between frames, about to read the next magic number
+    Between(Counted<R>),
+    Decoding(lz4::Decoder<FrameReader<R>>),
+    Done,
+}
+
+impl<R: Read> Lz4Frames<R> {
+    /// Decodes frames from `reader`, which is positioned at the frame starting at `start`
+    pub fn new(reader: R, start: FrameStart) -> Self {
+        Lz4Frames {
+            state: FrameState::Between(Counted {
+                inner: reader,
+                offset: start.compressed,
+                peeked: Vec::new(),
+            }),
+            position: start.uncompressed,
+            frame_start: Rc::new(Cell::new(start)),
+        }
+    }
+
+    /// Start of the frame being decoded, updated as decoding moves on
+    pub fn frame_start(&self) -> Rc<Cell<FrameStart>> {
+        self.frame_start.clone()
+    }
+
+    /// Starts decoding the next frame, or returns `None` at the end of the file
+    fn next_frame(
+        &mut self,
+        mut reader: Counted<R>,
+    ) -> io::Result<Option<lz4::Decoder<FrameReader<R>>>> {
+        loop {
+            let Some(magic) = reader.peek_magic()? else {
+                return Ok(None);
+            };
+            if magic & 0xffff_fff0 == SKIPPABLE_MAGIC {
+                let mut header = [0u8; 8];
+                reader.read_exact(&mut header)?;
+                let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+                io::copy(&mut (&mut reader).take(size as u64), &mut io::sink())?;
+                continue;
+            }
+            if magic != LZ4_MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("No LZ4 frame at offset {}", reader.offset),
+                ));
+            }
+
+            self.frame_start.set(FrameStart {
+                compressed: reader.offset,
+                uncompressed: self.position,
+            });
+            return lz4::Decoder::new(FrameReader::new(reader)).map(Some);
+        }
+    }
+}
+
+impl<R: Read> Read for Lz4Frames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match std::mem::replace(&mut self.state, FrameState::Done) {
+                FrameState::Done => return Ok(0),
+                FrameState::Between(reader) => {
+                    if let Some(decoder) = self.next_frame(reader)? {
+                        self.state = FrameState::Decoding(decoder);
+                    }
+                }
+                FrameState::Decoding(mut decoder) => {
+                    let read = decoder.read(buf)?;
+                    if read > 0 {
+                        self.position += read as u64;
+                        self.state = FrameState::Decoding(decoder);
+                        return Ok(read);
+                    }
+
+                    // The decoder stops at the end of its frame
+                    let (reader, result) = decoder.finish();
+                    result.map_err(|_| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "LZ4 frame is truncated")
+                    })?;
+                    self.state = FrameState::Between(reader.inner);
+                }
+            }
+        }
+    }
+}
+
+/// Part of an LZ4 frame being read
+enum FramePart {
+    /// The header, whose length is known once its flags are read
+    Header {
+        len: Option<usize>,
+    },
+
+    /// Size of the next block, or the end mark
+    BlockSize,
+
+    /// Block data and its checksum, if the frame has block checksums
+    Block {
+        remaining: u64,
+    },
+
+    /// Content checksum after the end mark
+    Checksum {
+        remaining: u64,
+    },
+
+    End,
+}
+
+/// The bytes of a single LZ4 frame
+///
+/// [`lz4::Decoder`] reads ahead past the end of its frame, so it is given
+/// a reader that follows the frame layout and reports the end of the input
+/// right after the frame's last byte.
+struct FrameReader<R> {
+    inner: Counted<R>,
+    part: FramePart,
+
+    /// Header or block size bytes read so far
+    field: Vec<u8>,
+
+    /// The frame's flags, once the header is read
+    flags: u8,
+}
+
+impl<R: Read> FrameReader<R> {
+    fn new(inner: Counted<R>) -> Self {
+        FrameReader {
+            inner,
+            part: FramePart::Header { len: None },
+            field: Vec::new(),
+            flags: 0,
+        }
+    }
+
+    /// Bytes left in the current part of the frame
+    fn remaining(&self) -> usize {
+        match self.part {
+            FramePart::Header { len } => len.unwrap_or(MIN_HEADER_SIZE) - self.field.len(),
+            FramePart::BlockSize => 4 - self.field.len(),
+            FramePart::Block { remaining } | FramePart::Checksum { remaining } => {
+                usize::try_from(remaining).unwrap_or(usize::MAX)
+            }
+            FramePart::End => 0,
+        }
+    }
+
+    /// Moves through the frame layout past `bytes`
+    fn advance(&mut self, bytes: &[u8]) {
+        let next = match &mut self.part {
+            FramePart::Header { len } => {
+                self.field.extend_from_slice(bytes);
+                if len.is_none() && self.field.len() > 4 {
+                    self.flags = self.field[4];
+                    let mut header = MIN_HEADER_SIZE;
+                    if self.flags & FLAG_CONTENT_SIZE != 0 {
+                        header += 8;
+                    }
+                    if self.flags & FLAG_DICT_ID != 0 {
+                        header += 4;
+                    }
+                    *len = Some(header);
+                }
+                (Some(self.field.len()) == *len).then_some(FramePart::BlockSize)
+            }
+            FramePart::BlockSize => {
+                self.field.extend_from_slice(bytes);
+                (self.field.len() == 4).then(|| {
+                    let size = u32::from_le_bytes([
+                        self.field[0],
+                        self.field[1],
+                        self.field[2],
+                        self.field[3],
+                    ]);
+                    if size != 0 {
+                        let checksum = if self.flags & FLAG_BLOCK_CHECKSUM != 0 {
+                            4
+                        } else {
+                            0
+                        };
+                        FramePart::Block {
+                            remaining: (size & 0x7fff_ffff) as u64 + checksum,
+                        }
+                    } else if self.flags & FLAG_CONTENT_CHECKSUM != 0 {
+                        FramePart::Checksum { remaining: 4 }
+                    } else {
+                        FramePart::End
+                    }
+                })
+            }
+            FramePart::Block { remaining } => {
+                *remaining -= bytes.len() as u64;
+                (*remaining == 0).then_some(FramePart::BlockSize)
+            }
+            FramePart::Checksum { remaining } => {
+                *remaining -= bytes.len() as u64;
+                (*remaining == 0).then_some(FramePart::End)
+            }
+            FramePart::End => None,
+        };
+
+        if let Some(next) = next {
+            self.field.clear();
+            self.part = next;
+        }
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        let read = self.inner.read(&mut buf[..len])?;
+        self.advance(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// A reader that counts the bytes consumed and can peek at a magic number
+struct Counted<R> {
+    inner: R,
+
+    /// Offset of the next byte in the archive file
+    offset: u64,
+
+    /// Bytes read ahead by `peek_magic`, served before the rest
+    peeked: Vec<u8>,
+}
+
+impl<R: Read> Counted<R> {
+    /// Reads the next four bytes without consuming them, or `None` at the end of the file
+    fn peek_magic(&mut self) -> io::Result<Option<u32>> {
+        while self.peeked.len() < 4 {
+            let mut byte = [0u8; 4];
+            let read = self.inner.read(&mut byte[..4 - self.peeked.len()])?;
+            if read == 0 {
+                break;
+            }
+            self.peeked.extend_from_slice(&byte[..read]);
+        }
+
+        match self.peeked[..] {
+            [] => Ok(None),
+            [a, b, c, d] => Ok(Some(u32::from_le_bytes([a, b, c, d]))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ends inside an LZ4 frame header",
+            )),
+        }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = if self.peeked.is_empty() {
+            self.inner.read(buf)?
+        } else {
+            let read = buf.len().min(self.peeked.len());
+            buf[..read].copy_from_slice(&self.peeked[..read]);
+            self.peeked.drain(..read);
+            read
+        };
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
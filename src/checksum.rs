@@ -0,0 +1,417 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::{Client, StatusCode, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use tracing::{info, instrument, warn};
+
+use crate::config::S3Config;
+use crate::error::Error;
+use crate::s3;
+
+/// Conventional checksum listings published next to release artifacts
+const CHECKSUM_LISTINGS: &[&str] = &["SHA256SUMS", "checksums.txt"];
+
+/// Buffer size used when hashing large files
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Per-chunk SHA256 digests of a large artifact
+///
+/// Published as JSON, e.g. `{"chunk_size": 1073741824, "chunks": ["<sha256>", ...]}`,
+/// where chunk `i` covers the `chunk_size` bytes starting at `i * chunk_size`
+/// and the last chunk may be shorter.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChunkManifest {
+    /// Size of every chunk but possibly the last
+    pub chunk_size: u64,
+
+    /// Hex SHA256 digest of each chunk, in order
+    pub chunks: Vec<String>,
+}
+
+impl ChunkManifest {
+    /// Checks that the manifest describes a file of `size` bytes
+    pub fn check_size(&self, size: u64) -> Result<()> {
+        let expected = size.div_ceil(self.chunk_size) as usize;
+        if self.chunks.len() != expected {
+            return Err(anyhow!(
+                "Chunk manifest lists {} chunks of {} bytes, but the file is {} bytes ({} chunks)",
+                self.chunks.len(),
+                self.chunk_size,
+                size,
+                expected
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether `digest` is the published digest of chunk `index`
+    pub fn matches(&self, index: usize, digest: &str) -> bool {
+        self.chunks
+            .get(index)
+            .is_some_and(|expected| expected.eq_ignore_ascii_case(digest))
+    }
+}
+
+/// Discovers and verifies SHA256 checksums of downloaded artifacts
+pub struct ChecksumVerifier {
+    client: Client,
+
+    /// Region, endpoint and credentials profile for checksums of `s3://` artifacts
+    s3: Option<S3Config>,
+
+    /// Whether [`ChecksumVerifier::discover`] looks for published checksums
+    discovery: bool,
+}
+
+impl Default for ChecksumVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChecksumVerifier {
+    /// Creates a new checksum verifier instance
+    pub fn new() -> Self {
+        ChecksumVerifier {
+            client: Client::new(),
+            s3: None,
+            discovery: true,
+        }
+    }
+
+    /// Looks up checksums of `s3://` artifacts with these settings
+    pub fn with_s3(mut self, s3: Option<S3Config>) -> Self {
+        self.s3 = s3;
+        self
+    }
+
+    /// Turns looking for published checksums on or off
+    pub fn with_discovery(mut self, enabled: bool) -> Self {
+        self.discovery = enabled;
+        self
+    }
+
+    /// Looks for a published checksum of the file at `url`
+    ///
+    /// Probes, in order, unless discovery is turned off:
+    /// - `<url>.sha256`
+    /// - `SHA256SUMS` in the same directory
+    /// - `checksums.txt` in the same directory
+    ///
+    /// # Returns
+    /// * `Result<Option<(String, String)>>` - The expected hex digest and the URL it came from
+    #[instrument(skip(self))]
+    pub async fn discover(&self, url: &str) -> Result<Option<(String, String)>> {
+        if !self.discovery {
+            return Ok(None);
+        }
+        let parsed = Url::parse(url).context("Failed to parse artifact URL")?;
+        let file_name = parsed
+            .path_segments()
+            .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+            .context("Failed to determine file name from URL")?
+            .to_string();
+
+        let mut candidates = Vec::new();
+
+        // The sidecar sits next to the file, before any query string
+        let mut sidecar = parsed.clone();
+        sidecar.set_path(&format!("{}.sha256", parsed.path()));
+        candidates.push(sidecar);
+
+        for listing in CHECKSUM_LISTINGS {
+            let mut listing_url = parsed.join(listing)?;
+            listing_url.set_query(parsed.query());
+            candidates.push(listing_url);
+        }
+
+        for candidate in candidates {
+            let Some(body) = self.fetch_optional(&candidate).await? else {
+                continue;
+            };
+
+            match parse_checksum_file(&body, &file_name) {
+                Some(digest) => {
+                    info!("Found checksum for {} in {}", file_name, candidate);
+                    return Ok(Some((digest, candidate.to_string())));
+                }
+                None => info!("{} has no entry for {}", candidate, file_name),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the digest for `file_name` from the checksum file at `checksum_url`
+    #[instrument(skip(self))]
+    pub async fn fetch_listed(&self, checksum_url: &str, file_name: &str) -> Result<String> {
+        let body = self
+            .client
+            .get(checksum_url)
+            .send()
+            .await
+            .context("Failed to fetch checksum file")?
+            .error_for_status()
+            .context("Checksum file request failed")?
+            .text()
+            .await
+            .context("Failed to read checksum file")?;
+
+        parse_checksum_file(&body, file_name)
+            .ok_or_else(|| anyhow!("{} has no entry for {}", checksum_url, file_name))
+    }
+
+    /// Fetches and checks a chunk manifest
+    #[instrument(skip(self))]
+    pub async fn fetch_chunk_manifest(&self, url: &str) -> Result<ChunkManifest> {
+        let manifest: ChunkManifest = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to fetch chunk manifest")?
+            .error_for_status()
+            .context("Chunk manifest request failed")?
+            .json()
+            .await
+            .context("Failed to parse chunk manifest")?;
+
+        if manifest.chunk_size == 0 {
+            return Err(anyhow!("Chunk manifest {} has a chunk_size of 0", url));
+        }
+        if let Some(digest) = manifest.chunks.iter().find(|d| !is_sha256_hex(d)) {
+            return Err(anyhow!(
+                "Chunk manifest {} lists an invalid SHA256 digest: {}",
+                url,
+                digest
+            ));
+        }
+
+        info!(
+            "Loaded chunk manifest with {} chunks of {} bytes",
+            manifest.chunks.len(),
+            manifest.chunk_size
+        );
+        Ok(manifest)
+    }
+
+    /// Fetches a small text file, returning `None` if it doesn't exist
+    async fn fetch_optional(&self, url: &Url) -> Result<Option<String>> {
+        let request_url = match url.scheme() {
+            "s3" => s3::presign(&self.client, url.as_str(), self.s3.as_ref()).await?,
+            _ => url.to_string(),
+        };
+        let response = match self.client.get(request_url).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to probe {}: {}", url, e);
+                return Ok(None);
+            }
+        };
+
+        match response.status() {
+            status if status.is_success() => {
+                let body = response
+                    .text()
+                    .await
+                    .context("Failed to read checksum file")?;
+                Ok(Some(body))
+            }
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => Ok(None),
+            status => {
+                warn!("Unexpected status {} when probing {}", status, url);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Verifies that the file at `path` has the expected SHA256 digest
+    #[instrument(skip(self, path), fields(path = %path.as_ref().display()))]
+    pub async fn verify<P: AsRef<Path>>(
+        &self,
+        path: P,
+        expected: &str,
+    ) -> crate::error::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        info!("Computing SHA256 checksum");
+
+        let hashed = path.clone();
+        let actual = tokio::task::spawn_blocking(move || sha256_file(&hashed))
+            .await
+            .context("Checksum task panicked")??;
+
+        if actual.eq_ignore_ascii_case(expected.trim()) {
+            info!("Checksum verified: {}", actual);
+            Ok(())
+        } else {
+            Err(Error::ChecksumMismatch {
+                path,
+                expected: expected.trim().to_string(),
+                actual,
+            })
+        }
+    }
+}
+
+/// Computes the hex-encoded SHA256 digest of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context("Failed to open file for hashing")?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .context("Failed to read file for hashing")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Computes the hex-encoded SHA256 digest of a byte range of a file
+pub fn sha256_range(path: &Path, range: Range<u64>) -> Result<String> {
+    let mut file = File::open(path).context("Failed to open file for hashing")?;
+    file.seek(SeekFrom::Start(range.start))
+        .context("Failed to seek in file for hashing")?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    let mut chunk = file.take(range.end - range.start);
+
+    loop {
+        let read = chunk
+            .read(&mut buffer)
+            .context("Failed to read file for hashing")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Extracts the digest for `file_name` from a checksum file
+///
+/// Accepts a bare digest (typical for `.sha256` sidecars) as well as the
+/// `<digest>  <name>` / `<digest> *<name>` lines produced by `sha256sum`.
+pub fn parse_checksum_file(content: &str, file_name: &str) -> Option<String> {
+    let lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'));
+
+    let mut bare_digest = None;
+    for line in lines {
+        let mut parts = line.split_whitespace();
+        let digest = parts.next()?;
+        if !is_sha256_hex(digest) {
+            continue;
+        }
+
+        match parts.next() {
+            Some(name) => {
+                let name = name.trim_start_matches('*');
+                let base = name.rsplit('/').next().unwrap_or(name);
+                if base == file_name {
+                    return Some(digest.to_lowercase());
+                }
+            }
+            None => bare_digest = bare_digest.or(Some(digest.to_lowercase())),
+        }
+    }
+
+    bare_digest
+}
+
+/// Checks whether a string looks like a hex-encoded SHA256 digest
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const OTHER: &str = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752";
+
+    #[test]
+    fn reads_a_bare_sidecar_digest() {
+        assert_eq!(
+            parse_checksum_file(&format!("{}\n", DIGEST.to_uppercase()), "snap.tar"),
+            Some(DIGEST.to_string())
+        );
+        assert_eq!(parse_checksum_file("", "snap.tar"), None);
+        assert_eq!(parse_checksum_file("not a digest\n", "snap.tar"), None);
+        assert_eq!(parse_checksum_file(&DIGEST[1..], "snap.tar"), None);
+    }
+
+    #[test]
+    fn picks_the_listed_file() {
+        let listing = format!(
+            "# release checksums\n\n{}  other.tar\n{} *dist/snap.tar\n",
+            OTHER, DIGEST
+        );
+        assert_eq!(
+            parse_checksum_file(&listing, "snap.tar"),
+            Some(DIGEST.to_string())
+        );
+        assert_eq!(
+            parse_checksum_file(&listing, "other.tar"),
+            Some(OTHER.to_string())
+        );
+        assert_eq!(parse_checksum_file(&listing, "missing.tar"), None);
+        assert_eq!(parse_checksum_file(&listing, "dist/snap.tar"), None);
+    }
+
+    #[test]
+    fn prefers_a_named_entry_over_a_bare_digest() {
+        let listing = format!("{}\n{}  snap.tar\n", OTHER, DIGEST);
+        assert_eq!(
+            parse_checksum_file(&listing, "snap.tar"),
+            Some(DIGEST.to_string())
+        );
+        assert_eq!(
+            parse_checksum_file(&listing, "other.tar"),
+            Some(OTHER.to_string())
+        );
+    }
+
+    #[test]
+    fn checks_chunk_manifests() {
+        let manifest = ChunkManifest {
+            chunk_size: 4,
+            chunks: vec![DIGEST.to_string(), OTHER.to_string()],
+        };
+        assert!(manifest.check_size(5).is_ok());
+        assert!(manifest.check_size(8).is_ok());
+        assert!(manifest.check_size(4).is_err());
+        assert!(manifest.check_size(9).is_err());
+        assert!(manifest.matches(1, &OTHER.to_uppercase()));
+        assert!(!manifest.matches(0, OTHER));
+        assert!(!manifest.matches(2, DIGEST));
+    }
+
+    #[tokio::test]
+    async fn skips_discovery_when_turned_off() {
+        // A probe would connect, and a failed probe also comes back as `None`
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/snap.tar", listener.local_addr().unwrap());
+
+        let verifier = ChecksumVerifier::new().with_discovery(false);
+        assert_eq!(verifier.discover(&url).await.unwrap(), None);
+        assert_eq!(
+            listener.accept().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+}
@@ -0,0 +1,585 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::{self, Config, RequestConfig, RetryStage};
+use crate::cosmovisor::Cosmovisor;
+use crate::downloader::Downloader;
+use crate::error;
+use crate::layout::Layout;
+use crate::manifest;
+use crate::notify;
+use crate::pipeline::{
+    apply_registry_peers, check_archive_space, checksum_verifier, configured_checksum, file_name,
+    install_node_artifact, listed_name, resolve_provider_snapshot, snapshot_extractor,
+    snapshot_name, split_checksum,
+};
+use crate::release::GithubReleases;
+use crate::retry::RetryPolicy;
+use crate::segmented;
+use crate::setup::CosmosSetup;
+use crate::share::ShareServer;
+use crate::snapshot_info::SnapshotInfo;
+use crate::state::RunState;
+use crate::supervisor::NodeSupervisor;
+use crate::sync::SyncWaiter;
+use crate::torrent;
+
+/// Requests the metadata of every configured download up front
+///
+/// Reports all unreachable URLs at once rather than failing partway through a
+/// run. Magnet links have no server to ask and are skipped.
+pub async fn check_reachability(config: &Config) -> Result<()> {
+    let mut urls = Vec::new();
+    for url in &config.snapshot_url {
+        urls.push(("snapshot_url", url.clone()));
+    }
+    for increment in &config.snapshot_increments {
+        urls.push(("snapshot_increments", increment.url().to_string()));
+    }
+    if let Some(provider) = &config.snapshot_provider {
+        urls.push(("snapshot_provider.index_url", provider.index_url.clone()));
+    }
+    if let Some(url) = config.binary_url.as_ref().and_then(|b| b.for_host().ok()) {
+        let (url, _) = split_checksum(url);
+        urls.push(("binary_url", url.to_string()));
+    }
+    for upgrade in &config.upgrades {
+        if let Some(url) = upgrade.binary_url.as_ref().and_then(|b| b.for_host().ok()) {
+            let (url, _) = split_checksum(url);
+            urls.push(("upgrades.binary_url", url.to_string()));
+        }
+    }
+    for (field, download) in [
+        ("genesis_url", &config.genesis_url),
+        ("addrbook_url", &config.addrbook_url),
+    ] {
+        if let Some(download) = download {
+            urls.push((field, download.url().to_string()));
+        }
+    }
+    if let Some(url) = &config.snapshot_chunk_manifest {
+        urls.push(("snapshot_chunk_manifest", url.clone()));
+    }
+    for (field, signature) in [
+        ("snapshot_signature.url", &config.snapshot_signature),
+        ("binary_signature.url", &config.binary_signature),
+    ] {
+        if let Some(signature) = signature {
+            urls.push((field, signature.url.clone()));
+        }
+    }
+
+    let mut unreachable = Vec::new();
+    for (field, url) in urls {
+        if url.starts_with("magnet:") {
+            continue;
+        }
+        // Gated endpoints only answer the customized request
+        let request = match field {
+            "snapshot_url" | "snapshot_increments" => config.snapshot_request.clone(),
+            "binary_url" => config.binary_request.clone(),
+            _ => None,
+        };
+        let downloader = Downloader::new()
+            .with_request(request)
+            .with_s3(config.s3.clone());
+        match downloader.probe(&url).await {
+            Ok(size) => info!(
+                "{} is reachable{}",
+                url,
+                size.map(|s| format!(" ({} bytes)", s)).unwrap_or_default()
+            ),
+            Err(e) => unreachable.push(format!("{}: {} ({:#})", field, url, e)),
+        }
+    }
+
+    if !unreachable.is_empty() {
+        return Err(anyhow!(
+            "{} configured URLs are unreachable:\n{}",
+            unreachable.len(),
+            unreachable.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+/// Benchmarks every configured snapshot mirror and prints them fastest first
+pub async fn bench_mirrors(config: &mut Config, sample_mb: u64) -> Result<()> {
+    resolve_provider_snapshot(config).await?;
+
+    let results = Downloader::new()
+        .with_s3(config.s3.clone())
+        .benchmark_mirrors(&config.snapshot_url, sample_mb * 1_048_576)
+        .await;
+
+    if results.is_empty() {
+        return Err(anyhow!("No snapshot mirror responded to the benchmark"));
+    }
+
+    for (rank, result) in results.iter().enumerate() {
+        println!(
+            "{}. {} - latency: {} ms, throughput: {:.2} MB/s",
+            rank + 1,
+            result.url,
+            result.latency.as_millis(),
+            result.throughput / 1_048_576.0
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the node set up in the output directory under supervision
+///
+/// With `sync_wait` set, the node is stopped with an error unless it catches
+/// up with the chain within the timeout; supervision continues once it has.
+pub async fn run_node(
+    config: &Config,
+    dirs: &Layout,
+    sync_wait: Option<(Duration, Option<String>)>,
+) -> Result<()> {
+    let data_dir = &dirs.home;
+    if let Some(entry) = &config.registry {
+        apply_registry_peers(config, entry, dirs);
+    }
+    // Providers refresh their addrbook often, and the node starts faster
+    // from a fresh one; the installed one still works if it can't be fetched.
+    // Its own directory keeps the last copy from being resumed as a partial file
+    if let Some(addrbook) = &config.addrbook_url {
+        let download_dir = dirs.scratch.join("artifacts").join("refresh");
+        let _ = std::fs::remove_dir_all(&download_dir);
+        if let Err(e) = install_node_artifact(
+            config,
+            dirs,
+            &download_dir,
+            "addrbook.json",
+            addrbook,
+            &config.addrbook_checksum,
+        )
+        .await
+        {
+            warn!("Keeping the installed addrbook: {:#}", e);
+        }
+    }
+
+    let mut supervisor =
+        NodeSupervisor::new(&config.cosmos, &config.supervisor, &dirs.bin, data_dir);
+    if let Some(cosmovisor) = &config.cosmovisor {
+        let layout = Cosmovisor::new(cosmovisor, data_dir, &config.cosmos.bin)?;
+        supervisor = supervisor.with_cosmovisor(layout.path(), layout.read_env_file()?);
+    }
+
+    let supervised = supervisor.run();
+    tokio::pin!(supervised);
+
+    if let Some((timeout, rpc_url)) = sync_wait {
+        let waiter = match rpc_url {
+            Some(url) => SyncWaiter::new(&url),
+            None => SyncWaiter::for_data_dir(data_dir),
+        };
+
+        tokio::select! {
+            result = &mut supervised => {
+                result?;
+                return Err(anyhow!("Node stopped before it finished syncing"));
+            }
+            synced = waiter.wait(timeout) => {
+                let height = synced.context("Node failed to sync")?;
+                info!("Node caught up with the chain at height {}", height);
+                notify::status(&format!("Node synced at height {}", height));
+            }
+        }
+    }
+
+    // Without a sync wait the service is up as soon as the node is started
+    notify::ready();
+    supervised.await
+}
+
+/// Prints the drift between the configured settings and a node home's TOML files
+pub fn config_diff(
+    config: &Config,
+    dirs: &Layout,
+    home: Option<PathBuf>,
+    exit_code: bool,
+) -> Result<()> {
+    let home = home.unwrap_or_else(|| dirs.home.clone());
+    let drift = CosmosSetup::new(&config.cosmos, &dirs.bin, &home)
+        .diff()
+        .context("Failed to compare node configuration")?;
+
+    if drift.is_empty() {
+        println!("{} matches the configuration", home.display());
+        return Ok(());
+    }
+
+    for setting in &drift {
+        match &setting.actual {
+            Some(actual) => println!(
+                "{}: {} = {} (configured: {})",
+                setting.file, setting.key, actual, setting.expected
+            ),
+            None => println!(
+                "{}: {} is missing (configured: {})",
+                setting.file, setting.key, setting.expected
+            ),
+        }
+    }
+
+    if exit_code {
+        return Err(anyhow!(
+            "{} settings differ from the configuration",
+            drift.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a node's data directory against the manifest written after the restore
+///
+/// Fails when any file changed, disappeared or was added since.
+pub fn verify_data(
+    config: &Config,
+    dirs: &Layout,
+    home: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+) -> Result<()> {
+    let home = home.unwrap_or_else(|| dirs.home.clone());
+    let manifest = manifest.unwrap_or_else(|| dirs.root.join(manifest::MANIFEST_FILE));
+    let diff = manifest::verify(&home, &manifest, config.jobs)
+        .context("Failed to verify the data directory")?;
+
+    if diff.is_empty() {
+        println!("{} matches {}", home.display(), manifest.display());
+        return Ok(());
+    }
+
+    for path in &diff.changed {
+        println!("changed: {}", path);
+    }
+    for path in &diff.missing {
+        println!("missing: {}", path);
+    }
+    for path in &diff.added {
+        println!("added: {}", path);
+    }
+
+    Err(anyhow!(
+        "{} files differ from {}",
+        diff.changed.len() + diff.missing.len() + diff.added.len(),
+        manifest.display()
+    ))
+}
+
+/// Walks a downloaded snapshot archive without extracting it
+///
+/// The whole archive is decoded, so truncation and corruption show up before
+/// a long extraction is started. With a checksum given or configured, the
+/// archive is hashed alongside the walk.
+pub async fn verify_archive(
+    config: &Config,
+    dirs: &Layout,
+    snapshot: Option<PathBuf>,
+    checksum: Option<String>,
+) -> Result<()> {
+    let cached = snapshot.is_none();
+    let path = match snapshot {
+        Some(path) => path,
+        None if config.snapshot_url.is_empty() => {
+            return Err(anyhow!(
+                "The snapshot comes from snapshot_provider; pass the archive with --snapshot"
+            ))
+        }
+        None => dirs
+            .snapshots
+            .join(snapshot_name(config, &RunState::load(&dirs.root))?),
+    };
+    if !path.is_file() {
+        return Err(anyhow!(
+            "{} doesn't exist; download it first or pass the archive with --snapshot",
+            path.display()
+        ));
+    }
+
+    let verifier = checksum_verifier(config);
+    let expected = match checksum.or_else(|| config.snapshot_checksum.clone()) {
+        Some(checksum) => {
+            let listed = match config.snapshot_url.first() {
+                Some(url) if cached => listed_name(url, &path, config.snapshot_filename.as_deref()),
+                _ => file_name(&path),
+            };
+            Some(configured_checksum(&verifier, &checksum, &listed).await?)
+        }
+        None => None,
+    };
+
+    let extractor = snapshot_extractor(config)?.with_format(config.snapshot_format);
+    let walked = path.clone();
+    let walk = tokio::task::spawn_blocking(move || extractor.verify(&walked));
+    let hash = async {
+        match &expected {
+            Some(expected) => verifier.verify(&path, expected).await,
+            None => Ok(()),
+        }
+    };
+    let (summary, hashed) = tokio::join!(walk, hash);
+    let summary = summary
+        .context("Archive verification panicked")?
+        .context("Archive verification failed")?;
+    hashed?;
+
+    println!(
+        "{}: {} entries, {:.2} GB unpacked from {:.2} GB",
+        path.display(),
+        summary.entries,
+        summary.unpacked_bytes as f64 / 1e9,
+        summary.archive_bytes as f64 / 1e9
+    );
+    match expected {
+        Some(expected) => println!("SHA256 matches {}", expected),
+        None => println!("No checksum given, the contents were not compared"),
+    }
+    Ok(())
+}
+
+/// Checks whether a run would change anything, without writing to disk
+///
+/// Resolves the snapshot as a run would, compares the local downloads against
+/// the remote files and their published checksums, and compares an existing
+/// node home against the configured settings. Every finding is printed and
+/// the check fails with [`error::Error::Outdated`], so a cron job can alert
+/// when a newer snapshot is published.
+pub async fn check_only(config: &mut Config, dirs: &Layout) -> Result<()> {
+    let snapshots_dir = &dirs.snapshots;
+    let home = &dirs.home;
+    let mut findings = Vec::new();
+
+    let provider_snapshot = resolve_provider_snapshot(config).await?;
+    // Too little room is something to report, not a reason to stop checking
+    if let Some(resolved) = &provider_snapshot {
+        if let Err(e) = check_archive_space(config, resolved, &dirs.scratch) {
+            findings.push(format!("snapshot: {:#}", e));
+        }
+    }
+    let snapshot_url = config.snapshot_url[0].clone();
+    let height = provider_snapshot
+        .as_ref()
+        .and_then(|p| p.snapshot.height)
+        .or_else(|| {
+            let name = snapshot_name(config, &RunState::load(&dirs.root)).ok()?;
+            SnapshotInfo::from_file_name(&name).height
+        });
+
+    let mut artifacts = vec![(
+        "snapshot",
+        snapshot_url,
+        config.snapshot_filename.clone(),
+        config.snapshot_checksum.clone().or_else(|| {
+            provider_snapshot
+                .as_ref()
+                .and_then(|p| p.snapshot.sha256.clone())
+        }),
+    )];
+    for increment in provider_snapshot.iter().flat_map(|p| &p.increments) {
+        artifacts.push((
+            "incremental snapshot",
+            increment.url.clone(),
+            None,
+            increment.sha256.clone(),
+        ));
+    }
+    match check_binary_source(config).await? {
+        Some((url, checksum)) => artifacts.push((
+            "binary",
+            url,
+            config.binary_filename.clone(),
+            config.binary_checksum.clone().or(checksum),
+        )),
+        None => println!("binary: built locally, not checked"),
+    }
+
+    let downloader = |request: &Option<RequestConfig>| {
+        Downloader::new()
+            .with_retry(RetryPolicy::for_stage(
+                &config.retries,
+                RetryStage::Download,
+            ))
+            .with_request(request.clone())
+            .with_s3(config.s3.clone())
+            .with_max_rate(config.max_rate)
+    };
+    let snapshot_downloader = downloader(&config.snapshot_request);
+    let binary_downloader = downloader(&config.binary_request);
+    let verifier = checksum_verifier(config);
+    for (what, url, file_name, checksum) in artifacts {
+        if torrent::is_torrent_url(&url) {
+            println!("{}: torrent downloads are not checked", what);
+            continue;
+        }
+
+        let downloader = match what {
+            "binary" => &binary_downloader,
+            _ => &snapshot_downloader,
+        };
+        let remote = downloader
+            .inspect(&url, snapshots_dir, file_name.as_deref())
+            .await
+            .with_context(|| format!("Failed to fetch metadata of {}", url))?;
+        let name = remote
+            .path
+            .file_name()
+            .map_or_else(|| url.clone(), |n| n.to_string_lossy().into_owned());
+        let local_size = std::fs::metadata(&remote.path).ok().map(|m| m.len());
+
+        match (local_size, remote.size) {
+            (None, _) if what == "snapshot" => findings.push(match height {
+                Some(height) => {
+                    format!("snapshot: {} at height {} is not downloaded", name, height)
+                }
+                None => format!("snapshot: {} is not downloaded", name),
+            }),
+            (None, _) => findings.push(format!("{}: {} is not downloaded", what, name)),
+            (Some(local), Some(remote_size)) if local != remote_size => findings.push(format!(
+                "{}: {} is incomplete ({} of {} bytes)",
+                what, name, local, remote_size
+            )),
+            (Some(_), _) if segmented::state_path(&remote.path).exists() => findings.push(format!(
+                "{}: {} is partly downloaded in segments",
+                what, name
+            )),
+            (Some(_), _) => {
+                let expected = match checksum {
+                    Some(checksum) => {
+                        let listed = listed_name(&url, &remote.path, file_name.as_deref());
+                        Some(configured_checksum(&verifier, &checksum, &listed).await?)
+                    }
+                    None => verifier.discover(&url).await?.map(|(expected, _)| expected),
+                };
+                match expected {
+                    Some(expected) => match verifier.verify(&remote.path, &expected).await {
+                        Ok(()) => println!("{}: {} is up to date", what, name),
+                        Err(error::Error::ChecksumMismatch { .. }) => findings.push(format!(
+                            "{}: {} does not match its published checksum",
+                            what, name
+                        )),
+                        Err(e) => return Err(e.into()),
+                    },
+                    None => println!("{}: {} is complete, no published checksum", what, name),
+                }
+            }
+        }
+    }
+
+    if home.join("config").is_dir() {
+        let drift = CosmosSetup::new(&config.cosmos, &dirs.bin, home)
+            .diff()
+            .context("Failed to compare node configuration")?;
+        findings.extend(drift.iter().map(|setting| match &setting.actual {
+            Some(actual) => format!(
+                "{}: {} = {} (configured: {})",
+                setting.file, setting.key, actual, setting.expected
+            ),
+            None => format!(
+                "{}: {} is missing (configured: {})",
+                setting.file, setting.key, setting.expected
+            ),
+        }));
+    } else {
+        println!("node home: {} is not set up yet", home.display());
+    }
+
+    if findings.is_empty() {
+        println!("Everything is up to date");
+        return Ok(());
+    }
+
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    Err(error::Error::Outdated(findings.len()).into())
+}
+
+/// Resolves the binary download and its checksum without building or pulling anything
+///
+/// Returns `None` for binaries built from source or taken from a container image.
+async fn check_binary_source(config: &Config) -> Result<Option<(String, Option<String>)>> {
+    let source = config.binary.as_ref();
+    if let Some(binary_url) = &config.binary_url {
+        return match binary_url.for_host() {
+            Ok(url) => {
+                let (url, checksum) = split_checksum(url);
+                Ok(Some((url.to_string(), checksum.map(str::to_string))))
+            }
+            // Hosts without an artifact of their own build from source
+            Err(_) if source.and_then(|s| s.build.as_ref()).is_some() => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+
+    let Some(repo) = source.and_then(|s| s.github.as_ref()) else {
+        return Ok(None);
+    };
+    let asset = GithubReleases::new()
+        .resolve(
+            repo,
+            source.and_then(|s| s.version.as_deref()),
+            source.and_then(|s| s.asset.as_deref()),
+        )
+        .await
+        .context("Failed to resolve binary from GitHub Releases")?;
+    Ok(Some((asset.url, asset.checksum)))
+}
+
+/// Patches a node home's TOML files with the configured settings
+///
+/// The node has to be restarted for the changes to take effect.
+pub fn config_apply(config: &Config, dirs: &Layout, home: Option<PathBuf>) -> Result<()> {
+    let home = home.unwrap_or_else(|| dirs.home.clone());
+    if !home.join("config").is_dir() {
+        return Err(anyhow!(
+            "{} has no config directory; is it a node home?",
+            home.display()
+        ));
+    }
+
+    let setup = CosmosSetup::new(&config.cosmos, &dirs.bin, &home);
+    let drift = setup
+        .diff()
+        .context("Failed to compare node configuration")?;
+    if drift.is_empty() {
+        println!("{} already matches the configuration", home.display());
+        return Ok(());
+    }
+
+    setup
+        .apply_settings()
+        .context("Failed to apply node configuration")?;
+    for setting in &drift {
+        println!(
+            "{}: {} = {} (was: {})",
+            setting.file,
+            setting.key,
+            setting.expected,
+            setting.actual.as_deref().unwrap_or("unset")
+        );
+    }
+    println!("Restart the node for the changes to take effect");
+
+    Ok(())
+}
+
+/// Serves the output directory's snapshot archives to sibling hosts
+pub async fn share_snapshots(config: &Config, dirs: &Layout, addr: Option<String>) -> Result<()> {
+    let fleet = config.fleet.as_ref();
+    let addr = addr
+        .or_else(|| fleet.map(|f| f.listen.clone()))
+        .unwrap_or_else(config::default_share_addr);
+    let token = fleet.and_then(|f| f.token.as_deref());
+    if token.is_none() {
+        warn!("No fleet token configured, snapshots are shared without authentication");
+    }
+
+    ShareServer::new(&dirs.snapshots, token).serve(&addr).await
+}
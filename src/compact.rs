@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context, Result};
+use rusty_leveldb::compressor::SnappyCompressor;
+use rusty_leveldb::{CompressorId, Options, DB};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, instrument, warn};
+
+use crate::config::CompactConfig;
+use crate::db_backend::{self, DbBackend};
+use crate::prune::dir_size;
+
+/// Upper bound of the key range compacted in-process
+///
+/// LevelDB has no "end of keyspace" marker, so a key that sorts after any key
+/// a node writes stands in for it.
+const RANGE_END: [u8; 256] = [0xff; 256];
+
+/// Rough memory held per open table, mostly its index block
+const TABLE_MEMORY: usize = 64 << 10;
+
+/// Open tables kept even under a tight memory cap
+const MIN_OPEN_FILES: usize = 16;
+
+/// Compacts the databases of a restored node home
+pub struct Compactor {
+    /// Compaction settings
+    config: CompactConfig,
+
+    /// Node home directory
+    data_dir: PathBuf,
+
+    /// Memory the in-process compaction's caches must fit in
+    max_memory: Option<u64>,
+}
+
+impl Compactor {
+    /// Creates a compactor for the node home at `data_dir`
+    pub fn new(config: &CompactConfig, data_dir: &Path) -> Result<Self> {
+        Ok(Compactor {
+            config: config.clone(),
+            data_dir: std::path::absolute(data_dir).context("Failed to resolve data directory")?,
+            max_memory: None,
+        })
+    }
+
+    /// Sizes the in-process compaction's caches to fit in `max_memory` bytes
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Compacts the node's databases
+    ///
+    /// Runs the configured node binary command when one is set; otherwise
+    /// every LevelDB database is compacted in-process and databases of other
+    /// engines are left alone.
+    ///
+    /// # Arguments
+    /// * `binary` - Node binary, used for the configured compaction command
+    #[instrument(skip(self, binary), fields(command = ?self.config.command))]
+    pub fn compact(&self, binary: &Path) -> Result<()> {
+        let db_dir = self.data_dir.join("data");
+        let size_before = dir_size(&db_dir)?;
+
+        match &self.config.command {
+            Some(subcommand) => self.run_node_command(binary, subcommand)?,
+            None => compact_leveldb_databases(&db_dir, self.max_memory)?,
+        }
+
+        let size_after = dir_size(&db_dir)?;
+        info!(
+            "Compacted data from {:.2} GB to {:.2} GB",
+            size_before as f64 / 1e9,
+            size_after as f64 / 1e9
+        );
+        Ok(())
+    }
+
+    /// Runs the node binary's compaction command against the node home
+    fn run_node_command(&self, binary: &Path, subcommand: &str) -> Result<()> {
+        info!("Running compaction command: {}", subcommand);
+        let status = Command::new(binary)
+            .args(subcommand.split_whitespace())
+            .arg("--home")
+            .arg(&self.data_dir)
+            .status()
+            .context("Failed to execute compaction command")?;
+        if !status.success() {
+            return Err(anyhow!("Compaction command failed with {}", status));
+        }
+
+        Ok(())
+    }
+}
+
+/// Compacts every LevelDB database directly below `db_dir`
+fn compact_leveldb_databases(db_dir: &Path, max_memory: Option<u64>) -> Result<()> {
+    let mut databases: Vec<_> = fs::read_dir(db_dir)
+        .with_context(|| format!("Failed to read {}", db_dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && path.extension().is_some_and(|ext| ext == "db"))
+        .collect();
+    databases.sort();
+
+    for path in databases {
+        match db_backend::classify(&path)? {
+            Some(DbBackend::GoLevel) => compact_leveldb(&path, max_memory)?,
+            Some(backend) => warn!(
+                "{} uses {}, which can't be compacted in-process; set compact.command instead",
+                path.display(),
+                backend.as_str()
+            ),
+            None => info!("{} is not a recognized database, skipping", path.display()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Compacts a single LevelDB database over its whole key range
+#[instrument(skip(path), fields(db = %path.display()))]
+fn compact_leveldb(path: &Path, max_memory: Option<u64>) -> Result<()> {
+    info!("Compacting {}", path.display());
+
+    // Match goleveldb, which writes Snappy-compressed tables by default
+    let mut options = Options {
+        create_if_missing: false,
+        compressor: SnappyCompressor::ID,
+        ..Options::default()
+    };
+    if let Some(max_memory) = max_memory {
+        // Split the budget between the block cache, the write buffer and open tables
+        let share = usize::try_from(max_memory / 4).unwrap_or(usize::MAX);
+        options.block_cache_capacity_bytes = options.block_cache_capacity_bytes.min(share);
+        options.write_buffer_size = options.write_buffer_size.min(share);
+        options.max_open_files = options
+            .max_open_files
+            .min((share / TABLE_MEMORY).max(MIN_OPEN_FILES));
+    }
+    let mut db =
+        DB::open(path, options).with_context(|| format!("Failed to open {}", path.display()))?;
+    db.compact_range(&[], &RANGE_END)
+        .with_context(|| format!("Failed to compact {}", path.display()))?;
+    db.close()
+        .with_context(|| format!("Failed to close {}", path.display()))?;
+
+    Ok(())
+}
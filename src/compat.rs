@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use tracing::{info, instrument, warn};
+
+use crate::config::{BinaryUrl, UpgradeConfig};
+use crate::registry::ChainEntry;
+
+/// Builds the upgrade schedule, preferring the configured table over the registry
+///
+/// Registry versions without an activation height are treated as the genesis
+/// version. The schedule is sorted by height.
+pub fn upgrade_schedule(
+    configured: &[UpgradeConfig],
+    registry: Option<&ChainEntry>,
+) -> Vec<UpgradeConfig> {
+    let mut schedule = if !configured.is_empty() {
+        configured.to_vec()
+    } else {
+        registry
+            .and_then(|entry| entry.codebase.as_ref())
+            .map(|codebase| {
+                codebase
+                    .versions
+                    .iter()
+                    .filter_map(|v| {
+                        Some(UpgradeConfig {
+                            name: v.name.clone(),
+                            height: v.height.unwrap_or(0),
+                            version: v.recommended_version.clone()?,
+                            binary_url: registry_binaries(&v.binaries),
+                            save_as: None,
+                            signature: None,
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    schedule.sort_by_key(|upgrade| upgrade.height);
+    schedule
+}
+
+/// Converts the registry's `os/arch` binary map to a per-platform binary URL
+pub fn registry_binaries(binaries: &HashMap<String, String>) -> Option<BinaryUrl> {
+    if binaries.is_empty() {
+        return None;
+    }
+
+    Some(BinaryUrl::PerPlatform(
+        binaries
+            .iter()
+            .map(|(platform, url)| (platform.replace('/', "-"), url.clone()))
+            .collect(),
+    ))
+}
+
+/// Refuses binaries that can't replay blocks from the snapshot's height
+///
+/// The upgrade active at `height` sets the minimum version; the next upgrade,
+/// if any, sets the version the binary must stay below.
+#[instrument(skip(binary_path, schedule), fields(bin_path = %binary_path.display()))]
+pub fn check_compatibility(
+    binary_path: &Path,
+    height: u64,
+    schedule: &[UpgradeConfig],
+) -> Result<()> {
+    let Some(index) = schedule.iter().rposition(|u| u.height <= height) else {
+        warn!(
+            "No upgrade in the schedule covers height {}, skipping compatibility check",
+            height
+        );
+        return Ok(());
+    };
+    let active = &schedule[index];
+    let next = schedule.get(index + 1);
+
+    let reported = binary_version(binary_path)?;
+    let (Some(version), Some(minimum)) = (parse_version(&reported), parse_version(&active.version))
+    else {
+        warn!(
+            "Could not compare binary version {} with {}, skipping compatibility check",
+            reported, active.version
+        );
+        return Ok(());
+    };
+
+    let below_next = next
+        .and_then(|n| parse_version(&n.version))
+        .is_none_or(|limit| version < limit);
+    if version >= minimum && below_next {
+        info!(
+            "Binary {} matches upgrade {} active at height {}",
+            reported, active.name, height
+        );
+        return Ok(());
+    }
+
+    let range = match next {
+        Some(next) => format!(
+            "heights {}..{} require {} (until upgrade {} at {})",
+            active.height, next.height, active.version, next.name, next.height
+        ),
+        None => format!("heights from {} require {}", active.height, active.version),
+    };
+    Err(anyhow!(
+        "Binary version {} can't be used with a snapshot at height {}: {}",
+        reported,
+        height,
+        range
+    ))
+}
+
+/// Runs `<binary> version` and returns the line carrying the version
+fn binary_version(binary_path: &Path) -> Result<String> {
+    let output = Command::new(binary_path)
+        .arg("version")
+        .output()
+        .context("Failed to run binary to query its version")?;
+
+    // Older Cosmos SDK releases print the version to stderr
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+
+    lines
+        .clone()
+        .find(|line| parse_version(line).is_some())
+        .or_else(|| lines.next())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Binary printed no version"))
+}
+
+/// Parses the `major.minor.patch` core of a version such as `v1.4.4-testnet`
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.trim().trim_start_matches('v');
+    let core = core.split(['-', '+', ' ']).next()?;
+    let mut parts = core.split('.').map(|part| part.parse::<u64>());
+
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().transpose().ok()?.unwrap_or(0);
+    let patch = parts.next().transpose().ok()?.unwrap_or(0);
+
+    Some((major, minor, patch))
+}
@@ -1,24 +1,1262 @@
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
+use crate::error::Error;
+use crate::exclude::{ExcludePreset, Exclusions};
+use crate::extractor::ArchiveFormat;
+use crate::registry::ChainEntry;
+
 /// Main application configuration
 ///
-/// Contains URLs for downloading required files and
-/// configuration for the Cosmos node setup
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Config {
-    /// URL to download the blockchain snapshot
-    pub snapshot_url: String,
+/// Contains URLs for downloading required files and
+/// configuration for the Cosmos node setup
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// URLs to download the blockchain snapshot from
+    ///
+    /// Accepts either a single URL or a list of mirrors serving the same file
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub snapshot_url: Vec<String>,
+
+    /// Incremental archives applied in order on top of the snapshot
+    #[serde(default)]
+    pub snapshot_increments: Vec<DownloadUrl>,
+
+    /// Provider index to resolve the latest snapshot from instead of `snapshot_url`
+    #[serde(default)]
+    pub snapshot_provider: Option<ProviderConfig>,
+
+    /// Benchmark the snapshot mirrors and download from the fastest first
+    #[serde(default)]
+    pub bench_mirrors: bool,
+
+    /// Limits on how old a snapshot may be before it is restored
+    #[serde(default)]
+    pub freshness: Option<FreshnessConfig>,
+
+    /// How many old archives and extractions to keep in the snapshots directory
+    #[serde(default)]
+    pub retention: Option<RetentionConfig>,
+
+    /// Free space check run before downloading and extracting
+    #[serde(default)]
+    pub disk_check: DiskCheckConfig,
+
+    /// Local file name for the downloaded snapshot, overriding the derived one
+    #[serde(default)]
+    pub snapshot_filename: Option<String>,
+
+    /// Expected SHA256 of the snapshot, or the URL of a checksum file listing it
+    #[serde(default)]
+    pub snapshot_checksum: Option<String>,
+
+    /// Look for a published checksum next to each download without a configured one
+    #[serde(default = "default_true")]
+    pub discover_checksums: bool,
+
+    /// Detached signature used to verify the snapshot
+    #[serde(default)]
+    pub snapshot_signature: Option<SignatureConfig>,
+
+    /// URL of a manifest of per-chunk SHA256 digests of the snapshot, checked
+    /// while downloading so only corrupt chunks are fetched again
+    #[serde(default)]
+    pub snapshot_chunk_manifest: Option<String>,
+
+    /// Compression of the snapshot archive, overriding detection
+    #[serde(default)]
+    pub snapshot_format: Option<ArchiveFormat>,
+
+    /// Key for snapshots published GPG- or age-encrypted (`.gpg`, `.age`)
+    #[serde(default)]
+    pub decryption: Option<DecryptionConfig>,
+
+    /// Directory for downloads, the extracted binary package and extraction
+    /// staging, instead of the output directory (`--tmp-dir` overrides it)
+    #[serde(default)]
+    pub tmp_dir: Option<String>,
+
+    /// Directory the binary package is extracted into, with `cosmos.bin`
+    /// relative to it, instead of `bin_extract` (`--bin-dir` overrides it)
+    #[serde(default)]
+    pub bin_dir: Option<String>,
+
+    /// Directory downloaded archives are kept in and the snapshot is staged
+    /// in, instead of `snapshots` (`--cache-dir` overrides it)
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+
+    /// Node home the snapshot is restored into, instead of `data` in the
+    /// output directory (`--data-dir` overrides it)
+    #[serde(default)]
+    pub data_dir: Option<String>,
+
+    /// Extract the snapshot straight into the data directory instead of
+    /// extracting it under `snapshots` and copying it over
+    #[serde(default)]
+    pub extract_to_data_dir: bool,
+
+    /// Extract the snapshot as it downloads instead of storing the archive first
+    #[serde(default)]
+    pub stream_extract: bool,
+
+    /// Leading path components stripped from archive entries extracted into the data directory
+    #[serde(default)]
+    pub strip_components: usize,
+
+    /// Named sets of index and cache data left out of the snapshot
+    #[serde(default)]
+    pub exclude: Vec<ExcludePreset>,
+
+    /// Further globs of archive entries left out of the snapshot, matched
+    /// against entry paths after `strip_components`
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+
+    /// Extract entries with absolute or `..` paths and links pointing outside
+    /// the output directory instead of refusing the archive
+    /// (`--allow-unsafe-paths` sets it)
+    #[serde(default)]
+    pub allow_unsafe_paths: bool,
+
+    /// URL to download the node binary, or a map of platform to URL
+    #[serde(default)]
+    pub binary_url: Option<BinaryUrl>,
+
+    /// Source to resolve the node binary from, as an alternative to `binary_url`
+    #[serde(default)]
+    pub binary: Option<BinarySource>,
+
+    /// Local file name for the downloaded binary, overriding the derived one
+    #[serde(default)]
+    pub binary_filename: Option<String>,
+
+    /// Expected SHA256 of the binary package, or the URL of a checksum file listing it
+    #[serde(default)]
+    pub binary_checksum: Option<String>,
+
+    /// Detached signature used to verify the binary
+    #[serde(default)]
+    pub binary_signature: Option<SignatureConfig>,
+
+    /// Compression of the binary package, overriding detection
+    #[serde(default)]
+    pub binary_format: Option<ArchiveFormat>,
+
+    /// URL of the chain's genesis.json, installed over the one created by init
+    #[serde(default)]
+    pub genesis_url: Option<DownloadUrl>,
+
+    /// Expected SHA256 of the genesis file, or the URL of a checksum file listing it
+    #[serde(default)]
+    pub genesis_checksum: Option<String>,
+
+    /// URL of an addrbook.json to seed the node's peer list with
+    #[serde(default)]
+    pub addrbook_url: Option<DownloadUrl>,
+
+    /// Expected SHA256 of the addrbook, or the URL of a checksum file listing it
+    #[serde(default)]
+    pub addrbook_checksum: Option<String>,
+
+    /// Chain registry name of the chain, e.g. `osmosis`; `--chain` overrides it
+    #[serde(default)]
+    pub chain: Option<String>,
+
+    /// Key of this chain in a multi-chain file's `chains` map, e.g. `osmosis-1`
+    #[serde(skip)]
+    pub chain_id: Option<String>,
+
+    /// Base URL of the chain registry
+    #[serde(default = "default_chain_registry_url")]
+    pub chain_registry_url: String,
+
+    /// Registry entry of `chain`, once fetched
+    #[serde(skip)]
+    pub registry: Option<ChainEntry>,
+
+    /// Chain upgrade schedule, used instead of the registry's when set
+    #[serde(default)]
+    pub upgrades: Vec<UpgradeConfig>,
+
+    /// Cosmos-specific configuration
+    pub cosmos: CosmosConfig,
+
+    /// Prune the restored data to a smaller footprint before the node starts
+    #[serde(default)]
+    pub prune: Option<PruneConfig>,
+
+    /// Compact the restored databases before the node starts
+    #[serde(default)]
+    pub compact: Option<CompactConfig>,
+
+    /// Record SHA256 digests of the restored data for the `verify` command
+    #[serde(default)]
+    pub data_manifest: bool,
+
+    /// Run the node under cosmovisor, with binaries laid out for upgrades;
+    /// `true` enables it with defaults
+    #[serde(default, deserialize_with = "deserialize_enabled")]
+    pub cosmovisor: Option<CosmovisorConfig>,
+
+    /// Restart policy for the `run` command
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+
+    /// Write a systemd unit that starts the node once setup finishes; `true`
+    /// enables it with defaults
+    #[serde(default, deserialize_with = "deserialize_enabled")]
+    pub systemd: Option<SystemdConfig>,
+
+    /// How transient network failures are retried
+    #[serde(default)]
+    pub retries: RetryConfig,
+
+    /// Download large files as parallel range requests
+    #[serde(default)]
+    pub segmented: Option<SegmentedConfig>,
+
+    /// How requests for the snapshot and its increments are made, for
+    /// endpoints that need more than a plain GET
+    #[serde(default)]
+    pub snapshot_request: Option<RequestConfig>,
+
+    /// How requests for the node binary are made
+    #[serde(default)]
+    pub binary_request: Option<RequestConfig>,
+
+    /// Upper bound on memory used for buffering while downloading,
+    /// extracting and compacting, e.g. `256MB`
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    pub max_memory: Option<u64>,
+
+    /// Bytes per second downloads may use, e.g. `50MB` (`--max-rate`
+    /// overrides it)
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    pub max_rate: Option<u64>,
+
+    /// Upper bound on parallel download connections and on copy and hashing
+    /// workers (`--jobs` overrides it)
+    #[serde(default)]
+    pub jobs: Option<usize>,
+
+    /// Threads decompressing gzip and XZ snapshots, `jobs` when unset
+    /// (`--extract-threads` overrides it)
+    #[serde(default)]
+    pub extract_threads: Option<usize>,
+
+    /// CPU and disk priority of the restore, so it doesn't starve running nodes
+    #[serde(default)]
+    pub priority: Option<PriorityConfig>,
+
+    /// BitTorrent settings for magnet and .torrent snapshot URLs
+    #[serde(default)]
+    pub torrent: TorrentConfig,
+
+    /// Region, endpoint and credentials profile for `s3://` URLs
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+
+    /// Sibling hosts to fetch the snapshot from before going to `snapshot_url`
+    #[serde(default)]
+    pub fleet: Option<FleetConfig>,
+}
+
+/// Key used to decrypt encrypted snapshot archives
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DecryptionConfig {
+    /// Secret key file imported into a throwaway keyring; without one, gpg uses
+    /// the default keyring and agent
+    #[serde(default)]
+    pub key_file: Option<String>,
+
+    /// File holding the key's passphrase, for keys not unlocked in an agent,
+    /// or the passphrase of an archive encrypted with `age --passphrase`
+    #[serde(default)]
+    pub passphrase_file: Option<String>,
+
+    /// age identity file (as written by `age-keygen`) for `.age` archives
+    #[serde(default)]
+    pub identity_file: Option<String>,
+}
+
+/// Snapshot sharing between the hosts of a fleet
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FleetConfig {
+    /// Base URLs of hosts running `share`, e.g. `http://10.0.0.5:8765`, tried in order
+    #[serde(default)]
+    pub peers: Vec<String>,
+
+    /// Shared secret sent to peers as a bearer token and required by `share`
+    #[serde(default)]
+    pub token: Option<String>,
+
+    /// Address `share` listens on
+    #[serde(default = "default_share_addr")]
+    pub listen: String,
+}
+
+/// Default listen address of the `share` command
+pub fn default_share_addr() -> String {
+    "0.0.0.0:8765".to_string()
+}
+
+/// BitTorrent settings
+///
+/// Seeding after the download is off unless a ratio or time limit is set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TorrentConfig {
+    /// aria2 executable used for torrent downloads
+    #[serde(default = "default_aria2c_path")]
+    pub aria2c: String,
+
+    /// Stop seeding once this upload/download ratio is reached
+    #[serde(default)]
+    pub seed_ratio: Option<f64>,
+
+    /// Stop seeding after this many minutes
+    #[serde(default)]
+    pub seed_time_mins: Option<u64>,
+}
+
+impl Default for TorrentConfig {
+    fn default() -> Self {
+        TorrentConfig {
+            aria2c: default_aria2c_path(),
+            seed_ratio: None,
+            seed_time_mins: None,
+        }
+    }
+}
+
+/// Default aria2 executable, looked up in PATH
+fn default_aria2c_path() -> String {
+    "aria2c".to_string()
+}
+
+/// Settings for `s3://` URLs
+///
+/// Credentials come from the standard AWS chain: the environment, the shared
+/// credentials and config files, web identity tokens, the ECS container
+/// endpoint and EC2 instance metadata.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct S3Config {
+    /// Region of the buckets (default: `AWS_REGION`, the profile's region or `us-east-1`)
+    #[serde(default)]
+    pub region: Option<String>,
+
+    /// Endpoint of an S3-compatible store such as MinIO or Cloudflare R2,
+    /// whose buckets are addressed path-style
+    #[serde(default)]
+    pub endpoint: Option<String>,
+
+    /// Profile of the shared AWS files to use (default: `AWS_PROFILE` or `default`)
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+/// Post-restore pruning settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PruneConfig {
+    /// Program that prunes the data
+    #[serde(default)]
+    pub tool: PruneTool,
+
+    /// Number of recent application state versions to keep
+    #[serde(default = "default_prune_keep_versions")]
+    pub keep_versions: u64,
+
+    /// Number of recent blocks to keep; only cosmprund prunes blocks
+    #[serde(default = "default_prune_keep_blocks")]
+    pub keep_blocks: u64,
+
+    /// cosmprund executable
+    #[serde(default = "default_cosmprund_path")]
+    pub cosmprund: String,
+
+    /// Extra arguments passed to the pruning command
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Program used to prune a restored data directory
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PruneTool {
+    /// The node binary's `prune` command, which prunes application state
+    #[default]
+    Node,
+
+    /// cosmprund, which prunes application state and blocks
+    Cosmprund,
+}
+
+/// Post-restore compaction settings
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompactConfig {
+    /// Node binary subcommand that compacts the databases, e.g.
+    /// `experimental-compact-goleveldb`; LevelDB databases are compacted
+    /// in-process when unset
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+/// Default number of application state versions kept when pruning
+fn default_prune_keep_versions() -> u64 {
+    100
+}
+
+/// Default number of blocks kept when pruning
+fn default_prune_keep_blocks() -> u64 {
+    100
+}
+
+/// Default cosmprund executable, looked up in PATH
+fn default_cosmprund_path() -> String {
+    "cosmprund".to_string()
+}
+
+/// Cosmovisor settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CosmovisorConfig {
+    /// Cosmovisor executable
+    #[serde(default = "default_cosmovisor_path")]
+    pub path: String,
+
+    /// Let cosmovisor download upgrade binaries itself; by default only when
+    /// an upcoming upgrade couldn't be pre-populated
+    #[serde(default)]
+    pub allow_download_binaries: Option<bool>,
+
+    /// Restart the node after an upgrade switches binaries
+    #[serde(default = "default_true")]
+    pub restart_after_upgrade: bool,
+}
+
+/// systemd service generated for the node
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SystemdConfig {
+    /// Unit name without `.service` (default: the node binary's file name)
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// User the node runs as (default: root)
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Directory the unit file is written to (default: the output directory,
+    /// or `/etc/systemd/system` when installing)
+    #[serde(default)]
+    pub dir: Option<String>,
+
+    /// Install the unit into systemd and reload it
+    #[serde(default)]
+    pub install: bool,
+
+    /// Enable the installed unit so the node starts at boot; implies `install`
+    #[serde(default)]
+    pub enable: bool,
+}
+
+impl Default for CosmovisorConfig {
+    fn default() -> Self {
+        CosmovisorConfig {
+            path: default_cosmovisor_path(),
+            allow_download_binaries: None,
+            restart_after_upgrade: true,
+        }
+    }
+}
+
+/// Default cosmovisor executable, looked up in PATH
+fn default_cosmovisor_path() -> String {
+    "cosmovisor".to_string()
+}
+
+/// Default for options that are enabled unless turned off
+fn default_true() -> bool {
+    true
+}
+
+/// A chain upgrade and the binary version it requires
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpgradeConfig {
+    /// Upgrade name, as used in the on-chain upgrade plan
+    pub name: String,
+
+    /// Height at which the upgrade activates
+    pub height: u64,
+
+    /// Binary version required from this height on
+    pub version: String,
+
+    /// Where to download this version's binary, or a map of platform to URL
+    #[serde(default)]
+    pub binary_url: Option<BinaryUrl>,
+
+    /// Local file name for the downloaded binary, overriding the derived one
+    #[serde(default)]
+    pub save_as: Option<String>,
+
+    /// Detached signature the downloaded binary must carry before it's installed
+    #[serde(default)]
+    pub signature: Option<SignatureConfig>,
+}
+
+/// Default chain registry location
+fn default_chain_registry_url() -> String {
+    "https://raw.githubusercontent.com/cosmos/chain-registry/master".to_string()
+}
+
+/// Restart and crash loop detection settings for a supervised node
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SupervisorConfig {
+    /// Consecutive rapid exits after which the node is considered crash looping
+    #[serde(default = "default_max_rapid_restarts")]
+    pub max_rapid_restarts: u32,
+
+    /// Exits sooner than this many seconds after starting count as rapid
+    #[serde(default = "default_min_uptime_secs")]
+    pub min_uptime_secs: u64,
+
+    /// Seconds to wait before restarting the node
+    #[serde(default = "default_restart_delay_secs")]
+    pub restart_delay_secs: u64,
+
+    /// Where and how the node's output is logged
+    #[serde(default)]
+    pub log: NodeLogConfig,
+
+    /// Open file limit (`RLIMIT_NOFILE`) raised before the node starts
+    #[serde(default)]
+    pub open_files_limit: Option<u64>,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        SupervisorConfig {
+            max_rapid_restarts: default_max_rapid_restarts(),
+            min_uptime_secs: default_min_uptime_secs(),
+            restart_delay_secs: default_restart_delay_secs(),
+            log: NodeLogConfig::default(),
+            open_files_limit: None,
+        }
+    }
+}
+
+/// Retry policy for network operations
+///
+/// Delays double after every failed attempt, starting at `base_delay_secs`
+/// and capped at `max_delay_secs`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    /// Attempts per operation, including the first; `1` disables retries
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Seconds to wait before the first retry
+    #[serde(default = "default_retry_base_delay_secs")]
+    pub base_delay_secs: u64,
+
+    /// Upper bound on the wait between attempts, in seconds
+    #[serde(default = "default_retry_max_delay_secs")]
+    pub max_delay_secs: u64,
+
+    /// Stages the policy applies to; the others fail on the first error
+    #[serde(default = "default_retry_stages")]
+    pub stages: Vec<RetryStage>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: default_retry_max_attempts(),
+            base_delay_secs: default_retry_base_delay_secs(),
+            max_delay_secs: default_retry_max_delay_secs(),
+            stages: default_retry_stages(),
+        }
+    }
+}
+
+/// Segmented download settings
+///
+/// The number of parallel connections adapts to the server: it grows while
+/// each added connection raises the overall rate and shrinks when the server
+/// throttles or requests start failing.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SegmentedConfig {
+    /// Upper bound on parallel connections
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+
+    /// Size of each range request, e.g. `64MB`
+    #[serde(
+        default = "default_segment_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub segment_size: u64,
+}
+
+impl Default for SegmentedConfig {
+    fn default() -> Self {
+        SegmentedConfig {
+            max_connections: default_max_connections(),
+            segment_size: default_segment_size(),
+        }
+    }
+}
+
+/// Customization of the requests that fetch a gated download
+///
+/// Private snapshot services may hand out the file only in response to a
+/// POST with a JSON body, or to a URL signed shortly before the request.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RequestConfig {
+    /// HTTP method, e.g. `POST` (default: `GET`)
+    #[serde(default)]
+    pub method: Option<String>,
+
+    /// Extra headers sent with every request
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Body sent with every request, e.g. a JSON document
+    #[serde(default)]
+    pub body: Option<String>,
+
+    /// Command run with the URL as its last argument, printing the signed URL
+    /// to request instead
+    ///
+    /// Run again before each attempt, so short-lived signatures don't expire
+    /// during retries.
+    #[serde(default)]
+    pub sign_command: Option<String>,
+}
+
+/// Default upper bound on parallel download connections
+fn default_max_connections() -> usize {
+    8
+}
+
+/// Default size of a download segment
+fn default_segment_size() -> u64 {
+    64_000_000
+}
+
+/// Scheduling priority of the restore pipeline
+///
+/// Applies to the download, extraction and setup work and to the commands
+/// it runs; the `run` command starts the node at normal priority.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PriorityConfig {
+    /// Niceness from -20 (most favorable) to 19 (least favorable)
+    #[serde(default)]
+    pub nice: Option<i32>,
+
+    /// I/O scheduling class, as with `ionice -c`
+    #[serde(default)]
+    pub io_class: Option<IoClass>,
+
+    /// Priority within the best-effort or realtime class, 0 (highest) to 7
+    #[serde(default)]
+    pub io_level: Option<u8>,
+
+    /// cgroup v2 directory to move the process into, created if missing
+    #[serde(default)]
+    pub cgroup: Option<String>,
+
+    /// `cpu.weight` of the cgroup, 1 to 10000 (100 is the kernel default)
+    #[serde(default)]
+    pub cpu_weight: Option<u32>,
+
+    /// `io.weight` of the cgroup, 1 to 10000 (100 is the kernel default)
+    #[serde(default)]
+    pub io_weight: Option<u32>,
+
+    /// Bytes per second the extraction and copy stages may write, e.g. `50MB`
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    pub max_write_rate: Option<u64>,
+}
+
+/// Linux I/O scheduling class
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IoClass {
+    /// Served before everything else; needs root
+    Realtime,
+
+    /// The default class, ordered by `io_level`
+    #[serde(rename = "best-effort")]
+    BestEffort,
+
+    /// Only served when no other process wants the disk
+    Idle,
+}
+
+/// Network operations a retry policy can apply to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetryStage {
+    /// Snapshot, binary and artifact downloads
+    Download,
+
+    /// Fetching the snapshot provider's index
+    Provider,
+
+    /// CometBFT RPC status queries
+    Rpc,
+}
+
+/// Default number of attempts per operation
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+
+/// Default delay before the first retry
+fn default_retry_base_delay_secs() -> u64 {
+    5
+}
+
+/// Default cap on the delay between attempts
+fn default_retry_max_delay_secs() -> u64 {
+    120
+}
+
+/// Retries apply to every stage by default
+fn default_retry_stages() -> Vec<RetryStage> {
+    vec![RetryStage::Download, RetryStage::Provider, RetryStage::Rpc]
+}
+
+/// Rotation settings for the supervised node's log files
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NodeLogConfig {
+    /// Rotate once the current file reaches this size, e.g. `100MB`
+    #[serde(
+        default = "default_log_max_size",
+        deserialize_with = "deserialize_optional_size"
+    )]
+    pub max_size: Option<u64>,
+
+    /// Rotate after the current file has been open this long
+    #[serde(default)]
+    pub rotate_every: LogRotation,
+
+    /// Number of rotated files to keep besides the current one
+    #[serde(default = "default_log_keep")]
+    pub keep: usize,
+
+    /// Also echo the node's output to the terminal
+    #[serde(default)]
+    pub echo: bool,
+}
+
+impl Default for NodeLogConfig {
+    fn default() -> Self {
+        NodeLogConfig {
+            max_size: default_log_max_size(),
+            rotate_every: LogRotation::default(),
+            keep: default_log_keep(),
+            echo: false,
+        }
+    }
+}
+
+/// Time-based rotation interval for log files
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// Rotate on size only
+    Never,
+
+    /// Rotate every hour
+    Hourly,
+
+    /// Rotate every day
+    #[default]
+    Daily,
+}
+
+/// Default maximum node log file size
+fn default_log_max_size() -> Option<u64> {
+    Some(100_000_000)
+}
+
+/// Default number of rotated node log files to keep
+fn default_log_keep() -> usize {
+    7
+}
+
+/// Default number of rapid exits tolerated before giving up
+fn default_max_rapid_restarts() -> u32 {
+    3
+}
+
+/// Default minimum uptime for an exit not to count as rapid
+fn default_min_uptime_secs() -> u64 {
+    60
+}
+
+/// Default delay between node restarts
+fn default_restart_delay_secs() -> u64 {
+    5
+}
+
+/// Where and how to look up the latest snapshot
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProviderConfig {
+    /// URL of the provider's snapshot index
+    pub index_url: String,
+
+    /// Layout of the index, when it isn't the native JSON format
+    #[serde(default)]
+    pub preset: ProviderPreset,
+
+    /// Snapshot variant to pick from the index
+    #[serde(default)]
+    pub pruning: Pruning,
+
+    /// JSONPath to the array of snapshot entries (default: `$.snapshots`)
+    #[serde(default)]
+    pub entries: Option<String>,
+
+    /// Where each field is found within an entry
+    #[serde(default)]
+    pub fields: ProviderFields,
+
+    /// Regex run over a text or HTML index instead of parsing JSON; named groups
+    /// `url`, `height`, `pruning`, `size`, `checksum`, `kind`, `base`, `format` and `created` fill the fields
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// Paths of snapshot fields relative to an index entry
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProviderFields {
+    /// Download URL
+    #[serde(default = "default_url_field")]
+    pub url: String,
+
+    /// Block height
+    #[serde(default = "default_height_field")]
+    pub height: String,
+
+    /// Pruning variant
+    #[serde(default = "default_pruning_field")]
+    pub pruning: String,
+
+    /// Archive size in bytes
+    #[serde(default = "default_size_field")]
+    pub size: String,
+
+    /// SHA256 checksum
+    #[serde(default = "default_checksum_field")]
+    pub checksum: String,
+
+    /// Layer kind, `full` or `incremental`
+    #[serde(default = "default_kind_field")]
+    pub kind: String,
+
+    /// Height of the full snapshot an incremental archive is based on
+    #[serde(default = "default_base_field")]
+    pub base: String,
+
+    /// Compression of the archive, e.g. `lz4` or `application/gzip`
+    #[serde(default = "default_format_field")]
+    pub format: String,
+
+    /// Creation time, ranking snapshots of the same or an unknown height
+    #[serde(default = "default_created_field")]
+    pub created: String,
+}
+
+impl Default for ProviderFields {
+    fn default() -> Self {
+        ProviderFields {
+            url: default_url_field(),
+            height: default_height_field(),
+            pruning: default_pruning_field(),
+            size: default_size_field(),
+            checksum: default_checksum_field(),
+            kind: default_kind_field(),
+            base: default_base_field(),
+            format: default_format_field(),
+            created: default_created_field(),
+        }
+    }
+}
+
+/// Default entry field holding the download URL
+fn default_url_field() -> String {
+    "url".to_string()
+}
+
+/// Default entry field holding the height
+fn default_height_field() -> String {
+    "height".to_string()
+}
+
+/// Default entry field holding the pruning variant
+fn default_pruning_field() -> String {
+    "pruning".to_string()
+}
+
+/// Default entry field holding the archive size
+fn default_size_field() -> String {
+    "size".to_string()
+}
+
+/// Default entry field holding the checksum
+fn default_checksum_field() -> String {
+    "sha256".to_string()
+}
+
+/// Default entry field holding the layer kind
+fn default_kind_field() -> String {
+    "kind".to_string()
+}
+
+/// Default entry field holding the base height of an incremental archive
+fn default_base_field() -> String {
+    "base_height".to_string()
+}
+
+/// Default entry field holding the archive compression
+fn default_format_field() -> String {
+    "format".to_string()
+}
+
+/// Default entry field holding the creation time
+fn default_created_field() -> String {
+    "created".to_string()
+}
+
+/// Index layouts of known snapshot providers
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderPreset {
+    /// JSON read with `entries` and `fields`, the native format by default
+    #[default]
+    Json,
+
+    /// Polkachu's snapshot API, e.g. `https://polkachu.com/api/v2/chain_snapshots/osmosis/mainnet`
+    Polkachu,
+
+    /// ChainLayer QuickSync's snapshot list, e.g. `https://quicksync.io/osmosis.json`
+    Quicksync,
+
+    /// HTML directory listing linking to the archives
+    Listing,
+}
+
+/// Pruning variant of a snapshot
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pruning {
+    /// The provider's standard variant
+    #[default]
+    Default,
+
+    /// Aggressively pruned state, the smallest variant
+    Pruned,
+
+    /// Full history
+    Archive,
+}
+
+impl Pruning {
+    /// Name used for the variant in provider indexes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Pruning::Default => "default",
+            Pruning::Pruned => "pruned",
+            Pruning::Archive => "archive",
+        }
+    }
+}
+
+/// Snapshot freshness limits
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FreshnessConfig {
+    /// Maximum snapshot age in days
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+
+    /// RPC endpoint used to look up the current chain height
+    #[serde(default)]
+    pub rpc_url: Option<String>,
+
+    /// Maximum number of blocks the snapshot may trail the chain by
+    #[serde(default)]
+    pub max_height_lag: Option<u64>,
+
+    /// What to do when a limit is exceeded
+    #[serde(default)]
+    pub action: FreshnessAction,
+}
+
+/// Reaction to a snapshot exceeding the freshness limits
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FreshnessAction {
+    /// Log a warning and continue
+    #[default]
+    Warn,
+
+    /// Abort the run
+    Reject,
+}
+
+/// Cleanup policy for the snapshots directory, applied after a successful run
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetentionConfig {
+    /// Number of most recent archives and extracted directories to keep
+    #[serde(default)]
+    pub keep_last: Option<usize>,
+
+    /// Maximum total size of the snapshots directory, e.g. `2TB`
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    pub max_cache_size: Option<u64>,
+}
+
+/// Free space check run before downloading and extracting
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiskCheckConfig {
+    /// Size of the extracted data as a multiple of the archive size
+    #[serde(default = "default_expansion_factor")]
+    pub expansion_factor: f64,
+
+    /// Space to leave free on every filesystem on top of the estimate, e.g. `20GB`
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub reserve: u64,
+
+    /// Start without checking; `--skip-disk-check` sets this for one run
+    #[serde(default)]
+    pub skip: bool,
+}
+
+impl Default for DiskCheckConfig {
+    fn default() -> Self {
+        DiskCheckConfig {
+            expansion_factor: default_expansion_factor(),
+            reserve: 0,
+            skip: false,
+        }
+    }
+}
+
+/// Typical size of extracted node data relative to its LZ4 or gzip archive
+fn default_expansion_factor() -> f64 {
+    2.0
+}
+
+/// Direct download location of the node binary
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum BinaryUrl {
+    /// A single URL used on every host
+    Single(String),
+
+    /// URLs keyed by platform, e.g. `linux-amd64`, `linux-arm64`, `darwin-arm64`
+    PerPlatform(HashMap<String, String>),
+}
+
+impl BinaryUrl {
+    /// Returns the URL to use on the running host
+    pub fn for_host(&self) -> Result<&str> {
+        match self {
+            BinaryUrl::Single(url) => Ok(url),
+            BinaryUrl::PerPlatform(urls) => {
+                let platform = host_platform();
+                urls.get(&platform).map(String::as_str).with_context(|| {
+                    let mut known: Vec<_> = urls.keys().map(String::as_str).collect();
+                    known.sort_unstable();
+                    format!(
+                        "No binary_url configured for platform {} (configured: {})",
+                        platform,
+                        known.join(", ")
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Download location of a single file
+///
+/// Either a plain URL or a map with the URL and the local file name to save
+/// it under, for servers that give different files the same name.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum DownloadUrl {
+    /// A URL whose file name is derived from the response or the URL
+    Plain(String),
 
-    /// URL to download the node binary
-    pub binary_url: String,
+    /// A URL saved under a fixed local file name
+    Named {
+        url: String,
+        #[serde(default)]
+        save_as: Option<String>,
+    },
+}
 
-    /// Cosmos-specific configuration
-    pub cosmos: CosmosConfig,
+impl DownloadUrl {
+    /// The URL to download from
+    pub fn url(&self) -> &str {
+        match self {
+            DownloadUrl::Plain(url) | DownloadUrl::Named { url, .. } => url,
+        }
+    }
+
+    /// The configured local file name, if any
+    pub fn save_as(&self) -> Option<&str> {
+        match self {
+            DownloadUrl::Plain(_) => None,
+            DownloadUrl::Named { save_as, .. } => save_as.as_deref(),
+        }
+    }
+}
+
+/// Returns the running host's platform in `<os>-<arch>` form, e.g. `linux-amd64`
+pub fn host_platform() -> String {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        other => other,
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        other => other,
+    };
+
+    format!("{}-{}", os, arch)
+}
+
+/// Where to obtain the node binary when no direct URL is given
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BinarySource {
+    /// GitHub repository publishing releases, in `owner/name` form
+    #[serde(default)]
+    pub github: Option<String>,
+
+    /// Release tag to use; the latest release when omitted
+    #[serde(default)]
+    pub version: Option<String>,
+
+    /// Regex selecting the release asset, overriding host platform matching
+    #[serde(default)]
+    pub asset: Option<String>,
+
+    /// Container image to take the binary from
+    #[serde(default)]
+    pub image: Option<ImageConfig>,
+
+    /// Build the binary from source when no prebuilt artifact exists for the host
+    #[serde(default)]
+    pub build: Option<BuildConfig>,
+}
+
+/// Container image publishing the node binary
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageConfig {
+    /// Image reference, e.g. `ghcr.io/cosmos/gaia:v15.0.0`
+    pub name: String,
+
+    /// Path of the daemon inside the image; defaults to `/usr/local/bin/<name of cosmos.bin>`
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// Whether to copy the binary out of the image or wrap it in a launcher script
+    #[serde(default)]
+    pub mode: ImageMode,
+
+    /// Container runtime used to fetch or run the image
+    #[serde(default = "default_container_runtime")]
+    pub runtime: String,
+}
+
+/// How a binary from a container image is made available to the node
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// Copy the daemon out of the image and run it natively
+    #[default]
+    Extract,
+
+    /// Generate a script that runs the daemon inside the container
+    Wrapper,
+}
+
+/// Default container runtime
+fn default_container_runtime() -> String {
+    "docker".to_string()
+}
+
+/// Instructions for building the node binary from source
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BuildConfig {
+    /// Git repository to clone
+    pub repo: String,
+
+    /// Tag or branch to build
+    pub tag: String,
+
+    /// Shell command that builds the binary inside the checkout
+    #[serde(default = "default_build_command")]
+    pub command: String,
+
+    /// Path of the produced binary relative to the checkout
+    ///
+    /// Defaults to `build/<name of cosmos.bin>`; `GOBIN` points at `build/`
+    /// so both `make build` and `make install` land there.
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+/// Default command used to build Cosmos SDK daemons
+fn default_build_command() -> String {
+    "make build".to_string()
+}
+
+/// Detached signature verification settings for a downloaded artifact
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SignatureConfig {
+    /// Signature scheme used by the publisher
+    #[serde(default)]
+    pub kind: SignatureKind,
+
+    /// URL of the detached signature (or Sigstore bundle for keyless cosign)
+    pub url: String,
+
+    /// Trusted public keys, either paths to key files or inline keys
+    #[serde(default)]
+    pub keys: Vec<String>,
+
+    /// Expected signer identity for keyless cosign verification
+    #[serde(default)]
+    pub certificate_identity: Option<String>,
+
+    /// Expected OIDC issuer for keyless cosign verification
+    #[serde(default)]
+    pub certificate_oidc_issuer: Option<String>,
+}
+
+/// Supported detached signature schemes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureKind {
+    /// OpenPGP signature verified with GnuPG
+    #[default]
+    Gpg,
+
+    /// Minisign signature
+    Minisign,
+
+    /// Sigstore cosign signature, key-based or keyless
+    Cosign,
 }
 
 /// Cosmos node configuration
@@ -26,15 +1264,33 @@ pub struct Config {
 /// Contains settings for initializing and running a Cosmos blockchain node
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CosmosConfig {
-    /// Path to the binary executable
+    /// Path to the binary executable (default with `chain`: the registry's daemon name)
+    #[serde(default)]
     pub bin: String,
 
-    /// Command to initialize the node
+    /// Command to initialize the node (default: `init <moniker>`)
+    #[serde(default)]
     pub init_command: String,
 
     /// Command to start the node
     pub start_command: String,
 
+    /// Chain id passed to init and written to client.toml
+    #[serde(default)]
+    pub chain_id: Option<String>,
+
+    /// Node moniker written to config.toml, and passed to init without `init_command`
+    #[serde(default)]
+    pub moniker: Option<String>,
+
+    /// Append `--home <node home>` to the init and start commands
+    #[serde(default)]
+    pub inject_home: bool,
+
+    /// Carry on with setup when the init command exits with an error
+    #[serde(default)]
+    pub ignore_init_failure: bool,
+
     /// Custom settings for app.toml configuration file
     #[serde(default)]
     pub app: HashMap<String, serde_yaml::Value>,
@@ -45,24 +1301,982 @@ pub struct CosmosConfig {
 }
 
 impl Config {
-    /// Loads configuration from a YAML file
+    /// Loads every chain configured in a YAML file
+    ///
+    /// A file with a `chains` map yields one configuration per chain id, each
+    /// made of the top-level settings with the chain's own settings merged over
+    /// them. Any other file yields a single configuration without a chain id.
     ///
     /// # Arguments
     /// * `path` - Path to the YAML configuration file
+    /// * `chain` - Chain registry name overriding `chain`, applied before validation
+    ///   since the registry fills in settings a configuration may leave out
+    /// * `overrides` - `key.path=value` settings replacing those in the file, with
+    ///   values parsed as YAML; with a `chains` map they apply to every chain
+    ///   unless the path starts with `chains.<chain id>`
     ///
     /// # Returns
-    /// * `Result<Config>` - The parsed configuration or an error
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        // Open and read the file
-        let mut file = File::open(path).context("Failed to open config file")?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)
-            .context("Failed to read config file")?;
-
-        // Parse YAML into Config struct
-        let config: Config =
-            serde_yaml::from_str(&content).context("Failed to parse YAML config")?;
-
-        Ok(config)
+    /// * `Result<Vec<Config>>` - The parsed configurations, in the order the file lists them
+    pub fn load_chains<P: AsRef<Path>>(
+        path: P,
+        chain: Option<&str>,
+        overrides: &[String],
+    ) -> crate::error::Result<Vec<Self>> {
+        Self::parse_chains(path, chain, overrides).map_err(|source| Error::Config(source.into()))
+    }
+
+    /// Reads, merges and validates the configuration of each chain
+    fn parse_chains<P: AsRef<Path>>(
+        path: P,
+        chain: Option<&str>,
+        overrides: &[String],
+    ) -> Result<Vec<Self>> {
+        let overrides = overrides
+            .iter()
+            .map(|setting| parse_override(setting))
+            .collect::<Result<Vec<_>>>()?;
+        let mut shared: serde_yaml::Value =
+            serde_yaml::from_str(&read_file(path)?).context("Failed to parse YAML config")?;
+        interpolate_env(&mut shared)?;
+        for (keys, value) in &overrides {
+            set_yaml(&mut shared, keys, value.clone())?;
+        }
+        set_chain(&mut shared, chain);
+        let chains = shared
+            .as_mapping_mut()
+            .and_then(|mapping| mapping.remove("chains"));
+        let Some(chains) = chains else {
+            let config: Config =
+                serde_yaml::from_value(shared).context("Failed to parse YAML config")?;
+            config.validate()?;
+            return Ok(vec![config]);
+        };
+
+        let serde_yaml::Value::Mapping(chains) = chains else {
+            return Err(anyhow!("chains must map chain ids to their settings"));
+        };
+        if chains.is_empty() {
+            return Err(anyhow!("chains must configure at least one chain"));
+        }
+
+        let configs = chains
+            .into_iter()
+            .map(|(chain_id, chain_settings)| {
+                let chain_id = chain_id
+                    .as_str()
+                    .context("Chain ids in chains must be strings")?
+                    .to_string();
+                // The chain id names the chain's directory in the output directory
+                if chain_id.is_empty()
+                    || chain_id.starts_with('.')
+                    || !chain_id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                {
+                    return Err(anyhow!("Invalid chain id in chains: {:?}", chain_id));
+                }
+                let mut merged = shared.clone();
+                merge_yaml(&mut merged, chain_settings);
+                // A chain's own settings don't win over the command line
+                for (keys, value) in &overrides {
+                    if keys[0] != "chains" {
+                        set_yaml(&mut merged, keys, value.clone())?;
+                    }
+                }
+                set_chain(&mut merged, chain);
+
+                let mut config: Config = serde_yaml::from_value(merged)
+                    .with_context(|| format!("Failed to parse configuration of {}", chain_id))?;
+                config
+                    .cosmos
+                    .chain_id
+                    .get_or_insert_with(|| chain_id.clone());
+                config
+                    .validate()
+                    .with_context(|| format!("Invalid configuration of {}", chain_id))?;
+                config.chain_id = Some(chain_id);
+                Ok(config)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Chains sharing a cache or node home would overwrite each other's files
+        let mut seen = HashMap::new();
+        for config in &configs {
+            for (field, dir) in [
+                ("cache_dir", &config.cache_dir),
+                ("data_dir", &config.data_dir),
+            ] {
+                let Some(dir) = dir else {
+                    continue;
+                };
+                if let Some(other) = seen.insert((field, dir), &config.chain_id) {
+                    return Err(anyhow!(
+                        "Chains {} and {} share {} {}",
+                        other.as_deref().unwrap_or_default(),
+                        config.chain_id.as_deref().unwrap_or_default(),
+                        field,
+                        dir
+                    ));
+                }
+            }
+        }
+
+        Ok(configs)
+    }
+
+    /// Checks constraints that can't be expressed in the YAML schema
+    fn validate(&self) -> Result<()> {
+        if self.snapshot_url.is_empty() && self.snapshot_provider.is_none() {
+            return Err(anyhow!(
+                "One of snapshot_url or snapshot_provider must be configured"
+            ));
+        }
+
+        if self.cosmos.init_command.trim().is_empty() && self.cosmos.moniker.is_none() {
+            return Err(anyhow!(
+                "One of cosmos.init_command or cosmos.moniker must be configured"
+            ));
+        }
+        // The chain registry fills in the daemon name and binaries
+        if self.cosmos.bin.trim().is_empty() && self.chain.is_none() {
+            return Err(anyhow!("cosmos.bin must name the node binary"));
+        }
+        if self.cosmos.start_command.trim().is_empty() {
+            return Err(anyhow!("cosmos.start_command must not be empty"));
+        }
+        for (field, command) in [
+            ("cosmos.init_command", &self.cosmos.init_command),
+            ("cosmos.start_command", &self.cosmos.start_command),
+        ] {
+            check_command(field, command)?;
+        }
+        for (field, request) in [
+            ("snapshot_request", &self.snapshot_request),
+            ("binary_request", &self.binary_request),
+        ] {
+            if let Some(request) = request {
+                check_request(field, request)?;
+            }
+        }
+
+        if self.jobs == Some(0) {
+            return Err(anyhow!("jobs must be at least 1"));
+        }
+        if self.extract_threads == Some(0) {
+            return Err(anyhow!("extract_threads must be at least 1"));
+        }
+        if self
+            .segmented
+            .as_ref()
+            .is_some_and(|s| s.max_connections == 0)
+        {
+            return Err(anyhow!("segmented.max_connections must be at least 1"));
+        }
+        let expansion_factor = self.disk_check.expansion_factor;
+        if !expansion_factor.is_finite() || expansion_factor <= 0.0 {
+            return Err(anyhow!(
+                "disk_check.expansion_factor must be a positive number"
+            ));
+        }
+
+        self.validate_urls()?;
+        self.exclusions()?;
+        if self.stream_extract {
+            self.check_stream_extract()?;
+        }
+        if self.snapshot_format.is_none() {
+            for url in &self.snapshot_url {
+                let name = self.snapshot_filename.as_deref().unwrap_or(url);
+                check_archive_name("snapshot_url", name)?;
+            }
+        }
+
+        let github = self.binary.as_ref().and_then(|b| b.github.as_ref());
+        let image = self.binary.as_ref().and_then(|b| b.image.as_ref());
+        let build = self.binary.as_ref().and_then(|b| b.build.as_ref());
+
+        let prebuilt_sources = [self.binary_url.is_some(), github.is_some(), image.is_some()]
+            .iter()
+            .filter(|configured| **configured)
+            .count();
+
+        if prebuilt_sources > 1 {
+            return Err(anyhow!(
+                "Only one of binary_url, binary.github and binary.image may be configured"
+            ));
+        }
+        if prebuilt_sources == 0 && build.is_none() && self.chain.is_none() {
+            return Err(anyhow!(
+                "One of binary_url, binary.github, binary.image or binary.build must be configured"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that nothing configured needs the snapshot archive on disk
+    fn check_stream_extract(&self) -> Result<()> {
+        let needs_archive = [
+            ("snapshot_signature", self.snapshot_signature.is_some()),
+            (
+                "snapshot_chunk_manifest",
+                self.snapshot_chunk_manifest.is_some(),
+            ),
+        ];
+        if let Some((field, _)) = needs_archive.iter().find(|(_, set)| *set) {
+            return Err(anyhow!(
+                "stream_extract can't be combined with {}, which needs the archive on disk",
+                field
+            ));
+        }
+        for url in &self.snapshot_url {
+            if crate::torrent::is_torrent_url(url) {
+                return Err(anyhow!(
+                    "stream_extract can't download {} over BitTorrent",
+                    url
+                ));
+            }
+            let name = self.snapshot_filename.as_deref().unwrap_or(url);
+            if crate::decrypt::is_encrypted(name) {
+                return Err(anyhow!(
+                    "stream_extract can't decrypt {}; download encrypted snapshots first",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Archive entries left out of the snapshot and its increments
+    pub fn exclusions(&self) -> crate::error::Result<Exclusions> {
+        Exclusions::new(&self.exclude, &self.exclude_globs)
+            .map_err(|source| Error::Config(source.into()))
+    }
+
+    /// Checks that every configured URL parses and uses a scheme the tool can fetch
+    fn validate_urls(&self) -> Result<()> {
+        const HTTP: &[&str] = &["http", "https"];
+        const DOWNLOAD: &[&str] = &["http", "https", "s3"];
+
+        for url in &self.snapshot_url {
+            check_url("snapshot_url", url, &["http", "https", "s3", "magnet"])?;
+        }
+        for increment in &self.snapshot_increments {
+            check_url("snapshot_increments", increment.url(), DOWNLOAD)?;
+            check_save_as("snapshot_increments.save_as", increment.save_as())?;
+        }
+        if let Some(provider) = &self.snapshot_provider {
+            check_url("snapshot_provider.index_url", &provider.index_url, HTTP)?;
+        }
+        if let Some(binary_url) = &self.binary_url {
+            check_binary_url("binary_url", binary_url)?;
+        }
+        for upgrade in &self.upgrades {
+            if let Some(binary_url) = &upgrade.binary_url {
+                check_binary_url(&format!("upgrades.{}.binary_url", upgrade.name), binary_url)?;
+            }
+            check_save_as(
+                &format!("upgrades.{}.save_as", upgrade.name),
+                upgrade.save_as.as_deref(),
+            )?;
+        }
+        for (field, download) in [
+            ("genesis_url", &self.genesis_url),
+            ("addrbook_url", &self.addrbook_url),
+        ] {
+            if let Some(download) = download {
+                check_url(field, download.url(), DOWNLOAD)?;
+                check_save_as(&format!("{}.save_as", field), download.save_as())?;
+            }
+        }
+        for (field, url) in [
+            (
+                "freshness.rpc_url",
+                &self.freshness.as_ref().and_then(|f| f.rpc_url.clone()),
+            ),
+            ("snapshot_chunk_manifest", &self.snapshot_chunk_manifest),
+            (
+                "snapshot_signature.url",
+                &self.snapshot_signature.as_ref().map(|s| s.url.clone()),
+            ),
+            (
+                "binary_signature.url",
+                &self.binary_signature.as_ref().map(|s| s.url.clone()),
+            ),
+        ] {
+            if let Some(url) = url {
+                check_url(field, url, HTTP)?;
+            }
+        }
+        for upgrade in &self.upgrades {
+            if let Some(signature) = &upgrade.signature {
+                check_url(
+                    &format!("upgrades.{}.signature.url", upgrade.name),
+                    &signature.url,
+                    HTTP,
+                )?;
+            }
+        }
+        for (field, checksum) in [
+            ("snapshot_checksum", &self.snapshot_checksum),
+            ("binary_checksum", &self.binary_checksum),
+            ("genesis_checksum", &self.genesis_checksum),
+            ("addrbook_checksum", &self.addrbook_checksum),
+        ] {
+            if let Some(checksum) = checksum {
+                check_checksum(field, checksum)?;
+            }
+        }
+        if let Some(endpoint) = self.s3.as_ref().and_then(|s3| s3.endpoint.as_ref()) {
+            check_url("s3.endpoint", endpoint, HTTP)?;
+        }
+        check_url("chain_registry_url", &self.chain_registry_url, HTTP)?;
+        for peer in self.fleet.iter().flat_map(|fleet| &fleet.peers) {
+            check_url("fleet.peers", peer, HTTP)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that a configured URL parses and uses one of `schemes`
+fn check_url(field: &str, url: &str, schemes: &[&str]) -> Result<()> {
+    let parsed = reqwest::Url::parse(url)
+        .with_context(|| format!("{} is not a valid URL: {}", field, url))?;
+    if !schemes.contains(&parsed.scheme()) {
+        return Err(anyhow!(
+            "{} uses unsupported scheme {}: {} (supported: {})",
+            field,
+            parsed.scheme(),
+            url,
+            schemes.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks every URL of a binary download, including each platform's
+fn check_binary_url(field: &str, binary_url: &BinaryUrl) -> Result<()> {
+    match binary_url {
+        BinaryUrl::Single(url) => check_url(field, url, &["http", "https", "s3"]),
+        BinaryUrl::PerPlatform(urls) => urls.iter().try_for_each(|(platform, url)| {
+            check_url(
+                &format!("{}.{}", field, platform),
+                url,
+                &["http", "https", "s3"],
+            )
+        }),
+    }
+}
+
+/// Checks that a `save_as` name is a plain file name
+fn check_save_as(field: &str, name: Option<&str>) -> Result<()> {
+    let Some(name) = name else {
+        return Ok(());
+    };
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(anyhow!(
+            "{} must be a file name without directories: {}",
+            field,
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects commands written for a shell, since commands are split on whitespace
+fn check_command(field: &str, command: &str) -> Result<()> {
+    if let Some(c) = command
+        .chars()
+        .find(|c| matches!(c, '"' | '\'' | '|' | '&' | ';' | '$' | '`'))
+    {
+        return Err(anyhow!(
+            "{} contains '{}', but commands are split on whitespace and not run through a shell: {}",
+            field,
+            c,
+            command
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects checksums that are neither a SHA256 hex digest nor a checksum file URL
+fn check_checksum(field: &str, checksum: &str) -> Result<()> {
+    if checksum.starts_with("http://") || checksum.starts_with("https://") {
+        return check_url(field, checksum, &["http", "https"]);
+    }
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!(
+            "{} must be a SHA256 hex digest or the URL of a checksum file: {}",
+            field,
+            checksum
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects request customizations reqwest can't send
+fn check_request(field: &str, request: &RequestConfig) -> Result<()> {
+    if let Some(method) = &request.method {
+        reqwest::Method::from_bytes(method.as_bytes())
+            .map_err(|_| anyhow!("{}.method is not a valid HTTP method: {}", field, method))?;
+    }
+    for (name, value) in &request.headers {
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow!("{}.headers has an invalid header name: {}", field, name))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| anyhow!("{}.headers.{} has an invalid value", field, name))?;
+    }
+    if let Some(command) = &request.sign_command {
+        if command.trim().is_empty() {
+            return Err(anyhow!("{}.sign_command must not be empty", field));
+        }
+        check_command(&format!("{}.sign_command", field), command)?;
+    }
+
+    Ok(())
+}
+
+/// Extensions of archive formats the extractor can't unpack
+const UNSUPPORTED_ARCHIVE_EXTENSIONS: &[&str] = &["tar", "zip", "7z", "rar"];
+
+/// Rejects snapshot names whose extension names an archive format that isn't supported
+///
+/// Names without a recognizable archive extension pass, as their format can
+/// still be learned from the server's Content-Disposition or Content-Type.
+fn check_archive_name(field: &str, url: &str) -> Result<()> {
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    if ArchiveFormat::from_file_name(name).is_some() {
+        return Ok(());
+    }
+
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+    if let Some(extension) = extension.filter(|e| UNSUPPORTED_ARCHIVE_EXTENSIONS.contains(e)) {
+        return Err(anyhow!(
+            "{} points to a .{} archive, which isn't supported: {} (set snapshot_format if the file is actually a supported format)",
+            field,
+            extension,
+            url
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads a configuration file into a string
+fn read_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = File::open(path).context("Failed to open config file")?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .context("Failed to read config file")?;
+
+    Ok(content)
+}
+
+/// Replaces `${VAR}` in every string of a parsed configuration with the
+/// environment variable's value
+///
+/// `${VAR:-default}` falls back to `default` when the variable is unset or
+/// empty, and `$${` stands for a literal `${`. Only strings are interpolated,
+/// after parsing, so a value can't change the structure of the file.
+fn interpolate_env(value: &mut serde_yaml::Value) -> Result<()> {
+    match value {
+        serde_yaml::Value::String(text) => *text = interpolate(text)?,
+        serde_yaml::Value::Sequence(items) => {
+            for item in items {
+                interpolate_env(item)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                interpolate_env(item)?;
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => interpolate_env(&mut tagged.value)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Expands the `${VAR}` references in one configuration string
+fn interpolate(text: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(reference) = rest.strip_prefix("${") else {
+            // A `$` not opening a reference is kept as it is
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = reference
+            .find('}')
+            .ok_or_else(|| anyhow!("Unterminated ${{...}} in configuration value {:?}", text))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!(
+                "Invalid environment variable name {:?} in configuration value {:?}",
+                name,
+                text
+            ));
+        }
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => expanded.push_str(default),
+            (Ok(value), _) => expanded.push_str(&value),
+            (Err(_), Some(default)) => expanded.push_str(default),
+            (Err(_), None) => {
+                return Err(anyhow!(
+                    "Environment variable {} used in the configuration is not set",
+                    name
+                ))
+            }
+        }
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Splits a `--set key.path=value` override into its keys and YAML value
+fn parse_override(setting: &str) -> Result<(Vec<String>, serde_yaml::Value)> {
+    let (path, value) = setting
+        .split_once('=')
+        .ok_or_else(|| anyhow!("--set {:?} must have the form key.path=value", setting))?;
+    let keys: Vec<String> = path.split('.').map(|key| key.trim().to_string()).collect();
+    if keys.iter().any(|key| key.is_empty()) {
+        return Err(anyhow!(
+            "--set {:?} has an empty key in {:?}",
+            setting,
+            path
+        ));
+    }
+    let value = serde_yaml::from_str(value)
+        .with_context(|| format!("Failed to parse the value of --set {:?}", setting))?;
+    Ok((keys, value))
+}
+
+/// Sets the value at `keys` in a parsed configuration, creating mappings on the way
+fn set_yaml(
+    config: &mut serde_yaml::Value,
+    keys: &[String],
+    value: serde_yaml::Value,
+) -> Result<()> {
+    let mut current = config;
+    for (depth, key) in keys.iter().enumerate() {
+        if current.is_null() {
+            *current = serde_yaml::Value::Mapping(Default::default());
+        }
+        let mapping = current.as_mapping_mut().ok_or_else(|| {
+            anyhow!(
+                "Can't set {}: {} is not a mapping",
+                keys.join("."),
+                keys[..depth].join(".")
+            )
+        })?;
+        current = mapping
+            .entry(key.as_str().into())
+            .or_insert(serde_yaml::Value::Null);
+    }
+    *current = value;
+    Ok(())
+}
+
+/// Sets `chain` in a parsed configuration when overridden
+fn set_chain(config: &mut serde_yaml::Value, chain: Option<&str>) {
+    if let (Some(chain), Some(mapping)) = (chain, config.as_mapping_mut()) {
+        mapping.insert("chain".into(), chain.into());
+    }
+}
+
+/// Merges `overrides` into `base`, recursing into mappings present in both
+///
+/// Anything else, including lists, is replaced by the override.
+fn merge_yaml(base: &mut serde_yaml::Value, overrides: serde_yaml::Value) {
+    match (base, overrides) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Deserializes either a single string or a list of strings into a list
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let urls = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![url],
+        OneOrMany::Many(urls) => urls,
+    };
+
+    if urls.is_empty() {
+        return Err(serde::de::Error::custom("at least one URL is required"));
+    }
+
+    Ok(urls)
+}
+
+/// Deserializes an optional section given as its settings or as a switch
+///
+/// `true` enables the section with its defaults, `false` leaves it off.
+fn deserialize_enabled<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Section<T> {
+        Switch(bool),
+        Settings(T),
+    }
+
+    Ok(match Option::<Section<T>>::deserialize(deserializer)? {
+        None | Some(Section::Switch(false)) => None,
+        Some(Section::Switch(true)) => Some(T::default()),
+        Some(Section::Settings(settings)) => Some(settings),
+    })
+}
+
+/// Parses a byte size such as `500`, `50MB`, `1.5GiB` or `2TB`
+///
+/// Decimal suffixes (KB, MB, GB, TB) are powers of 1000 and binary suffixes
+/// (KiB, MiB, GiB, TiB) powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size: {}", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        other => return Err(anyhow!("Unknown size unit '{}' in {}", other, value)),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Deserializes a byte size given as a number or a string like `64MB`
+fn deserialize_size<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_optional_size(deserializer)?
+        .ok_or_else(|| serde::de::Error::custom("expected a size"))
+}
+
+/// Deserializes an optional byte size given as a number or a string like `2TB`
+fn deserialize_optional_size<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => parse_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_environment_variables() {
+        std::env::set_var("SNAPSHOT_TEST_HOST", "rpc.example.com");
+        std::env::set_var("SNAPSHOT_TEST_EMPTY", "");
+        std::env::remove_var("SNAPSHOT_TEST_UNSET");
+
+        assert_eq!(
+            interpolate("https://${SNAPSHOT_TEST_HOST}/snap.tar").unwrap(),
+            "https://rpc.example.com/snap.tar"
+        );
+        assert_eq!(
+            interpolate("${SNAPSHOT_TEST_UNSET:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            interpolate("${SNAPSHOT_TEST_EMPTY:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(interpolate("${SNAPSHOT_TEST_UNSET:-}").unwrap(), "");
+        assert_eq!(
+            interpolate("${SNAPSHOT_TEST_HOST:-fallback}").unwrap(),
+            "rpc.example.com"
+        );
+        assert_eq!(interpolate("${SNAPSHOT_TEST_EMPTY}").unwrap(), "");
+    }
+
+    #[test]
+    fn keeps_escaped_and_lone_dollars() {
+        assert_eq!(
+            interpolate("$${SNAPSHOT_TEST_HOST} costs $5").unwrap(),
+            "${SNAPSHOT_TEST_HOST} costs $5"
+        );
+        assert_eq!(interpolate("$$${").unwrap(), "$${");
+        assert_eq!(interpolate("trailing $").unwrap(), "trailing $");
+    }
+
+    #[test]
+    fn refuses_bad_references() {
+        std::env::remove_var("SNAPSHOT_TEST_MISSING");
+        for text in [
+            "${SNAPSHOT_TEST_MISSING}",
+            "${SNAPSHOT_TEST_HOST",
+            "prefix ${",
+            "${}",
+            "${:-default}",
+            "${SNAPSHOT-TEST}",
+            "${SNAPSHOT TEST:-x}",
+        ] {
+            assert!(interpolate(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn interpolates_only_strings() {
+        std::env::set_var("SNAPSHOT_TEST_PORT", "26657");
+        let mut value: serde_yaml::Value = serde_yaml::from_str(
+            "rpc: ['http://localhost:${SNAPSHOT_TEST_PORT}']\nport: 26657\nkey: !tag '${SNAPSHOT_TEST_PORT}'\n",
+        )
+        .unwrap();
+        interpolate_env(&mut value).unwrap();
+        assert_eq!(
+            value,
+            serde_yaml::from_str::<serde_yaml::Value>(
+                "rpc: ['http://localhost:26657']\nport: 26657\nkey: !tag '26657'\n"
+            )
+            .unwrap()
+        );
+    }
+
+    /// Settings every valid configuration needs
+    const MINIMAL: &str = "snapshot_url: https://host/snap.tar.lz4
+binary_url: https://host/noded.tar.gz
+cosmos:
+  bin: noded
+  init_command: init node
+  start_command: start
+";
+
+    /// Loads `yaml` as a configuration file with `overrides` from the command line
+    fn load(yaml: &str, overrides: &[&str]) -> crate::error::Result<Vec<Config>> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), yaml).unwrap();
+        let overrides: Vec<String> = overrides.iter().map(|s| s.to_string()).collect();
+        Config::load_chains(file.path(), None, &overrides)
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let (keys, value) = parse_override("cosmos.config.p2p.seeds = a@b:26656").unwrap();
+        assert_eq!(keys, ["cosmos", "config", "p2p", "seeds"]);
+        assert_eq!(value, serde_yaml::Value::from("a@b:26656"));
+
+        let (_, value) = parse_override("jobs=4").unwrap();
+        assert_eq!(value, serde_yaml::Value::from(4));
+        let (_, value) = parse_override("snapshot_url=[a, b]").unwrap();
+        assert_eq!(
+            value,
+            serde_yaml::from_str::<serde_yaml::Value>("[a, b]").unwrap()
+        );
+        let (_, value) = parse_override("moniker=").unwrap();
+        assert!(value.is_null());
+
+        for setting in ["jobs", "=4", "cosmos..bin=x", "cosmos.=x", "jobs=[1"] {
+            assert!(parse_override(setting).is_err(), "{}", setting);
+        }
+    }
+
+    #[test]
+    fn sets_values_creating_mappings() {
+        let mut config: serde_yaml::Value =
+            serde_yaml::from_str("cosmos: {bin: noded}\nretries:\n").unwrap();
+        let keys = |path: &str| path.split('.').map(str::to_string).collect::<Vec<_>>();
+
+        set_yaml(&mut config, &keys("cosmos.moniker"), "node".into()).unwrap();
+        set_yaml(&mut config, &keys("retries.max_attempts"), 3.into()).unwrap();
+        set_yaml(&mut config, &keys("torrent.seed.ratio"), 1.into()).unwrap();
+        assert_eq!(
+            config,
+            serde_yaml::from_str::<serde_yaml::Value>(
+                "cosmos: {bin: noded, moniker: node}\nretries: {max_attempts: 3}\ntorrent: {seed: {ratio: 1}}\n"
+            )
+            .unwrap()
+        );
+
+        let error = set_yaml(&mut config, &keys("cosmos.bin.path"), "x".into()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't set cosmos.bin.path: cosmos.bin is not a mapping"
+        );
+    }
+
+    #[test]
+    fn overrides_replace_file_settings() {
+        let configs = load(
+            MINIMAL,
+            &[
+                "jobs=2",
+                "cosmos.moniker=override",
+                "snapshot_url=https://other/snap.tar.gz",
+            ],
+        )
+        .unwrap();
+        assert_eq!(configs[0].jobs, Some(2));
+        assert_eq!(configs[0].cosmos.moniker.as_deref(), Some("override"));
+        assert_eq!(configs[0].snapshot_url, ["https://other/snap.tar.gz"]);
+
+        assert!(load(MINIMAL, &["jobs=0"]).is_err());
+        assert!(load(MINIMAL, &["snapshot_url.mirror=x"]).is_err());
+    }
+
+    #[test]
+    fn overrides_win_over_chain_settings() {
+        let yaml = format!(
+            "{}chains:\n  chain-a:\n    jobs: 8\n  chain-b:\n    jobs: 6\n",
+            MINIMAL
+        );
+        let configs = load(&yaml, &["jobs=2", "chains.chain-b.cosmos.moniker=b"]).unwrap();
+        let jobs: Vec<_> = configs.iter().map(|config| config.jobs).collect();
+        assert_eq!(jobs, [Some(2), Some(2)]);
+        let monikers: Vec<_> = configs
+            .iter()
+            .map(|config| config.cosmos.moniker.as_deref())
+            .collect();
+        assert_eq!(monikers, [None, Some("b")]);
+    }
+
+    #[test]
+    fn loads_each_chain_over_the_shared_settings() {
+        let yaml = format!(
+            "{}jobs: 4
+chains:
+  cosmoshub-4:
+    data_dir: /srv/hub
+    cosmos:
+      moniker: hub
+  osmosis-1:
+    snapshot_url: https://host/osmosis.tar.lz4
+    data_dir: /srv/osmosis
+    cosmos:
+      chain_id: osmosis-mainnet
+",
+            MINIMAL
+        );
+        let configs = load(&yaml, &[]).unwrap();
+        assert_eq!(configs.len(), 2);
+
+        let (hub, osmosis) = (&configs[0], &configs[1]);
+        assert_eq!(hub.chain_id.as_deref(), Some("cosmoshub-4"));
+        assert_eq!(hub.cosmos.chain_id.as_deref(), Some("cosmoshub-4"));
+        assert_eq!(hub.cosmos.moniker.as_deref(), Some("hub"));
+        assert_eq!(hub.cosmos.bin, "noded");
+        assert_eq!(hub.snapshot_url, ["https://host/snap.tar.lz4"]);
+        assert_eq!(hub.jobs, Some(4));
+
+        assert_eq!(osmosis.chain_id.as_deref(), Some("osmosis-1"));
+        assert_eq!(osmosis.cosmos.chain_id.as_deref(), Some("osmosis-mainnet"));
+        assert_eq!(osmosis.snapshot_url, ["https://host/osmosis.tar.lz4"]);
+        assert_eq!(osmosis.data_dir.as_deref(), Some("/srv/osmosis"));
+        assert_eq!(osmosis.jobs, Some(4));
+
+        let single = load(MINIMAL, &[]).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].chain_id, None);
+    }
+
+    #[test]
+    fn refuses_invalid_chain_ids() {
+        for chain_id in ["../escape", ".hidden", "a/b", "\"\"", "chain id", "1"] {
+            let yaml = format!("{}chains:\n  {}:\n    jobs: 1\n", MINIMAL, chain_id);
+            assert!(load(&yaml, &[]).is_err(), "{}", chain_id);
+        }
+        assert!(load(&format!("{}chains: {{}}\n", MINIMAL), &[]).is_err());
+        assert!(load(&format!("{}chains: [a, b]\n", MINIMAL), &[]).is_err());
+    }
+
+    #[test]
+    fn refuses_chains_sharing_directories() {
+        for field in ["data_dir", "cache_dir"] {
+            let yaml = format!(
+                "{}chains:\n  a:\n    {field}: /srv/node\n  b:\n    {field}: /srv/node\n",
+                MINIMAL
+            );
+            let Error::Config(source) = load(&yaml, &[]).unwrap_err() else {
+                panic!("expected a configuration error");
+            };
+            assert_eq!(
+                source.to_string(),
+                format!("Chains a and b share {} /srv/node", field)
+            );
+        }
+
+        let shared = format!(
+            "{}data_dir: /srv/node\nchains:\n  a: {{}}\n  b: {{}}\n",
+            MINIMAL
+        );
+        assert!(load(&shared, &[]).is_err());
+        let separate = format!(
+            "{}chains:\n  a: {{data_dir: /srv/a}}\n  b: {{data_dir: /srv/b}}\n",
+            MINIMAL
+        );
+        assert_eq!(load(&separate, &[]).unwrap().len(), 2);
     }
 }
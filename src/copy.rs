@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+use tracing::{info, instrument};
+
+use crate::throttle::{Throttle, Throttled};
+
+/// Upper bound on copy workers; beyond this the disk, not the CPU, is the limit
+const MAX_COPY_THREADS: usize = 8;
+
+/// A regular file queued for copying
+struct CopyJob {
+    from: PathBuf,
+    to: PathBuf,
+    size: u64,
+}
+
+/// Copies a directory tree with several worker threads and a progress bar
+///
+/// Directories and symlinks are recreated up front, then regular files are
+/// copied largest first so one huge file doesn't end up last on a single
+/// worker. Existing files in `dest` are overwritten. With `max_write_rate`
+/// set, all workers together write at most that many bytes per second.
+/// `workers` sets the number of worker threads instead of the number of CPUs.
+#[instrument(skip(source, dest, max_write_rate, workers), fields(from = %source.display(), to = %dest.display()))]
+pub fn copy_tree(
+    source: &Path,
+    dest: &Path,
+    max_write_rate: Option<u64>,
+    workers: Option<usize>,
+) -> Result<()> {
+    let mut jobs = Vec::new();
+    prepare(source, dest, &mut jobs)?;
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.size));
+
+    let total_bytes: u64 = jobs.iter().map(|job| job.size).sum();
+    let threads = workers
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .min(MAX_COPY_THREADS)
+        })
+        .min(jobs.len().max(1));
+    info!(
+        "Copying {} files ({:.2} GB) with {} threads",
+        jobs.len(),
+        total_bytes as f64 / 1e9,
+        threads
+    );
+
+    let progress_bar = ProgressBar::new(total_bytes);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg} ({eta})")?
+            .progress_chars("#>-"),
+    );
+
+    let throttle = Throttle::from_rate(max_write_rate);
+    if let Some(rate) = max_write_rate {
+        info!("Limiting writes to {:.1} MB/s", rate as f64 / 1e6);
+    }
+
+    let started = Instant::now();
+    let next = AtomicUsize::new(0);
+    let copied_files = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let error = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    if let Err(e) = copy_file(job, throttle.as_ref()) {
+                        failed.store(true, Ordering::Relaxed);
+                        let e =
+                            anyhow!(e).context(format!("Failed to copy {}", job.from.display()));
+                        error.lock().unwrap().get_or_insert(e);
+                        break;
+                    }
+                    let done = copied_files.fetch_add(1, Ordering::Relaxed) + 1;
+                    progress_bar.set_message(format!("{}/{} files", done, jobs.len()));
+                    progress_bar.inc(job.size);
+                }
+            });
+        }
+    });
+
+    if let Some(e) = error.into_inner().unwrap() {
+        progress_bar.abandon();
+        return Err(e);
+    }
+    progress_bar.finish();
+
+    let elapsed = started.elapsed().as_secs_f64();
+    info!(
+        "Copied {:.2} GB in {:.0}s ({:.1} MB/s)",
+        total_bytes as f64 / 1e9,
+        elapsed,
+        total_bytes as f64 / 1e6 / elapsed.max(0.001)
+    );
+    Ok(())
+}
+
+/// Copies one file, through the throttle when there is one
+fn copy_file(job: &CopyJob, throttle: Option<&Throttle>) -> io::Result<()> {
+    let Some(throttle) = throttle else {
+        return fs::copy(&job.from, &job.to).map(|_| ());
+    };
+
+    let source = File::open(&job.from)?;
+    let permissions = source.metadata()?.permissions();
+    let mut dest = File::create(&job.to)?;
+    io::copy(&mut Throttled::new(source, Some(throttle)), &mut dest)?;
+    dest.set_permissions(permissions)
+}
+
+/// Recreates the directories and symlinks of `source` under `dest` and
+/// queues its regular files
+fn prepare(source: &Path, dest: &Path, jobs: &mut Vec<CopyJob>) -> Result<()> {
+    fs::create_dir_all(dest).with_context(|| format!("Failed to create {}", dest.display()))?;
+
+    for entry in
+        fs::read_dir(source).with_context(|| format!("Failed to read {}", source.display()))?
+    {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+
+        if file_type.is_dir() {
+            prepare(&from, &to, jobs)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&from, &to)?;
+        } else {
+            jobs.push(CopyJob {
+                size: entry.metadata()?.len(),
+                from,
+                to,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Recreates a symlink, replacing whatever is at the destination
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> Result<()> {
+    let target = fs::read_link(from)?;
+    if to.symlink_metadata().is_ok() {
+        fs::remove_file(to)?;
+    }
+    std::os::unix::fs::symlink(target, to)
+        .with_context(|| format!("Failed to create symlink {}", to.display()))
+}
+
+/// Symlinks are copied as the files they point to on this platform
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> Result<()> {
+    fs::copy(from, to).with_context(|| format!("Failed to copy {}", from.display()))?;
+    Ok(())
+}
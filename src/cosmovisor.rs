@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+
+use crate::config::CosmovisorConfig;
+
+/// Name of the file holding the environment cosmovisor is run with
+const ENV_FILE_NAME: &str = "cosmovisor.env";
+
+/// Manages the cosmovisor directory layout inside the node home
+///
+/// Binaries live in `cosmovisor/genesis/bin` and `cosmovisor/upgrades/<name>/bin`,
+/// with `cosmovisor/current` pointing at the one in use.
+pub struct Cosmovisor {
+    /// Cosmovisor settings
+    config: CosmovisorConfig,
+
+    /// Node home directory (`DAEMON_HOME`)
+    data_dir: PathBuf,
+
+    /// The `cosmovisor` directory inside the node home
+    root: PathBuf,
+
+    /// File name of the node binary (`DAEMON_NAME`)
+    daemon_name: String,
+}
+
+impl Cosmovisor {
+    /// Creates a layout manager for the node home at `data_dir`
+    ///
+    /// # Arguments
+    /// * `config` - Cosmovisor settings
+    /// * `data_dir` - Path to the data directory for the node
+    /// * `bin` - Configured binary path, whose file name is the daemon name
+    pub fn new(config: &CosmovisorConfig, data_dir: &Path, bin: &str) -> Result<Self> {
+        let daemon_name = Path::new(bin)
+            .file_name()
+            .context("Failed to determine binary name from cosmos.bin")?
+            .to_string_lossy()
+            .into_owned();
+        let data_dir = std::path::absolute(data_dir).context("Failed to resolve data directory")?;
+
+        Ok(Cosmovisor {
+            config: config.clone(),
+            root: data_dir.join("cosmovisor"),
+            data_dir,
+            daemon_name,
+        })
+    }
+
+    /// File name of the node binary
+    pub fn daemon_name(&self) -> &str {
+        &self.daemon_name
+    }
+
+    /// Cosmovisor executable to launch
+    pub fn path(&self) -> &str {
+        &self.config.path
+    }
+
+    /// File holding the environment cosmovisor is run with
+    pub fn env_file(&self) -> PathBuf {
+        self.root.join(ENV_FILE_NAME)
+    }
+
+    /// Installs the binary for the chain's current version and points `current` at it
+    ///
+    /// # Arguments
+    /// * `binary` - Binary to install
+    /// * `upgrade` - Name of the upgrade the binary belongs to, or `None` for genesis
+    #[instrument(skip(self, binary), fields(bin_path = %binary.display()))]
+    pub fn install_current(&self, binary: &Path, upgrade: Option<&str>) -> Result<()> {
+        // Cosmovisor expects both even when the binary belongs to an upgrade
+        for skeleton in [Path::new("genesis").join("bin"), PathBuf::from("upgrades")] {
+            fs::create_dir_all(self.root.join(skeleton))
+                .context("Failed to create cosmovisor directory")?;
+        }
+
+        let relative = match upgrade {
+            Some(name) => Path::new("upgrades").join(name),
+            None => PathBuf::from("genesis"),
+        };
+        self.install(binary, &relative)?;
+
+        let current = self.root.join("current");
+        if current.symlink_metadata().is_ok() {
+            fs::remove_file(&current).context("Failed to replace cosmovisor current link")?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&relative, &current)
+            .context("Failed to link cosmovisor current binary")?;
+
+        info!("Cosmovisor current binary set to {}", relative.display());
+        Ok(())
+    }
+
+    /// Whether a binary for the named upgrade is already in place
+    pub fn has_upgrade(&self, name: &str) -> bool {
+        self.upgrade_dir(name)
+            .join("bin")
+            .join(&self.daemon_name)
+            .exists()
+    }
+
+    /// Installs the binary cosmovisor switches to at the named upgrade
+    pub fn install_upgrade(&self, name: &str, binary: &Path) -> Result<()> {
+        self.install(binary, &Path::new("upgrades").join(name))?;
+        info!("Pre-populated binary for upgrade {}", name);
+        Ok(())
+    }
+
+    /// Writes the environment cosmovisor needs next to its layout
+    ///
+    /// # Returns
+    /// * `Result<PathBuf>` - Path to the environment file
+    pub fn write_env_file(&self, allow_download: bool) -> Result<PathBuf> {
+        let env = [
+            ("DAEMON_NAME", self.daemon_name.clone()),
+            ("DAEMON_HOME", self.data_dir.display().to_string()),
+            ("DAEMON_ALLOW_DOWNLOAD_BINARIES", allow_download.to_string()),
+            (
+                "DAEMON_RESTART_AFTER_UPGRADE",
+                self.config.restart_after_upgrade.to_string(),
+            ),
+        ];
+        let content: String = env
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect();
+
+        fs::create_dir_all(&self.root).context("Failed to create cosmovisor directory")?;
+        let path = self.env_file();
+        fs::write(&path, content).context("Failed to write cosmovisor environment file")?;
+
+        info!(
+            "Wrote {} (DAEMON_ALLOW_DOWNLOAD_BINARIES={})",
+            path.display(),
+            allow_download
+        );
+        Ok(path)
+    }
+
+    /// Reads back the environment written by [`Cosmovisor::write_env_file`]
+    pub fn read_env_file(&self) -> Result<Vec<(String, String)>> {
+        let path = self.env_file();
+        let content = fs::read_to_string(&path).with_context(|| {
+            format!(
+                "Failed to read {}; run the setup with cosmovisor enabled first",
+                path.display()
+            )
+        })?;
+
+        Ok(content
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+
+    /// Directory of the named upgrade
+    fn upgrade_dir(&self, name: &str) -> PathBuf {
+        self.root.join("upgrades").join(name)
+    }
+
+    /// Copies a binary into `<relative>/bin` under the cosmovisor directory
+    fn install(&self, binary: &Path, relative: &Path) -> Result<()> {
+        let bin_dir = self.root.join(relative).join("bin");
+        fs::create_dir_all(&bin_dir).context("Failed to create cosmovisor bin directory")?;
+
+        let target = bin_dir.join(&self.daemon_name);
+        fs::copy(binary, &target)
+            .with_context(|| format!("Failed to install binary into {}", bin_dir.display()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target, fs::Permissions::from_mode(0o755))
+                .context("Failed to make binary executable")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+use tracing::{info, instrument, warn};
+
+/// Databases written by CometBFT, whose backend `db_backend` selects
+const COMETBFT_DATABASES: [&str; 2] = ["blockstore.db", "state.db"];
+
+/// Database holding the application state
+const APPLICATION_DATABASE: &str = "application.db";
+
+/// Storage engine of a node database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbBackend {
+    /// LevelDB as implemented in Go
+    GoLevel,
+
+    /// RocksDB
+    Rocks,
+
+    /// Pebble
+    Pebble,
+}
+
+impl DbBackend {
+    /// Name used for the backend in `db_backend`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DbBackend::GoLevel => "goleveldb",
+            DbBackend::Rocks => "rocksdb",
+            DbBackend::Pebble => "pebbledb",
+        }
+    }
+}
+
+/// Detects the backend of the CometBFT databases in a node's `data` directory
+///
+/// Returns `None` when no database can be classified. The application
+/// database is checked too, and a warning is logged when it uses a different
+/// engine, since that needs `app-db-backend` in app.toml instead.
+#[instrument(skip(db_dir), fields(dir = %db_dir.display()))]
+pub fn detect(db_dir: &Path) -> Result<Option<DbBackend>> {
+    let mut detected: Option<(DbBackend, &str)> = None;
+
+    for name in COMETBFT_DATABASES {
+        let Some(backend) = classify(&db_dir.join(name))? else {
+            continue;
+        };
+        match detected {
+            Some((other, other_name)) if other != backend => {
+                return Err(anyhow!(
+                    "{} uses {} but {} uses {}",
+                    other_name,
+                    other.as_str(),
+                    name,
+                    backend.as_str()
+                ));
+            }
+            _ => detected = Some((backend, name)),
+        }
+    }
+
+    let Some((backend, _)) = detected else {
+        return Ok(None);
+    };
+    info!("Snapshot databases use {}", backend.as_str());
+
+    if let Some(app_backend) = classify(&db_dir.join(APPLICATION_DATABASE))? {
+        if app_backend != backend {
+            warn!(
+                "{} uses {} while the CometBFT databases use {}; set app-db-backend in app.toml accordingly",
+                APPLICATION_DATABASE,
+                app_backend.as_str(),
+                backend.as_str()
+            );
+        }
+    }
+
+    Ok(Some(backend))
+}
+
+/// Classifies a single database directory from the files its engine writes
+///
+/// Pebble leaves `marker.*` files and RocksDB an `IDENTITY` file; both record
+/// their version in `OPTIONS-*`. LevelDB is recognized by its `.ldb` tables.
+pub fn classify(path: &Path) -> Result<Option<DbBackend>> {
+    if !path.is_dir() {
+        return Ok(None);
+    }
+
+    let mut has_ldb = false;
+    for entry in fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if name.starts_with("marker.") {
+            return Ok(Some(DbBackend::Pebble));
+        }
+        if name == "IDENTITY" {
+            return Ok(Some(DbBackend::Rocks));
+        }
+        if name.starts_with("OPTIONS-") {
+            let options = fs::read_to_string(entry.path()).unwrap_or_default();
+            if options.contains("pebble_version") {
+                return Ok(Some(DbBackend::Pebble));
+            }
+            if options.contains("rocksdb_version") {
+                return Ok(Some(DbBackend::Rocks));
+            }
+        }
+        has_ldb |= name.ends_with(".ldb");
+    }
+
+    Ok(has_ldb.then_some(DbBackend::GoLevel))
+}
@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use tracing::info;
+
+/// Size of the chunks a [`ReadAhead`] decodes at a time
+const READ_AHEAD_CHUNK: usize = 1024 * 1024;
+
+/// Decoded chunks a [`ReadAhead`] keeps ready before waiting for the reader
+const READ_AHEAD_CHUNKS: usize = 8;
+
+/// Decompressed data read from an external decompression tool
+///
+/// The compressed input is fed to the tool's stdin from a thread, so it may
+/// itself be a stream such as a decrypting reader. The tool's exit status is
+/// checked at the end of the stream, so a corrupt or truncated archive fails
+/// the read instead of passing for a short one.
+pub struct CommandDecoder {
+    /// Name of the tool, for error messages
+    program: &'static str,
+
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl CommandDecoder {
+    /// Decodes a Zstandard stream with the `zstd` tool
+    ///
+    /// Archives compressed with long-distance matching (`zstd --long`) need a
+    /// window larger than the tool's default 128 MiB. Windows up to 2 GiB are
+    /// accepted, or up to `max_memory` when it is set.
+    pub fn zstd<R: Read + Send + 'static>(input: R, max_memory: Option<u64>) -> Result<Self> {
+        let mut command = Command::new("zstd");
+        command.arg("--decompress").arg("--stdout").arg("--quiet");
+        match max_memory {
+            Some(max_memory) => command.arg(format!("--memory={}", max_memory)),
+            None => command.arg("--long=31"),
+        };
+        Self::spawn("zstd", command, input)
+    }
+
+    /// Decodes a gzip stream with `pigz` on `threads` threads
+    ///
+    /// gzip can't be inflated in parallel, but `pigz` reads, inflates, writes
+    /// and checksums on separate threads, which roughly halves the time
+    /// `gzip` takes on large archives.
+    pub fn pigz<R: Read + Send + 'static>(input: R, threads: usize) -> Result<Self> {
+        let mut command = Command::new("pigz");
+        command
+            .arg("--decompress")
+            .arg("--stdout")
+            .arg("--processes")
+            .arg(threads.to_string());
+        Self::spawn("pigz", command, input)
+    }
+
+    /// Decodes an XZ stream with the `xz` tool on up to `threads` threads
+    ///
+    /// Only archives written in several blocks, as multi-threaded `xz`
+    /// compresses them, decode in parallel. The decoder's memory is capped at
+    /// `max_memory` when it is set.
+    pub fn xz<R: Read + Send + 'static>(
+        input: R,
+        max_memory: Option<u64>,
+        threads: usize,
+    ) -> Result<Self> {
+        let mut command = Command::new("xz");
+        command
+            .arg("--decompress")
+            .arg("--stdout")
+            .arg("--quiet")
+            .arg(format!("--threads={}", threads));
+        if let Some(max_memory) = max_memory {
+            command.arg(format!("--memlimit-decompress={}", max_memory));
+        }
+        Self::spawn("xz", command, input)
+    }
+
+    /// Decodes a bzip2 stream with the `bzip2` tool
+    pub fn bzip2<R: Read + Send + 'static>(input: R) -> Result<Self> {
+        let mut command = Command::new("bzip2");
+        command.arg("--decompress").arg("--stdout").arg("--quiet");
+        Self::spawn("bzip2", command, input)
+    }
+
+    /// Starts `command` and feeds it `input`
+    fn spawn<R: Read + Send + 'static>(
+        program: &'static str,
+        mut command: Command,
+        mut input: R,
+    ) -> Result<Self> {
+        info!("Decompressing with {}", program);
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}; is it installed?", program))?;
+
+        let mut stdin = child
+            .stdin
+            .take()
+            .with_context(|| format!("{} has no stdin", program))?;
+        let stdout = child
+            .stdout
+            .take()
+            .with_context(|| format!("{} has no stdout", program))?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .with_context(|| format!("{} has no stderr", program))?;
+
+        // stdin is closed when the thread ends, which ends the tool's input
+        let feeder = thread::spawn(move || io::copy(&mut input, &mut stdin));
+        let stderr = thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
+
+        Ok(CommandDecoder {
+            program,
+            child,
+            stdout,
+            feeder: Some(feeder),
+            stderr: Some(stderr),
+        })
+    }
+
+    /// Waits for the tool to exit and fails unless it decoded the whole input
+    fn finish(&mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        let fed = self
+            .feeder
+            .take()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("Input thread panicked")))
+            })
+            .unwrap_or(Ok(0));
+        let stderr = self
+            .stderr
+            .take()
+            .map(|handle| handle.join().unwrap_or_default())
+            .unwrap_or_default();
+
+        // A failure to read the input explains whatever the tool made of it
+        if let Err(e) = fed {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+        }
+        if status.success() {
+            return Ok(());
+        }
+
+        Err(io::Error::other(format!(
+            "{} failed to decompress the archive ({}): {}",
+            self.program,
+            status,
+            stderr.trim()
+        )))
+    }
+}
+
+impl Read for CommandDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() && self.stderr.is_some() {
+            self.finish()?;
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for CommandDecoder {
+    fn drop(&mut self) {
+        // Stop the tool when extraction gave up before the end of the stream;
+        // the input thread then ends on the closed pipe
+        if self.stderr.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Whether `program` is an executable in `PATH`
+pub fn is_installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .any(|dir| dir.join(program).is_file())
+}
+
+/// A reader decoded on a thread of its own
+///
+/// The decoder runs ahead of the reader by a few chunks, so decompressing an
+/// archive overlaps with writing out the files unpacked from it instead of
+/// taking turns with it on one thread.
+pub struct ReadAhead {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl ReadAhead {
+    /// Starts decoding `decoder` on a new thread
+    pub fn new<R: Read + Send + 'static>(mut decoder: R) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(READ_AHEAD_CHUNKS);
+        // The thread ends at the end of the stream, on an error, or when the
+        // reader is dropped and the next chunk can't be sent
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; READ_AHEAD_CHUNK];
+            let result = match decoder.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Ok(chunk)
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = result.is_err();
+            if sender.send(result).is_err() || failed {
+                break;
+            }
+        });
+
+        ReadAhead {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match self.chunks.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Ok(Err(e)) => {
+                    self.done = true;
+                    return Err(e);
+                }
+                // The decoder thread ended at the end of the stream
+                Err(_) => self.done = true,
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::JoinHandle;
+use tempfile::TempDir;
+use tracing::info;
+
+use crate::age::{self, AgeKeys};
+use crate::config::DecryptionConfig;
+
+/// Extensions of GPG-encrypted files
+const GPG_EXTENSIONS: [&str; 2] = [".gpg", ".pgp"];
+
+/// Extension of age-encrypted files
+const AGE_EXTENSION: &str = ".age";
+
+/// Whether a file name marks an encrypted file, e.g. `snapshot.tar.lz4.gpg`
+pub fn is_encrypted(name: &str) -> bool {
+    name.ends_with(AGE_EXTENSION) || GPG_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+/// The name of the file inside an encrypted one, e.g. `snapshot.tar.lz4`
+pub fn strip_encryption(name: &str) -> &str {
+    GPG_EXTENSIONS
+        .iter()
+        .chain([&AGE_EXTENSION])
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(name)
+}
+
+/// Opens an archive, decrypting it on the fly when its name says it is encrypted
+///
+/// GPG archives stream through `gpg --decrypt` and age archives are
+/// decrypted in-process, so the plaintext is never written to disk.
+pub fn open(path: &Path, config: Option<&DecryptionConfig>) -> Result<Box<dyn Read + Send>> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    if !is_encrypted(&name) {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open archive {}", path.display()))?;
+        return Ok(Box::new(file));
+    }
+
+    if name.ends_with(AGE_EXTENSION) {
+        let keys = AgeKeys::load(
+            config.and_then(|c| c.identity_file.as_deref()),
+            config.and_then(|c| c.passphrase_file.as_deref()),
+        )?;
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open archive {}", path.display()))?;
+        info!("Decrypting {} with age", path.display());
+        let reader = age::decrypt(file, &keys)
+            .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+        return Ok(Box::new(reader));
+    }
+
+    Ok(Box::new(GpgReader::spawn(path, config)?))
+}
+
+/// Plaintext read from a `gpg --decrypt` process
+///
+/// The process's exit status is checked at the end of the stream, so a
+/// failed integrity check or a missing key fails the read instead of
+/// passing for a short archive.
+struct GpgReader {
+    child: Child,
+    stdout: ChildStdout,
+    stderr: Option<JoinHandle<String>>,
+
+    /// Keyring holding the configured key, removed once decryption is done
+    keyring: Option<TempDir>,
+}
+
+impl GpgReader {
+    /// Starts decrypting `path` with the configured key, or the user's keyring and agent
+    fn spawn(path: &Path, config: Option<&DecryptionConfig>) -> Result<Self> {
+        let key_file = config.and_then(|c| c.key_file.as_deref());
+        let keyring = key_file.map(import_key).transpose()?;
+
+        let mut command = Command::new("gpg");
+        command.arg("--batch").arg("--quiet");
+        if let Some(keyring) = &keyring {
+            command.arg("--homedir").arg(keyring.path());
+        }
+        if let Some(passphrase_file) = config.and_then(|c| c.passphrase_file.as_deref()) {
+            command
+                .arg("--pinentry-mode")
+                .arg("loopback")
+                .arg("--passphrase-file")
+                .arg(passphrase_file);
+        }
+
+        info!(
+            "Decrypting {} with {}",
+            path.display(),
+            key_file.unwrap_or("the default GPG keyring")
+        );
+        let mut child = command
+            .arg("--decrypt")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run gpg; is GnuPG installed?")?;
+
+        let stdout = child.stdout.take().context("gpg has no stdout")?;
+        let mut stderr = child.stderr.take().context("gpg has no stderr")?;
+        let stderr = std::thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
+
+        Ok(GpgReader {
+            child,
+            stdout,
+            stderr: Some(stderr),
+            keyring,
+        })
+    }
+
+    /// Waits for gpg to exit and fails unless it decrypted the whole file
+    fn finish(&mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        let stderr = self
+            .stderr
+            .take()
+            .map(|handle| handle.join().unwrap_or_default())
+            .unwrap_or_default();
+        if status.success() {
+            return Ok(());
+        }
+
+        Err(io::Error::other(format!(
+            "gpg failed to decrypt the archive ({}): {}",
+            status,
+            stderr.trim()
+        )))
+    }
+}
+
+impl Read for GpgReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() && self.stderr.is_some() {
+            self.finish()?;
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for GpgReader {
+    fn drop(&mut self) {
+        // Stop gpg when extraction gave up before the end of the stream
+        if self.stderr.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+
+        // gpg started an agent for the throwaway keyring
+        if let Some(keyring) = &self.keyring {
+            let _ = Command::new("gpgconf")
+                .arg("--homedir")
+                .arg(keyring.path())
+                .arg("--kill")
+                .arg("gpg-agent")
+                .output();
+        }
+    }
+}
+
+/// Imports a secret key into a throwaway keyring
+fn import_key(key_file: &str) -> Result<TempDir> {
+    let keyring = TempDir::new().context("Failed to create temporary keyring directory")?;
+
+    // gpg refuses to trust a homedir that other users can read
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(keyring.path(), fs::Permissions::from_mode(0o700))
+            .context("Failed to restrict temporary keyring permissions")?;
+    }
+
+    let output = Command::new("gpg")
+        .arg("--batch")
+        .arg("--homedir")
+        .arg(keyring.path())
+        .arg("--import")
+        .arg(key_file)
+        .output()
+        .context("Failed to run gpg; is GnuPG installed?")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to import decryption key {}: {}",
+            key_file,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(keyring)
+}
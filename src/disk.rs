@@ -0,0 +1,122 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// Returns the bytes available to unprivileged users on the filesystem holding `path`
+///
+/// A path that doesn't exist yet is looked up on its nearest existing ancestor.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Result<u64> {
+    use anyhow::Context;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = existing_ancestor(path);
+    let c_path = CString::new(path.as_os_str().as_bytes()).context("Invalid path")?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // SAFETY: c_path is NUL-terminated and stats is a valid out pointer
+    let result = unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query free space of {}", path.display()));
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Free space can't be queried on this platform, so it is reported as unlimited
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> Result<u64> {
+    Ok(u64::MAX)
+}
+
+/// Identifies the filesystem holding `path`, or its nearest existing ancestor
+#[cfg(unix)]
+pub fn filesystem_id(path: &Path) -> Result<u64> {
+    use anyhow::Context;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = existing_ancestor(path);
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Failed to look up the filesystem of {}", path.display()))?;
+    Ok(metadata.dev())
+}
+
+/// Filesystems can't be told apart on this platform, so all paths share one
+#[cfg(not(unix))]
+pub fn filesystem_id(_path: &Path) -> Result<u64> {
+    Ok(0)
+}
+
+/// Bytes a download of `size` bytes into `path` still takes from free space
+///
+/// Partial downloads are preallocated to their full size while their length
+/// stays at the bytes written, so the blocks the file already holds are
+/// subtracted rather than its length.
+#[cfg(unix)]
+pub fn space_still_needed(path: &Path, size: u64) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    let allocated = std::fs::metadata(path).map_or(0, |metadata| metadata.blocks() * 512);
+    size.saturating_sub(allocated)
+}
+
+/// Allocated blocks can't be queried on this platform, so the written length is used
+#[cfg(not(unix))]
+pub fn space_still_needed(path: &Path, size: u64) -> u64 {
+    size.saturating_sub(std::fs::metadata(path).map_or(0, |metadata| metadata.len()))
+}
+
+/// Nearest ancestor of `path`, itself included, that exists
+#[cfg(unix)]
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const SIZE: u64 = 1 << 20;
+
+    #[test]
+    fn missing_download_needs_its_size() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            space_still_needed(&dir.path().join("snap.tar.lz4"), SIZE),
+            SIZE
+        );
+    }
+
+    #[test]
+    fn partial_download_needs_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snap.tar.lz4");
+        std::fs::write(&path, vec![1; 4096]).unwrap();
+        assert_eq!(space_still_needed(&path, SIZE), SIZE - 4096);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocated_download_needs_nothing_more() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snap.tar.lz4");
+        let mut file = std::fs::File::create(&path).unwrap();
+        // SAFETY: the descriptor is owned by `file` and stays open for the call
+        let ret =
+            unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, SIZE as i64) };
+        assert_eq!(ret, 0, "{}", std::io::Error::last_os_error());
+        file.write_all(&[1; 4096]).unwrap();
+        file.sync_all().unwrap();
+
+        // A resumed download's length is what was written, its blocks the whole reservation
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4096);
+        assert_eq!(space_still_needed(&path, SIZE), 0);
+    }
+}
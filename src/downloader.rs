@@ -1,15 +1,266 @@
 use anyhow::{anyhow, Context, Result};
+use bytes::{Buf, Bytes};
 use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
+use percent_encoding::percent_decode_str;
 use reqwest::Client;
 use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 use tracing::{info, warn};
 
+use crate::checksum::ChunkManifest;
+use crate::config::{RequestConfig, S3Config, SegmentedConfig};
+use crate::error::Error;
+use crate::extractor::ArchiveFormat;
+use crate::metrics;
+use crate::notify;
+use crate::retry::RetryPolicy;
+use crate::s3;
+use crate::segmented::{self, SegmentState};
+use crate::throttle::Throttle;
+
+/// Measured performance of a single download mirror
+#[derive(Debug, Clone)]
+pub struct MirrorBenchmark {
+    /// URL of the mirror
+    pub url: String,
+
+    /// Time until response headers were received
+    pub latency: Duration,
+
+    /// Observed transfer rate in bytes per second
+    pub throughput: f64,
+}
+
+/// Orders mirrors fastest first by their benchmark `results`
+///
+/// Mirrors missing from the results failed the short benchmark but may still
+/// serve a failover during a long download, so they follow in their
+/// configured order rather than being dropped.
+pub fn rank_mirrors(urls: &[String], results: &[MirrorBenchmark]) -> Vec<String> {
+    let mut ranked: Vec<String> = results.iter().map(|result| result.url.clone()).collect();
+    for url in urls {
+        if !ranked.contains(url) {
+            ranked.push(url.clone());
+        }
+    }
+    ranked
+}
+
+/// Metadata about a remote file gathered before downloading
+#[derive(Debug, Clone)]
+struct RemoteMetadata {
+    /// Total file size, if the server reported it
+    size: Option<u64>,
+
+    /// Whether the server supports range requests for resumable downloads
+    supports_range: bool,
+
+    /// File name suggested by the Content-Disposition header
+    file_name: Option<String>,
+}
+
+/// A remote file and the local path a download of it is stored at
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    /// Where [`Downloader::download`] stores the file
+    pub path: PathBuf,
+
+    /// Total file size, if the server reported it
+    pub size: Option<u64>,
+}
+
+/// Chunks of a streamed download in flight between the download and its reader
+const STREAM_BUFFER_CHUNKS: usize = 64;
+
+/// Sending end of a streamed download, see [`Downloader::stream`]
+pub type StreamSender = mpsc::Sender<io::Result<Bytes>>;
+
+/// Receiving end of a streamed download, read as the file arrives
+///
+/// Reads block until the next chunk arrives, so the reader belongs on a
+/// blocking thread. A download that fails for good ends the stream with its
+/// error rather than a clean end of file.
+pub struct StreamReader {
+    receiver: mpsc::Receiver<io::Result<Bytes>>,
+
+    /// Unread rest of the current chunk
+    chunk: Bytes,
+}
+
+/// Creates the two ends of a streamed download
+pub fn stream_channel() -> (StreamSender, StreamReader) {
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER_CHUNKS);
+    (
+        sender,
+        StreamReader {
+            receiver,
+            chunk: Bytes::new(),
+        },
+    )
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len());
+        buf[..read].copy_from_slice(&self.chunk[..read]);
+        self.chunk.advance(read);
+        Ok(read)
+    }
+}
+
+/// Name a download of `url` is saved under unless the server names the file
+///
+/// That is `file_name` when given, otherwise the last non-empty path segment
+/// of the URL, percent-decoded. Either way the name is sanitized as
+/// [`Downloader::download`] sanitizes it, so callers looking for a finished
+/// download find it under the name the downloader wrote.
+pub fn local_file_name(url: &str, file_name: Option<&str>) -> Result<String> {
+    match file_name {
+        Some(name) => sanitize_file_name(name),
+        None => {
+            sanitize_file_name(&percent_decode_str(&file_name_from_url(url)?).decode_utf8_lossy())
+        }
+    }
+}
+
+/// Makes a file name safe to join onto the output directory
+///
+/// Path separators and control characters are replaced and leading dots are
+/// stripped, so names like `../../etc/passwd` can't escape the directory.
+fn sanitize_file_name(name: &str) -> Result<String> {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+
+    if cleaned.is_empty() {
+        return Err(anyhow!(
+            "Derived file name '{}' is not usable; configure a filename override",
+            name
+        ));
+    }
+
+    if cleaned != name {
+        warn!("Sanitized file name '{}' to '{}'", name, cleaned);
+    }
+
+    Ok(cleaned)
+}
+
+/// Derives a file name from the last non-empty path segment of a URL
+fn file_name_from_url(url: &str) -> Result<String> {
+    let parsed = reqwest::Url::parse(url).context("Failed to parse download URL")?;
+
+    parsed
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .map(str::to_string)
+        .with_context(|| {
+            format!(
+                "Failed to determine file name from URL {}; configure a filename override",
+                url
+            )
+        })
+}
+
+/// Creates a progress bar for tracking download progress
+pub(crate) fn progress_bar(total_size: u64) -> Result<ProgressBar> {
+    let progress_bar = ProgressBar::new(total_size);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+            .progress_chars("#>-"),
+    );
+    Ok(progress_bar)
+}
+
+/// Starts a request for `url` with the method, headers and body of `request`
+///
+/// Without a request customization this is a plain GET.
+pub(crate) fn build_request(
+    client: &Client,
+    url: &str,
+    request: Option<&RequestConfig>,
+) -> Result<reqwest::RequestBuilder> {
+    let Some(request) = request else {
+        return Ok(client.get(url));
+    };
+
+    let method = match &request.method {
+        Some(method) => reqwest::Method::from_bytes(method.as_bytes())
+            .map_err(|_| anyhow!("Invalid HTTP method: {}", method))?,
+        None => reqwest::Method::GET,
+    };
+    let mut builder = client.request(method, url);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = &request.body {
+        builder = builder.body(body.clone());
+    }
+    Ok(builder)
+}
+
 /// A robust file downloader that supports resumable downloads
 pub struct Downloader {
     client: Client,
+
+    /// Retries of failed downloads, each resuming where the last stopped
+    retry: RetryPolicy,
+
+    /// Parallel range requests for files larger than one segment
+    segmented: Option<SegmentedConfig>,
+
+    /// Memory cap that bounds the number of parallel connections
+    max_memory: Option<u64>,
+
+    /// Upper bound on parallel connections
+    jobs: Option<usize>,
+
+    /// Per-chunk digests the downloaded file is verified against as it streams in
+    chunk_manifest: Option<ChunkManifest>,
+
+    /// Method, headers, body and URL signing of every request
+    request: Option<RequestConfig>,
+
+    /// Region, endpoint and credentials profile for `s3://` URLs
+    s3: Option<S3Config>,
+
+    /// Bandwidth cap shared by all connections of a download
+    throttle: Option<Arc<Throttle>>,
+
+    /// Furthest the current download has got, telling retries that made
+    /// progress from ones that didn't
+    reached: AtomicU64,
+
+    /// Name the current download's file was given, kept by mirrors failing over
+    resolved_name: Mutex<Option<String>>,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Downloader {
@@ -17,20 +268,95 @@ impl Downloader {
     pub fn new() -> Self {
         Downloader {
             client: Client::new(),
+            retry: RetryPolicy::none(),
+            segmented: None,
+            max_memory: None,
+            jobs: None,
+            chunk_manifest: None,
+            request: None,
+            s3: None,
+            throttle: None,
+            reached: AtomicU64::new(0),
+            resolved_name: Mutex::new(None),
         }
     }
 
+    /// Retries failed downloads according to `policy`
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Downloads files over parallel range requests when the server allows it
+    pub fn with_segmented(mut self, config: Option<SegmentedConfig>) -> Self {
+        self.segmented = config;
+        self
+    }
+
+    /// Opens no more parallel connections than fit in `max_memory` bytes
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Opens no more than `jobs` parallel connections
+    pub fn with_jobs(mut self, jobs: Option<usize>) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Verifies every chunk of the download against `manifest`
+    ///
+    /// Downloads then go over range requests in the manifest's chunks, so a
+    /// corrupt chunk is fetched again on its own.
+    pub fn with_chunk_manifest(mut self, manifest: Option<ChunkManifest>) -> Self {
+        self.chunk_manifest = manifest;
+        self
+    }
+
+    /// Makes every request as `request` describes instead of a plain GET
+    pub fn with_request(mut self, request: Option<RequestConfig>) -> Self {
+        self.request = request;
+        self
+    }
+
+    /// Resolves `s3://` URLs with these settings instead of the environment's defaults
+    pub fn with_s3(mut self, s3: Option<S3Config>) -> Self {
+        self.s3 = s3;
+        self
+    }
+
+    /// Downloads at no more than `max_rate` bytes per second
+    pub fn with_max_rate(mut self, max_rate: Option<u64>) -> Self {
+        self.throttle = Throttle::from_rate(max_rate).map(Arc::new);
+        self
+    }
+
+    /// Creates a downloader that authenticates every request with a bearer token
+    pub fn with_bearer_token(token: &str) -> Result<Self> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .context("Invalid bearer token")?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+
+        Ok(Downloader {
+            client: Client::builder()
+                .default_headers(headers)
+                .build()
+                .context("Failed to build HTTP client")?,
+            ..Self::new()
+        })
+    }
+
     /// Fetches metadata about a remote file before downloading
     ///
-    /// Returns:
-    /// - The total file size (if available)
-    /// - Whether the server supports range requests for resumable downloads
-    async fn fetch_remote_file_metadata(&self, url: &str) -> Result<(Option<u64>, bool)> {
+    /// Returns the total file size (if available), whether the server supports
+    /// range requests, and any file name suggested by Content-Disposition
+    async fn fetch_remote_file_metadata(&self, url: &str) -> Result<RemoteMetadata> {
         // Use a GET request with a minimal range instead of HEAD request
         // This has better compatibility with servers that reject HEAD requests
-        let response = self
-            .client
-            .get(url)
+        let response = build_request(&self.client, url, self.request.as_ref())?
             .header("Range", "bytes=0-0") // Request just the first byte
             .send()
             .await
@@ -50,12 +376,45 @@ impl Downloader {
             return Err(anyhow!("Request failed with status: {}", status));
         };
 
+        let file_name = self.extract_name_from_content_disposition(&response);
+
         info!(
-            "File metadata - Content length: {:?}, Supports range: {}",
-            content_length, supports_range
+            "File metadata - Content length: {:?}, Supports range: {}, Suggested name: {:?}",
+            content_length, supports_range, file_name
         );
 
-        Ok((content_length, supports_range))
+        Ok(RemoteMetadata {
+            size: content_length,
+            supports_range,
+            file_name,
+        })
+    }
+
+    /// Extracts the suggested file name from the Content-Disposition header
+    ///
+    /// Prefers the RFC 5987 `filename*=charset''name` form over plain `filename=`
+    fn extract_name_from_content_disposition(
+        &self,
+        response: &reqwest::Response,
+    ) -> Option<String> {
+        let header = response
+            .headers()
+            .get("content-disposition")
+            .and_then(|v| v.to_str().ok())?;
+
+        let mut plain = None;
+        for param in header.split(';').map(str::trim) {
+            if let Some(value) = param.strip_prefix("filename*=") {
+                // Skip the charset and language prefix
+                if let Some(name) = value.splitn(3, '\'').nth(2) {
+                    return Some(name.to_string()).filter(|n| !n.is_empty());
+                }
+            } else if let Some(value) = param.strip_prefix("filename=") {
+                plain = Some(value.trim_matches('"').to_string());
+            }
+        }
+
+        plain.filter(|n| !n.is_empty())
     }
 
     /// Extracts total file size from Content-Range header
@@ -81,28 +440,462 @@ impl Downloader {
             .and_then(|v| v.parse::<u64>().ok())
     }
 
+    /// Asks the server for a file's compression via its Content-Type
+    ///
+    /// Returns `None` when the request fails or the type names no known format,
+    /// as with the common `application/octet-stream`.
+    pub async fn probe_format(&self, url: &str) -> Option<ArchiveFormat> {
+        let signed_url = self.sign_url(url).await.ok()?;
+        let response = build_request(&self.client, &signed_url, self.request.as_ref())
+            .ok()?
+            .header("Range", "bytes=0-0")
+            .send()
+            .await
+            .ok()?;
+
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())?;
+        let format = ArchiveFormat::from_label(content_type);
+        if let Some(format) = format {
+            info!(
+                "{} is served as {} ({})",
+                url,
+                format.as_str(),
+                content_type
+            );
+        }
+        format
+    }
+
+    /// Benchmarks each mirror and returns the reachable ones, fastest first
+    ///
+    /// Mirrors that fail to respond are logged and left out of the result.
+    pub async fn benchmark_mirrors(
+        &self,
+        urls: &[String],
+        sample_size: u64,
+    ) -> Vec<MirrorBenchmark> {
+        let mut results = Vec::with_capacity(urls.len());
+
+        for url in urls {
+            match self.benchmark_mirror(url, sample_size).await {
+                Ok(result) => {
+                    info!(
+                        "Mirror {} - latency: {} ms, throughput: {:.2} MB/s",
+                        url,
+                        result.latency.as_millis(),
+                        result.throughput / 1_048_576.0
+                    );
+                    results.push(result);
+                }
+                Err(e) => warn!("Mirror {} failed benchmark: {:#}", url, e),
+            }
+        }
+
+        results.sort_by(|a, b| b.throughput.total_cmp(&a.throughput));
+        results
+    }
+
+    /// Downloads the first `sample_size` bytes from a mirror and measures the transfer
+    async fn benchmark_mirror(&self, url: &str, sample_size: u64) -> Result<MirrorBenchmark> {
+        let signed_url = self.sign_url(url).await?;
+        let start = Instant::now();
+        let response = build_request(&self.client, &signed_url, self.request.as_ref())?
+            .header(
+                "Range",
+                format!("bytes=0-{}", sample_size.saturating_sub(1)),
+            )
+            .send()
+            .await
+            .context("Failed to send benchmark request")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Request failed with status: {}", response.status()));
+        }
+
+        let latency = start.elapsed();
+        let mut received = 0u64;
+        let mut stream = response.bytes_stream();
+
+        // Servers that ignore the Range header send the whole file, so stop at the sample size
+        while let Some(item) = stream.next().await {
+            received += item.context("Error while benchmarking mirror")?.len() as u64;
+            if received >= sample_size {
+                break;
+            }
+        }
+
+        let transfer_secs = (start.elapsed() - latency).as_secs_f64().max(f64::EPSILON);
+
+        Ok(MirrorBenchmark {
+            url: url.to_string(),
+            latency,
+            throughput: received as f64 / transfer_secs,
+        })
+    }
+
     /// Downloads a file from a URL and saves it to the specified directory
     ///
     /// Features:
     /// - Automatic resume of partial downloads when possible
     /// - Progress tracking with ETA
     /// - Handles server quirks and edge cases
-    pub async fn download<P: AsRef<Path>>(&self, url: &str, output_dir: P) -> Result<PathBuf> {
-        // Extract filename from URL and create full output path
-        let (file_name, output_path) = self.prepare_output_path(url, output_dir)?;
+    ///
+    /// The local file name is `file_name` when given, otherwise it comes from
+    /// Content-Disposition, falling back to the last path segment of the URL.
+    pub async fn download<P: AsRef<Path>>(
+        &self,
+        url: &str,
+        output_dir: P,
+        file_name: Option<&str>,
+    ) -> crate::error::Result<PathBuf> {
+        let output_dir = output_dir.as_ref();
+        self.reached.store(0, Ordering::Relaxed);
+        self.retry
+            .run_resumable(
+                &format!("Download of {}", url),
+                || self.reached.load(Ordering::Relaxed),
+                || self.fetch(url, output_dir, file_name),
+            )
+            .await
+            .map_err(|source| Error::Download {
+                url: url.to_string(),
+                source: source.into(),
+            })
+    }
 
-        // Check if file exists to determine if we're resuming
-        let (file_exists, file_size) = self.check_existing_file(&output_path).await?;
+    /// Downloads a file served by several mirrors, failing over between them
+    ///
+    /// A failed request or a download broken off midway moves on to the next
+    /// mirror, which resumes the partial file where the last one stopped.
+    /// Once every mirror has failed, the round starts over after the retry
+    /// policy's delay. All mirrors store the file under one name, since they
+    /// must resume the same file: `file_name`, or the one the first mirror to
+    /// respond resolves the way [`Downloader::download`] does.
+    pub async fn download_mirrors<P: AsRef<Path>>(
+        &self,
+        urls: &[String],
+        output_dir: P,
+        file_name: Option<&str>,
+    ) -> crate::error::Result<PathBuf> {
+        let output_dir = output_dir.as_ref();
+        let first = urls
+            .first()
+            .ok_or_else(|| anyhow!("No download URLs configured"))?;
+        if urls.len() == 1 {
+            return self.download(first, output_dir, file_name).await;
+        }
+
+        self.reached.store(0, Ordering::Relaxed);
+        *self.lock_resolved_name() = None;
+        self.retry
+            .run_resumable(
+                &format!("Download of {}", file_name.unwrap_or(first)),
+                || self.reached.load(Ordering::Relaxed),
+                || async {
+                    let mut last_error = None;
+                    for (index, url) in urls.iter().enumerate() {
+                        let resolved = self.lock_resolved_name().clone();
+                        let name = file_name.or(resolved.as_deref());
+                        match self.fetch(url, output_dir, name).await {
+                            Ok(path) => return Ok(path),
+                            Err(e) if index + 1 < urls.len() => {
+                                warn!(
+                                    "Download from mirror {} failed, failing over to {}: {:#}",
+                                    url,
+                                    urls[index + 1],
+                                    e
+                                );
+                                last_error = Some(e);
+                            }
+                            Err(e) => last_error = Some(e),
+                        }
+                    }
+                    Err(last_error.unwrap_or_else(|| anyhow!("No download URLs configured")))
+                },
+            )
+            .await
+            .map_err(|source| Error::Download {
+                url: first.to_string(),
+                source: source.into(),
+            })
+    }
+
+    /// Streams a file served by one or more mirrors into `sender` without storing it
+    ///
+    /// A connection that breaks off is resumed with a range request from the
+    /// bytes already streamed, failing over between mirrors the way
+    /// [`Downloader::download_mirrors`] does. Streaming stops quietly when the
+    /// reading end goes away, since the reader's own error tells why.
+    ///
+    /// Returns the SHA256 of the streamed file, which can only be checked once
+    /// the reader has consumed it.
+    pub async fn stream(
+        &self,
+        urls: &[String],
+        sender: StreamSender,
+    ) -> crate::error::Result<String> {
+        let first = urls
+            .first()
+            .ok_or_else(|| anyhow!("No download URLs configured"))?;
+        let hasher = Mutex::new(Sha256::new());
+        self.reached.store(0, Ordering::Relaxed);
+
+        let result = self
+            .retry
+            .run_resumable(
+                &format!("Stream of {}", first),
+                || self.reached.load(Ordering::Relaxed),
+                || async {
+                    let mut last_error = None;
+                    for (index, url) in urls.iter().enumerate() {
+                        match self.fetch_stream(url, &sender, &hasher).await {
+                            Ok(()) => return Ok(()),
+                            Err(e) if index + 1 < urls.len() => {
+                                warn!(
+                                    "Stream from mirror {} failed, failing over to {}: {:#}",
+                                    url,
+                                    urls[index + 1],
+                                    e
+                                );
+                                last_error = Some(e);
+                            }
+                            Err(e) => last_error = Some(e),
+                        }
+                    }
+                    Err(last_error.unwrap_or_else(|| anyhow!("No download URLs configured")))
+                },
+            )
+            .await;
+
+        if let Err(e) = result {
+            // Without an error the reader would take the cut-off stream for a whole one
+            let _ = sender
+                .send(Err(io::Error::other(format!("Download failed: {:#}", e))))
+                .await;
+            return Err(Error::Download {
+                url: first.to_string(),
+                source: e.into(),
+            });
+        }
+
+        let hasher = hasher.into_inner().unwrap_or_else(|e| e.into_inner());
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Streams a file from one URL, continuing after the bytes already streamed
+    async fn fetch_stream(
+        &self,
+        url: &str,
+        sender: &StreamSender,
+        hasher: &Mutex<Sha256>,
+    ) -> Result<()> {
+        let signed_url = self.sign_url(url).await?;
+        let offset = self.reached.load(Ordering::Relaxed);
+
+        let mut request = build_request(&self.client, &signed_url, self.request.as_ref())?;
+        if offset > 0 {
+            info!("Resuming stream from byte position {}", offset);
+            request = request.header("Range", format!("bytes={}-", offset));
+        }
+        let response = request
+            .send()
+            .await
+            .context("Failed to send download request")?;
+        self.log_response_details(&response);
+
+        // A server ignoring the range resends the start, which was already streamed
+        let (mut skip, total_size) = match response.status() {
+            StatusCode::PARTIAL_CONTENT if offset > 0 => (
+                0,
+                self.extract_size_from_content_range(&response).or(self
+                    .extract_size_from_content_length(&response)
+                    .map(|length| offset + length)),
+            ),
+            StatusCode::OK => {
+                if offset > 0 {
+                    warn!(
+                        "Server ignored the range request, skipping the first {} bytes",
+                        offset
+                    );
+                }
+                (offset, self.extract_size_from_content_length(&response))
+            }
+            status => return Err(anyhow!("Unexpected response status: {}", status)),
+        };
+
+        let progress_bar = progress_bar(total_size.unwrap_or(0))?;
+        progress_bar.set_position(offset);
+        let mut streamed = offset;
+        let mut stream = response.bytes_stream();
+        while let Some(item) = stream.next().await {
+            let mut chunk = item.context("Error while downloading file")?;
+            metrics::add_downloaded(chunk.len() as u64);
+            if let Some(throttle) = &self.throttle {
+                throttle.consume_async(chunk.len()).await;
+            }
+            let skipped = chunk.len().min(skip as usize);
+            chunk.advance(skipped);
+            skip -= skipped as u64;
+            if chunk.is_empty() {
+                continue;
+            }
+
+            hasher
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .update(&chunk);
+            streamed += chunk.len() as u64;
+            if sender.send(Ok(chunk)).await.is_err() {
+                progress_bar.abandon();
+                return Ok(());
+            }
+            self.reached.store(streamed, Ordering::Relaxed);
+            progress_bar.set_position(streamed);
+        }
+
+        // A connection closed early ends the stream without an error
+        if let Some(total_size) = total_size {
+            if streamed != total_size {
+                return Err(anyhow!(
+                    "Stream of {} ended after {} of {} bytes",
+                    url,
+                    streamed,
+                    total_size
+                ));
+            }
+        }
+
+        progress_bar.finish();
+        info!(
+            "Completed stream of {} ({:.2} MB)",
+            url,
+            streamed as f64 / 1_048_576.0
+        );
+        Ok(())
+    }
+
+    /// Checks that a URL can be fetched, returning the file's size when the server reports it
+    pub async fn probe(&self, url: &str) -> crate::error::Result<Option<u64>> {
+        let probe = async {
+            let signed_url = self.sign_url(url).await?;
+            Ok(self.fetch_remote_file_metadata(&signed_url).await?.size)
+        };
+        probe
+            .await
+            .map_err(|source: anyhow::Error| Error::Download {
+                url: url.to_string(),
+                source: source.into(),
+            })
+    }
+
+    /// Looks up a remote file without downloading it
+    ///
+    /// Resolves the local path the same way [`Downloader::download`] does, so
+    /// callers can compare an existing download against the remote file.
+    pub async fn inspect<P: AsRef<Path>>(
+        &self,
+        url: &str,
+        output_dir: P,
+        file_name: Option<&str>,
+    ) -> crate::error::Result<RemoteFile> {
+        let metadata = self
+            .retry
+            .run(&format!("Metadata request for {}", url), || async {
+                let signed_url = self.sign_url(url).await?;
+                self.fetch_remote_file_metadata(&signed_url).await
+            })
+            .await
+            .map_err(|source| Error::Download {
+                url: url.to_string(),
+                source: source.into(),
+            })?;
+        let (_, path) =
+            self.prepare_output_path(url, output_dir, file_name, metadata.file_name.as_deref())?;
+
+        Ok(RemoteFile {
+            path,
+            size: metadata.size,
+        })
+    }
+
+    /// Downloads a file, resuming a partial one when the server allows it
+    async fn fetch<P: AsRef<Path>>(
+        &self,
+        url: &str,
+        output_dir: P,
+        file_name: Option<&str>,
+    ) -> Result<PathBuf> {
+        // Requests go to the signed URL, while the file is still named after the original
+        let signed_url = self.sign_url(url).await?;
 
         // Get metadata about the remote file
-        let (remote_size, supports_range) = self.fetch_remote_file_metadata(url).await?;
+        let metadata = self.fetch_remote_file_metadata(&signed_url).await?;
+        let remote_size = metadata.size;
+        let supports_range = metadata.supports_range;
+
+        // Determine the filename and create full output path
+        let (file_name, output_path) =
+            self.prepare_output_path(url, output_dir, file_name, metadata.file_name.as_deref())?;
+        *self.lock_resolved_name() = Some(file_name.clone());
+
+        if let Some(manifest) = &self.chunk_manifest {
+            let remote_size = remote_size
+                .filter(|_| supports_range)
+                .ok_or_else(|| anyhow!("Chunk manifest verification needs a server that reports the file size and supports range requests"))?;
+            manifest.check_size(remote_size)?;
+            let config = SegmentedConfig {
+                segment_size: manifest.chunk_size,
+                ..self.segmented.clone().unwrap_or_default()
+            };
+            self.fetch_segmented(&signed_url, &output_path, remote_size, &config)
+                .await?;
+            return Ok(output_path);
+        }
+
+        if let (Some(config), Some(remote_size)) = (&self.segmented, remote_size) {
+            if supports_range && remote_size > config.segment_size {
+                self.fetch_segmented(&signed_url, &output_path, remote_size, config)
+                    .await?;
+                return Ok(output_path);
+            }
+        }
+
+        // Check if file exists to determine if we're resuming
+        let (mut file_exists, mut file_size) = self.check_existing_file(&output_path).await?;
+
+        // A segmented download leaves the file at full length with gaps, so it can't be resumed here
+        let state_path = segmented::state_path(&output_path);
+        if state_path.exists() {
+            warn!(
+                "{} was partly downloaded in segments, restarting download from zero",
+                output_path.display()
+            );
+            tokio::fs::remove_file(&state_path).await?;
+            file_exists = false;
+            file_size = 0;
+        }
+
+        // A local file larger than the remote one means the provider replaced the file
+        if self.is_local_file_larger(file_exists, file_size, remote_size) {
+            warn!(
+                "Local file {} ({} bytes) is larger than the remote file ({} bytes), restarting download from zero",
+                output_path.display(),
+                file_size,
+                remote_size.unwrap_or(0)
+            );
+            file_exists = false;
+            file_size = 0;
+        }
 
         // Log download start/resume status
         self.log_download_start(&file_name, &output_path, file_exists, file_size);
 
         // Check if file is already complete
         if self.is_download_complete(file_exists, file_size, remote_size) {
-            info!("File is already complete or larger, skipping download");
+            info!("File is already complete, skipping download");
             return Ok(output_path);
         }
 
@@ -111,9 +904,18 @@ impl Downloader {
             .open_output_file(&output_path, file_exists, file_size, supports_range)
             .await?;
 
+        // Reserve disk space up front so a full disk fails now rather than hours in
+        if let Some(remote_size) = remote_size {
+            self.preallocate_file(&file, remote_size)?;
+        }
+
         // Create and send the HTTP request
-        let request = self.build_download_request(url, file_exists, file_size, supports_range);
-        let response = request.send().await.context("Failed to send GET request")?;
+        let request =
+            self.build_download_request(&signed_url, file_exists, file_size, supports_range)?;
+        let response = request
+            .send()
+            .await
+            .context("Failed to send download request")?;
 
         // Log response details for troubleshooting
         self.log_response_details(&response);
@@ -123,23 +925,119 @@ impl Downloader {
             .await
     }
 
+    /// Downloads a file over parallel range requests, resuming unfinished segments
+    async fn fetch_segmented(
+        &self,
+        url: &str,
+        output_path: &Path,
+        remote_size: u64,
+        config: &SegmentedConfig,
+    ) -> Result<()> {
+        let mut state = SegmentState::load(output_path, remote_size, config.segment_size)?;
+        if let Some(manifest) = &self.chunk_manifest {
+            info!("Verifying downloaded chunks against the chunk manifest");
+            let corrupt = state.verify_chunks(output_path, manifest)?;
+            if corrupt > 0 {
+                warn!("Downloading {} corrupt chunks again", corrupt);
+            }
+        }
+        if state.is_complete() {
+            info!("File is already complete, skipping download");
+            return Ok(());
+        }
+
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(output_path)
+            .await
+            .context("Failed to open output file")?;
+        self.preallocate_file(&file, remote_size)?;
+        file.set_len(remote_size)
+            .await
+            .context("Failed to size output file")?;
+        drop(file);
+
+        let mut connections = config.max_connections;
+        if let Some(jobs) = self.jobs {
+            connections = connections.min(jobs);
+        }
+        if let Some(max_memory) = self.max_memory {
+            let fitting = segmented::connections_within(max_memory);
+            if fitting < connections {
+                info!(
+                    "Limiting download to {} connections to stay within max_memory",
+                    fitting
+                );
+                connections = fitting;
+            }
+        }
+
+        let result = segmented::download(
+            &self.client,
+            url,
+            output_path,
+            state,
+            connections,
+            self.chunk_manifest.as_ref(),
+            self.request.as_ref(),
+            self.throttle.as_ref(),
+        )
+        .await;
+        if result.is_err() {
+            // Segments finished before the failure are kept for the next attempt
+            if let Ok(state) = SegmentState::load(output_path, remote_size, config.segment_size) {
+                self.reached
+                    .fetch_max(state.downloaded(), Ordering::Relaxed);
+            }
+        }
+        result?;
+        info!(
+            "Completed segmented download of {} ({:.2} MB)",
+            output_path.display(),
+            remote_size as f64 / 1_048_576.0
+        );
+        Ok(())
+    }
+
     /// Prepares the output path for the downloaded file
+    ///
+    /// The name is chosen from, in order: the configured override, the server's
+    /// Content-Disposition header, and the last non-empty path segment of the URL
+    /// (ignoring query strings and fragments).
     fn prepare_output_path<P: AsRef<Path>>(
         &self,
         url: &str,
         output_dir: P,
+        override_name: Option<&str>,
+        disposition_name: Option<&str>,
     ) -> Result<(String, PathBuf)> {
-        let file_name = url
-            .split('/')
-            .next_back()
-            .context("Failed to determine file name from URL")?
-            .to_string();
+        let file_name = match (override_name, disposition_name) {
+            // Servers may percent-encode the name they suggest
+            (None, Some(name)) => {
+                sanitize_file_name(&percent_decode_str(name).decode_utf8_lossy())?
+            }
+            _ => local_file_name(url, override_name)?,
+        };
+
+        if !file_name.contains('.') {
+            warn!(
+                "File name '{}' has no extension; set a filename override if it isn't recognized",
+                file_name
+            );
+        }
 
         let output_path = output_dir.as_ref().join(&file_name);
 
         Ok((file_name, output_path))
     }
 
+    /// Name the current download resolved, surviving a panicked holder
+    fn lock_resolved_name(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        self.resolved_name.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     /// Checks if a file already exists and returns its size
     async fn check_existing_file(&self, path: &Path) -> Result<(bool, u64)> {
         let file_exists = path.exists();
@@ -186,6 +1084,19 @@ impl Downloader {
         }
     }
 
+    /// Checks if an existing local file is larger than the remote file
+    fn is_local_file_larger(
+        &self,
+        file_exists: bool,
+        file_size: u64,
+        remote_size: Option<u64>,
+    ) -> bool {
+        match remote_size {
+            Some(remote_size) => file_exists && file_size > remote_size,
+            None => false,
+        }
+    }
+
     /// Opens the output file in the appropriate mode (create or append)
     async fn open_output_file(
         &self,
@@ -208,6 +1119,48 @@ impl Downloader {
         }
     }
 
+    /// Reserves disk blocks for the full remote size without changing the file length
+    ///
+    /// Uses `fallocate` with `FALLOC_FL_KEEP_SIZE` so the on-disk length still
+    /// reflects the bytes actually downloaded, which keeps resume detection intact.
+    /// Filesystems that don't support preallocation are skipped with a warning;
+    /// running out of space is reported as an error.
+    #[cfg(target_os = "linux")]
+    fn preallocate_file(&self, file: &tokio::fs::File, total_size: u64) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let len =
+            libc::off_t::try_from(total_size).context("File size too large to preallocate")?;
+        // SAFETY: the descriptor is owned by `file` and stays open for the duration of the call
+        let ret = unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) };
+
+        if ret == 0 {
+            info!("Preallocated {} bytes for download", total_size);
+            return Ok(());
+        }
+
+        let err = std::io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENOSPC) => Err(anyhow!(
+                "Insufficient disk space to preallocate {:.2} MB for download",
+                total_size as f64 / 1_048_576.0
+            )),
+            _ => {
+                warn!(
+                    "Preallocation not supported, continuing without it: {}",
+                    err
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Preallocation is a no-op on platforms without `fallocate`
+    #[cfg(not(target_os = "linux"))]
+    fn preallocate_file(&self, _file: &tokio::fs::File, _total_size: u64) -> Result<()> {
+        Ok(())
+    }
+
     /// Builds the HTTP request for downloading, adding Range header if resuming
     fn build_download_request(
         &self,
@@ -215,15 +1168,55 @@ impl Downloader {
         file_exists: bool,
         file_size: u64,
         supports_range: bool,
-    ) -> reqwest::RequestBuilder {
-        let mut request = self.client.get(url);
+    ) -> Result<reqwest::RequestBuilder> {
+        let mut request = build_request(&self.client, url, self.request.as_ref())?;
 
         if file_exists && file_size > 0 && supports_range {
             info!("Resuming from byte position {}", file_size);
             request = request.header("Range", format!("bytes={}-", file_size));
         }
 
-        request
+        Ok(request)
+    }
+
+    /// Signs `url` with the configured sign command, or returns it as is without one
+    ///
+    /// `s3://` URLs are presigned with AWS credentials instead.
+    async fn sign_url(&self, url: &str) -> Result<String> {
+        if s3::is_s3_url(url) {
+            return s3::presign(&self.client, url, self.s3.as_ref()).await;
+        }
+
+        let Some(command) = self.request.as_ref().and_then(|r| r.sign_command.as_ref()) else {
+            return Ok(url.to_string());
+        };
+
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| anyhow!("sign_command must not be empty"))?;
+        let output = tokio::process::Command::new(program)
+            .args(parts)
+            .arg(url)
+            .output()
+            .await
+            .with_context(|| format!("Failed to run sign command {}", program))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Sign command {} failed with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let signed_url = String::from_utf8(output.stdout)
+            .context("Sign command printed a URL that isn't UTF-8")?
+            .trim()
+            .to_string();
+        reqwest::Url::parse(&signed_url)
+            .with_context(|| format!("Sign command printed an invalid URL: {:?}", signed_url))?;
+        Ok(signed_url)
     }
 
     /// Logs details about the HTTP response
@@ -297,10 +1290,12 @@ impl Downloader {
             .await
             .context("Failed to create new output file for restart")?;
 
+        if let Some(remote_size) = remote_size {
+            self.preallocate_file(&file, remote_size)?;
+        }
+
         // Get a new response without range header
-        let new_response = self
-            .client
-            .get(url)
+        let new_response = build_request(&self.client, url, self.request.as_ref())?
             .send()
             .await
             .context("Failed to send new GET request after restart")?;
@@ -344,6 +1339,15 @@ impl Downloader {
         // Get content length from response
         let content_length = self.extract_size_from_content_length(&response);
 
+        // Full size of the file once this response has been written
+        let expected_size = if is_resuming {
+            self.extract_size_from_content_range(&response)
+                .or(content_length.map(|length| existing_file_size + length))
+        } else {
+            content_length
+        }
+        .or(known_content_length);
+
         // Calculate total size
         let total_size = self.calculate_total_download_size(
             is_resuming,
@@ -353,7 +1357,7 @@ impl Downloader {
         );
 
         // Set up progress tracking
-        let progress_bar = self.create_progress_bar(total_size)?;
+        let progress_bar = progress_bar(total_size)?;
 
         // Set initial position if resuming
         let mut downloaded = if is_resuming && existing_file_size > 0 {
@@ -369,6 +1373,25 @@ impl Downloader {
             .stream_file_contents(response, &mut file, progress_bar, downloaded)
             .await?;
 
+        // A connection closed early can end the stream without an error, so
+        // check what reached the disk; the retry then resumes from there
+        file.flush().await.context("Error while writing to file")?;
+        if let Some(expected_size) = expected_size {
+            let written = file
+                .metadata()
+                .await
+                .context("Failed to read the size of the downloaded file")?
+                .len();
+            if written != expected_size {
+                return Err(anyhow!(
+                    "Download of {} ended after {} of {} bytes",
+                    file_name,
+                    written,
+                    expected_size
+                ));
+            }
+        }
+
         // Log completion
         info!(
             "Completed download of {} ({:.2} MB)",
@@ -404,17 +1427,6 @@ impl Downloader {
         }
     }
 
-    /// Creates a progress bar for tracking download progress
-    fn create_progress_bar(&self, total_size: u64) -> Result<ProgressBar> {
-        let progress_bar = ProgressBar::new(total_size);
-        progress_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
-                .progress_chars("#>-"),
-        );
-        Ok(progress_bar)
-    }
-
     /// Streams file contents from the HTTP response to the local file
     async fn stream_file_contents(
         &self,
@@ -433,15 +1445,22 @@ impl Downloader {
                 .context("Error while writing to file")?;
 
             downloaded += chunk.len() as u64;
+            metrics::add_downloaded(chunk.len() as u64);
+            self.reached.fetch_max(downloaded, Ordering::Relaxed);
             progress_bar.set_position(downloaded);
+            if let Some(throttle) = &self.throttle {
+                throttle.consume_async(chunk.len()).await;
+            }
 
             // Log progress periodically (every 5MB)
             if !chunk.is_empty() && downloaded % (5 * 1024 * 1024) < chunk.len() as u64 {
-                info!(
+                let status = format!(
                     "Downloaded: {:.2} MB / {:.2} MB",
                     downloaded as f64 / 1_048_576.0,
                     progress_bar.length().unwrap_or(0) as f64 / 1_048_576.0
                 );
+                info!("{}", status);
+                notify::status(&status);
             }
         }
 
@@ -453,3 +1472,118 @@ impl Downloader {
         Ok(downloaded)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// File name the downloader picks for `url` in `/downloads`
+    fn name_for(url: &str, disposition: Option<&str>) -> Result<String> {
+        let dir = Path::new("/downloads");
+        let (name, path) = Downloader::new().prepare_output_path(url, dir, None, disposition)?;
+        assert_eq!(
+            path.parent(),
+            Some(dir),
+            "{} escaped to {}",
+            url,
+            path.display()
+        );
+        Ok(name)
+    }
+
+    #[test]
+    fn names_files_after_the_last_path_segment() {
+        let name = |url| name_for(url, None).unwrap();
+        assert_eq!(name("https://host/snapshots/snap.tar.lz4"), "snap.tar.lz4");
+        assert_eq!(name("https://host/snapshots/snap.tar/"), "snap.tar");
+        assert_eq!(
+            name("https://host/snap.tar?X-Amz-Signature=a/b"),
+            "snap.tar"
+        );
+        assert_eq!(name("https://host/snap%20v2.tar#part"), "snap v2.tar");
+    }
+
+    #[test]
+    fn keeps_encoded_traversal_inside_the_directory() {
+        let name = |url| name_for(url, None).unwrap();
+        assert_eq!(
+            name("https://host/a/%2e%2e%2f%2e%2e%2fetc%2fpasswd"),
+            "_.._etc_passwd"
+        );
+        assert_eq!(name("https://host/a/..%5cboot.ini"), "_boot.ini");
+        assert_eq!(name("https://host/a/%2Ehidden.tar"), "hidden.tar");
+        assert_eq!(name("https://host/a/%2e%2e%2f"), "_");
+        assert_eq!(name("https://host/a/snap%0a.tar"), "snap_.tar");
+
+        let name = |disposition| name_for("https://host/snap.tar", Some(disposition)).unwrap();
+        assert_eq!(name("../../etc/cron.d/job"), "_.._etc_cron.d_job");
+        assert_eq!(name("/etc/passwd"), "_etc_passwd");
+    }
+
+    #[test]
+    fn derives_local_names_like_downloads() {
+        for url in [
+            "https://host/snap%20shot.tar.lz4",
+            "https://host/a/%2e%2e%2fsnap.tar?sig=x",
+            "https://host/snap.tar/",
+        ] {
+            assert_eq!(
+                local_file_name(url, None).unwrap(),
+                name_for(url, None).unwrap(),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(
+            local_file_name("https://host/snap.tar", Some("../mine.tar")).unwrap(),
+            "_mine.tar"
+        );
+        assert!(local_file_name("https://host/?file=snap.tar", None).is_err());
+    }
+
+    #[test]
+    fn keeps_failed_mirrors_after_the_measured_ones() {
+        let urls: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let measured = |url: &str, throughput| MirrorBenchmark {
+            url: url.to_string(),
+            latency: Duration::ZERO,
+            throughput,
+        };
+        assert_eq!(
+            rank_mirrors(&urls, &[measured("c", 9.0), measured("a", 1.0)]),
+            ["c", "a", "b", "d"]
+        );
+        assert_eq!(rank_mirrors(&urls, &[]), urls);
+    }
+
+    #[test]
+    fn decodes_invalid_utf8_lossily() {
+        assert_eq!(
+            name_for("https://host/snap%ff%fe.tar", None).unwrap(),
+            "snap\u{fffd}\u{fffd}.tar"
+        );
+    }
+
+    #[test]
+    fn refuses_names_with_nothing_left() {
+        for url in [
+            "https://host/",
+            "https://host/?file=snap.tar",
+            "https://host/a/..",
+            "https://host/a/%2e%2e",
+            "https://host/%20",
+        ] {
+            assert!(name_for(url, None).is_err(), "{}", url);
+        }
+        for disposition in ["", "..", ".", " ", "..."] {
+            assert!(
+                name_for("https://host/snap.tar", Some(disposition)).is_err(),
+                "{:?}",
+                disposition
+            );
+        }
+        assert!(Downloader::new()
+            .prepare_output_path("https://host/snap.tar", "/downloads", Some(".."), None)
+            .is_err());
+    }
+}
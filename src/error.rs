@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Boxed underlying cause of a failed stage
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Result type of the library-facing modules
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Failures callers may want to tell apart, e.g. to pick an exit code
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The configuration file couldn't be read or is invalid
+    #[error("Invalid configuration")]
+    Config(#[source] BoxError),
+
+    /// A file couldn't be downloaded
+    #[error("Failed to download {url}")]
+    Download {
+        url: String,
+        #[source]
+        source: BoxError,
+    },
+
+    /// A file's SHA256 digest differs from the published one
+    #[error("Checksum mismatch for {}: expected {expected}, got {actual}", path.display())]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    /// An archive's extension isn't one the extractor handles
+    #[error("Unsupported archive format: {0}")]
+    UnsupportedFormat(String),
+
+    /// An archive couldn't be unpacked
+    #[error("Failed to extract {}", archive.display())]
+    Extract {
+        archive: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    /// Initializing or configuring the node home failed
+    #[error("Node setup failed")]
+    Setup(#[source] BoxError),
+
+    /// A `--check-only` run found artifacts or settings that need updating
+    #[error("{0} checks found something to update")]
+    Outdated(usize),
+
+    /// Any other failure
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Error {
+    /// Process exit code reported for the error
+    ///
+    /// Codes are stable so provisioning scripts can react to them: 3 for
+    /// downloads, 4 for checksum mismatches, 5 for unsupported archives, 6 for
+    /// extraction, 7 for node setup and 8 when `--check-only` finds something
+    /// to update; anything else exits with 1.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Config(_) => 1,
+            Error::Download { .. } => 3,
+            Error::ChecksumMismatch { .. } => 4,
+            Error::UnsupportedFormat(_) => 5,
+            Error::Extract { .. } => 6,
+            Error::Setup(_) => 7,
+            Error::Outdated(_) => 8,
+            Error::Other(_) => 1,
+        }
+    }
+}
+
+/// Exit code for an error, taken from the first typed error in its chain
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<Error>())
+        .find(|typed| !matches!(typed, Error::Other(_)))
+        .map_or(1, Error::exit_code)
+}
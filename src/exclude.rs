@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path};
+
+/// Named sets of node data that can be left out of a restore
+///
+/// Each preset expands to globs covering the directory names used by the
+/// Cosmos SDK, CometBFT, wasmd and Ethermint-based chains, wherever the
+/// archive puts the data directory.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExcludePreset {
+    /// CometBFT's transaction index; rebuilt only for blocks synced after the restore
+    TxIndex,
+
+    /// Ethermint's EVM transaction index, as kept by Cronos and Evmos with `enable-indexer`
+    EvmIndex,
+
+    /// Compiled CosmWasm modules, recompiled from the stored code on first use
+    WasmCache,
+
+    /// State sync snapshots the node produced for its peers
+    SnapshotsDir,
+}
+
+impl ExcludePreset {
+    /// Globs the preset expands to
+    pub fn globs(&self) -> &'static [&'static str] {
+        match self {
+            ExcludePreset::TxIndex => &["**/data/tx_index.db"],
+            ExcludePreset::EvmIndex => &["**/data/evmindexer.db"],
+            // wasmd keeps it in `data/wasm/cache`, older releases in `wasm/wasm/cache`
+            ExcludePreset::WasmCache => &["**/wasm/cache"],
+            ExcludePreset::SnapshotsDir => &["**/data/snapshots"],
+        }
+    }
+}
+
+/// Archive entries left out of extraction
+///
+/// An entry is excluded when its path, or the path of a directory holding
+/// it, matches one of the globs. `*` and `?` match within a path component
+/// and `**` across components.
+#[derive(Debug, Default)]
+pub struct Exclusions {
+    patterns: Vec<Regex>,
+}
+
+impl Exclusions {
+    /// Compiles the globs of the given presets along with extra globs
+    pub fn new(presets: &[ExcludePreset], globs: &[String]) -> Result<Self> {
+        let globs = presets
+            .iter()
+            .flat_map(|preset| preset.globs().iter().copied())
+            .chain(globs.iter().map(String::as_str));
+
+        let mut patterns = Vec::new();
+        for glob in globs {
+            let pattern = Regex::new(&glob_to_regex(glob))
+                .with_context(|| format!("Invalid exclude glob {:?}", glob))?;
+            patterns.push(pattern);
+        }
+        Ok(Exclusions { patterns })
+    }
+
+    /// Whether nothing is excluded
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether an archive entry's path is excluded
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+
+        let path = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        self.patterns.iter().any(|pattern| pattern.is_match(&path))
+    }
+}
+
+/// Translates a glob into a regex matching the path and everything under it
+fn glob_to_regex(glob: &str) -> String {
+    let glob = glob.trim_start_matches("./").trim_end_matches('/');
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push_str("(?:/.*)?$");
+    regex
+}
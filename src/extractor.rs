@@ -1,18 +1,279 @@
 use anyhow::{anyhow, Context, Result};
 use flate2::read::GzDecoder;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
-use tar::Archive;
-use tracing::{info, instrument};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tar::{Archive, Entry, EntryType};
+use tracing::{debug, info, instrument};
+
+use crate::checkpoint::{Checkpoint, CheckpointFile, FrameStart, Lz4Frames};
+use crate::config::DecryptionConfig;
+use crate::decompress::{self, CommandDecoder, ReadAhead};
+use crate::decrypt;
+use crate::error::Error;
+use crate::exclude::Exclusions;
+use crate::progress::ExtractionProgress;
+use crate::throttle::{Throttle, Throttled};
+
+/// Magic number opening every LZ4 frame, little endian
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+/// How often a resumable extraction records a checkpoint
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Size of a tar header block
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// Upper bound on the default number of decompression threads
+const MAX_DECOMPRESSION_THREADS: usize = 8;
+
+/// What walking an archive without extracting it found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// Entries in the tar stream
+    pub entries: u64,
+
+    /// Bytes the entries unpack to
+    pub unpacked_bytes: u64,
+
+    /// Size of the archive file
+    pub archive_bytes: u64,
+}
+
+/// Compression of a tar archive
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    /// gzip, `.tar.gz` or `.tgz`
+    Gzip,
+
+    /// LZ4 frames, `.tar.lz4`
+    Lz4,
+
+    /// Zstandard, `.tar.zst`, decoded with the `zstd` tool
+    Zstd,
+
+    /// XZ, `.tar.xz` or `.txz`, decoded with the `xz` tool
+    Xz,
+
+    /// bzip2, `.tar.bz2` or `.tbz2`, decoded with the `bzip2` tool
+    Bzip2,
+}
+
+impl ArchiveFormat {
+    /// Every supported format
+    pub const ALL: [ArchiveFormat; 5] = [
+        ArchiveFormat::Gzip,
+        ArchiveFormat::Lz4,
+        ArchiveFormat::Zstd,
+        ArchiveFormat::Xz,
+        ArchiveFormat::Bzip2,
+    ];
+
+    /// Name of the format as written in configs and indexes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Gzip => "gzip",
+            ArchiveFormat::Lz4 => "lz4",
+            ArchiveFormat::Zstd => "zstd",
+            ArchiveFormat::Xz => "xz",
+            ArchiveFormat::Bzip2 => "bzip2",
+        }
+    }
+
+    /// MIME type served for archives of this format
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Gzip => "application/gzip",
+            ArchiveFormat::Lz4 => "application/x-lz4",
+            ArchiveFormat::Zstd => "application/zstd",
+            ArchiveFormat::Xz => "application/x-xz",
+            ArchiveFormat::Bzip2 => "application/x-bzip2",
+        }
+    }
+
+    /// File name extensions of archives in this format, the usual one first
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            ArchiveFormat::Gzip => &["tar.gz", "tgz"],
+            ArchiveFormat::Lz4 => &["tar.lz4"],
+            ArchiveFormat::Zstd => &["tar.zst", "tar.zstd", "tzst"],
+            ArchiveFormat::Xz => &["tar.xz", "txz"],
+            ArchiveFormat::Bzip2 => &["tar.bz2", "tbz2", "tbz"],
+        }
+    }
+
+    /// External tool decoding the format, for those not decoded in-process
+    pub fn tool(&self) -> Option<&'static str> {
+        match self {
+            ArchiveFormat::Gzip | ArchiveFormat::Lz4 => None,
+            ArchiveFormat::Zstd => Some("zstd"),
+            ArchiveFormat::Xz => Some("xz"),
+            ArchiveFormat::Bzip2 => Some("bzip2"),
+        }
+    }
+
+    /// Detects the format from a file name's extension
+    ///
+    /// Encrypted archives such as `.tar.lz4.gpg` or `.tar.gz.age` report the format inside.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let name = decrypt::strip_encryption(name);
+        Self::ALL.into_iter().find(|format| {
+            format.extensions().iter().any(|extension| {
+                name.strip_suffix(extension)
+                    .is_some_and(|stem| stem.ends_with('.'))
+            })
+        })
+    }
+
+    /// Parses a format label as providers advertise it
+    ///
+    /// Accepts format names (`gzip`, `lz4`, `zstd`, `xz`, `bzip2`), extensions
+    /// (`tar.gz`, `.tgz`, `tar.lz4`, `tar.zst`, `tar.xz`, `tar.bz2`) and MIME
+    /// types such as `application/gzip`, `application/x-lz4` or
+    /// `application/x-xz`, ignoring case and MIME parameters.
+    pub fn from_label(label: &str) -> Option<Self> {
+        let label = label.split(';').next().unwrap_or_default().trim();
+        let label = label.to_ascii_lowercase();
+        let label = label.rsplit('/').next().unwrap_or_default();
+        let label = label.trim_start_matches('.').trim_start_matches("x-");
+        let label = label.strip_prefix("tar+").unwrap_or(label);
+
+        let alias = match label {
+            "gz" => Some(ArchiveFormat::Gzip),
+            "zst" => Some(ArchiveFormat::Zstd),
+            "bz2" => Some(ArchiveFormat::Bzip2),
+            _ => None,
+        };
+        alias.or_else(|| {
+            Self::ALL
+                .into_iter()
+                .find(|format| format.as_str() == label || format.extensions().contains(&label))
+        })
+    }
+}
 
 /// Handles extraction of compressed archive files
-pub struct Extractor {}
+pub struct Extractor {
+    /// Leading path components dropped from every entry, as in `tar --strip-components`
+    strip_components: usize,
+
+    /// Format to decode with instead of the one implied by the file name
+    format: Option<ArchiveFormat>,
+
+    /// Memory the decoder's buffers must fit in
+    max_memory: Option<u64>,
+
+    /// Key used for encrypted archives
+    decryption: Option<DecryptionConfig>,
+
+    /// Limit on the rate unpacked data is written at
+    throttle: Option<Throttle>,
+
+    /// Entries left out of the extraction
+    exclusions: Exclusions,
+
+    /// Threads decompressing gzip and XZ archives; one per CPU, up to 8, when unset
+    threads: Option<usize>,
+
+    /// Whether entries and links may point outside the output directory
+    allow_unsafe_paths: bool,
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Extractor {
     /// Creates a new extractor instance
     pub fn new() -> Self {
-        Extractor {}
+        Extractor {
+            strip_components: 0,
+            format: None,
+            max_memory: None,
+            decryption: None,
+            throttle: None,
+            exclusions: Exclusions::default(),
+            threads: None,
+            allow_unsafe_paths: false,
+        }
+    }
+
+    /// Decrypts `.gpg` archives with the configured key instead of the default keyring,
+    /// and `.age` archives with the configured identity or passphrase
+    pub fn with_decryption(mut self, decryption: Option<DecryptionConfig>) -> Self {
+        self.decryption = decryption;
+        self
+    }
+
+    /// Skips entries matching `exclusions`, matched after stripping components
+    pub fn with_exclusions(mut self, exclusions: Exclusions) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    /// Refuses archives whose decoder would need more than `max_memory` bytes
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Writes unpacked data at no more than `max_write_rate` bytes per second
+    pub fn with_max_write_rate(mut self, max_write_rate: Option<u64>) -> Self {
+        self.throttle = Throttle::from_rate(max_write_rate);
+        self
+    }
+
+    /// Decodes archives as `format` regardless of their file name
+    pub fn with_format(mut self, format: Option<ArchiveFormat>) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Decompresses gzip and XZ archives on `threads` threads
+    ///
+    /// With more than one, gzip is decoded by `pigz` when it is installed and
+    /// otherwise on a thread of its own, alongside unpacking, and `xz` decodes
+    /// archives written in several blocks in parallel. Zstandard is
+    /// always decoded by the `zstd` tool in a process of its own, as its
+    /// decoder has no parallel mode to take more threads.
+    pub fn with_threads(mut self, threads: Option<usize>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Extracts archives with entries or links pointing outside the output directory
+    ///
+    /// By default such an archive is refused at the first unsafe entry: one
+    /// with an absolute path or a `..` component, or a symbolic or hard link
+    /// whose target lies outside the output directory. Allowed, they are left
+    /// to the `tar` crate, which drops leading `/`, skips entries with `..`
+    /// and writes links as they are.
+    pub fn with_unsafe_paths(mut self, allow_unsafe_paths: bool) -> Self {
+        self.allow_unsafe_paths = allow_unsafe_paths;
+        self
+    }
+
+    /// Drops the given number of leading path components from every entry
+    pub fn with_strip_components(mut self, strip_components: usize) -> Self {
+        self.strip_components = strip_components;
+        self
+    }
+
+    /// Checks whether a file name has an archive extension this extractor supports
+    pub fn is_supported<P: AsRef<Path>>(archive_path: P) -> bool {
+        archive_path
+            .as_ref()
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(ArchiveFormat::from_file_name)
+            .is_some()
     }
 
     /// Extracts an archive file to the specified directory
@@ -20,6 +281,14 @@ impl Extractor {
     /// Supports multiple archive formats:
     /// - .tar.gz / .tgz (gzip compressed tar)
     /// - .tar.lz4 (LZ4 compressed tar)
+    /// - .tar.zst (Zstandard compressed tar, requires the `zstd` tool)
+    /// - .tar.xz / .txz (XZ compressed tar, requires the `xz` tool)
+    /// - .tar.bz2 / .tbz2 (bzip2 compressed tar, requires the `bzip2` tool)
+    ///
+    /// Any of them may be GPG- or age-encrypted (`.gpg`, `.age`), in which case it is decrypted as
+    /// it is unpacked.
+    ///
+    /// A format set with [`Extractor::with_format`] takes precedence over the extension.
     ///
     /// # Arguments
     /// * `archive_path` - Path to the archive file
@@ -29,7 +298,7 @@ impl Extractor {
         &self,
         archive_path: P,
         output_dir: Q,
-    ) -> Result<()> {
+    ) -> crate::error::Result<()> {
         let path = archive_path.as_ref();
         let file_name = path
             .file_name()
@@ -38,72 +307,785 @@ impl Extractor {
 
         info!("Extracting archive: {}", file_name);
 
-        // Determine extraction method based on file extension
-        match file_name {
-            name if name.ends_with(".tar.gz") || name.ends_with(".tgz") => {
-                self.extract_tar_gz(path, output_dir.as_ref())
+        // Determine extraction method from the override or the file extension
+        let format = self
+            .format
+            .or_else(|| ArchiveFormat::from_file_name(file_name));
+        let result = match format {
+            Some(ArchiveFormat::Lz4) => self.extract_tar_lz4(path, output_dir.as_ref()),
+            Some(format) => self.extract_tar(path, output_dir.as_ref(), format),
+            None => return Err(Error::UnsupportedFormat(file_name.to_string())),
+        };
+
+        result.map_err(|source| Error::Extract {
+            archive: path.to_path_buf(),
+            source: source.into(),
+        })
+    }
+
+    /// Decodes an archive to the end without writing anything
+    ///
+    /// Every entry is read through, so a truncated or corrupt archive, or
+    /// one whose decoder reports a bad checksum, fails just as extracting
+    /// it would. Unsafe entries fail too, unless allowed with
+    /// [`Extractor::with_unsafe_paths`]. Exclusions and stripped components
+    /// don't apply; the summary counts every entry.
+    #[instrument(skip(self, archive_path), fields(file_name = archive_path.as_ref().file_name().and_then(|n| n.to_str())))]
+    pub fn verify<P: AsRef<Path>>(&self, archive_path: P) -> crate::error::Result<ArchiveSummary> {
+        let path = archive_path.as_ref();
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Failed to get archive filename")?;
+        let Some(format) = self
+            .format
+            .or_else(|| ArchiveFormat::from_file_name(file_name))
+        else {
+            return Err(Error::UnsupportedFormat(file_name.to_string()));
+        };
+
+        info!("Verifying archive: {}", file_name);
+        self.walk(path, format).map_err(|source| Error::Extract {
+            archive: path.to_path_buf(),
+            source: source.into(),
+        })
+    }
+
+    /// Reads an archive's entries through to the end of the stream
+    fn walk(&self, archive_path: &Path, format: ArchiveFormat) -> Result<ArchiveSummary> {
+        let archive_bytes = fs::metadata(archive_path)?.len();
+        let file = decrypt::open(archive_path, self.decryption.as_ref())?;
+        let mut progress = ExtractionProgress::new(archive_bytes, 0)?;
+        let mut archive = Archive::new(self.decoder(format, progress.reader(file))?);
+
+        let mut summary = ArchiveSummary {
+            entries: 0,
+            unpacked_bytes: 0,
+            archive_bytes,
+        };
+        for entry in archive.entries()? {
+            let mut entry = entry.context("Failed to read tar archive")?;
+            let path = entry.path()?.into_owned();
+            if !self.allow_unsafe_paths {
+                check_entry_path(&entry, &path, 0)?;
             }
-            name if name.ends_with(".tar.lz4") => self.extract_tar_lz4(path, output_dir.as_ref()),
-            _ => Err(anyhow!("Unsupported archive format: {}", file_name)),
+            progress.entry(entry.size());
+            io::copy(&mut entry, &mut io::sink())
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            summary.entries += 1;
+            summary.unpacked_bytes += entry.size();
         }
+
+        // The decoder checks the stream's trailer, and a tool its exit status, at the end
+        io::copy(&mut archive.into_inner(), &mut io::sink())
+            .context("Failed to read the end of the archive")?;
+        progress.finish();
+        Ok(summary)
     }
 
-    /// Extracts a tar.gz compressed archive
+    /// Extracts an archive read from a stream, e.g. a download in progress
+    ///
+    /// Works like [`Extractor::extract`] without the archive ever touching the
+    /// disk, so the format must be known up front and nothing can be resumed:
+    /// an interrupted extraction starts over. `name` identifies the archive
+    /// in errors. Encrypted archives aren't supported.
+    #[instrument(skip(self, reader, output_dir))]
+    pub fn extract_stream<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+        name: &str,
+        format: ArchiveFormat,
+        output_dir: &Path,
+    ) -> crate::error::Result<()> {
+        info!("Extracting streamed {} archive", format.as_str());
+        let mut progress = ExtractionProgress::hidden();
+        let result = self.decoder(format, reader).and_then(|decoder| {
+            let mut archive = Archive::new(self.throttled(decoder));
+            self.unpack(&mut archive, output_dir, &mut progress)
+        });
+
+        result.map_err(|source| Error::Extract {
+            archive: PathBuf::from(name),
+            source: source.into(),
+        })?;
+        info!("Extraction completed successfully");
+        Ok(())
+    }
+
+    /// Extracts a tar archive compressed in `format`
     ///
     /// Uses a streaming approach to minimize memory usage during extraction
-    #[instrument(skip(self, archive_path, output_dir), fields(path = %archive_path.as_ref().display()))]
-    fn extract_tar_gz<P: AsRef<Path>, Q: AsRef<Path>>(
+    #[instrument(skip(self, archive_path, output_dir), fields(path = %archive_path.display()))]
+    fn extract_tar(
         &self,
-        archive_path: P,
-        output_dir: Q,
+        archive_path: &Path,
+        output_dir: &Path,
+        format: ArchiveFormat,
     ) -> Result<()> {
-        info!("Opening tar.gz archive");
-        let file = File::open(archive_path).context("Failed to open .tar.gz archive")?;
+        let extension = format.extensions()[0];
+        info!("Opening {} archive", extension);
+        let file = decrypt::open(archive_path, self.decryption.as_ref())
+            .with_context(|| format!("Failed to open .{} archive", extension))?;
+        let mut progress = ExtractionProgress::new(fs::metadata(archive_path)?.len(), 0)?;
 
-        info!("Creating gzip decoder");
-        let gz_decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(gz_decoder);
+        let decoder = self.decoder(format, progress.reader(file))?;
+        let mut archive = Archive::new(self.throttled(decoder));
 
-        info!("Unpacking tar archive to {}", output_dir.as_ref().display());
-        archive
-            .unpack(output_dir)
-            .context("Failed to extract .tar.gz archive")?;
+        info!("Unpacking tar archive to {}", output_dir.display());
+        self.unpack(&mut archive, output_dir, &mut progress)
+            .with_context(|| format!("Failed to extract .{} archive", extension))?;
 
+        progress.finish();
         info!("Extraction completed successfully");
         Ok(())
     }
 
     /// Extracts a tar.lz4 compressed archive
     ///
-    /// Uses buffered reading and streaming extraction to handle large files efficiently
+    /// Unencrypted archives are extracted resumably, see [`Extractor::extract_tar_lz4_resumable`];
+    /// encrypted ones are decrypted and decoded as a stream.
     #[instrument(skip(self, archive_path, output_dir), fields(path = %archive_path.as_ref().display()))]
     fn extract_tar_lz4<P: AsRef<Path>, Q: AsRef<Path>>(
         &self,
         archive_path: P,
         output_dir: Q,
     ) -> Result<()> {
-        info!("Opening LZ4 compressed file");
-        let file = File::open(archive_path).context("Failed to open .tar.lz4 archive")?;
+        let archive_path = archive_path.as_ref();
+        let name = archive_path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        if !decrypt::is_encrypted(&name) {
+            return self.extract_tar_lz4_resumable(archive_path, output_dir.as_ref());
+        }
 
-        // Use a BufReader to improve performance with large files
-        let buf_reader = BufReader::new(file);
+        self.extract_tar(archive_path, output_dir.as_ref(), ArchiveFormat::Lz4)
+    }
+
+    /// Extracts an unencrypted tar.lz4 archive, recording checkpoints as it goes
+    ///
+    /// Every [`CHECKPOINT_INTERVAL`] the start of the current LZ4 frame and the
+    /// next tar entry are recorded in `<archive>.checkpoint`. An interrupted
+    /// extraction then seeks to that frame and skips to that entry instead of
+    /// decoding the archive from the start. Archives written as a single frame
+    /// can only restart at the beginning, but still skip rewriting the entries
+    /// already extracted.
+    fn extract_tar_lz4_resumable(&self, archive_path: &Path, output_dir: &Path) -> Result<()> {
+        if let Some(max_memory) = self.max_memory {
+            let file = File::open(archive_path).context("Failed to open .tar.lz4 archive")?;
+            check_lz4_block_size(&mut BufReader::new(file), max_memory)?;
+        }
 
-        info!("Creating LZ4 decoder");
-        let lz4_decoder = lz4::Decoder::new(buf_reader).context("Failed to create LZ4 decoder")?;
+        let checkpoints = CheckpointFile::new(archive_path, output_dir, self.strip_components)?;
+        let checkpoint = checkpoints.load();
+
+        info!("Opening LZ4 compressed file");
+        let mut file = File::open(archive_path).context("Failed to open .tar.lz4 archive")?;
+        let total = file.metadata()?.len();
+        let start = match &checkpoint {
+            Some(checkpoint) => {
+                info!(
+                    "Resuming extraction after {} entries, from byte {} of the archive",
+                    checkpoint.entries, checkpoint.frame.compressed
+                );
+                file.seek(SeekFrom::Start(checkpoint.frame.compressed))
+                    .context("Failed to seek to the extraction checkpoint")?;
+                checkpoint.frame
+            }
+            None => FrameStart::default(),
+        };
+
+        let mut progress = ExtractionProgress::new(total, start.compressed)?;
+        let mut frames = Lz4Frames::new(BufReader::new(progress.reader(file)), start);
+        let frame_start = frames.frame_start();
+        let (base, extracted) = match &checkpoint {
+            Some(checkpoint) => {
+                let skip = checkpoint
+                    .entry
+                    .saturating_sub(checkpoint.frame.uncompressed);
+                io::copy(&mut (&mut frames).take(skip), &mut io::sink())
+                    .context("Failed to skip to the extraction checkpoint")?;
+                (checkpoint.entry, checkpoint.entries)
+            }
+            None => (0, 0),
+        };
 
         info!("Decompressing LZ4 data (this may take a while for large archives)");
-        // Pipe the LZ4 decoder directly to the tar extractor for memory efficiency
-        let mut archive = Archive::new(lz4_decoder);
+        let mut archive = Archive::new(self.throttled(frames));
+        info!("Extracting tar archive to {}", output_dir.display());
+        fs::create_dir_all(output_dir)?;
 
-        info!(
-            "Extracting tar archive to {}",
-            output_dir.as_ref().display()
-        );
-        archive
-            .unpack(output_dir)
-            .context("Failed to extract tar archive")?;
+        let mut last_checkpoint = Instant::now();
+        // Where the next header lies when no extension headers precede it
+        let mut next_header = 0;
+        for (index, entry) in archive.entries()?.enumerate() {
+            let mut entry = entry.context("Failed to extract tar archive")?;
+            let header = entry.raw_header_position();
+            let plain = header == next_header;
+            next_header = header
+                + TAR_BLOCK_SIZE
+                + entry.header().entry_size()?.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+
+            // Restarting at a header that long-name or pax headers precede would lose them
+            if plain && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                record_checkpoint(
+                    &checkpoints,
+                    &frame_start,
+                    base + header,
+                    extracted + index as u64,
+                )?;
+                last_checkpoint = Instant::now();
+            }
+
+            progress.entry(entry.size());
+            self.unpack_entry(&mut entry, output_dir)
+                .context("Failed to extract tar archive")?;
+        }
 
+        checkpoints.remove()?;
+        progress.finish();
         info!("Extraction completed successfully");
         Ok(())
     }
+
+    /// Limits the tar stream to the configured write rate
+    ///
+    /// Nearly every byte of the tar stream ends up written to disk, so pacing
+    /// the stream paces the writes without touching the unpacking itself.
+    fn throttled<R: Read>(&self, reader: R) -> Throttled<'_, R> {
+        Throttled::new(reader, self.throttle.as_ref())
+    }
+
+    /// Decoder for a tar stream compressed in `format`
+    ///
+    /// Zstandard is decoded by the `zstd` tool, with the window needed by
+    /// archives compressed in long mode allowed unless `max_memory` caps it;
+    /// XZ and bzip2 by their tools too.
+    fn decoder<R: Read + Send + 'static>(
+        &self,
+        format: ArchiveFormat,
+        input: R,
+    ) -> Result<Box<dyn Read>> {
+        Ok(match format {
+            ArchiveFormat::Gzip => self.gzip_decoder(input)?,
+            ArchiveFormat::Lz4 => {
+                let mut buf_reader = BufReader::new(input);
+                if let Some(max_memory) = self.max_memory {
+                    check_lz4_block_size(&mut buf_reader, max_memory)?;
+                }
+                Box::new(Lz4Frames::new(buf_reader, FrameStart::default()))
+            }
+            ArchiveFormat::Zstd => Box::new(CommandDecoder::zstd(input, self.max_memory)?),
+            ArchiveFormat::Xz => {
+                Box::new(CommandDecoder::xz(input, self.max_memory, self.threads())?)
+            }
+            ArchiveFormat::Bzip2 => Box::new(CommandDecoder::bzip2(input)?),
+        })
+    }
+
+    /// Threads decompression may use
+    fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .min(MAX_DECOMPRESSION_THREADS)
+        })
+    }
+
+    /// Decoder for a gzip stream, spread over the configured threads
+    fn gzip_decoder<R: Read + Send + 'static>(&self, input: R) -> Result<Box<dyn Read>> {
+        let threads = self.threads();
+        if threads <= 1 {
+            info!("Creating gzip decoder");
+            return Ok(Box::new(GzDecoder::new(input)));
+        }
+        if decompress::is_installed("pigz") {
+            return Ok(Box::new(CommandDecoder::pigz(input, threads)?));
+        }
+
+        info!("Creating gzip decoder on a separate thread (install pigz to decompress faster)");
+        Ok(Box::new(ReadAhead::new(GzDecoder::new(input))))
+    }
+
+    /// Unpacks a tar stream, stripping leading components and skipping
+    /// excluded entries when configured
+    ///
+    /// Directories are unpacked last, deepest first, as `tar` does, so a
+    /// read-only directory's permissions don't keep its contents out.
+    fn unpack<R: Read>(
+        &self,
+        archive: &mut Archive<R>,
+        output_dir: &Path,
+        progress: &mut ExtractionProgress,
+    ) -> Result<()> {
+        if self.strip_components > 0 {
+            info!(
+                "Stripping {} leading path components",
+                self.strip_components
+            );
+        }
+        fs::create_dir_all(output_dir)?;
+        let mut directories = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            progress.entry(entry.size());
+            if entry.header().entry_type() == EntryType::Directory {
+                directories.push(entry);
+            } else {
+                self.unpack_entry(&mut entry, output_dir)?;
+            }
+        }
+
+        directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+        for mut directory in directories {
+            self.unpack_entry(&mut directory, output_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Unpacks one entry, stripping leading components and skipping excluded
+    /// entries when configured
+    fn unpack_entry<R: Read>(&self, entry: &mut Entry<R>, output_dir: &Path) -> Result<()> {
+        let path = entry.path()?.into_owned();
+        if !self.allow_unsafe_paths {
+            check_entry_path(entry, &path, self.strip_components)?;
+        }
+        if self.strip_components == 0 {
+            if self.exclusions.is_excluded(&path) {
+                debug!("Excluding {}", path.display());
+                return Ok(());
+            }
+            entry
+                .unpack_in(output_dir)
+                .with_context(|| format!("Failed to unpack {}", path.display()))?;
+            return Ok(());
+        }
+
+        let Some(target) = strip_path(&path, self.strip_components)? else {
+            debug!("Skipping {}", path.display());
+            return Ok(());
+        };
+        if self.exclusions.is_excluded(&target) {
+            debug!("Excluding {}", path.display());
+            return Ok(());
+        }
+
+        // Unlike `unpack_in`, `unpack` doesn't check where the entry lands on
+        // disk, so links extracted earlier are resolved here
+        let target = output_dir.join(target);
+        if let Some(parent) = target.parent() {
+            if self.allow_unsafe_paths {
+                fs::create_dir_all(parent)?;
+            } else {
+                create_dir_within(&output_dir.canonicalize()?, parent, &path)?;
+            }
+        }
+        if entry.header().entry_type() == EntryType::Link {
+            return self.unpack_hard_link(entry, &path, &target, output_dir);
+        }
+        entry
+            .unpack(&target)
+            .with_context(|| format!("Failed to unpack {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Creates a hard link entry at `target` when stripping components
+    ///
+    /// The link's target is stripped like the entry's path and taken from the
+    /// output directory; `Entry::unpack` would resolve it against the working
+    /// directory instead.
+    fn unpack_hard_link<R: Read>(
+        &self,
+        entry: &Entry<R>,
+        path: &Path,
+        target: &Path,
+        output_dir: &Path,
+    ) -> Result<()> {
+        let link = entry
+            .link_name()?
+            .ok_or_else(|| anyhow!("Hard link {} has no target", path.display()))?;
+        let Some(stripped) = strip_path(&link, self.strip_components)? else {
+            return Err(anyhow!(
+                "Hard link {} points at {}, which stripping components removes",
+                path.display(),
+                link.display()
+            ));
+        };
+        let source = output_dir.join(stripped);
+        if !self.allow_unsafe_paths {
+            if let Some(parent) = source.parent() {
+                check_within(&output_dir.canonicalize()?, parent, path)?;
+            }
+        }
+
+        if target
+            .symlink_metadata()
+            .is_ok_and(|metadata| !metadata.is_dir())
+        {
+            fs::remove_file(target)?;
+        }
+        fs::hard_link(&source, target).with_context(|| {
+            format!(
+                "Failed to link {} to {}",
+                target.display(),
+                source.display()
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// Creates `dir` and its missing ancestors inside the canonical directory `root`
+///
+/// The closest existing ancestor is resolved on disk first, so a symlink
+/// extracted earlier can't lead the entry `path` outside `root`.
+fn create_dir_within(root: &Path, dir: &Path, path: &Path) -> Result<()> {
+    let mut missing = Vec::new();
+    let mut existing = dir;
+    while existing.symlink_metadata().is_err() {
+        missing.push(existing);
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => break,
+        }
+    }
+    check_within(root, existing, path)?;
+    for dir in missing.into_iter().rev() {
+        match fs::create_dir(dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Refuses the entry `path` unless `dir` resolves on disk to a directory inside `root`
+fn check_within(root: &Path, dir: &Path, path: &Path) -> Result<()> {
+    let resolved = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+    if !resolved.starts_with(root) {
+        return Err(unsafe_entry(
+            path,
+            &format!(
+                "it would be written through a link to {}, outside the output directory",
+                resolved.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses an entry that would be written, or link, outside the output directory
+///
+/// Paths are checked as written in the archive, without consulting the
+/// disk: the entry must be relative without `..` components, and a link's
+/// target must stay inside the output directory, resolved from the entry's
+/// directory for symbolic links and from the archive root for hard links.
+fn check_entry_path<R: Read>(entry: &Entry<R>, path: &Path, strip_components: usize) -> Result<()> {
+    let escapes = path.components().any(|component| {
+        matches!(
+            component,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    if escapes {
+        return Err(unsafe_entry(path, "its path is absolute or has `..`"));
+    }
+
+    let entry_type = entry.header().entry_type();
+    if !matches!(entry_type, EntryType::Symlink | EntryType::Link) {
+        return Ok(());
+    }
+    let Some(target) = entry.link_name()? else {
+        return Ok(());
+    };
+    let base = match entry_type {
+        EntryType::Symlink => {
+            let location: PathBuf = path
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .skip(strip_components)
+                .collect();
+            location.parent().map(Path::to_path_buf).unwrap_or_default()
+        }
+        _ => PathBuf::new(),
+    };
+    if !is_contained(&base, &target) {
+        return Err(unsafe_entry(
+            path,
+            &format!(
+                "it links to {} outside the output directory",
+                target.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `path`, taken relative to `base`, stays within the root both are relative to
+///
+/// Resolved lexically: absolute paths and `..` climbing above the root escape.
+fn is_contained(base: &Path, path: &Path) -> bool {
+    let mut depth = base.components().count();
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Error refusing an archive entry, pointing at the escape hatch
+fn unsafe_entry(path: &Path, reason: &str) -> anyhow::Error {
+    anyhow!(
+        "Refusing archive entry {}: {} (allow it with --allow-unsafe-paths)",
+        path.display(),
+        reason
+    )
+}
+
+/// Records a checkpoint at the entry whose header starts at `entry`
+///
+/// Skipped when the header straddles a frame boundary, as decoding can only
+/// restart at the start of the frame holding the header.
+fn record_checkpoint(
+    checkpoints: &CheckpointFile,
+    frame_start: &Rc<Cell<FrameStart>>,
+    entry: u64,
+    entries: u64,
+) -> Result<()> {
+    let frame = frame_start.get();
+    if frame.uncompressed > entry {
+        return Ok(());
+    }
+
+    debug!(
+        "Checkpoint at entry {} in frame at byte {}",
+        entries, frame.compressed
+    );
+    checkpoints.save(&Checkpoint {
+        frame,
+        entry,
+        entries,
+    })
+}
+
+/// Fails if the LZ4 frame's blocks need more memory to decode than allowed
+///
+/// The decoder holds a compressed and a decompressed block at a time, and
+/// the block size is fixed by the frame header, so archives written with
+/// large blocks can't be decoded in less.
+fn check_lz4_block_size<R: BufRead>(reader: &mut R, max_memory: u64) -> Result<()> {
+    let header = reader
+        .fill_buf()
+        .context("Failed to read LZ4 frame header")?;
+    if header.len() < 6 || header[..4] != LZ4_MAGIC {
+        // Not a frame we can inspect; the decoder reports the problem
+        return Ok(());
+    }
+
+    let block_size: u64 = match (header[5] >> 4) & 0x7 {
+        4 => 64 << 10,
+        5 => 256 << 10,
+        6 => 1 << 20,
+        7 => 4 << 20,
+        _ => return Ok(()),
+    };
+    debug!("LZ4 frame uses {} KiB blocks", block_size >> 10);
+
+    if block_size * 2 > max_memory {
+        return Err(anyhow!(
+            "Archive uses {} KiB LZ4 blocks, which need more than max_memory ({} bytes) to decode",
+            block_size >> 10,
+            max_memory
+        ));
+    }
+
+    Ok(())
+}
+
+/// Drops the leading components of an entry path
+///
+/// Returns `None` for entries that lie entirely within the stripped prefix
+/// and fails on paths that would escape the output directory.
+fn strip_path(path: &Path, strip_components: usize) -> Result<Option<PathBuf>> {
+    let mut stripped = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => stripped.push(part),
+            Component::CurDir => {}
+            _ => return Err(anyhow!("Refusing archive entry {}", path.display())),
+        }
+    }
+
+    let remaining: PathBuf = stripped.components().skip(strip_components).collect();
+    Ok((!remaining.as_os_str().is_empty()).then_some(remaining))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tar::{Builder, Header};
+    use tempfile::TempDir;
+
+    /// Contents of a test archive entry
+    enum Item {
+        File(&'static str),
+        Symlink(&'static str),
+        HardLink(&'static str),
+    }
+
+    /// Builds a tar archive with names written as given, even unsafe ones
+    ///
+    /// Relative paths and hard link targets are put under `prefix`.
+    fn archive(entries: &[(&str, Item)], prefix: &str) -> Vec<u8> {
+        let prefixed = |path: &str| match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("{}{}", prefix, path),
+        };
+        let mut builder = Builder::new(Vec::new());
+        for (path, item) in entries {
+            let (entry_type, link, data) = match item {
+                Item::File(contents) => (EntryType::Regular, String::new(), contents.as_bytes()),
+                Item::Symlink(target) => (EntryType::Symlink, target.to_string(), &[][..]),
+                Item::HardLink(target) => (EntryType::Link, prefixed(target), &[][..]),
+            };
+            let path = prefixed(path);
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            header.set_entry_type(entry_type);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Extracts `entries` into `out/` of a fresh directory, under a directory
+    /// that is stripped when `strip` is set
+    fn extract(entries: &[(&str, Item)], strip: bool) -> (TempDir, Result<()>) {
+        let prefix = if strip { "top/" } else { "" };
+        let dir = TempDir::new().unwrap();
+        let extractor = Extractor::new().with_strip_components(strip as usize);
+        let mut tar = Archive::new(Cursor::new(archive(entries, prefix)));
+        let result = extractor.unpack(
+            &mut tar,
+            &dir.path().join("out"),
+            &mut ExtractionProgress::hidden(),
+        );
+        (dir, result)
+    }
+
+    /// Asserts that extracting `entries` fails without writing `escaped` beside `out/`
+    fn assert_refused(entries: &[(&str, Item)], escaped: &str) {
+        for strip in [false, true] {
+            let (dir, result) = extract(entries, strip);
+            assert!(result.is_err(), "extracted with strip {}", strip);
+            assert!(
+                !dir.path().join(escaped).exists(),
+                "wrote {} with strip {}",
+                escaped,
+                strip
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_parent_dir_paths() {
+        assert_refused(&[("../evil", Item::File("x"))], "evil");
+        assert_refused(&[("a/../../evil", Item::File("x"))], "evil");
+    }
+
+    #[test]
+    fn refuses_absolute_paths() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("evil").display().to_string();
+        for strip in [false, true] {
+            let (_, result) = extract(&[(&path, Item::File("x"))], strip);
+            assert!(result.is_err());
+            assert!(!dir.path().join("evil").exists());
+        }
+    }
+
+    #[test]
+    fn refuses_outward_symlinks() {
+        assert_refused(
+            &[
+                ("link", Item::Symlink("..")),
+                ("link/evil", Item::File("x")),
+            ],
+            "evil",
+        );
+        assert_refused(&[("link", Item::Symlink("/etc"))], "evil");
+    }
+
+    #[test]
+    fn refuses_symlink_chains() {
+        assert_refused(
+            &[
+                ("x/l1", Item::Symlink("..")),
+                ("x/l1/l2", Item::Symlink("..")),
+                ("x/l1/l2/evil", Item::File("x")),
+            ],
+            "evil",
+        );
+        assert_refused(
+            &[
+                ("x/l1", Item::Symlink("..")),
+                ("x/l1/l2", Item::Symlink("..")),
+                ("x/l1/l2/sub/evil", Item::File("x")),
+            ],
+            "sub",
+        );
+    }
+
+    #[test]
+    fn refuses_outward_hard_links() {
+        assert_refused(&[("h", Item::HardLink("../secret"))], "h");
+        assert_refused(&[("h", Item::HardLink("/etc/passwd"))], "h");
+    }
+
+    #[test]
+    fn refuses_hard_links_through_symlink_chains() {
+        for strip in [false, true] {
+            let (dir, result) = extract(
+                &[
+                    ("x/l1", Item::Symlink("..")),
+                    ("x/l1/l2", Item::Symlink("..")),
+                    ("h", Item::HardLink("x/l1/l2/secret")),
+                ],
+                strip,
+            );
+            assert!(result.is_err(), "extracted with strip {}", strip);
+            assert!(!dir.path().join("out/h").exists());
+        }
+    }
+
+    #[test]
+    fn links_hard_links_within_the_output_directory() {
+        for strip in [false, true] {
+            let (dir, result) = extract(
+                &[
+                    ("data/a", Item::File("contents")),
+                    ("data/b", Item::HardLink("data/a")),
+                    ("data/c", Item::Symlink("a")),
+                ],
+                strip,
+            );
+            result.unwrap();
+            let out = dir.path().join("out/data");
+            assert_eq!(fs::read_to_string(out.join("b")).unwrap(), "contents");
+            assert_eq!(fs::read_to_string(out.join("c")).unwrap(), "contents");
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, instrument, warn};
+
+use crate::config::{ImageConfig, ImageMode};
+
+/// Obtains the node binary from a container image
+pub struct ImageBinary {
+    /// Directory where the extracted binary or wrapper script is written
+    work_dir: PathBuf,
+}
+
+impl ImageBinary {
+    /// Creates a new instance writing its output into `work_dir`
+    pub fn new(work_dir: &Path) -> Self {
+        ImageBinary {
+            work_dir: work_dir.to_path_buf(),
+        }
+    }
+
+    /// Makes the daemon from the image available as a local executable
+    ///
+    /// In `extract` mode the binary is copied out of the image; in `wrapper`
+    /// mode a launcher script running the image is generated instead.
+    ///
+    /// # Arguments
+    /// * `image` - Image reference and options
+    /// * `bin` - Configured binary path, whose file name is the default in-image name
+    #[instrument(skip(self, image), fields(image = %image.name, mode = ?image.mode))]
+    pub fn acquire(&self, image: &ImageConfig, bin: &str) -> Result<PathBuf> {
+        let name = Path::new(bin)
+            .file_name()
+            .context("Failed to determine binary name from cosmos.bin")?;
+        let path_in_image = image
+            .path
+            .clone()
+            .unwrap_or_else(|| format!("/usr/local/bin/{}", name.to_string_lossy()));
+
+        fs::create_dir_all(&self.work_dir).context("Failed to create image work directory")?;
+        let target = self.work_dir.join(name);
+
+        self.run(&image.runtime, &["pull", &image.name])
+            .context("Failed to pull image")?;
+
+        match image.mode {
+            ImageMode::Extract => self.extract(image, &path_in_image, &target)?,
+            ImageMode::Wrapper => self.write_wrapper(image, &path_in_image, &target)?,
+        }
+
+        Ok(target)
+    }
+
+    /// Copies the binary out of a stopped container created from the image
+    fn extract(&self, image: &ImageConfig, path_in_image: &str, target: &Path) -> Result<()> {
+        info!("Extracting {} from {}", path_in_image, image.name);
+
+        // The command is never run; it only satisfies images without a default one
+        let container_id = self
+            .run(&image.runtime, &["create", &image.name, "/bin/true"])
+            .context("Failed to create container")?;
+        let container_id = container_id.trim();
+
+        let source = format!("{}:{}", container_id, path_in_image);
+        let copied = self.run(
+            &image.runtime,
+            &["cp", "-L", &source, &target.to_string_lossy()],
+        );
+
+        if let Err(e) = self.run(&image.runtime, &["rm", container_id]) {
+            warn!("Failed to remove container {}: {:#}", container_id, e);
+        }
+        copied.with_context(|| format!("Failed to copy {} out of the image", path_in_image))?;
+
+        Ok(())
+    }
+
+    /// Writes a script that runs the daemon inside the container
+    ///
+    /// The working directory is mounted at the same path so node commands that
+    /// refer to the home directory behave as if the binary ran natively.
+    fn write_wrapper(&self, image: &ImageConfig, path_in_image: &str, target: &Path) -> Result<()> {
+        info!("Generating {} wrapper for {}", image.runtime, image.name);
+
+        let script = format!(
+            "#!/bin/sh\n\
+             # Generated by snapshot-downloader: runs {path} from {image}\n\
+             exec {runtime} run --rm -i --network host --user \"$(id -u):$(id -g)\" \\\n\
+             \x20 -v \"$PWD:$PWD\" -w \"$PWD\" --entrypoint {path} {image} \"$@\"\n",
+            runtime = image.runtime,
+            image = image.name,
+            path = path_in_image,
+        );
+        fs::write(target, script).context("Failed to write wrapper script")?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(target, fs::Permissions::from_mode(0o755))
+                .context("Failed to make wrapper script executable")?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a container runtime command and returns its stdout
+    fn run(&self, runtime: &str, args: &[&str]) -> Result<String> {
+        let output = Command::new(runtime)
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to run {}; is it installed?", runtime))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} {} failed: {}",
+                runtime,
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+/// Directories a run keeps its downloads, node home and binary in
+#[derive(Debug, Clone)]
+pub struct Layout {
+    /// Directory holding the node home, along with files such as the data manifest
+    pub root: PathBuf,
+
+    /// Directory for scratch work such as downloads and extraction staging;
+    /// the root unless `tmp_dir` moves it elsewhere
+    pub scratch: PathBuf,
+
+    /// Downloaded archives, also used to stage the snapshot's extraction;
+    /// `cache_dir` when configured
+    pub snapshots: PathBuf,
+
+    /// Node home the snapshot is restored into; `data_dir` when configured
+    pub home: PathBuf,
+
+    /// Extracted binary package; `bin_dir` when configured
+    pub bin: PathBuf,
+}
+
+impl Layout {
+    /// Layout of a single-chain configuration, directly in the output directory
+    ///
+    /// Scratch work goes to `scratch_dir`, which is usually the output directory too.
+    pub fn flat(output_dir: &Path, scratch_dir: &Path) -> Self {
+        Layout {
+            root: output_dir.to_path_buf(),
+            scratch: scratch_dir.to_path_buf(),
+            snapshots: scratch_dir.join("snapshots"),
+            home: output_dir.join("data"),
+            bin: scratch_dir.join("bin_extract"),
+        }
+    }
+
+    /// Layout of one chain of a multi-chain configuration
+    ///
+    /// Each chain gets `<output_dir>/<chain_id>/home` and
+    /// `<scratch_dir>/<chain_id>/{snapshots,bin}`, so chains never share
+    /// downloads or data.
+    pub fn per_chain(output_dir: &Path, scratch_dir: &Path, chain_id: &str) -> Self {
+        let root = output_dir.join(chain_id);
+        let scratch = scratch_dir.join(chain_id);
+        Layout {
+            snapshots: scratch.join("snapshots"),
+            home: root.join("home"),
+            bin: scratch.join("bin"),
+            root,
+            scratch,
+        }
+    }
+
+    /// Layout for a configuration, per chain when it came from a `chains` map
+    ///
+    /// `bin_dir`, `cache_dir` and `data_dir` replace the directories derived
+    /// from the output directory when configured.
+    pub fn for_config(config: &Config, output_dir: &Path) -> Self {
+        let scratch_dir = config
+            .tmp_dir
+            .as_deref()
+            .map(Path::new)
+            .unwrap_or(output_dir);
+        let mut layout = match &config.chain_id {
+            Some(chain_id) => Self::per_chain(output_dir, scratch_dir, chain_id),
+            None => Self::flat(output_dir, scratch_dir),
+        };
+        if let Some(bin_dir) = &config.bin_dir {
+            layout.bin = PathBuf::from(bin_dir);
+        }
+        if let Some(cache_dir) = &config.cache_dir {
+            layout.snapshots = PathBuf::from(cache_dir);
+        }
+        if let Some(data_dir) = &config.data_dir {
+            layout.home = PathBuf::from(data_dir);
+        }
+        layout
+    }
+}
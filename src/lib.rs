@@ -0,0 +1,88 @@
+//! Downloads, extracts and sets up Cosmos node snapshots
+//!
+//! The `snapshot-downloader` binary is a thin command line front end over
+//! this library, so the same pipeline can be embedded in other tools. The
+//! main building blocks are:
+//!
+//! - [`Config`]: the YAML configuration, loaded and validated with
+//!   [`Config::load_chains`]
+//! - [`Downloader`]: resumable HTTP downloads with retries, mirror failover
+//!   and parallel range requests
+//! - [`Extractor`]: unpacks `.tar.lz4`, `.tar.gz`, `.tar.zst`, `.tar.xz`
+//!   and `.tar.bz2` archives, from disk or from a stream
+//! - [`CosmosSetup`]: initializes a node home and writes its TOML settings
+//! - [`pipeline::run_pipeline`]: the download, extract and setup stages the
+//!   binary runs, with its standalone commands in [`commands`]
+//!
+//! Their fallible methods return [`Result`], whose [`Error`] tells the
+//! stage that failed apart, e.g. a download from a checksum mismatch.
+//!
+//! ```no_run
+//! use snapshot_downloader::{Config, CosmosSetup, Downloader, Extractor};
+//! use std::path::Path;
+//!
+//! # async fn restore() -> snapshot_downloader::Result<()> {
+//! let config = Config::load_chains("config.yaml", None, &[])?.remove(0);
+//! let home = Path::new("/var/lib/cronos");
+//!
+//! let archive = Downloader::new()
+//!     .download(&config.snapshot_url[0], "/tmp/snapshots", None)
+//!     .await?;
+//! Extractor::new().extract(&archive, home)?;
+//! CosmosSetup::new(&config.cosmos, Path::new("/usr/local/bin"), home).init()?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod age;
+pub mod build;
+mod checkpoint;
+pub mod checksum;
+pub mod commands;
+pub mod compact;
+pub mod compat;
+pub mod config;
+mod copy;
+pub mod cosmovisor;
+pub mod db_backend;
+mod decompress;
+pub mod decrypt;
+pub mod disk;
+pub mod downloader;
+pub mod error;
+pub mod exclude;
+pub mod extractor;
+pub mod image;
+pub mod layout;
+pub mod manifest;
+pub mod metrics;
+mod node_log;
+pub mod notify;
+pub mod pipeline;
+pub mod priority;
+mod progress;
+pub mod provider;
+pub mod prune;
+pub mod registry;
+pub mod release;
+pub mod report;
+pub mod retention;
+pub mod retry;
+pub mod s3;
+pub mod segmented;
+pub mod service;
+pub mod setup;
+pub mod share;
+pub mod signature;
+pub mod snapshot_info;
+pub mod state;
+pub mod supervisor;
+pub mod sync;
+mod throttle;
+pub mod torrent;
+
+pub use config::Config;
+pub use downloader::Downloader;
+pub use error::{Error, Result};
+pub use extractor::{ArchiveFormat, ArchiveSummary, Extractor};
+pub use setup::CosmosSetup;
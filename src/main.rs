@@ -1,34 +1,280 @@
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand};
 use std::path::{Path, PathBuf};
-use tracing::{info, Level};
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::{info, warn, Level};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
 
-mod config;
-mod downloader;
-mod extractor;
-mod setup;
+use snapshot_downloader::{
+    commands::{
+        bench_mirrors, check_only, check_reachability, config_apply, config_diff, run_node,
+        share_snapshots, verify_archive, verify_data,
+    },
+    config::{self, Config, LogRotation, SegmentedConfig},
+    error,
+    layout::Layout,
+    metrics::RunMetrics,
+    notify,
+    pipeline::{
+        apply_registry_defaults, fetch_registry_entry, run_pipeline, Phases,
+        DEFAULT_BENCH_SAMPLE_MB,
+    },
+    share::ShareServer,
+};
 
-use config::Config;
-use downloader::Downloader;
-use extractor::Extractor;
-use setup::CosmosSetup;
+/// Default time allowed for the node to catch up with `--wait-synced`
+const DEFAULT_SYNC_TIMEOUT_SECS: u64 = 6 * 3600;
+
+/// Default listen address of the `serve` command
+const DEFAULT_SERVE_ADDR: &str = ":8080";
+
+/// Default number of log files kept with `--log-file`
+const DEFAULT_LOG_KEEP: usize = 7;
 
 /// Command-line arguments for the snapshot downloader
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path to the configuration file
-    #[arg(short, long, default_value = "config.yaml")]
+    #[arg(short, long, default_value = "config.yaml", global = true)]
     config: PathBuf,
 
     /// Path to store downloaded files and extracted data
-    #[arg(short, long, default_value = ".")]
+    #[arg(short, long, default_value = ".", global = true)]
     output_dir: PathBuf,
 
+    /// Directory for downloads, the extracted binary package and extraction
+    /// staging, e.g. on a large slow disk while the node data lands in `--output-dir`
+    #[arg(long, global = true)]
+    tmp_dir: Option<PathBuf>,
+
+    /// Directory to extract the binary package into, e.g. `/usr/local/bin`
+    #[arg(long, global = true)]
+    bin_dir: Option<PathBuf>,
+
+    /// Directory to keep downloaded archives in and stage the snapshot in,
+    /// e.g. on scratch storage
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// Node home to restore the snapshot into, e.g. on the node volume
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// Upper bound on parallel download connections and on copy and hashing
+    /// workers, for sharing the host with other services
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
+
+    /// Decompress gzip and XZ snapshots on this many threads, gzip with pigz
+    /// when it is installed
+    #[arg(long, global = true)]
+    extract_threads: Option<usize>,
+
+    /// Download files larger than one segment over up to this many parallel
+    /// range requests, enabling `segmented` when it isn't configured
+    #[arg(long, global = true)]
+    connections: Option<usize>,
+
+    /// Limit downloads to this many bytes per second, e.g. `50MB`
+    #[arg(long, global = true, value_parser = config::parse_size)]
+    max_rate: Option<u64>,
+
     /// Enable verbose output for detailed logs
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// Also write logs to this file, rotated as set by `--log-rotation`
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Level of the messages written to the log file, independent of `--verbose`
+    #[arg(long, default_value_t = LevelFilter::INFO, global = true)]
+    log_file_level: LevelFilter,
+
+    /// How often the log file is rotated; rotated files get a date suffix
+    #[arg(long, value_enum, default_value_t = LogRotation::Daily, global = true)]
+    log_rotation: LogRotation,
+
+    /// Number of log files to keep, including the current one
+    #[arg(long, default_value_t = DEFAULT_LOG_KEEP, global = true)]
+    log_keep: usize,
+
+    /// Send logs to journald instead of the console (Linux only)
+    #[arg(long, global = true)]
+    journald: bool,
+
+    /// Write a JSON summary of the completed run to this path
+    #[arg(long, global = true)]
+    result_json: Option<PathBuf>,
+
+    /// Write run metrics for node_exporter's textfile collector to this path, e.g.
+    /// `/var/lib/node_exporter/textfile/snapshot_downloader.prom`
+    #[arg(long, global = true)]
+    metrics_file: Option<PathBuf>,
+
+    /// Chain registry name of the chain, e.g. `osmosis`
+    #[arg(long, global = true)]
+    chain: Option<String>,
+
+    /// Override a configuration value, e.g. `--set cosmos.moniker=node-3`; the
+    /// value is parsed as YAML and the flag may be repeated
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+
+    /// Chain to act on when the configuration has a `chains` map (default: all
+    /// chains for a run, required for other commands unless `--all` is given)
+    #[arg(long, global = true, visible_alias = "chain-name")]
+    chain_id: Option<String>,
+
+    /// Act on every chain of a `chains` map in turn, e.g. to verify each node's data
+    #[arg(long, global = true, conflicts_with = "chain_id")]
+    all: bool,
+
+    /// Report what a run would download or change without writing anything;
+    /// exits with code 8 when something needs updating
+    #[arg(long, global = true)]
+    check_only: bool,
+
+    /// Request every configured URL before starting and fail if any is unreachable
+    #[arg(long, global = true)]
+    check_reachability: bool,
+
+    /// Start even when the disks look too small for the snapshot
+    #[arg(long, global = true)]
+    skip_disk_check: bool,
+
+    /// Extract snapshots with entries or links pointing outside the output
+    /// directory instead of refusing them
+    #[arg(long, global = true)]
+    allow_unsafe_paths: bool,
+
+    /// Command to run; the full pipeline runs when omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Pipeline phases and standalone commands
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Download and verify the snapshot and binary without extracting them
+    Download,
+
+    /// Restore the downloaded archives into the node home without downloading anything
+    Extract {
+        /// Snapshot archive to restore (default: the configured snapshot in the cache)
+        #[arg(long)]
+        snapshot: Option<PathBuf>,
+
+        /// Binary package to install (default: the configured `binary_url` in the
+        /// cache, or the binary already installed)
+        #[arg(long)]
+        binary: Option<PathBuf>,
+    },
+
+    /// Initialize and configure the node over already restored data
+    Setup,
+
+    /// Download, extract and set up, as when no command is given
+    All,
+
+    /// Measure latency and throughput of each snapshot mirror
+    BenchMirrors {
+        /// Number of megabytes to download from each mirror
+        #[arg(long, default_value_t = DEFAULT_BENCH_SAMPLE_MB)]
+        sample_mb: u64,
+    },
+
+    /// Start the node from the output directory and restart it if it exits
+    Run {
+        /// Fail unless the node reports it has caught up with the chain within the timeout
+        #[arg(long)]
+        wait_synced: bool,
+
+        /// Seconds to wait for the node to sync
+        #[arg(long, default_value_t = DEFAULT_SYNC_TIMEOUT_SECS)]
+        sync_timeout: u64,
+
+        /// RPC endpoint to poll (default: the `[rpc] laddr` from config.toml)
+        #[arg(long)]
+        rpc_url: Option<String>,
+    },
+
+    /// Serve the downloaded snapshots to other hosts of the fleet
+    Share {
+        /// Address to listen on (default: `fleet.listen`)
+        #[arg(long)]
+        addr: Option<String>,
+    },
+
+    /// Publish a directory of snapshot archives with a JSON index over HTTP
+    Serve {
+        /// Directory holding the archives (default: `--cache-dir`, or `snapshots` in
+        /// `--tmp-dir` or the output directory)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+
+        /// Address to listen on; `:8080` listens on all interfaces
+        #[arg(long, default_value = DEFAULT_SERVE_ADDR)]
+        addr: String,
+    },
+
+    /// Check the node's data directory against the manifest written after the restore
+    Verify {
+        /// Node home to check (default: `data_dir`, or `data` in the output directory)
+        #[arg(long)]
+        home: Option<PathBuf>,
+
+        /// Manifest to check against (default: `data-manifest.sha256` in the output directory)
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+
+    /// Decode a downloaded snapshot archive to the end without extracting it,
+    /// reporting its entries and unpacked size
+    VerifyArchive {
+        /// Archive to check (default: the configured snapshot in the cache)
+        #[arg(long)]
+        snapshot: Option<PathBuf>,
+
+        /// Expected SHA256 of the archive, or the URL of a checksum file listing
+        /// it (default: `snapshot_checksum`)
+        #[arg(long)]
+        checksum: Option<String>,
+    },
+
+    /// Inspect or manage the node configuration of an existing home
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+/// Commands operating on an existing node home
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigCommand {
+    /// Show where app.toml and config.toml differ from `cosmos.app` and `cosmos.config`
+    Diff {
+        /// Node home to inspect (default: `data_dir`, or `data` in the output directory)
+        #[arg(long)]
+        home: Option<PathBuf>,
+
+        /// Fail when any setting differs, for use in audits
+        #[arg(long)]
+        exit_code: bool,
+    },
+
+    /// Write `cosmos.app` and `cosmos.config` into an existing home without restoring it
+    Apply {
+        /// Node home to reconfigure (default: `data_dir`, or `data` in the output directory)
+        #[arg(long)]
+        home: Option<PathBuf>,
+    },
 }
 
 /// Main entry point for the snapshot downloader application
@@ -37,126 +283,394 @@ struct Args {
 /// 1. Downloads a blockchain snapshot and node binary
 /// 2. Extracts them to the specified directories
 /// 3. Sets up a Cosmos node with the snapshot data
+///
+/// Failures exit with a code identifying the failed stage, see [`error::Error::exit_code`].
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
+
+/// Parses the arguments and runs the requested command
+async fn run() -> Result<()> {
     // Parse command line arguments
     let args = Args::parse();
 
     // Initialize logging
-    setup_logging(args.verbose)?;
+    setup_logging(&args)?;
 
-    // Create necessary directories
-    let (snapshots_dir, data_dir) = create_directories(&args.output_dir)?;
+    // Publishing a directory needs no configuration
+    if let Some(Command::Serve { dir, addr }) = &args.command {
+        let dir = dir
+            .clone()
+            .or_else(|| args.cache_dir.clone())
+            .unwrap_or_else(|| {
+                args.tmp_dir
+                    .as_ref()
+                    .unwrap_or(&args.output_dir)
+                    .join("snapshots")
+            });
+        return ShareServer::new(&dir, None).serve(addr).await;
+    }
 
     // Load and parse configuration
     info!("Loading configuration from: {}", args.config.display());
-    let config = Config::from_file(&args.config).context("Failed to parse configuration file")?;
-
-    // Download and extract files
-    let (snapshot_path, binary_path) = download_required_files(&config, &snapshots_dir).await?;
-    extract_files(
-        &snapshot_path,
-        &binary_path,
-        &snapshots_dir,
-        &args.output_dir,
-    )
-    .await?;
-
-    // Move snapshot to data directory
-    info!("Moving snapshot to data directory");
-    setup::move_snapshot(&snapshots_dir, &data_dir)
-        .context("Failed to move snapshot to data directory")?;
-
-    // Setup and initialize Cosmos node
-    setup_cosmos_node(&config, &args.output_dir, &data_dir)?;
-
-    info!("Setup complete! You can now start your node.");
+    let mut configs = Config::load_chains(&args.config, args.chain.as_deref(), &args.set)
+        .context("Failed to parse configuration file")?;
+    if let Some(chain_id) = &args.chain_id {
+        configs.retain(|config| config.chain_id.as_ref() == Some(chain_id));
+        if configs.is_empty() {
+            return Err(anyhow!("The configuration has no chain {}", chain_id));
+        }
+    }
+    if args.chain.is_some() && configs.len() > 1 {
+        return Err(anyhow!(
+            "--chain applies to a single chain; select one with --chain-id"
+        ));
+    }
+    if let Some(tmp_dir) = &args.tmp_dir {
+        for config in &mut configs {
+            config.tmp_dir = Some(tmp_dir.display().to_string());
+        }
+    }
+    if configs.len() > 1 && (args.cache_dir.is_some() || args.data_dir.is_some()) {
+        return Err(anyhow!(
+            "--cache-dir and --data-dir apply to a single chain; select one with --chain-id"
+        ));
+    }
+    for config in &mut configs {
+        if let Some(bin_dir) = &args.bin_dir {
+            config.bin_dir = Some(bin_dir.display().to_string());
+        }
+        if let Some(cache_dir) = &args.cache_dir {
+            config.cache_dir = Some(cache_dir.display().to_string());
+        }
+        if let Some(data_dir) = &args.data_dir {
+            config.data_dir = Some(data_dir.display().to_string());
+        }
+    }
+    if let Some(jobs) = args.jobs {
+        if jobs == 0 {
+            return Err(anyhow!("--jobs must be at least 1"));
+        }
+        for config in &mut configs {
+            config.jobs = Some(jobs);
+        }
+    }
+    if let Some(extract_threads) = args.extract_threads {
+        if extract_threads == 0 {
+            return Err(anyhow!("--extract-threads must be at least 1"));
+        }
+        for config in &mut configs {
+            config.extract_threads = Some(extract_threads);
+        }
+    }
+    if let Some(connections) = args.connections {
+        if connections == 0 {
+            return Err(anyhow!("--connections must be at least 1"));
+        }
+        for config in &mut configs {
+            config
+                .segmented
+                .get_or_insert_with(SegmentedConfig::default)
+                .max_connections = connections;
+        }
+    }
+
+    if let Some(max_rate) = args.max_rate {
+        for config in &mut configs {
+            config.max_rate = Some(max_rate);
+        }
+    }
+
+    if args.skip_disk_check {
+        for config in &mut configs {
+            config.disk_check.skip = true;
+        }
+    }
+    if args.allow_unsafe_paths {
+        for config in &mut configs {
+            config.allow_unsafe_paths = true;
+        }
+    }
+
+    // Fill in what the chain registry knows about each chain
+    for config in &mut configs {
+        if let Some(entry) = fetch_registry_entry(config).await {
+            apply_registry_defaults(config, entry)?;
+        }
+    }
+
+    notify::spawn_watchdog();
+
+    // Only the pipeline runs for several chains at once
+    let phases = args
+        .command
+        .as_ref()
+        .map_or_else(|| Some(Phases::all()), Command::phases);
+    if let Some(phases) = phases {
+        return run_chains(&args, configs, &phases).await;
+    }
+    let Some(command) = args.command.clone() else {
+        unreachable!("the pipeline is handled above")
+    };
+    if configs.len() > 1 {
+        if !args.all {
+            return Err(anyhow!(
+                "The configuration has {} chains; select one with --chain-id or pass --all",
+                configs.len()
+            ));
+        }
+        if matches!(command, Command::Run { .. } | Command::Share { .. }) {
+            return Err(anyhow!(
+                "run and share keep going until stopped; select a chain with --chain-id"
+            ));
+        }
+    }
+
+    // Chains are handled one after the other, stopping at the first failure
+    let several = configs.len() > 1;
+    for mut config in configs {
+        let dirs = Layout::for_config(&config, &args.output_dir);
+        match config.chain_id.clone() {
+            Some(chain_id) if several => {
+                println!("{}:", chain_id);
+                run_command(command.clone(), &mut config, &dirs)
+                    .await
+                    .with_context(|| format!("Chain {} failed", chain_id))?;
+            }
+            _ => run_command(command.clone(), &mut config, &dirs).await?,
+        }
+    }
     Ok(())
 }
 
-/// Sets up the logging system with appropriate verbosity
-fn setup_logging(verbose: bool) -> Result<()> {
-    let log_level = if verbose { Level::INFO } else { Level::WARN };
+/// Runs a standalone command for one chain
+async fn run_command(command: Command, config: &mut Config, dirs: &Layout) -> Result<()> {
+    match command {
+        Command::BenchMirrors { sample_mb } => bench_mirrors(config, sample_mb).await,
+        Command::Run {
+            wait_synced,
+            sync_timeout,
+            rpc_url,
+        } => {
+            let sync_wait = wait_synced.then(|| (Duration::from_secs(sync_timeout), rpc_url));
+            run_node(config, dirs, sync_wait).await
+        }
+        Command::Share { addr } => share_snapshots(config, dirs, addr).await,
+        Command::Verify { home, manifest } => verify_data(config, dirs, home, manifest),
+        Command::VerifyArchive { snapshot, checksum } => {
+            verify_archive(config, dirs, snapshot, checksum).await
+        }
+        Command::Config {
+            action: ConfigCommand::Diff { home, exit_code },
+        } => config_diff(config, dirs, home, exit_code),
+        Command::Config {
+            action: ConfigCommand::Apply { home },
+        } => config_apply(config, dirs, home),
+        Command::Serve { .. } => unreachable!("serve is handled before loading the config"),
+        Command::Download | Command::Extract { .. } | Command::Setup | Command::All => {
+            unreachable!("the pipeline is handled above")
+        }
+    }
+}
 
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::from_default_env().add_directive(log_level.into()))
-        .with_target(false)
-        .finish();
+/// Runs the pipeline, or the `--check-only` checks, for each chain in turn
+///
+/// The pipeline stops at the first chain that fails, while checks cover every
+/// chain before reporting. With a `chains` map, `--result-json` and
+/// `--metrics-file` are written per chain with the chain id before the extension.
+async fn run_chains(args: &Args, configs: Vec<Config>, phases: &Phases) -> Result<()> {
+    if args.check_reachability {
+        for config in &configs {
+            check_reachability(config).await?;
+        }
+    }
 
-    tracing::subscriber::set_global_default(subscriber)
-        .context("Failed to set tracing subscriber")?;
+    let mut outdated = 0;
+    for mut config in configs {
+        let dirs = Layout::for_config(&config, &args.output_dir);
+        let chain_id = config.chain_id.clone();
+
+        if args.check_only {
+            if let Some(chain_id) = &chain_id {
+                println!("{}:", chain_id);
+            }
+            match check_only(&mut config, &dirs).await {
+                Ok(()) => {}
+                Err(e) => match e.downcast_ref::<error::Error>() {
+                    Some(error::Error::Outdated(count)) => outdated += count,
+                    _ => return Err(e),
+                },
+            }
+            continue;
+        }
 
+        let result_json = args
+            .result_json
+            .as_deref()
+            .map(|path| chain_file(path, chain_id.as_deref()));
+        let mut metrics = RunMetrics::new();
+        let result = run_pipeline(
+            &mut config,
+            &dirs,
+            phases,
+            result_json.as_deref(),
+            &mut metrics,
+        )
+        .await;
+        if let Some(path) = &args.metrics_file {
+            let path = chain_file(path, chain_id.as_deref());
+            match metrics.write_textfile(&path, result.is_ok()) {
+                Ok(()) => info!("Wrote run metrics to {}", path.display()),
+                Err(e) => warn!("Failed to write run metrics: {:#}", e),
+            }
+        }
+        match chain_id {
+            Some(chain_id) => result.with_context(|| format!("Setup of {} failed", chain_id))?,
+            None => result?,
+        }
+    }
+
+    if outdated > 0 {
+        return Err(error::Error::Outdated(outdated).into());
+    }
     Ok(())
 }
 
-/// Creates necessary directories for downloads and data
-fn create_directories(base_dir: &Path) -> Result<(PathBuf, PathBuf)> {
-    let snapshots_dir = base_dir.join("snapshots");
-    std::fs::create_dir_all(&snapshots_dir).context("Failed to create snapshots directory")?;
-
-    let data_dir = base_dir.join("data");
-    std::fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
+/// Path of a per-run output file for one chain, e.g. `run.osmosis-1.json` for `run.json`
+fn chain_file(path: &Path, chain_id: Option<&str>) -> PathBuf {
+    let Some(chain_id) = chain_id else {
+        return path.to_path_buf();
+    };
 
-    Ok((snapshots_dir, data_dir))
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, chain_id, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, chain_id),
+    };
+    path.with_file_name(name)
 }
 
-/// Downloads the snapshot and binary files
-async fn download_required_files(
-    config: &Config,
-    snapshots_dir: &Path,
-) -> Result<(PathBuf, PathBuf)> {
-    let downloader = Downloader::new();
-
-    // Download snapshot
-    info!("Downloading snapshot from: {}", config.snapshot_url);
-    let snapshot_path = downloader
-        .download(&config.snapshot_url, snapshots_dir)
-        .await
-        .context("Failed to download snapshot")?;
-
-    // Download binary
-    info!("Downloading binary from: {}", config.binary_url);
-    let binary_path = downloader
-        .download(&config.binary_url, snapshots_dir)
-        .await
-        .context("Failed to download binary")?;
-
-    Ok((snapshot_path, binary_path))
+impl Command {
+    /// Pipeline phases the command runs, if it is a pipeline command
+    fn phases(&self) -> Option<Phases> {
+        match self {
+            Command::Download => Some(Phases {
+                download: true,
+                ..Default::default()
+            }),
+            Command::Extract { snapshot, binary } => Some(Phases {
+                extract: true,
+                snapshot: snapshot.clone(),
+                binary: binary.clone(),
+                ..Default::default()
+            }),
+            Command::Setup => Some(Phases {
+                setup: true,
+                ..Default::default()
+            }),
+            Command::All => Some(Phases::all()),
+            _ => None,
+        }
+    }
 }
 
-/// Extracts the snapshot and binary files
-async fn extract_files(
-    snapshot_path: &Path,
-    binary_path: &Path,
-    snapshots_dir: &Path,
-    output_dir: &Path,
-) -> Result<()> {
-    let extractor = Extractor::new();
-
-    // Extract binary
-    info!("Extracting binary package");
-    let binary_extract_path = output_dir.join("bin_extract");
-    std::fs::create_dir_all(&binary_extract_path)?;
-    extractor
-        .extract(binary_path, &binary_extract_path)
-        .context("Failed to extract binary package")?;
-
-    // Extract snapshot
-    info!("Extracting blockchain snapshot");
-    extractor
-        .extract(snapshot_path, snapshots_dir)
-        .context("Failed to extract snapshot")?;
+/// Sets up the logging system with appropriate verbosity
+///
+/// The console, or journald in its place, honours `--verbose` and
+/// `RUST_LOG`; the optional log file has its own level so unattended runs
+/// keep a full record either way.
+fn setup_logging(args: &Args) -> Result<()> {
+    let log_level = if args.verbose {
+        Level::INFO
+    } else {
+        Level::WARN
+    };
+    let filter = EnvFilter::from_default_env().add_directive(log_level.into());
+
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+    if args.journald {
+        layers.push(journald_layer()?.with_filter(filter).boxed());
+    } else {
+        layers.push(fmt::layer().with_target(false).with_filter(filter).boxed());
+    }
+
+    if let Some(path) = &args.log_file {
+        let appender = log_file_appender(path, args.log_rotation, args.log_keep)?;
+        layers.push(
+            fmt::layer()
+                .with_target(false)
+                .with_ansi(false)
+                .with_writer(appender)
+                .with_filter(args.log_file_level)
+                .boxed(),
+        );
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .try_init()
+        .context("Failed to set tracing subscriber")?;
 
     Ok(())
 }
 
-/// Sets up the Cosmos node with the downloaded data
-fn setup_cosmos_node(config: &Config, output_dir: &Path, data_dir: &Path) -> Result<()> {
-    let binary_extract_path = output_dir.join("bin_extract");
-    let cosmos_setup = CosmosSetup::new(&config.cosmos, &binary_extract_path, data_dir);
+/// Connects to journald, mapping levels to syslog priorities
+///
+/// Routine progress logged at INFO lands as `info` rather than the crate's
+/// default `notice`, so `journalctl -p notice` shows only what needs attention.
+#[cfg(target_os = "linux")]
+fn journald_layer() -> Result<tracing_journald::Layer> {
+    use tracing_journald::{Priority, PriorityMappings};
 
-    info!("Initializing Cosmos node");
-    cosmos_setup.init().context("Failed to initialize node")?;
+    Ok(tracing_journald::layer()
+        .context("Failed to connect to journald")?
+        .with_syslog_identifier(env!("CARGO_PKG_NAME").to_string())
+        .with_priority_mappings(PriorityMappings {
+            info: Priority::Informational,
+            debug: Priority::Debug,
+            ..PriorityMappings::new()
+        }))
+}
 
-    Ok(())
+/// journald only exists on Linux
+#[cfg(not(target_os = "linux"))]
+fn journald_layer() -> Result<fmt::Layer<Registry>> {
+    Err(anyhow!("--journald is only supported on Linux"))
+}
+
+/// Opens the rotating appender behind `--log-file`
+fn log_file_appender(
+    path: &Path,
+    rotation: LogRotation,
+    keep: usize,
+) -> Result<RollingFileAppender> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Log file path has no file name")?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    RollingFileAppender::builder()
+        .rotation(match rotation {
+            LogRotation::Never => Rotation::NEVER,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+        })
+        .filename_prefix(file_name)
+        .max_log_files(keep.max(1))
+        .build(dir)
+        .with_context(|| format!("Failed to open log file {}", path.display()))
 }
@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use tracing::{info, instrument};
+
+use crate::checksum::sha256_file;
+
+/// Name of the manifest written to the output directory
+pub const MANIFEST_FILE: &str = "data-manifest.sha256";
+
+/// Upper bound on hashing workers; beyond this the disk, not the CPU, is the limit
+const MAX_HASH_THREADS: usize = 8;
+
+/// Files under `data` that a node rewrites on its own and are left out
+const VOLATILE_FILES: &[&str] = &["priv_validator_state.json"];
+
+/// Files whose contents differ from a data directory manifest
+#[derive(Debug, Default)]
+pub struct ManifestDiff {
+    /// Files whose digest changed
+    pub changed: Vec<String>,
+
+    /// Files listed in the manifest that are gone
+    pub missing: Vec<String>,
+
+    /// Files not listed in the manifest
+    pub added: Vec<String>,
+}
+
+impl ManifestDiff {
+    /// Whether the data directory matches the manifest
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty() && self.added.is_empty()
+    }
+}
+
+/// Hashes the node's `data` directory and writes the digests to `manifest_path`
+///
+/// The manifest uses the `sha256sum` format with paths relative to the node
+/// home, so `sha256sum -c` run from the home checks it as well. `jobs` sets
+/// the number of hashing threads instead of the number of CPUs.
+#[instrument(skip(home, manifest_path, jobs), fields(home = %home.display()))]
+pub fn write(home: &Path, manifest_path: &Path, jobs: Option<usize>) -> Result<()> {
+    let digests = hash_data_dir(home, jobs)?;
+
+    let mut contents = String::new();
+    for (path, digest) in &digests {
+        contents.push_str(&format!("{}  {}\n", digest, path));
+    }
+    fs::write(manifest_path, contents)
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+
+    info!(
+        "Wrote digests of {} files to {}",
+        digests.len(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+/// Compares the node's `data` directory against a manifest written by [`write()`]
+#[instrument(skip(home, manifest_path, jobs), fields(home = %home.display()))]
+pub fn verify(home: &Path, manifest_path: &Path, jobs: Option<usize>) -> Result<ManifestDiff> {
+    let contents = fs::read_to_string(manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let mut expected = BTreeMap::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let (digest, path) = line
+            .split_once("  ")
+            .with_context(|| format!("Malformed manifest line: {}", line))?;
+        expected.insert(path.to_string(), digest.to_string());
+    }
+
+    let mut actual = hash_data_dir(home, jobs)?;
+    let mut diff = ManifestDiff::default();
+    for (path, digest) in expected {
+        match actual.remove(&path) {
+            Some(found) if found != digest => diff.changed.push(path),
+            Some(_) => {}
+            None => diff.missing.push(path),
+        }
+    }
+    diff.added = actual.into_keys().collect();
+
+    Ok(diff)
+}
+
+/// Computes the digest of every file under the home's `data` directory in parallel
+///
+/// Returns the digests keyed by path relative to the home, with `/` separators.
+fn hash_data_dir(home: &Path, jobs: Option<usize>) -> Result<BTreeMap<String, String>> {
+    let mut files = Vec::new();
+    list_files(&home.join("data"), &mut files)?;
+    let files: Vec<(PathBuf, u64)> = files
+        .into_iter()
+        .map(|path| {
+            let size = fs::metadata(&path).map_or(0, |m| m.len());
+            (path, size)
+        })
+        .collect();
+
+    let total_bytes: u64 = files.iter().map(|(_, size)| size).sum();
+    let threads = jobs
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .min(MAX_HASH_THREADS)
+        })
+        .min(files.len().max(1));
+    info!(
+        "Hashing {} files ({:.2} GB) with {} threads",
+        files.len(),
+        total_bytes as f64 / 1e9,
+        threads
+    );
+
+    let progress_bar = ProgressBar::new(total_bytes);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+            .progress_chars("#>-"),
+    );
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let digests = Mutex::new(BTreeMap::new());
+    let error = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some((path, size)) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let result = relative_name(home, path).and_then(|name| {
+                        sha256_file(path)
+                            .with_context(|| format!("Failed to hash {}", path.display()))
+                            .map(|digest| (name, digest))
+                    });
+                    match result {
+                        Ok((name, digest)) => {
+                            digests.lock().unwrap().insert(name, digest);
+                            progress_bar.inc(*size);
+                        }
+                        Err(e) => {
+                            failed.store(true, Ordering::Relaxed);
+                            error.lock().unwrap().get_or_insert(e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(e) = error.into_inner().unwrap() {
+        progress_bar.abandon();
+        return Err(e);
+    }
+    progress_bar.finish();
+
+    Ok(digests.into_inner().unwrap())
+}
+
+/// Collects the regular files below `dir`, leaving out volatile ones
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+
+        if file_type.is_dir() {
+            list_files(&path, files)?;
+        } else if file_type.is_file()
+            && !VOLATILE_FILES.contains(&entry.file_name().to_string_lossy().as_ref())
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Path of a file relative to the home, as written to the manifest
+fn relative_name(home: &Path, path: &Path) -> Result<String> {
+    let relative = path
+        .strip_prefix(home)
+        .map_err(|_| anyhow!("{} is outside {}", path.display(), home.display()))?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Ok(parts.join("/"))
+}
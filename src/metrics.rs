@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::report::unix_seconds;
+
+/// Prefix of every exported metric
+const PREFIX: &str = "snapshot_downloader";
+
+/// Bytes received over HTTP by all downloads of this process
+static BYTES_DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+
+/// Counts bytes received by a download
+pub fn add_downloaded(bytes: u64) {
+    BYTES_DOWNLOADED.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Timings and results of a pipeline run, exported for Prometheus
+#[derive(Debug, Default)]
+pub struct RunMetrics {
+    /// Finished stages and how long each took, in order
+    stages: Vec<(&'static str, Duration)>,
+
+    /// The stage running now and when it started
+    current: Option<(&'static str, Instant)>,
+
+    /// Block height of the restored snapshot, when known
+    pub snapshot_height: Option<u64>,
+}
+
+impl RunMetrics {
+    /// Creates an empty set of metrics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ends the running stage, if any, and starts timing `stage`
+    pub fn stage(&mut self, stage: &'static str) {
+        self.end_stage();
+        self.current = Some((stage, Instant::now()));
+    }
+
+    /// Ends the running stage
+    fn end_stage(&mut self) {
+        if let Some((stage, started)) = self.current.take() {
+            self.stages.push((stage, started.elapsed()));
+        }
+    }
+
+    /// Writes the metrics in the node_exporter textfile format
+    ///
+    /// The last success time and the download counter carry over from the
+    /// previous file, so they stay meaningful across failed runs. The file is
+    /// replaced atomically so the collector never reads a partial one.
+    pub fn write_textfile(&mut self, path: &Path, success: bool) -> Result<()> {
+        self.end_stage();
+        let previous = read_previous(path);
+        let now = unix_seconds(SystemTime::now());
+
+        let last_success = if success {
+            Some(now as f64)
+        } else {
+            previous.get("last_success_timestamp").copied()
+        };
+        let bytes_total = previous
+            .get("bytes_downloaded_total")
+            .copied()
+            .unwrap_or_default()
+            + BYTES_DOWNLOADED.load(Ordering::Relaxed) as f64;
+
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "last_run_timestamp",
+            "gauge",
+            "Unix time the last run finished",
+            now as f64,
+        );
+        metric(
+            &mut out,
+            "last_run_success",
+            "gauge",
+            "Whether the last run succeeded",
+            if success { 1.0 } else { 0.0 },
+        );
+        if let Some(last_success) = last_success {
+            metric(
+                &mut out,
+                "last_success_timestamp",
+                "gauge",
+                "Unix time of the last successful run",
+                last_success,
+            );
+        }
+        if let Some(height) = self.snapshot_height {
+            metric(
+                &mut out,
+                "snapshot_height",
+                "gauge",
+                "Block height of the snapshot restored by the last run",
+                height as f64,
+            );
+        }
+        metric(
+            &mut out,
+            "bytes_downloaded_total",
+            "counter",
+            "Bytes downloaded over HTTP across runs",
+            bytes_total,
+        );
+
+        let name = format!("{}_stage_duration_seconds", PREFIX);
+        out.push_str(&format!(
+            "# HELP {} Time the last run spent in each stage\n# TYPE {} gauge\n",
+            name, name
+        ));
+        for (stage, duration) in &self.stages {
+            out.push_str(&format!(
+                "{}{{stage=\"{}\"}} {:.3}\n",
+                name,
+                stage,
+                duration.as_secs_f64()
+            ));
+        }
+
+        let mut temp = path.as_os_str().to_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, out).with_context(|| format!("Failed to write {}", path.display()))?;
+        fs::rename(&temp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Appends one unlabeled metric with its help and type lines
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    out.push_str(&format!("# HELP {}_{} {}\n", PREFIX, name, help));
+    out.push_str(&format!("# TYPE {}_{} {}\n", PREFIX, name, kind));
+    out.push_str(&format!("{}_{} {}\n", PREFIX, name, value));
+}
+
+/// Reads the unlabeled metrics of a previous textfile, keyed without the prefix
+fn read_previous(path: &Path) -> HashMap<String, f64> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            let name = name.strip_prefix(PREFIX)?.strip_prefix('_')?;
+            Some((name.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
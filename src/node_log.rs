@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::{LogRotation, NodeLogConfig};
+
+/// Name of the file the node's output is currently written to
+const LOG_FILE_NAME: &str = "node.log";
+
+/// Writes node output to a log file, rotating it by size and age
+///
+/// Rotated files are renamed `node.log.1` (newest) to `node.log.<keep>`
+/// (oldest); anything beyond `keep` is deleted.
+pub struct RotatingLog {
+    /// Directory holding the current and rotated log files
+    dir: PathBuf,
+
+    /// Rotation settings
+    policy: NodeLogConfig,
+
+    /// Currently open log file
+    file: File,
+
+    /// Bytes written to the current file
+    written: u64,
+
+    /// When the current file was started
+    opened_at: Instant,
+}
+
+impl RotatingLog {
+    /// Opens (or continues) `node.log` in `dir`, creating the directory if needed
+    pub fn open(dir: &Path, policy: &NodeLogConfig) -> Result<Self> {
+        fs::create_dir_all(dir).context("Failed to create node log directory")?;
+        let (file, written) = open_current(dir)?;
+
+        Ok(RotatingLog {
+            dir: dir.to_path_buf(),
+            policy: policy.clone(),
+            file,
+            written,
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Path of the file currently being written
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(LOG_FILE_NAME)
+    }
+
+    /// Appends a line of node output, rotating first if the file is due
+    ///
+    /// Write failures are reported but never stop the node.
+    pub fn write_line(&mut self, line: &str) {
+        if self.is_due() {
+            if let Err(e) = self.rotate() {
+                warn!("Failed to rotate node log: {:#}", e);
+            }
+        }
+
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => self.written += line.len() as u64 + 1,
+            Err(e) => warn!("Failed to write node log: {}", e),
+        }
+    }
+
+    /// Whether the current file has hit its size or age limit
+    fn is_due(&self) -> bool {
+        let too_large = self
+            .policy
+            .max_size
+            .is_some_and(|max_size| self.written >= max_size);
+        let too_old = rotation_interval(self.policy.rotate_every)
+            .is_some_and(|interval| self.opened_at.elapsed() >= interval);
+
+        self.written > 0 && (too_large || too_old)
+    }
+
+    /// Shifts rotated files up by one, moves the current file to `.1` and reopens
+    fn rotate(&mut self) -> Result<()> {
+        let current = self.path();
+        let numbered = |n: usize| self.dir.join(format!("{}.{}", LOG_FILE_NAME, n));
+
+        if self.policy.keep == 0 {
+            fs::remove_file(&current).context("Failed to remove node log")?;
+        } else {
+            let oldest = numbered(self.policy.keep);
+            if oldest.exists() {
+                fs::remove_file(&oldest).context("Failed to remove old node log")?;
+            }
+            for n in (1..self.policy.keep).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    fs::rename(&from, numbered(n + 1)).context("Failed to rotate node log")?;
+                }
+            }
+            fs::rename(&current, numbered(1)).context("Failed to rotate node log")?;
+        }
+
+        let (file, written) = open_current(&self.dir)?;
+        self.file = file;
+        self.written = written;
+        self.opened_at = Instant::now();
+
+        Ok(())
+    }
+}
+
+/// Opens the current log file for appending and returns its existing length
+fn open_current(dir: &Path) -> Result<(File, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(LOG_FILE_NAME))
+        .context("Failed to open node log")?;
+    let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+    Ok((file, written))
+}
+
+/// Converts a rotation setting into the maximum age of a log file
+fn rotation_interval(rotation: LogRotation) -> Option<Duration> {
+    match rotation {
+        LogRotation::Never => None,
+        LogRotation::Hourly => Some(Duration::from_secs(3600)),
+        LogRotation::Daily => Some(Duration::from_secs(86_400)),
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Socket of the service manager, opened on first use
+static NOTIFIER: OnceLock<Option<Notifier>> = OnceLock::new();
+
+/// Connection to systemd's notification socket
+struct Notifier {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+
+    #[cfg(unix)]
+    address: std::os::unix::net::SocketAddr,
+}
+
+impl Notifier {
+    /// Connects to the socket named by `NOTIFY_SOCKET`, if the service manager set one
+    #[cfg(unix)]
+    fn from_env() -> Option<Self> {
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+        let address = match path.strip_prefix('@') {
+            Some(name) => abstract_address(name)?,
+            None => SocketAddr::from_pathname(&path).ok()?,
+        };
+        let socket = UnixDatagram::unbound().ok()?;
+        debug!("Sending service notifications to {}", path);
+
+        Some(Notifier { socket, address })
+    }
+
+    /// Service notifications are a systemd feature
+    #[cfg(not(unix))]
+    fn from_env() -> Option<Self> {
+        None
+    }
+
+    /// Sends a notification, ignoring failures as sd_notify does
+    #[cfg(unix)]
+    fn send(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            debug!("Failed to notify service manager: {}", e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _state: &str) {}
+}
+
+/// Address of a socket in the Linux abstract namespace
+#[cfg(target_os = "linux")]
+fn abstract_address(name: &str) -> Option<std::os::unix::net::SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+
+    std::os::unix::net::SocketAddr::from_abstract_name(name).ok()
+}
+
+/// Abstract sockets only exist on Linux
+#[cfg(all(unix, not(target_os = "linux")))]
+fn abstract_address(_name: &str) -> Option<std::os::unix::net::SocketAddr> {
+    None
+}
+
+/// Sends a notification when running as a systemd service with `Type=notify`
+fn notify(state: &str) {
+    if let Some(notifier) = NOTIFIER.get_or_init(Notifier::from_env) {
+        notifier.send(state);
+    }
+}
+
+/// Tells the service manager that startup has finished
+pub fn ready() {
+    notify("READY=1");
+}
+
+/// Tells the service manager that the service is shutting down
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// Updates the status line shown by `systemctl status`
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
+
+/// Keeps the service manager's watchdog fed while the process is alive
+///
+/// Does nothing unless systemd enabled the watchdog for this process with
+/// `WatchdogSec=`; pings are then sent at half the configured interval.
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+
+    info!(
+        "Feeding the systemd watchdog every {}s",
+        (interval / 2).as_secs_f64()
+    );
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval / 2);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+/// Watchdog interval set for this process, if any
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
@@ -0,0 +1,1993 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::build::SourceBuilder;
+use crate::checksum::ChecksumVerifier;
+use crate::compact::Compactor;
+use crate::compat;
+use crate::config::{
+    self, Config, CosmovisorConfig, DownloadUrl, Pruning, RequestConfig, RetryStage, S3Config,
+    SystemdConfig, UpgradeConfig,
+};
+use crate::cosmovisor::Cosmovisor;
+use crate::db_backend::{self, DbBackend};
+use crate::disk;
+use crate::downloader::{self, Downloader};
+use crate::error;
+use crate::extractor::{ArchiveFormat, Extractor};
+use crate::image::ImageBinary;
+use crate::layout::Layout;
+use crate::manifest;
+use crate::metrics::RunMetrics;
+use crate::notify;
+use crate::priority;
+use crate::provider::{IndexEntry, ProviderSnapshot, SnapshotProvider};
+use crate::prune::Pruner;
+use crate::registry::{ChainEntry, ChainRegistry};
+use crate::release::GithubReleases;
+use crate::report::{self, RunReport, SnapshotSource};
+use crate::retention;
+use crate::retry::RetryPolicy;
+use crate::service::ServiceUnit;
+use crate::setup::{self, CosmosSetup};
+use crate::share;
+use crate::signature::SignatureVerifier;
+use crate::snapshot_info::{FreshnessChecker, SnapshotInfo};
+use crate::state::{RunState, Step};
+use crate::torrent::{self, TorrentDownload, TorrentDownloader};
+
+/// Free space required for an archive variant, as a multiple of its archive size
+///
+/// Covers the archive itself plus its extraction at a typical 2x compression ratio.
+const ARCHIVE_SPACE_FACTOR: u64 = 3;
+
+/// Size of the range requested from each mirror when benchmarking
+pub const DEFAULT_BENCH_SAMPLE_MB: u64 = 4;
+
+/// Directory beside the node home holding the validator's files during a restore
+const VALIDATOR_BACKUP_DIR: &str = ".validator-backup";
+
+/// Phases of the pipeline a run goes through
+#[derive(Debug, Clone, Default)]
+pub struct Phases {
+    /// Download and verify the snapshot and binary
+    pub download: bool,
+
+    /// Restore the archives into the node home and install the binary
+    pub extract: bool,
+
+    /// Initialize and configure the node over the restored data
+    pub setup: bool,
+
+    /// Snapshot archive to extract instead of the cached download
+    pub snapshot: Option<PathBuf>,
+
+    /// Binary package to extract instead of the cached download
+    pub binary: Option<PathBuf>,
+}
+
+impl Phases {
+    /// Every phase, as run when no command is given
+    pub fn all() -> Self {
+        Phases {
+            download: true,
+            extract: true,
+            setup: true,
+            ..Default::default()
+        }
+    }
+
+    /// Whether this is a full run
+    pub fn is_all(&self) -> bool {
+        self.download && self.extract && self.setup
+    }
+}
+
+/// Archives a run restores from, with what is known about them
+struct Archives {
+    snapshot_path: PathBuf,
+    snapshot_format: Option<ArchiveFormat>,
+    snapshot_info: SnapshotInfo,
+    snapshot_source: SnapshotSource,
+
+    /// Incremental archives layered on top of the snapshot, oldest first
+    increment_paths: Vec<PathBuf>,
+    increment_formats: Vec<Option<ArchiveFormat>>,
+
+    /// Binary package, absent when extraction reuses the installed binary
+    binary_path: Option<PathBuf>,
+    binary_format: Option<ArchiveFormat>,
+    binary_source: String,
+
+    /// The snapshot's torrent, when it was downloaded from a swarm
+    torrent_download: Option<TorrentDownload>,
+
+    /// Whether the snapshot is streamed into place during extraction, leaving
+    /// `snapshot_path` unused
+    streamed: bool,
+
+    /// Checksum the provider published for the snapshot
+    snapshot_checksum: Option<String>,
+}
+
+/// Runs the given phases of the download, extract and setup pipeline
+///
+/// Without the download phase, extraction restores the archives an earlier
+/// download left in the cache, and setup configures the node home as it is.
+pub async fn run_pipeline(
+    config: &mut Config,
+    dirs: &Layout,
+    phases: &Phases,
+    result_json: Option<&Path>,
+    metrics: &mut RunMetrics,
+) -> Result<()> {
+    enter_stage(metrics, "resolve");
+    if let Some(priority) = &config.priority {
+        priority::apply(priority).context("Failed to apply priority settings")?;
+    }
+
+    // Create necessary directories
+    let (snapshots_dir, data_dir) = create_directories(dirs)?;
+    let mut state = RunState::load(&dirs.root);
+
+    let archives = if phases.download {
+        Some(download_archives(config, dirs, phases, metrics, &mut state).await?)
+    } else if phases.extract {
+        let archives = cached_archives(config, dirs, phases, &state)?;
+        bind_state(
+            &mut state,
+            &archives.snapshot_path,
+            archives.binary_path.as_deref(),
+            &archives.increment_paths,
+        );
+        verify_cached_binary(config, &archives).await?;
+        if !config.disk_check.skip {
+            check_extract_space(config, dirs, &archives)?;
+        }
+        Some(archives)
+    } else {
+        None
+    };
+    let height = archives.as_ref().and_then(|a| a.snapshot_info.height);
+    metrics.snapshot_height = height;
+
+    let schedule = compat::upgrade_schedule(&config.upgrades, config.registry.as_ref());
+
+    let installed_binary = match &archives {
+        Some(archives) if phases.extract => {
+            enter_stage(metrics, "extract");
+            extract_archives(config, dirs, archives, &schedule, &mut state).await?
+        }
+        _ => dirs.bin.join(&config.cosmos.bin),
+    };
+
+    if phases.setup {
+        setup_node(
+            config,
+            dirs,
+            &installed_binary,
+            &schedule,
+            height,
+            metrics,
+            &mut state,
+        )
+        .await?;
+    }
+
+    let Some(archives) = archives else {
+        notify::status("Setup complete");
+        notify::ready();
+        info!("Setup complete! You can now start your node.");
+        return Ok(());
+    };
+
+    // Trim the cache now that the run has succeeded
+    if let Some(policy) = &config.retention {
+        let mut protected = vec![archives.snapshot_path.clone()];
+        protected.extend(archives.binary_path.iter().cloned());
+        protected.extend(archives.increment_paths.iter().cloned());
+        if let Some(download) = &archives.torrent_download {
+            protected.push(download.torrent_file.clone());
+        }
+        if let Err(e) = retention::apply_retention(&snapshots_dir, policy, &protected) {
+            warn!("Failed to apply snapshot retention policy: {:#}", e);
+        }
+    }
+
+    // The summary describes a complete restore
+    if let Some(path) = result_json.filter(|_| phases.is_all()) {
+        let mut report = RunReport {
+            snapshot_url: config.snapshot_url.first().cloned().unwrap_or_default(),
+            snapshot_source: archives.snapshot_source,
+            snapshot_file: file_name(&archives.snapshot_path),
+            snapshot_height: height,
+            snapshot_created: archives.snapshot_info.created.map(report::unix_seconds),
+            binary_source: archives.binary_source.clone(),
+            data_dir: data_dir.display().to_string(),
+            ..Default::default()
+        };
+        report.write(path)?;
+        info!("Wrote run summary to {}", path.display());
+    }
+
+    // Give the snapshot back to the swarm it came from
+    if let Some(download) = &archives.torrent_download {
+        let torrent = TorrentDownloader::new(&config.torrent);
+        if torrent.seeding_enabled() {
+            match torrent.start_seeding(download) {
+                Ok(pid) => info!(
+                    "Seeding {} in the background (pid {})",
+                    download.path.display(),
+                    pid
+                ),
+                Err(e) => warn!("Failed to start seeding the snapshot: {:#}", e),
+            }
+        }
+    }
+
+    let done = match (phases.setup, phases.extract) {
+        (true, _) => "Setup",
+        (false, true) => "Extraction",
+        (false, false) => "Download",
+    };
+    notify::status(&format!("{} complete", done));
+    notify::ready();
+    match (height, phases.setup) {
+        (Some(height), true) => info!(
+            "Setup complete! Restored snapshot at height {}. You can now start your node.",
+            height
+        ),
+        (None, true) => info!("Setup complete! You can now start your node."),
+        (Some(height), false) => info!("{} complete! Snapshot height: {}", done, height),
+        (None, false) => info!("{} complete!", done),
+    }
+    Ok(())
+}
+
+/// Resolves, downloads and verifies the snapshot, its increments and the binary
+///
+/// With `stream`, the snapshot is left to be streamed during extraction.
+async fn download_archives(
+    config: &mut Config,
+    dirs: &Layout,
+    phases: &Phases,
+    metrics: &mut RunMetrics,
+    state: &mut RunState,
+) -> Result<Archives> {
+    let snapshots_dir = &dirs.snapshots;
+    // Streaming only pays off when the snapshot is extracted in the same run
+    let stream = config.stream_extract && phases.extract;
+
+    // Turn the provider's index into a concrete snapshot URL
+    let provider_snapshot = resolve_provider_snapshot(config).await?;
+    if let Some(resolved) = &provider_snapshot {
+        check_archive_space(config, resolved, &dirs.scratch)?;
+    }
+
+    // Put the fastest mirror first when requested
+    if config.bench_mirrors && config.snapshot_url.len() > 1 {
+        info!(
+            "Benchmarking {} snapshot mirrors",
+            config.snapshot_url.len()
+        );
+        let results = Downloader::new()
+            .with_s3(config.s3.clone())
+            .benchmark_mirrors(&config.snapshot_url, DEFAULT_BENCH_SAMPLE_MB * 1_048_576)
+            .await;
+        if results.is_empty() {
+            warn!("All mirrors failed the benchmark, keeping configured order");
+        } else {
+            config.snapshot_url = downloader::rank_mirrors(&config.snapshot_url, &results);
+        }
+    }
+
+    // Incremental archives layered on top of the snapshot, oldest first
+    let increments: Vec<IndexEntry> = match &provider_snapshot {
+        Some(resolved) => resolved.increments.clone(),
+        None => config
+            .snapshot_increments
+            .iter()
+            .map(|increment| IndexEntry {
+                url: increment.url().to_string(),
+                ..Default::default()
+            })
+            .collect(),
+    };
+
+    // Refuse or flag stale snapshots before spending hours downloading them
+    let layer_name = match increments.last() {
+        Some(increment) => download_name(state, &increment.url, None),
+        None => snapshot_name(config, state),
+    }
+    .unwrap_or_default();
+    let mut snapshot_info = SnapshotInfo::from_file_name(&layer_name);
+    if let Some(height) = provider_snapshot.as_ref().and_then(|p| p.height()) {
+        snapshot_info.height = Some(height);
+    }
+    if let Some(freshness) = &config.freshness {
+        FreshnessChecker::new()
+            .with_retry(RetryPolicy::for_stage(&config.retries, RetryStage::Rpc))
+            .check(&config.snapshot_url[0], &mut snapshot_info, freshness)
+            .await
+            .context("Snapshot freshness check failed")?;
+    }
+
+    // Resolve where the binary comes from
+    let binary = resolve_binary(config, &dirs.scratch).await?;
+
+    if !config.disk_check.skip {
+        check_download_space(
+            config,
+            dirs,
+            provider_snapshot.as_ref(),
+            &increments,
+            stream,
+            phases.extract,
+        )
+        .await?;
+    }
+
+    enter_stage(metrics, "download");
+    let (snapshot_path, binary_path, torrent_download) =
+        download_required_files(config, &binary, snapshots_dir, stream, state).await?;
+    let increment_paths = download_increments(config, &increments, snapshots_dir).await?;
+    if !stream {
+        for url in &config.snapshot_url {
+            state.record_download(url, &snapshot_path);
+        }
+    }
+    for (increment, path) in increments.iter().zip(&increment_paths) {
+        state.record_download(&increment.url, path);
+    }
+    if let ResolvedBinary::Remote { url, .. } = &binary {
+        state.record_download(url, &binary_path);
+    }
+    bind_state(state, &snapshot_path, Some(&binary_path), &increment_paths);
+    state.complete(Step::Downloaded)?;
+    let snapshot_checksum = provider_snapshot
+        .as_ref()
+        .and_then(|p| p.snapshot.sha256.as_deref());
+    enter_stage(metrics, "verify");
+    if state.is_done(Step::Verified) {
+        skip_step(Step::Verified);
+    } else {
+        verify_downloads(
+            config,
+            (!stream).then_some(snapshot_path.as_path()),
+            snapshot_checksum,
+            &increments,
+            &increment_paths,
+            &binary,
+            &binary_path,
+        )
+        .await?;
+        state.complete(Step::Verified)?;
+    }
+
+    // The server-provided file name may carry a height the URL didn't
+    if snapshot_info.height.is_none() {
+        let top_layer = increment_paths.last().unwrap_or(&snapshot_path);
+        snapshot_info.height = SnapshotInfo::from_file_name(&file_name(top_layer)).height;
+    }
+    match snapshot_info.height {
+        Some(height) => info!("Snapshot height: {}", height),
+        None => info!(
+            "Snapshot height could not be determined from {}",
+            file_name(&snapshot_path)
+        ),
+    }
+    // Pick decoders from config overrides, provider metadata, file names or Content-Type
+    let snapshot_format = archive_format(
+        config.snapshot_format,
+        provider_snapshot
+            .as_ref()
+            .and_then(|p| p.snapshot.format.as_deref()),
+        &config.snapshot_url[0],
+        &snapshot_path,
+        config.snapshot_request.as_ref(),
+        config.s3.as_ref(),
+    )
+    .await;
+    let mut increment_formats = Vec::with_capacity(increments.len());
+    for (increment, path) in increments.iter().zip(&increment_paths) {
+        increment_formats.push(
+            archive_format(
+                None,
+                increment.format.as_deref(),
+                &increment.url,
+                path,
+                config.snapshot_request.as_ref(),
+                config.s3.as_ref(),
+            )
+            .await,
+        );
+    }
+    let binary_format = match &binary {
+        ResolvedBinary::Remote { url, .. } => {
+            archive_format(
+                config.binary_format,
+                None,
+                url,
+                &binary_path,
+                config.binary_request.as_ref(),
+                config.s3.as_ref(),
+            )
+            .await
+        }
+        ResolvedBinary::Local(_) => local_format(config.binary_format, &binary_path),
+    };
+
+    let snapshot_source = match (&provider_snapshot, &config.snapshot_provider) {
+        (Some(_), _) => SnapshotSource::Provider,
+        (None, Some(_)) => SnapshotSource::Fallback,
+        (None, None) => SnapshotSource::SnapshotUrl,
+    };
+    Ok(Archives {
+        snapshot_path,
+        snapshot_format,
+        snapshot_info,
+        snapshot_source,
+        increment_paths,
+        increment_formats,
+        binary_path: Some(binary_path),
+        binary_format,
+        binary_source: binary.describe(),
+        torrent_download,
+        streamed: stream,
+        snapshot_checksum: snapshot_checksum.map(str::to_string),
+    })
+}
+
+/// Finds the archives an earlier download left in the cache, without any network access
+///
+/// The cached archives are found under the names the last download wrote,
+/// or derived from the configuration the way downloads name them, unless
+/// `--snapshot` or `--binary` point at the archives directly. A binary
+/// package that can't be found leaves the installed binary in place.
+fn cached_archives(
+    config: &Config,
+    dirs: &Layout,
+    phases: &Phases,
+    state: &RunState,
+) -> Result<Archives> {
+    let cached = |what: &str, name: &str| -> Result<PathBuf> {
+        let path = dirs.snapshots.join(name);
+        if !path.is_file() {
+            return Err(anyhow!(
+                "{} {} is not in {}; download it first or pass the archive's path",
+                what,
+                name,
+                dirs.snapshots.display()
+            ));
+        }
+        Ok(path)
+    };
+
+    let snapshot_path = match &phases.snapshot {
+        Some(path) => path.clone(),
+        None if config.snapshot_url.is_empty() => {
+            return Err(anyhow!(
+                "The snapshot comes from snapshot_provider; pass the archive with --snapshot"
+            ))
+        }
+        None => cached("Snapshot", &snapshot_name(config, state)?)?,
+    };
+
+    let mut increment_paths = Vec::with_capacity(config.snapshot_increments.len());
+    for increment in &config.snapshot_increments {
+        let name = download_name(state, increment.url(), increment.save_as())?;
+        increment_paths.push(cached("Incremental snapshot", &name)?);
+    }
+    let increment_formats = increment_paths
+        .iter()
+        .map(|path| local_format(None, path))
+        .collect();
+
+    let binary_path = match &phases.binary {
+        Some(path) => Some(path.clone()),
+        None => config
+            .binary_url
+            .as_ref()
+            .and_then(|binary_url| binary_url.for_host().ok())
+            .and_then(|url| download_name(state, url, config.binary_filename.as_deref()).ok())
+            .map(|name| dirs.snapshots.join(name))
+            .filter(|path| path.is_file()),
+    };
+
+    let top_layer = increment_paths.last().unwrap_or(&snapshot_path);
+    let snapshot_info = SnapshotInfo::from_file_name(&file_name(top_layer));
+    match snapshot_info.height {
+        Some(height) => info!("Snapshot height: {}", height),
+        None => info!(
+            "Snapshot height could not be determined from {}",
+            file_name(&snapshot_path)
+        ),
+    }
+
+    Ok(Archives {
+        snapshot_format: local_format(config.snapshot_format, &snapshot_path),
+        snapshot_path,
+        snapshot_info,
+        snapshot_source: SnapshotSource::SnapshotUrl,
+        increment_paths,
+        increment_formats,
+        binary_format: binary_path
+            .as_deref()
+            .and_then(|path| local_format(config.binary_format, path)),
+        binary_source: binary_path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        binary_path,
+        torrent_download: None,
+        streamed: false,
+        snapshot_checksum: None,
+    })
+}
+
+/// Ties the run state to the archives this run restores from
+fn bind_state(
+    state: &mut RunState,
+    snapshot: &Path,
+    binary: Option<&Path>,
+    increments: &[PathBuf],
+) {
+    let mut archives = vec![snapshot];
+    archives.extend(binary);
+    archives.extend(increments.iter().map(PathBuf::as_path));
+    state.bind(&archives);
+}
+
+/// Logs that a step an earlier run completed is skipped
+fn skip_step(step: Step) {
+    info!(
+        "Skipping the {} step, which an earlier run completed for these archives",
+        step.as_str()
+    );
+}
+
+/// Verifies the signature of a binary package found in the cache
+///
+/// The package may be left over from a download whose verification failed, so
+/// it is checked again before it can be installed and run.
+async fn verify_cached_binary(config: &Config, archives: &Archives) -> Result<()> {
+    let (Some(signature), Some(path)) = (&config.binary_signature, &archives.binary_path) else {
+        return Ok(());
+    };
+
+    info!("Verifying signature of {}", path.display());
+    SignatureVerifier::new()
+        .verify(path, signature)
+        .await
+        .with_context(|| format!("Signature verification of {} failed", path.display()))
+}
+
+/// Format of a local archive, as configured or recognized from its file name
+fn local_format(configured: Option<ArchiveFormat>, path: &Path) -> Option<ArchiveFormat> {
+    configured.or_else(|| ArchiveFormat::from_file_name(&file_name(path)))
+}
+
+/// File name of a path, or an empty string when it has none
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Installs the binary and restores the snapshot and its increments into the node home
+///
+/// Returns the path of the installed binary.
+async fn extract_archives(
+    config: &Config,
+    dirs: &Layout,
+    archives: &Archives,
+    schedule: &[UpgradeConfig],
+    state: &mut RunState,
+) -> Result<PathBuf> {
+    let installed_binary = match &archives.binary_path {
+        Some(binary_path) => extract_binary(
+            binary_path,
+            archives.binary_format,
+            &config.cosmos.bin,
+            &dirs.bin,
+            config.max_memory,
+        )?,
+        None => {
+            let installed = dirs.bin.join(&config.cosmos.bin);
+            if !installed.is_file() {
+                return Err(anyhow!(
+                    "No binary package in {} and no binary installed at {}; pass one with --binary",
+                    dirs.snapshots.display(),
+                    installed.display()
+                ));
+            }
+            info!("Keeping the installed binary {}", installed.display());
+            installed
+        }
+    };
+
+    // Don't restore a snapshot the binary can't continue from
+    match archives.snapshot_info.height {
+        Some(height) if !schedule.is_empty() => {
+            compat::check_compatibility(&installed_binary, height, schedule)
+                .context("Binary is incompatible with the snapshot")?;
+        }
+        None if !schedule.is_empty() => {
+            warn!("Snapshot height is unknown, skipping compatibility check")
+        }
+        _ => info!("No upgrade schedule available, skipping compatibility check"),
+    }
+
+    // The validator's own state and keys survive whatever the archives carry,
+    // and are put back even when the restore fails
+    let validator_backup = dirs.root.join(VALIDATOR_BACKUP_DIR);
+    setup::preserve_validator_files(&dirs.home, &validator_backup)
+        .context("Failed to back up the validator's files")?;
+    let restored = restore_data(config, dirs, archives, state).await;
+    setup::restore_validator_files(&dirs.home, &validator_backup)
+        .context("Failed to restore the validator's files")?;
+    restored?;
+
+    Ok(installed_binary)
+}
+
+/// Restores the snapshot and its increments into the node home
+async fn restore_data(
+    config: &Config,
+    dirs: &Layout,
+    archives: &Archives,
+    state: &mut RunState,
+) -> Result<()> {
+    let max_write_rate = config.priority.as_ref().and_then(|p| p.max_write_rate);
+    let (target_dir, strip_components) = if config.extract_to_data_dir {
+        (&dirs.home, config.strip_components)
+    } else {
+        (&dirs.snapshots, 0)
+    };
+
+    // Steps an earlier run completed are only skipped while their output is still there
+    let restored = dirs.home.join("data").is_dir();
+    let staged = !config.extract_to_data_dir && has_subdirectory(&dirs.snapshots);
+    // The move renames the staged data away, so a crash right after it leaves no staging
+    let moved = !config.extract_to_data_dir
+        && restored
+        && (state.is_done(Step::Moved) || (state.is_done(Step::Extracted) && !staged));
+    if moved || (state.is_done(Step::Extracted) && (restored || staged)) {
+        skip_step(Step::Extracted);
+    } else {
+        state.redo(Step::Extracted);
+        if archives.streamed {
+            stream_snapshot(config, archives, target_dir, strip_components).await?;
+        } else {
+            extract_snapshot(
+                config,
+                &archives.snapshot_path,
+                archives.snapshot_format,
+                target_dir,
+                strip_components,
+            )?;
+        }
+        state.complete(Step::Extracted)?;
+    }
+    if moved {
+        skip_step(Step::Moved);
+    } else if !config.extract_to_data_dir {
+        // Move snapshot to data directory
+        state.redo(Step::Moved);
+        info!("Moving snapshot to data directory");
+        setup::move_snapshot(&dirs.snapshots, &dirs.home, max_write_rate, config.jobs)
+            .context("Failed to move snapshot to data directory")?;
+        state.complete(Step::Moved)?;
+    }
+    setup::validate_data_dir(&dirs.home)?;
+    apply_increments(
+        config,
+        &archives.increment_paths,
+        &archives.increment_formats,
+        &dirs.home,
+    )
+}
+
+/// Initializes and configures the node over the restored data
+async fn setup_node(
+    config: &Config,
+    dirs: &Layout,
+    installed_binary: &Path,
+    schedule: &[UpgradeConfig],
+    height: Option<u64>,
+    metrics: &mut RunMetrics,
+    state: &mut RunState,
+) -> Result<()> {
+    enter_stage(metrics, "setup");
+    setup_cosmos_node(config, dirs, state)?;
+    let db_backend = align_db_backend(config, dirs)?;
+    if let Some(prune) = &config.prune {
+        enter_stage(metrics, "prune");
+        Pruner::new(prune, &dirs.home)?
+            .prune(installed_binary, db_backend)
+            .context("Failed to prune the restored data")?;
+    }
+    if let Some(compact) = &config.compact {
+        enter_stage(metrics, "compact");
+        Compactor::new(compact, &dirs.home)?
+            .with_max_memory(config.max_memory)
+            .compact(installed_binary)
+            .context("Failed to compact the restored data")?;
+    }
+    if config.data_manifest {
+        enter_stage(metrics, "manifest");
+        manifest::write(
+            &dirs.home,
+            &dirs.root.join(manifest::MANIFEST_FILE),
+            config.jobs,
+        )
+        .context("Failed to write the data manifest")?;
+    }
+    enter_stage(metrics, "finalize");
+    install_node_artifacts(config, dirs).await?;
+    if let Some(entry) = &config.registry {
+        apply_registry_peers(config, entry, dirs);
+    }
+    if let Some(cosmovisor) = &config.cosmovisor {
+        setup_cosmovisor(config, cosmovisor, schedule, height, installed_binary, dirs)
+            .await
+            .context("Failed to set up cosmovisor")?;
+    }
+    if let Some(systemd) = &config.systemd {
+        write_service_unit(config, systemd, dirs, installed_binary)
+            .context("Failed to write the systemd unit")?;
+    }
+    Ok(())
+}
+
+/// Writes a systemd unit starting the node, and installs it when configured
+fn write_service_unit(
+    config: &Config,
+    systemd: &SystemdConfig,
+    dirs: &Layout,
+    installed_binary: &Path,
+) -> Result<()> {
+    let mut unit = ServiceUnit::new(systemd, &config.cosmos, installed_binary, &dirs.home)?
+        .with_restart_policy(
+            config.supervisor.restart_delay_secs,
+            config.supervisor.open_files_limit,
+        );
+    if let Some(cosmovisor) = &config.cosmovisor {
+        let layout = Cosmovisor::new(cosmovisor, &dirs.home, &config.cosmos.bin)?;
+        unit = unit.with_cosmovisor(layout.path(), layout.env_file());
+    }
+
+    let path = unit.write(&dirs.root)?;
+    if systemd.install || systemd.enable {
+        info!("Start the node with: systemctl start {}", unit.unit_name());
+    } else {
+        info!(
+            "Install {} into /etc/systemd/system to run the node as a service",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Marks the start of a pipeline stage in the metrics and the service status
+fn enter_stage(metrics: &mut RunMetrics, stage: &'static str) {
+    metrics.stage(stage);
+    notify::status(&format!("Stage: {}", stage));
+}
+
+/// Name of the file a download of `url` is saved under in the snapshots directory
+///
+/// Unless `file_name` overrides it, the name an earlier download recorded
+/// wins, since the server may have chosen it; otherwise the name is derived
+/// the way the downloader derives it.
+fn download_name(state: &RunState, url: &str, file_name: Option<&str>) -> Result<String> {
+    if file_name.is_none() {
+        if let Some(name) = state.downloaded_name(url) {
+            return Ok(name.to_string());
+        }
+        if let Some(name) = torrent::magnet_name(url) {
+            return Ok(name);
+        }
+    }
+    downloader::local_file_name(url, file_name)
+}
+
+/// Name of the snapshot archive in the snapshots directory, downloaded from any mirror
+pub(crate) fn snapshot_name(config: &Config, state: &RunState) -> Result<String> {
+    let recorded = config
+        .snapshot_url
+        .iter()
+        .find_map(|url| state.downloaded_name(url))
+        .filter(|_| config.snapshot_filename.is_none());
+    match recorded {
+        Some(name) => Ok(name.to_string()),
+        None => download_name(
+            state,
+            &config.snapshot_url[0],
+            config.snapshot_filename.as_deref(),
+        ),
+    }
+}
+
+/// Resolves the configured snapshot provider, replacing `snapshot_url`
+///
+/// When the provider can't be reached or its index has no usable snapshot,
+/// a configured `snapshot_url` is used instead and `None` is returned.
+pub(crate) async fn resolve_provider_snapshot(
+    config: &mut Config,
+) -> Result<Option<ProviderSnapshot>> {
+    let Some(provider) = &config.snapshot_provider else {
+        return Ok(None);
+    };
+
+    let resolved = match SnapshotProvider::new()
+        .with_retry(RetryPolicy::for_stage(
+            &config.retries,
+            RetryStage::Provider,
+        ))
+        .resolve(provider)
+        .await
+    {
+        Ok(resolved) => resolved,
+        Err(e) if !config.snapshot_url.is_empty() => {
+            warn!(
+                "Failed to resolve snapshot from provider, falling back to snapshot_url: {:#}",
+                e
+            );
+            return Ok(None);
+        }
+        Err(e) => return Err(e.context("Failed to resolve snapshot from provider")),
+    };
+
+    config.snapshot_url = vec![resolved.snapshot.url.clone()];
+    Ok(Some(resolved))
+}
+
+/// Rejects an archive variant from the provider when the filesystem of
+/// `output_dir` can't hold the archive and its extraction
+pub(crate) fn check_archive_space(
+    config: &Config,
+    resolved: &ProviderSnapshot,
+    output_dir: &Path,
+) -> Result<()> {
+    let pruning = config.snapshot_provider.as_ref().map(|p| p.pruning);
+    if let (Some(Pruning::Archive), Some(size)) = (pruning, resolved.snapshot.size) {
+        let required = size.saturating_mul(ARCHIVE_SPACE_FACTOR);
+        let available = disk::available_space(output_dir)?;
+        if available < required {
+            return Err(anyhow!(
+                "Archive snapshot needs about {:.1} GB of free space but only {:.1} GB is available in {}",
+                required as f64 / 1e9,
+                available as f64 / 1e9,
+                output_dir.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Refuses to start downloads the disks can't hold
+///
+/// The archives need their remote size, less what earlier attempts already
+/// downloaded, in the download cache. When they are extracted in the same
+/// run, the extracted data needs `disk_check.expansion_factor` times their
+/// size in the node home and, for a snapshot staged in the cache before it
+/// is copied over, once more there. Archives whose size the server doesn't
+/// report can't be accounted for, so the check is skipped for them.
+async fn check_download_space(
+    config: &Config,
+    dirs: &Layout,
+    provider_snapshot: Option<&ProviderSnapshot>,
+    increments: &[IndexEntry],
+    stream: bool,
+    extract: bool,
+) -> Result<()> {
+    if config
+        .snapshot_url
+        .iter()
+        .any(|url| torrent::is_torrent_url(url))
+    {
+        info!("Torrent sizes aren't known up front, skipping the disk space check");
+        return Ok(());
+    }
+
+    let snapshot = (
+        config.snapshot_url[0].as_str(),
+        config.snapshot_filename.as_deref(),
+        provider_snapshot.and_then(|p| p.snapshot.size),
+    );
+    let layers = increments
+        .iter()
+        .map(|increment| (increment.url.as_str(), None, increment.size));
+
+    let downloader = retrying_downloader(config).with_request(config.snapshot_request.clone());
+    let mut sizes = Vec::new();
+    let mut remaining = 0u64;
+    for (url, file_name, known_size) in std::iter::once(snapshot).chain(layers) {
+        let remote = downloader
+            .inspect(url, &dirs.snapshots, file_name)
+            .await
+            .with_context(|| format!("Failed to look up the size of {}", url))?;
+        let Some(size) = known_size.or(remote.size) else {
+            warn!(
+                "{} doesn't report its size, skipping the disk space check",
+                url
+            );
+            return Ok(());
+        };
+        remaining += disk::space_still_needed(&remote.path, size);
+        sizes.push(size);
+    }
+
+    let expand = |size: u64| (size as f64 * config.disk_check.expansion_factor) as u64;
+    let mut needs = Vec::new();
+    if !stream {
+        needs.push((dirs.snapshots.as_path(), remaining));
+    }
+    if extract {
+        needs.extend(extraction_needs(
+            config,
+            dirs,
+            sizes[0],
+            &sizes[1..],
+            expand,
+        ));
+    }
+    check_disk_space(config, &needs)
+}
+
+/// Refuses to extract cached archives the disks can't hold
+fn check_extract_space(config: &Config, dirs: &Layout, archives: &Archives) -> Result<()> {
+    let size = |path: &Path| {
+        std::fs::metadata(path)
+            .map(|m| m.len())
+            .with_context(|| format!("Failed to read the size of {}", path.display()))
+    };
+    let snapshot_size = size(&archives.snapshot_path)?;
+    let increment_sizes = archives
+        .increment_paths
+        .iter()
+        .map(|path| size(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let expand = |size: u64| (size as f64 * config.disk_check.expansion_factor) as u64;
+    let needs = extraction_needs(config, dirs, snapshot_size, &increment_sizes, expand);
+    check_disk_space(config, &needs)
+}
+
+/// Space the extracted snapshot and increments take, by directory
+///
+/// Increments are always unpacked into the node home; the snapshot is too
+/// unless it is staged in the cache and moved over. Staged data only takes
+/// room twice when it has to be copied: into a home on another filesystem,
+/// or over data already restored there.
+fn extraction_needs<'a>(
+    config: &Config,
+    dirs: &'a Layout,
+    snapshot_size: u64,
+    increment_sizes: &[u64],
+    expand: impl Fn(u64) -> u64,
+) -> Vec<(&'a Path, u64)> {
+    let restored = expand(snapshot_size + increment_sizes.iter().sum::<u64>());
+    let mut needs = vec![(dirs.home.as_path(), restored)];
+    if !config.extract_to_data_dir {
+        let renamed = !dirs.home.join("data").exists()
+            && matches!(
+                (disk::filesystem_id(&dirs.snapshots), disk::filesystem_id(&dirs.home)),
+                (Ok(staging), Ok(home)) if staging == home
+            );
+        if !renamed {
+            needs.push((dirs.snapshots.as_path(), expand(snapshot_size)));
+        }
+    }
+    needs
+}
+
+/// Checks every filesystem has room for what the run will write to it
+///
+/// Needs of directories on the same filesystem add up, and
+/// `disk_check.reserve` is kept free on top of them.
+fn check_disk_space(config: &Config, needs: &[(&Path, u64)]) -> Result<()> {
+    let mut filesystems: Vec<(u64, &Path, u64)> = Vec::new();
+    for &(dir, bytes) in needs {
+        let id = disk::filesystem_id(dir)?;
+        match filesystems.iter_mut().find(|(other, _, _)| *other == id) {
+            Some((_, _, total)) => *total += bytes,
+            None => filesystems.push((id, dir, bytes)),
+        }
+    }
+
+    for (_, dir, bytes) in filesystems {
+        let required = bytes.saturating_add(config.disk_check.reserve);
+        let available = disk::available_space(dir)?;
+        if available < required {
+            return Err(anyhow!(
+                "Not enough free space for {}: about {:.1} GB needed but only {:.1} GB is available; free up space or pass --skip-disk-check",
+                dir.display(),
+                required as f64 / 1e9,
+                available as f64 / 1e9
+            ));
+        }
+        info!(
+            "{} has {:.1} GB free, about {:.1} GB needed",
+            dir.display(),
+            available as f64 / 1e9,
+            required as f64 / 1e9
+        );
+    }
+
+    Ok(())
+}
+
+/// Creates necessary directories for downloads and data
+fn create_directories(dirs: &Layout) -> Result<(PathBuf, PathBuf)> {
+    let snapshots_dir = dirs.snapshots.clone();
+    std::fs::create_dir_all(&snapshots_dir).context("Failed to create snapshots directory")?;
+
+    let data_dir = dirs.home.clone();
+    std::fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
+
+    Ok((snapshots_dir, data_dir))
+}
+
+/// Where the node binary comes from once its source has been resolved
+enum ResolvedBinary {
+    /// A downloadable artifact, with its checksum when one is published alongside it
+    Remote {
+        url: String,
+        checksum: Option<String>,
+    },
+
+    /// A binary produced locally, built from source or taken from a container image
+    Local(PathBuf),
+}
+
+impl ResolvedBinary {
+    /// Describes where the binary came from for logs and reports
+    fn describe(&self) -> String {
+        match self {
+            ResolvedBinary::Remote { url, .. } => url.clone(),
+            ResolvedBinary::Local(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// Determines where to obtain the node binary for this host
+///
+/// Prebuilt artifacts from `binary_url`, GitHub Releases or a container image are
+/// preferred; `binary.build` is used when none has an artifact for the host platform.
+async fn resolve_binary(config: &Config, output_dir: &Path) -> Result<ResolvedBinary> {
+    let source = config.binary.as_ref();
+    let build = source.and_then(|s| s.build.as_ref());
+
+    let prebuilt = if let Some(binary_url) = &config.binary_url {
+        binary_url.for_host().map(|url| {
+            info!("Using binary URL for platform {}", config::host_platform());
+            let (url, checksum) = split_checksum(url);
+            ResolvedBinary::Remote {
+                url: url.to_string(),
+                checksum: checksum.map(str::to_string),
+            }
+        })
+    } else if let Some(repo) = source.and_then(|s| s.github.as_ref()) {
+        info!("Resolving binary from GitHub releases of {}", repo);
+        GithubReleases::new()
+            .resolve(
+                repo,
+                source.and_then(|s| s.version.as_deref()),
+                source.and_then(|s| s.asset.as_deref()),
+            )
+            .await
+            .context("Failed to resolve binary from GitHub Releases")
+            .map(|asset| {
+                info!("Using release asset {} from {}", asset.name, asset.url);
+                ResolvedBinary::Remote {
+                    url: asset.url,
+                    checksum: asset.checksum,
+                }
+            })
+    } else if let Some(image) = source.and_then(|s| s.image.as_ref()) {
+        info!("Taking binary from container image {}", image.name);
+        ImageBinary::new(&output_dir.join("bin_image"))
+            .acquire(image, &config.cosmos.bin)
+            .context("Failed to obtain binary from container image")
+            .map(ResolvedBinary::Local)
+    } else {
+        Err(anyhow!("No prebuilt binary source configured"))
+    };
+
+    match (prebuilt, build) {
+        (Ok(resolved), _) => Ok(resolved),
+        (Err(e), Some(build)) => {
+            info!(
+                "No prebuilt binary available ({:#}), building from source",
+                e
+            );
+            let builder = SourceBuilder::new(&output_dir.join("build_src"));
+            let binary = builder
+                .build(build, &config.cosmos.bin)
+                .context("Failed to build binary from source")?;
+            Ok(ResolvedBinary::Local(binary))
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// Downloads the snapshot and, unless it was built locally, the binary
+///
+/// Torrent snapshots also return the download needed to seed them afterwards.
+async fn download_required_files(
+    config: &Config,
+    binary: &ResolvedBinary,
+    snapshots_dir: &Path,
+    stream: bool,
+    state: &RunState,
+) -> Result<(PathBuf, PathBuf, Option<TorrentDownload>)> {
+    let downloader = retrying_downloader(config).with_request(config.binary_request.clone());
+
+    // Download snapshot
+    let (snapshot_path, torrent_download) = if stream {
+        info!(
+            "The snapshot will be streamed from {} during extraction",
+            config.snapshot_url[0]
+        );
+        (snapshots_dir.join(snapshot_name(config, state)?), None)
+    } else {
+        download_snapshot(config, snapshots_dir, state).await?
+    };
+
+    // Download binary
+    let binary_path = match binary {
+        ResolvedBinary::Remote { url, .. } => {
+            info!("Downloading binary from: {}", url);
+            downloader
+                .download(url, snapshots_dir, config.binary_filename.as_deref())
+                .await
+                .context("Failed to download binary")?
+        }
+        ResolvedBinary::Local(path) => path.clone(),
+    };
+
+    Ok((snapshot_path, binary_path, torrent_download))
+}
+
+/// Downloads the snapshot over BitTorrent, from a fleet peer or from its mirrors
+async fn download_snapshot(
+    config: &Config,
+    snapshots_dir: &Path,
+    state: &RunState,
+) -> Result<(PathBuf, Option<TorrentDownload>)> {
+    let snapshot_url = &config.snapshot_url[0];
+    info!("Downloading snapshot from: {}", snapshot_url);
+    if torrent::is_torrent_url(snapshot_url) {
+        let download = TorrentDownloader::new(&config.torrent)
+            .download(snapshot_url, snapshots_dir)
+            .await
+            .context("Failed to download snapshot torrent")?;
+        Ok((download.path.clone(), Some(download)))
+    } else if let Some(path) = download_from_fleet(config, snapshots_dir, state).await {
+        Ok((path, None))
+    } else {
+        let chunk_manifest = match &config.snapshot_chunk_manifest {
+            Some(url) => Some(ChecksumVerifier::new().fetch_chunk_manifest(url).await?),
+            None => None,
+        };
+        // Torrent mirrors can't resume an HTTP download, so failover skips them
+        let mirrors = config
+            .snapshot_url
+            .iter()
+            .filter(|url| !torrent::is_torrent_url(url))
+            .cloned()
+            .collect::<Vec<_>>();
+        let path = retrying_downloader(config)
+            .with_request(config.snapshot_request.clone())
+            .with_chunk_manifest(chunk_manifest)
+            .download_mirrors(&mirrors, snapshots_dir, config.snapshot_filename.as_deref())
+            .await
+            .context("Failed to download snapshot")?;
+        Ok((path, None))
+    }
+}
+
+/// A downloader that retries according to the configured download policy
+fn retrying_downloader(config: &Config) -> Downloader {
+    Downloader::new()
+        .with_retry(RetryPolicy::for_stage(
+            &config.retries,
+            RetryStage::Download,
+        ))
+        .with_segmented(config.segmented.clone())
+        .with_max_memory(config.max_memory)
+        .with_jobs(config.jobs)
+        .with_s3(config.s3.clone())
+        .with_max_rate(config.max_rate)
+}
+
+/// A checksum verifier that looks up checksums as configured
+pub(crate) fn checksum_verifier(config: &Config) -> ChecksumVerifier {
+    ChecksumVerifier::new()
+        .with_s3(config.s3.clone())
+        .with_discovery(config.discover_checksums)
+}
+
+/// Downloads the snapshot from a fleet peer that already has it
+///
+/// Peers serve the snapshot under the name their own download wrote, which is
+/// looked up the way this host names its download of `snapshot_url`. The
+/// archive keeps that name, so a failed transfer is resumed from there.
+async fn download_from_fleet(
+    config: &Config,
+    snapshots_dir: &Path,
+    state: &RunState,
+) -> Option<PathBuf> {
+    let fleet = config.fleet.as_ref()?;
+    let file_name = snapshot_name(config, state).ok()?;
+    let url = share::find_peer(fleet, &file_name).await?;
+
+    info!("Downloading snapshot from fleet peer: {}", url);
+    let download = async {
+        let downloader = match &fleet.token {
+            Some(token) => Downloader::with_bearer_token(token)?,
+            None => Downloader::new(),
+        };
+        downloader
+            .with_max_rate(config.max_rate)
+            .download(&url, snapshots_dir, Some(&file_name))
+            .await
+    };
+
+    match download.await {
+        Ok(path) => Some(path),
+        Err(e) => {
+            warn!(
+                "Failed to download snapshot from fleet peer, falling back to {}: {:#}",
+                config.snapshot_url[0], e
+            );
+            None
+        }
+    }
+}
+
+/// Downloads the incremental archives layered on top of the snapshot
+///
+/// Archives already complete in the snapshots directory are kept, so a
+/// refresh only fetches the increments published since the last run.
+async fn download_increments(
+    config: &Config,
+    increments: &[IndexEntry],
+    snapshots_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let downloader = retrying_downloader(config).with_request(config.snapshot_request.clone());
+    let mut paths = Vec::with_capacity(increments.len());
+
+    for (index, increment) in increments.iter().enumerate() {
+        info!(
+            "Downloading incremental snapshot {}/{} from: {}",
+            index + 1,
+            increments.len(),
+            increment.url
+        );
+        let save_as = config
+            .snapshot_increments
+            .iter()
+            .find(|configured| configured.url() == increment.url)
+            .and_then(DownloadUrl::save_as);
+        let path = downloader
+            .download(&increment.url, snapshots_dir, save_as)
+            .await
+            .with_context(|| {
+                format!("Failed to download incremental snapshot {}", increment.url)
+            })?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Verifies downloaded files against published checksums and configured signatures
+///
+/// A checksum already known for an artifact (e.g. from its GitHub release) is
+/// used directly; otherwise sidecar checksum files are probed next to the URL.
+///
+/// Local binaries have nothing to verify against and are skipped.
+async fn verify_downloads(
+    config: &Config,
+    snapshot_path: Option<&Path>,
+    snapshot_checksum: Option<&str>,
+    increments: &[IndexEntry],
+    increment_paths: &[PathBuf],
+    binary: &ResolvedBinary,
+    binary_path: &Path,
+) -> Result<()> {
+    let verifier = checksum_verifier(config);
+    let signature_verifier = SignatureVerifier::new();
+    let unsigned = None;
+
+    // A configured checksum wins over the provider's or the release's
+    let mut artifacts = Vec::new();
+    if let Some(snapshot_path) = snapshot_path {
+        let snapshot_url = config.snapshot_url[0].as_str();
+        let snapshot_checksum = match &config.snapshot_checksum {
+            Some(checksum) => {
+                let file_name = listed_name(
+                    snapshot_url,
+                    snapshot_path,
+                    config.snapshot_filename.as_deref(),
+                );
+                Some(configured_checksum(&verifier, checksum, &file_name).await?)
+            }
+            None => snapshot_checksum.map(str::to_string),
+        };
+        artifacts.push((
+            snapshot_url,
+            snapshot_path,
+            snapshot_checksum,
+            &config.snapshot_signature,
+        ));
+    }
+    for (increment, path) in increments.iter().zip(increment_paths) {
+        artifacts.push((
+            increment.url.as_str(),
+            path,
+            increment.sha256.clone(),
+            &unsigned,
+        ));
+    }
+    if let ResolvedBinary::Remote { url, checksum } = binary {
+        let checksum = match &config.binary_checksum {
+            Some(configured) => {
+                let file_name = listed_name(url, binary_path, config.binary_filename.as_deref());
+                Some(configured_checksum(&verifier, configured, &file_name).await?)
+            }
+            None => checksum.clone(),
+        };
+        artifacts.push((
+            url.as_str(),
+            binary_path,
+            checksum,
+            &config.binary_signature,
+        ));
+    }
+
+    for (url, path, known_checksum, signature) in artifacts {
+        // Chunks listed in a chunk manifest were checked as they arrived
+        let chunk_verified =
+            config.snapshot_chunk_manifest.is_some() && url == config.snapshot_url[0];
+        let checksum = match known_checksum {
+            Some(expected) => Some((expected, url.to_string())),
+            // Torrent pieces are hash-checked as they arrive
+            None if torrent::is_torrent_url(url) => None,
+            None => verifier.discover(url).await?,
+        };
+
+        match checksum {
+            Some((expected, source)) => {
+                info!("Verifying {} against {}", path.display(), source);
+                verifier
+                    .verify(path, &expected)
+                    .await
+                    .with_context(|| format!("Verification of {} failed", path.display()))?;
+            }
+            None if torrent::is_torrent_url(url) => info!(
+                "{} was verified against its torrent's piece hashes",
+                path.display()
+            ),
+            None if chunk_verified => {
+                info!("{} was verified against its chunk manifest", path.display())
+            }
+            None if !config.discover_checksums => warn!(
+                "No checksum configured for {}, skipping verification",
+                path.display()
+            ),
+            None => warn!(
+                "No published checksum found for {}, skipping verification",
+                path.display()
+            ),
+        }
+
+        if let Some(signature) = signature {
+            info!("Verifying signature of {}", path.display());
+            signature_verifier
+                .verify(path, signature)
+                .await
+                .with_context(|| format!("Signature verification of {} failed", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves a configured checksum, either a hex digest or the URL of a
+/// checksum file listing `file_name`
+pub(crate) async fn configured_checksum(
+    verifier: &ChecksumVerifier,
+    checksum: &str,
+    file_name: &str,
+) -> Result<String> {
+    if checksum.starts_with("http://") || checksum.starts_with("https://") {
+        verifier.fetch_listed(checksum, file_name).await
+    } else {
+        Ok(checksum.to_string())
+    }
+}
+
+/// Name a checksum file lists a download under
+///
+/// Checksum files list the published name, not a local name it was saved as.
+pub(crate) fn listed_name(url: &str, path: &Path, local_name: Option<&str>) -> String {
+    match local_name {
+        Some(_) => downloader::local_file_name(url, None).unwrap_or_default(),
+        None => path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// Downloads, verifies and installs the configured genesis and addrbook files
+async fn install_node_artifacts(config: &Config, dirs: &Layout) -> Result<()> {
+    let artifacts = [
+        (
+            "genesis.json",
+            &config.genesis_url,
+            &config.genesis_checksum,
+        ),
+        (
+            "addrbook.json",
+            &config.addrbook_url,
+            &config.addrbook_checksum,
+        ),
+    ];
+    for (name, download, checksum) in artifacts {
+        if let Some(download) = download {
+            let download_dir = dirs.scratch.join("artifacts");
+            install_node_artifact(config, dirs, &download_dir, name, download, checksum).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads, verifies and installs one file into the node's `config` directory
+///
+/// The download is kept in `download_dir`, where an earlier one is resumed.
+/// A configured checksum may be a hex digest or the URL of a checksum file;
+/// without one, a published checksum is looked up next to the file. Files
+/// that fail verification are never installed, and gzipped ones are
+/// decompressed as they are installed.
+pub(crate) async fn install_node_artifact(
+    config: &Config,
+    dirs: &Layout,
+    download_dir: &Path,
+    name: &str,
+    download: &DownloadUrl,
+    checksum: &Option<String>,
+) -> Result<()> {
+    let data_dir = &dirs.home;
+    let verifier = checksum_verifier(config);
+    let url = download.url();
+
+    info!("Downloading {}", name);
+    std::fs::create_dir_all(download_dir)?;
+    let path = retrying_downloader(config)
+        .download(url, download_dir, download.save_as())
+        .await
+        .with_context(|| format!("Failed to download {}", name))?;
+
+    let expected = match checksum {
+        Some(checksum) => {
+            let file_name = listed_name(url, &path, download.save_as());
+            Some(configured_checksum(&verifier, checksum, &file_name).await?)
+        }
+        None => verifier.discover(url).await?.map(|(digest, _)| digest),
+    };
+    match expected {
+        Some(expected) => verifier
+            .verify(&path, &expected)
+            .await
+            .with_context(|| format!("Verification of {} failed", name))?,
+        None => warn!("No checksum available for {}, skipping verification", name),
+    }
+
+    let target = data_dir.join("config").join(name);
+    std::fs::create_dir_all(data_dir.join("config"))?;
+    setup::install_config_file(&path, &target)
+        .with_context(|| format!("Failed to install {}", name))?;
+    info!("Installed {}", target.display());
+
+    Ok(())
+}
+
+/// Extracts the binary package and returns the path of the node binary
+///
+/// Binaries published as a bare executable rather than an archive are
+/// installed directly at the configured `bin` path.
+fn extract_binary(
+    binary_path: &Path,
+    format: Option<ArchiveFormat>,
+    bin: &str,
+    binary_extract_path: &Path,
+    max_memory: Option<u64>,
+) -> Result<PathBuf> {
+    std::fs::create_dir_all(binary_extract_path)?;
+    if format.is_some() {
+        info!("Extracting binary package");
+        Extractor::new()
+            .with_format(format)
+            .with_max_memory(max_memory)
+            .extract(binary_path, binary_extract_path)
+            .context("Failed to extract binary package")?;
+    } else {
+        info!("Installing bare binary {}", binary_path.display());
+        install_bare_binary(binary_path, &binary_extract_path.join(bin))
+            .context("Failed to install binary")?;
+    }
+
+    Ok(binary_extract_path.join(bin))
+}
+
+/// Decides how a downloaded archive is compressed
+///
+/// A configured override wins, then the format the provider advertises, then
+/// the file's extension; only when all of those are silent is the server
+/// asked for the Content-Type it serves the file with.
+async fn archive_format(
+    configured: Option<ArchiveFormat>,
+    advertised: Option<&str>,
+    url: &str,
+    path: &Path,
+    request: Option<&RequestConfig>,
+    s3: Option<&S3Config>,
+) -> Option<ArchiveFormat> {
+    if configured.is_some() {
+        return configured;
+    }
+    if let Some(label) = advertised {
+        match ArchiveFormat::from_label(label) {
+            Some(format) => return Some(format),
+            None => warn!("Provider advertises unknown format {} for {}", label, url),
+        }
+    }
+    let from_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(ArchiveFormat::from_file_name);
+    if from_name.is_some() || torrent::is_torrent_url(url) {
+        return from_name;
+    }
+
+    Downloader::new()
+        .with_request(request.cloned())
+        .with_s3(s3.cloned())
+        .probe_format(url)
+        .await
+}
+
+/// Extractor for the snapshot and its increments, with the configured
+/// limits, key and exclusions
+pub(crate) fn snapshot_extractor(config: &Config) -> Result<Extractor> {
+    Ok(Extractor::new()
+        .with_max_memory(config.max_memory)
+        .with_max_write_rate(config.priority.as_ref().and_then(|p| p.max_write_rate))
+        .with_decryption(config.decryption.clone())
+        .with_exclusions(config.exclusions()?)
+        .with_threads(config.extract_threads.or(config.jobs))
+        .with_unsafe_paths(config.allow_unsafe_paths))
+}
+
+/// Extracts the snapshot archive into the snapshots or data directory
+fn extract_snapshot(
+    config: &Config,
+    snapshot_path: &Path,
+    format: Option<ArchiveFormat>,
+    target_dir: &Path,
+    strip_components: usize,
+) -> Result<()> {
+    info!("Extracting blockchain snapshot to {}", target_dir.display());
+    snapshot_extractor(config)?
+        .with_format(format)
+        .with_strip_components(strip_components)
+        .extract(snapshot_path, target_dir)
+        .context("Failed to extract snapshot")?;
+
+    Ok(())
+}
+
+/// Extracts the snapshot while it downloads, without storing the archive
+///
+/// The stream's digest is checked once extraction finishes; a mismatch fails
+/// the restore, since the extracted data can't be trusted.
+async fn stream_snapshot(
+    config: &Config,
+    archives: &Archives,
+    target_dir: &Path,
+    strip_components: usize,
+) -> Result<()> {
+    let format = archives.snapshot_format.ok_or_else(|| {
+        anyhow!("Can't tell how the snapshot is compressed; set snapshot_format to stream it")
+    })?;
+    let extractor = snapshot_extractor(config)?.with_strip_components(strip_components);
+    let name = file_name(&archives.snapshot_path);
+    let target = target_dir.to_path_buf();
+
+    info!(
+        "Streaming blockchain snapshot into {}",
+        target_dir.display()
+    );
+    let (sender, reader) = downloader::stream_channel();
+    let extraction = tokio::task::spawn_blocking(move || {
+        extractor.extract_stream(reader, &name, format, &target)
+    });
+    let mirrors = config
+        .snapshot_url
+        .iter()
+        .filter(|url| !torrent::is_torrent_url(url))
+        .cloned()
+        .collect::<Vec<_>>();
+    let downloaded = retrying_downloader(config)
+        .with_request(config.snapshot_request.clone())
+        .stream(&mirrors, sender)
+        .await;
+    let extracted = extraction.await.context("Extraction task panicked")?;
+
+    // A failed download explains whatever the extractor made of the stream
+    let actual = downloaded.context("Failed to stream snapshot")?;
+    extracted.context("Failed to extract snapshot")?;
+
+    let url = mirrors[0].as_str();
+    let verifier = checksum_verifier(config);
+    let expected = match (&config.snapshot_checksum, &archives.snapshot_checksum) {
+        (Some(checksum), _) => {
+            let file_name = listed_name(
+                url,
+                &archives.snapshot_path,
+                config.snapshot_filename.as_deref(),
+            );
+            Some(configured_checksum(&verifier, checksum, &file_name).await?)
+        }
+        (None, Some(checksum)) => Some(checksum.clone()),
+        (None, None) => verifier.discover(url).await?.map(|(digest, _)| digest),
+    };
+    match expected {
+        Some(expected) if expected.eq_ignore_ascii_case(&actual) => {
+            info!("Streamed snapshot matches its checksum")
+        }
+        Some(expected) => {
+            return Err(error::Error::ChecksumMismatch {
+                path: archives.snapshot_path.clone(),
+                expected,
+                actual,
+            })
+            .context("Streamed snapshot is corrupt; the extracted data can't be trusted");
+        }
+        None if !config.discover_checksums => {
+            warn!("No checksum configured for the snapshot, skipping verification")
+        }
+        None => warn!("No published checksum found for the snapshot, skipping verification"),
+    }
+
+    Ok(())
+}
+
+/// Applies incremental archives over the restored snapshot, oldest first
+///
+/// Increments are unpacked into the data directory with the same layout as
+/// the snapshot archive, overwriting the files they carry.
+fn apply_increments(
+    config: &Config,
+    increment_paths: &[PathBuf],
+    formats: &[Option<ArchiveFormat>],
+    data_dir: &Path,
+) -> Result<()> {
+    for (index, (path, format)) in increment_paths.iter().zip(formats).enumerate() {
+        info!(
+            "Applying incremental snapshot {}/{}: {}",
+            index + 1,
+            increment_paths.len(),
+            path.display()
+        );
+        snapshot_extractor(config)?
+            .with_format(*format)
+            .with_strip_components(config.strip_components)
+            .extract(path, data_dir)
+            .with_context(|| format!("Failed to apply incremental snapshot {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Copies a downloaded executable into place and marks it executable
+fn install_bare_binary(source: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(source, target)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(target, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+/// Sets up the Cosmos node with the downloaded data
+///
+/// A home an earlier run initialized only gets its settings written again.
+fn setup_cosmos_node(config: &Config, dirs: &Layout, state: &mut RunState) -> Result<()> {
+    let cosmos_setup = CosmosSetup::new(&config.cosmos, &dirs.bin, &dirs.home);
+
+    if state.is_done(Step::Initialized) && dirs.home.join("config").is_dir() {
+        skip_step(Step::Initialized);
+        cosmos_setup
+            .apply_settings()
+            .context("Failed to configure node")?;
+        return Ok(());
+    }
+
+    info!("Initializing Cosmos node");
+    cosmos_setup.init().context("Failed to initialize node")?;
+    state.complete(Step::Initialized)?;
+
+    Ok(())
+}
+
+/// Whether a directory has a subdirectory, such as an extracted snapshot
+fn has_subdirectory(dir: &Path) -> bool {
+    std::fs::read_dir(dir).is_ok_and(|entries| {
+        entries
+            .filter_map(Result::ok)
+            .any(|entry| entry.path().is_dir())
+    })
+}
+
+/// Makes config.toml's `db_backend` match the restored databases
+///
+/// A `db_backend` set in `cosmos.config` that disagrees with the snapshot is
+/// an error, as the node would otherwise panic on its first start.
+///
+/// # Returns
+/// * `Result<Option<DbBackend>>` - The detected backend, if any
+fn align_db_backend(config: &Config, dirs: &Layout) -> Result<Option<DbBackend>> {
+    let Some(backend) = db_backend::detect(&dirs.home.join("data"))
+        .context("Failed to detect the snapshot's database backend")?
+    else {
+        warn!("Could not determine the snapshot's database backend, leaving db_backend unchanged");
+        return Ok(None);
+    };
+
+    if let Some(configured) = config.cosmos.config.get("db_backend") {
+        let configured = configured.as_str().unwrap_or_default();
+        if configured != backend.as_str() {
+            return Err(anyhow!(
+                "cosmos.config.db_backend is {} but the snapshot's databases use {}",
+                configured,
+                backend.as_str()
+            ));
+        }
+        return Ok(Some(backend));
+    }
+
+    info!("Setting db_backend to {}", backend.as_str());
+    CosmosSetup::new(&config.cosmos, &dirs.bin, &dirs.home)
+        .configure_db_backend(backend.as_str())
+        .context("Failed to set db_backend")?;
+    Ok(Some(backend))
+}
+
+/// Lays out the node binaries for cosmovisor
+///
+/// The current binary is installed for the upgrade active at the snapshot's
+/// height, and binaries for upcoming upgrades are downloaded ahead of time.
+/// Cosmovisor is allowed to download binaries itself when some couldn't be
+/// pre-populated, unless `allow_download_binaries` says otherwise.
+async fn setup_cosmovisor(
+    config: &Config,
+    cosmovisor: &CosmovisorConfig,
+    schedule: &[UpgradeConfig],
+    height: Option<u64>,
+    installed_binary: &Path,
+    dirs: &Layout,
+) -> Result<()> {
+    let layout = Cosmovisor::new(cosmovisor, &dirs.home, &config.cosmos.bin)?;
+
+    let active = height.and_then(|h| schedule.iter().rfind(|u| u.height <= h));
+    let active_upgrade = active.filter(|u| u.height > 0).map(|u| u.name.as_str());
+    layout.install_current(installed_binary, active_upgrade)?;
+
+    let mut missing = Vec::new();
+    let upcoming = schedule
+        .iter()
+        .filter(|u| u.height > 0 && height.is_none_or(|h| u.height > h));
+    for upgrade in upcoming {
+        if layout.has_upgrade(&upgrade.name) {
+            continue;
+        }
+        match fetch_upgrade_binary(config, upgrade, &layout, &dirs.scratch).await {
+            Ok(true) => {}
+            Ok(false) => missing.push(upgrade.name.clone()),
+            Err(e) => {
+                warn!(
+                    "Failed to pre-populate binary for upgrade {}: {:#}",
+                    upgrade.name, e
+                );
+                missing.push(upgrade.name.clone());
+            }
+        }
+    }
+
+    let allow_download = cosmovisor
+        .allow_download_binaries
+        .unwrap_or(!missing.is_empty());
+    if !missing.is_empty() && !allow_download {
+        warn!(
+            "No binary for upcoming upgrades {}; the node will halt when it reaches them",
+            missing.join(", ")
+        );
+    }
+    layout.write_env_file(allow_download)?;
+
+    Ok(())
+}
+
+/// Splits the checksum off a binary URL from the chain registry
+///
+/// Registry URLs carry it go-getter style: `<url>?checksum=sha256:<hex>`.
+pub(crate) fn split_checksum(url: &str) -> (&str, Option<&str>) {
+    match url.split_once("?checksum=") {
+        Some((base, checksum)) => (base, checksum.strip_prefix("sha256:")),
+        None => (url, None),
+    }
+}
+
+/// Downloads an upgrade's binary into its cosmovisor directory
+///
+/// # Returns
+/// * `Result<bool>` - Whether a binary is known for this host
+async fn fetch_upgrade_binary(
+    config: &Config,
+    upgrade: &UpgradeConfig,
+    layout: &Cosmovisor,
+    output_dir: &Path,
+) -> Result<bool> {
+    let Some(url) = upgrade
+        .binary_url
+        .as_ref()
+        .and_then(|binary_url| binary_url.for_host().ok())
+    else {
+        return Ok(false);
+    };
+
+    let (url, checksum) = split_checksum(url);
+
+    info!("Downloading binary for upgrade {}", upgrade.name);
+    let download_dir = output_dir.join("upgrade_downloads").join(&upgrade.name);
+    std::fs::create_dir_all(&download_dir)?;
+    let path = retrying_downloader(config)
+        .download(url, &download_dir, upgrade.save_as.as_deref())
+        .await?;
+    if let Some(checksum) = checksum {
+        ChecksumVerifier::new().verify(&path, checksum).await?;
+    }
+    if let Some(signature) = &upgrade.signature {
+        info!("Verifying signature of {}", path.display());
+        SignatureVerifier::new()
+            .verify(&path, signature)
+            .await
+            .with_context(|| format!("Signature verification of {} failed", path.display()))?;
+    }
+
+    let binary = if Extractor::is_supported(&path) {
+        let extract_dir = download_dir.join("extract");
+        if extract_dir.exists() {
+            std::fs::remove_dir_all(&extract_dir)?;
+        }
+        Extractor::new()
+            .with_max_memory(config.max_memory)
+            .extract(&path, &extract_dir)
+            .context("Failed to extract upgrade binary package")?;
+
+        let configured = extract_dir.join(&config.cosmos.bin);
+        if configured.is_file() {
+            configured
+        } else {
+            find_file(&extract_dir, layout.daemon_name())?
+                .with_context(|| format!("No {} found in upgrade package", layout.daemon_name()))?
+        }
+    } else {
+        path
+    };
+
+    layout.install_upgrade(&upgrade.name, &binary)?;
+    Ok(true)
+}
+
+/// Searches a directory tree for a file with the given name
+fn find_file(dir: &Path, name: &str) -> Result<Option<PathBuf>> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if let Some(found) = find_file(&path, name)? {
+                return Ok(Some(found));
+            }
+        } else if path.file_name().is_some_and(|n| n == name) {
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Fetches the selected chain's registry entry, if a chain is selected
+///
+/// Failures are only logged; features depending on the registry are skipped.
+pub async fn fetch_registry_entry(config: &Config) -> Option<ChainEntry> {
+    let chain = config.chain.as_ref()?;
+
+    match ChainRegistry::new(&config.chain_registry_url)
+        .fetch(chain)
+        .await
+    {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            warn!("Failed to fetch {} from the chain registry: {:#}", chain, e);
+            None
+        }
+    }
+}
+
+/// Fills in settings the configuration leaves out from the chain's registry entry
+///
+/// The daemon name, chain id, genesis URL and the binaries of the recommended
+/// release are taken from the registry; configured values always win. The
+/// entry is kept on the configuration for the upgrade schedule and peers.
+pub fn apply_registry_defaults(config: &mut Config, entry: ChainEntry) -> Result<()> {
+    if config.cosmos.bin.trim().is_empty() {
+        config.cosmos.bin = entry
+            .daemon_name
+            .clone()
+            .context("cosmos.bin is not configured and the chain registry lists no daemon_name")?;
+        info!("Using daemon {} from the chain registry", config.cosmos.bin);
+    }
+    if config.cosmos.chain_id.is_none() {
+        info!("Using chain id {} from the chain registry", entry.chain_id);
+        config.cosmos.chain_id = Some(entry.chain_id.clone());
+    }
+
+    let codebase = entry.codebase.as_ref();
+    if config.genesis_url.is_none() {
+        if let Some(url) = codebase
+            .and_then(|c| c.genesis.as_ref())
+            .and_then(|g| g.genesis_url.clone())
+        {
+            info!("Using genesis from the chain registry: {}", url);
+            config.genesis_url = Some(DownloadUrl::Plain(url));
+        }
+    }
+    if config.binary_url.is_none() && config.binary.is_none() {
+        let (version, binaries) = codebase
+            .and_then(|c| c.recommended_binaries())
+            .context("No binary source is configured and the chain registry lists no binaries")?;
+        info!("Using {} binaries from the chain registry", version);
+        config.binary_url = compat::registry_binaries(binaries);
+    }
+
+    config.registry = Some(entry);
+    Ok(())
+}
+
+/// Applies the chain registry's current seeds and peers to config.toml
+///
+/// Failures are only logged, leaving the existing peer settings in place.
+pub(crate) fn apply_registry_peers(config: &Config, entry: &ChainEntry, dirs: &Layout) {
+    info!("Applying chain registry peers for {}", entry.chain_id);
+    let cosmos_setup = CosmosSetup::new(&config.cosmos, &dirs.bin, &dirs.home);
+    if let Err(e) = cosmos_setup.configure_peers(
+        &entry.peers.seeds_list(),
+        &entry.peers.persistent_peers_list(),
+    ) {
+        warn!("Failed to apply chain registry peers: {:#}", e);
+    }
+}
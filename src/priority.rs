@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::config::{IoClass, PriorityConfig};
+
+/// Applies the configured CPU and disk priority to this process
+///
+/// Nice values and I/O priorities are per thread on Linux, so they are set
+/// on every thread that exists now; threads started later inherit them from
+/// the thread that starts them, as do child processes.
+pub fn apply(config: &PriorityConfig) -> Result<()> {
+    if let Some(cgroup) = &config.cgroup {
+        join_cgroup(Path::new(cgroup), config)?;
+    } else if config.cpu_weight.is_some() || config.io_weight.is_some() {
+        warn!("priority.cpu_weight and priority.io_weight only apply with priority.cgroup set");
+    }
+
+    let io_priority = match (config.io_class, config.io_level) {
+        (None, None) => None,
+        (class, level) => Some(io_priority(class.unwrap_or(IoClass::BestEffort), level)?),
+    };
+
+    for thread in threads()? {
+        if let Some(nice) = config.nice {
+            set_nice(thread, nice)?;
+        }
+        if let Some(priority) = io_priority {
+            set_io_priority(thread, priority)?;
+        }
+    }
+
+    if let Some(nice) = config.nice {
+        info!("Running with niceness {}", nice);
+    }
+    if let Some(class) = config.io_class {
+        info!("Running in I/O class {:?}", class);
+    }
+
+    Ok(())
+}
+
+/// Sets the cgroup's weights and moves this process into it
+fn join_cgroup(cgroup: &Path, config: &PriorityConfig) -> Result<()> {
+    fs::create_dir_all(cgroup)
+        .with_context(|| format!("Failed to create cgroup {}", cgroup.display()))?;
+
+    for (file, weight) in [
+        ("cpu.weight", config.cpu_weight),
+        ("io.weight", config.io_weight),
+    ] {
+        if let Some(weight) = weight {
+            if !(1..=10000).contains(&weight) {
+                return Err(anyhow!("{} must be between 1 and 10000", file));
+            }
+            fs::write(cgroup.join(file), weight.to_string()).with_context(|| {
+                format!(
+                    "Failed to set {} of {}; is the controller enabled?",
+                    file,
+                    cgroup.display()
+                )
+            })?;
+        }
+    }
+
+    fs::write(cgroup.join("cgroup.procs"), std::process::id().to_string())
+        .with_context(|| format!("Failed to move into cgroup {}", cgroup.display()))?;
+    info!("Moved into cgroup {}", cgroup.display());
+
+    Ok(())
+}
+
+/// Encodes an I/O class and level the way `ioprio_set` expects them
+fn io_priority(class: IoClass, level: Option<u8>) -> Result<i32> {
+    const IOPRIO_CLASS_SHIFT: i32 = 13;
+
+    let level = level.unwrap_or(4);
+    if level > 7 {
+        return Err(anyhow!("priority.io_level must be between 0 and 7"));
+    }
+
+    let (class, level) = match class {
+        IoClass::Realtime => (1, level),
+        IoClass::BestEffort => (2, level),
+        // The idle class has no levels
+        IoClass::Idle => (3, 0),
+    };
+    Ok((class << IOPRIO_CLASS_SHIFT) | i32::from(level))
+}
+
+/// Thread IDs of this process
+#[cfg(target_os = "linux")]
+fn threads() -> Result<Vec<u32>> {
+    let mut threads = Vec::new();
+    for entry in fs::read_dir("/proc/self/task").context("Failed to list threads")? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            threads.push(tid);
+        }
+    }
+    Ok(threads)
+}
+
+/// Priorities apply to the whole process on other platforms
+#[cfg(not(target_os = "linux"))]
+fn threads() -> Result<Vec<u32>> {
+    Ok(vec![0])
+}
+
+/// Sets the niceness of a thread
+#[cfg(unix)]
+fn set_nice(thread: u32, nice: i32) -> Result<()> {
+    // SAFETY: setpriority takes plain integers and reports failure through errno
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, thread as libc::id_t, nice) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to set niceness {}", nice));
+    }
+    Ok(())
+}
+
+/// Niceness can't be set on this platform
+#[cfg(not(unix))]
+fn set_nice(_thread: u32, _nice: i32) -> Result<()> {
+    Err(anyhow!("priority.nice is not supported on this platform"))
+}
+
+/// Sets the I/O priority of a thread
+#[cfg(target_os = "linux")]
+fn set_io_priority(thread: u32, priority: i32) -> Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+    // SAFETY: ioprio_set takes plain integers and reports failure through errno
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            thread as libc::c_int,
+            priority,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to set I/O priority");
+    }
+    Ok(())
+}
+
+/// I/O priorities are Linux-only
+#[cfg(not(target_os = "linux"))]
+fn set_io_priority(_thread: u32, _priority: i32) -> Result<()> {
+    Err(anyhow!("priority.io_class is only supported on Linux"))
+}
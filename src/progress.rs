@@ -0,0 +1,110 @@
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::notify;
+
+/// How often an extraction logs the entries and bytes unpacked so far
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the bar's entry count is redrawn
+const MESSAGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress of one extraction
+///
+/// The bar follows the compressed bytes read, the only measure known up
+/// front, while the entries and bytes unpacked are logged every 30 seconds
+/// so a long extraction shows it is still moving.
+pub struct ExtractionProgress {
+    bar: ProgressBar,
+    entries: u64,
+    unpacked: u64,
+    last_log: Instant,
+    last_message: Instant,
+}
+
+impl ExtractionProgress {
+    /// Tracks an archive of `total` compressed bytes, `start` of which were
+    /// extracted by an earlier run
+    pub fn new(total: u64, start: u64) -> Result<Self> {
+        let bar = ProgressBar::new(total);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg} ({eta})")?
+                .progress_chars("#>-"),
+        );
+        bar.set_position(start);
+        Ok(Self::with_bar(bar))
+    }
+
+    /// Tracks an archive of unknown size, e.g. a stream whose download shows its own bar
+    pub fn hidden() -> Self {
+        Self::with_bar(ProgressBar::hidden())
+    }
+
+    fn with_bar(bar: ProgressBar) -> Self {
+        let now = Instant::now();
+        ExtractionProgress {
+            bar,
+            entries: 0,
+            unpacked: 0,
+            last_log: now,
+            last_message: now,
+        }
+    }
+
+    /// Wraps the compressed input, advancing the bar as it is read
+    pub fn reader<R: Read>(&self, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            bar: self.bar.clone(),
+        }
+    }
+
+    /// Counts an entry of `size` bytes about to be unpacked
+    pub fn entry(&mut self, size: u64) {
+        self.entries += 1;
+        self.unpacked += size;
+
+        if self.last_message.elapsed() >= MESSAGE_INTERVAL {
+            self.bar.set_message(format!("{} entries", self.entries));
+            self.last_message = Instant::now();
+        }
+        if self.last_log.elapsed() >= LOG_INTERVAL {
+            let status = format!(
+                "Extracted {} entries, {:.2} GB unpacked",
+                self.entries,
+                self.unpacked as f64 / 1e9
+            );
+            info!("{}", status);
+            notify::status(&status);
+            self.last_log = Instant::now();
+        }
+    }
+
+    /// Completes the bar and logs the totals
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+        info!(
+            "Extracted {} entries, {:.2} GB unpacked",
+            self.entries,
+            self.unpacked as f64 / 1e9
+        );
+    }
+}
+
+/// Compressed input whose reads advance an extraction's progress bar
+pub struct ProgressReader<R> {
+    inner: R,
+    bar: ProgressBar,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bar.inc(read as u64);
+        Ok(read)
+    }
+}
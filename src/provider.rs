@@ -0,0 +1,449 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::SystemTime;
+use tracing::{info, instrument};
+
+use crate::config::{ProviderConfig, ProviderFields, ProviderPreset, Pruning};
+use crate::extractor::ArchiveFormat;
+use crate::retry::RetryPolicy;
+use crate::snapshot_info::SnapshotInfo;
+
+/// A snapshot listed in a provider index
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Download URL, absolute or relative to the index
+    pub url: String,
+
+    /// Block height the snapshot was taken at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+
+    /// Pruning variant, e.g. `pruned` or `archive`; unlabeled entries are `default`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pruning: Option<String>,
+
+    /// Archive size in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+
+    /// Hex-encoded SHA256 of the archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// `full` or `incremental`; unlabeled entries are full snapshots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    /// Height of the full snapshot an incremental archive applies on top of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_height: Option<u64>,
+
+    /// Compression of the archive, when the file name doesn't tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+
+    /// When the snapshot was taken, e.g. `2025-03-05T04:00:00Z`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+}
+
+impl IndexEntry {
+    /// Whether the entry is the requested pruning variant
+    fn is_variant(&self, pruning: Pruning) -> bool {
+        let variant = self.pruning.as_deref().unwrap_or(Pruning::Default.as_str());
+        variant.eq_ignore_ascii_case(pruning.as_str())
+    }
+
+    /// Whether the entry is an incremental archive rather than a full snapshot
+    fn is_incremental(&self) -> bool {
+        self.kind
+            .as_deref()
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("incremental"))
+    }
+
+    /// Name of the archive, the last segment of its URL
+    fn file_name(&self) -> &str {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        path.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Sort key of full snapshots: height, then the creation time from the
+    /// index, then the date in the file name
+    fn rank(&self) -> (Option<u64>, Option<&str>, Option<SystemTime>) {
+        (
+            self.height,
+            self.created.as_deref(),
+            SnapshotInfo::from_file_name(self.file_name()).created,
+        )
+    }
+}
+
+/// Pruning variant named in a file name, e.g. `osmosis_123-pruned.tar.lz4`
+pub fn pruning_from_name(name: &str) -> Option<String> {
+    let lower = name.to_ascii_lowercase();
+    ["pruned", "archive"]
+        .into_iter()
+        .find(|variant| lower.contains(variant))
+        .map(str::to_string)
+}
+
+/// A full snapshot and the incremental archives layered on top of it
+#[derive(Debug, Clone)]
+pub struct ProviderSnapshot {
+    /// Full snapshot to restore
+    pub snapshot: IndexEntry,
+
+    /// Incremental archives to apply in order, oldest first
+    pub increments: Vec<IndexEntry>,
+}
+
+impl ProviderSnapshot {
+    /// Height reached once every layer is applied
+    pub fn height(&self) -> Option<u64> {
+        self.increments
+            .last()
+            .map_or(self.snapshot.height, |increment| increment.height)
+    }
+}
+
+/// Entry list used when no `entries` path is configured, matching the native
+/// index format `{"snapshots": [{"url": ..., "height": ..., ...}]}`
+const DEFAULT_ENTRIES_PATH: &str = "$.snapshots";
+
+/// Resolves the latest snapshot from a provider index
+pub struct SnapshotProvider {
+    client: Client,
+
+    /// Retries of failed index fetches
+    retry: RetryPolicy,
+}
+
+impl Default for SnapshotProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotProvider {
+    /// Creates a new snapshot provider client
+    pub fn new() -> Self {
+        SnapshotProvider {
+            client: Client::new(),
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Retries failed index fetches according to `policy`
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Picks the highest snapshot of the configured pruning variant
+    ///
+    /// The index is parsed as JSON using the configured entry path and field
+    /// mappings or the preset's, scanned with `pattern` when one is set, or
+    /// read as a directory listing. Entries without a height rank below those
+    /// with one; ties go to the newer entry by creation time, then keep the
+    /// index's order. Incremental archives of the same variant above the
+    /// chosen snapshot's height (and based on it, when they say so) are
+    /// returned in height order. All returned URLs are absolute.
+    #[instrument(skip(self, config), fields(index = %config.index_url, pruning = config.pruning.as_str()))]
+    pub async fn resolve(&self, config: &ProviderConfig) -> Result<ProviderSnapshot> {
+        let index_url = Url::parse(&config.index_url).context("Invalid provider index URL")?;
+        let body = self
+            .retry
+            .run("Provider index fetch", || self.fetch_index(&index_url))
+            .await?;
+        let snapshots = parse_index(&body, config).context("Failed to parse provider index")?;
+
+        let mut best: Option<&IndexEntry> = None;
+        let variant = || snapshots.iter().filter(|e| e.is_variant(config.pruning));
+        for entry in variant().filter(|e| !e.is_incremental()) {
+            if best.is_none_or(|b| entry.rank() > b.rank()) {
+                best = Some(entry);
+            }
+        }
+
+        let Some(best) = best else {
+            let mut variants: Vec<_> = snapshots
+                .iter()
+                .map(|e| e.pruning.as_deref().unwrap_or(Pruning::Default.as_str()))
+                .collect();
+            variants.sort_unstable();
+            variants.dedup();
+            return Err(anyhow!(
+                "Provider lists no {} snapshot (available: {})",
+                config.pruning.as_str(),
+                if variants.is_empty() {
+                    "none".to_string()
+                } else {
+                    variants.join(", ")
+                }
+            ));
+        };
+
+        let absolute = |entry: &IndexEntry| -> Result<IndexEntry> {
+            let mut entry = entry.clone();
+            entry.url = index_url
+                .join(&entry.url)
+                .context("Invalid snapshot URL in provider index")?
+                .to_string();
+            Ok(entry)
+        };
+
+        let snapshot = absolute(best)?;
+        info!(
+            "Provider's latest {} snapshot: {} (height {})",
+            config.pruning.as_str(),
+            snapshot.url,
+            snapshot
+                .height
+                .map_or_else(|| "unknown".to_string(), |h| h.to_string())
+        );
+
+        // Increments can only be ordered against a base of known height
+        let mut increments = Vec::new();
+        if let Some(base) = snapshot.height {
+            let mut layered: Vec<_> = variant()
+                .filter(|e| e.is_incremental())
+                .filter(|e| e.height.is_some_and(|h| h > base))
+                .filter(|e| e.base_height.is_none_or(|b| b == base))
+                .collect();
+            layered.sort_by_key(|e| e.height);
+            for entry in layered {
+                let entry = absolute(entry)?;
+                info!(
+                    "Incremental snapshot on top: {} (height {})",
+                    entry.url,
+                    entry.height.unwrap_or_default()
+                );
+                increments.push(entry);
+            }
+        }
+
+        Ok(ProviderSnapshot {
+            snapshot,
+            increments,
+        })
+    }
+
+    /// Downloads the index body
+    async fn fetch_index(&self, index_url: &Url) -> Result<String> {
+        self.client
+            .get(index_url.clone())
+            .send()
+            .await
+            .context("Failed to fetch provider index")?
+            .error_for_status()
+            .context("Provider index request failed")?
+            .text()
+            .await
+            .context("Failed to read provider index")
+    }
+}
+
+/// Extracts the snapshot entries from an index body
+///
+/// Presets fill in heights their index leaves out from the file names.
+fn parse_index(body: &str, config: &ProviderConfig) -> Result<Vec<IndexEntry>> {
+    if let Some(pattern) = &config.pattern {
+        return parse_text_index(body, pattern);
+    }
+    if config.preset == ProviderPreset::Listing {
+        return Ok(parse_listing(body));
+    }
+
+    let document: Value = serde_json::from_str(body).context("Index is not valid JSON")?;
+    let path = config
+        .entries
+        .as_deref()
+        .unwrap_or(preset_entries_path(config.preset));
+    let fields = preset_fields(config.preset, &config.fields);
+    let entries = json_path(&document, path)?
+        .into_iter()
+        .flat_map(|value| match value {
+            Value::Array(items) => items.iter().collect(),
+            other => vec![other],
+        });
+
+    let mut entries: Vec<_> = entries
+        .filter_map(|entry| map_entry(entry, &fields))
+        .collect();
+    if config.preset != ProviderPreset::Json {
+        for entry in entries.iter_mut().filter(|e| e.height.is_none()) {
+            entry.height = SnapshotInfo::from_file_name(entry.file_name()).height;
+        }
+    }
+    Ok(entries)
+}
+
+/// Path of the entry list in a preset's index
+fn preset_entries_path(preset: ProviderPreset) -> &'static str {
+    match preset {
+        ProviderPreset::Polkachu => "$.snapshot",
+        ProviderPreset::Quicksync => "$[*]",
+        ProviderPreset::Json | ProviderPreset::Listing => DEFAULT_ENTRIES_PATH,
+    }
+}
+
+/// Field paths of a preset's entries, for the fields not configured otherwise
+fn preset_fields(preset: ProviderPreset, configured: &ProviderFields) -> ProviderFields {
+    let defaults = ProviderFields::default();
+    let mut fields = configured.clone();
+    let fill = |field: &mut String, default: &str, path: &str| {
+        if field == default {
+            *field = path.to_string();
+        }
+    };
+
+    match preset {
+        ProviderPreset::Polkachu => fill(&mut fields.created, &defaults.created, "time"),
+        ProviderPreset::Quicksync => {
+            fill(&mut fields.pruning, &defaults.pruning, "network");
+            fill(&mut fields.size, &defaults.size, "filesize");
+            fill(&mut fields.checksum, &defaults.checksum, "hash");
+            fill(&mut fields.created, &defaults.created, "createdat");
+        }
+        ProviderPreset::Json | ProviderPreset::Listing => {}
+    }
+    fields
+}
+
+/// Collects the archives an HTML directory listing links to
+///
+/// Heights and creation dates come from the file names and the pruning
+/// variant from `pruned` or `archive` in them, as with `serve`.
+fn parse_listing(body: &str) -> Vec<IndexEntry> {
+    let links = Regex::new(r#"(?i)href\s*=\s*["']([^"']+)["']"#).expect("valid link pattern");
+
+    let mut entries: Vec<IndexEntry> = Vec::new();
+    for captures in links.captures_iter(body) {
+        let url = &captures[1];
+        let entry = IndexEntry {
+            url: url.to_string(),
+            ..Default::default()
+        };
+        let name = entry.file_name();
+        if ArchiveFormat::from_file_name(name).is_none()
+            || entries.iter().any(|listed| listed.url == url)
+        {
+            continue;
+        }
+
+        let info = SnapshotInfo::from_file_name(name);
+        let pruning = pruning_from_name(name);
+        entries.push(IndexEntry {
+            height: info.height,
+            pruning,
+            ..entry
+        });
+    }
+    entries
+}
+
+/// Builds an entry from a JSON object using the field mappings; entries without a URL are skipped
+fn map_entry(entry: &Value, fields: &ProviderFields) -> Option<IndexEntry> {
+    let field = |path: &str| {
+        json_path(entry, path)
+            .ok()
+            .and_then(|values| values.into_iter().next())
+            .filter(|value| !value.is_null())
+    };
+    let text = |path: &str| {
+        field(path).map(|value| match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        })
+    };
+    let number = |path: &str| text(path).and_then(|value| value.trim().parse::<u64>().ok());
+
+    Some(IndexEntry {
+        url: text(&fields.url)?,
+        height: number(&fields.height),
+        pruning: text(&fields.pruning),
+        size: number(&fields.size),
+        sha256: text(&fields.checksum),
+        kind: text(&fields.kind),
+        base_height: number(&fields.base),
+        format: text(&fields.format),
+        created: text(&fields.created),
+    })
+}
+
+/// Scans a text index with a regex whose named groups fill the entry fields
+fn parse_text_index(body: &str, pattern: &str) -> Result<Vec<IndexEntry>> {
+    let regex = Regex::new(pattern).context("Invalid provider pattern")?;
+    if !regex.capture_names().flatten().any(|name| name == "url") {
+        return Err(anyhow!("Provider pattern needs a named `url` group"));
+    }
+
+    Ok(regex
+        .captures_iter(body)
+        .filter_map(|captures| {
+            let group = |name: &str| captures.name(name).map(|m| m.as_str().to_string());
+            Some(IndexEntry {
+                url: group("url")?,
+                height: group("height").and_then(|h| h.parse().ok()),
+                pruning: group("pruning"),
+                size: group("size").and_then(|s| s.parse().ok()),
+                sha256: group("checksum"),
+                kind: group("kind"),
+                base_height: group("base").and_then(|b| b.parse().ok()),
+                format: group("format"),
+                created: group("created"),
+            })
+        })
+        .collect())
+}
+
+/// Evaluates a small JSONPath subset: `$`, `.key`, `['key']`, `[n]` and `[*]`
+///
+/// A leading `$` is optional, so field mappings can be written as plain
+/// dotted paths relative to an entry.
+fn json_path<'a>(root: &'a Value, path: &str) -> Result<Vec<&'a Value>> {
+    let mut current = vec![root];
+    let mut rest = path.trim().strip_prefix('$').unwrap_or(path.trim());
+
+    while !rest.is_empty() {
+        let (step, remaining) = if let Some(after) = rest.strip_prefix('[') {
+            let end = after
+                .find(']')
+                .with_context(|| format!("Unclosed '[' in path {}", path))?;
+            (&after[..end], &after[end + 1..])
+        } else {
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        rest = remaining;
+
+        let step = step.trim();
+        current = if step == "*" {
+            current
+                .into_iter()
+                .flat_map(|value| match value {
+                    Value::Array(items) => items.iter().collect::<Vec<_>>(),
+                    Value::Object(map) => map.values().collect(),
+                    _ => Vec::new(),
+                })
+                .collect()
+        } else if let Ok(index) = step.parse::<usize>() {
+            current
+                .into_iter()
+                .filter_map(|value| value.get(index))
+                .collect()
+        } else {
+            let key = step.trim_matches(|c| c == '\'' || c == '"');
+            current
+                .into_iter()
+                .filter_map(|value| value.get(key))
+                .collect()
+        };
+    }
+
+    Ok(current)
+}
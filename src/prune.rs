@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, instrument};
+
+use crate::config::{PruneConfig, PruneTool};
+use crate::db_backend::DbBackend;
+
+/// Blocks between pruning runs passed to the node's `prune` command
+const NODE_PRUNING_INTERVAL: u64 = 10;
+
+/// Prunes a restored node home down to the configured retention
+pub struct Pruner {
+    /// Pruning settings
+    config: PruneConfig,
+
+    /// Node home directory
+    data_dir: PathBuf,
+}
+
+impl Pruner {
+    /// Creates a pruner for the node home at `data_dir`
+    pub fn new(config: &PruneConfig, data_dir: &Path) -> Result<Self> {
+        Ok(Pruner {
+            config: config.clone(),
+            data_dir: std::path::absolute(data_dir).context("Failed to resolve data directory")?,
+        })
+    }
+
+    /// Runs the configured pruning tool against the node's databases
+    ///
+    /// # Arguments
+    /// * `binary` - Node binary, used for its `prune` command
+    /// * `backend` - Database backend of the restored data, when detected
+    #[instrument(skip(self, binary), fields(tool = ?self.config.tool))]
+    pub fn prune(&self, binary: &Path, backend: Option<DbBackend>) -> Result<()> {
+        let db_dir = self.data_dir.join("data");
+        let size_before = dir_size(&db_dir)?;
+
+        let mut command = match self.config.tool {
+            PruneTool::Node => {
+                let mut command = Command::new(binary);
+                command
+                    .arg("prune")
+                    .arg("custom")
+                    .arg(format!(
+                        "--pruning-keep-recent={}",
+                        self.config.keep_versions
+                    ))
+                    .arg(format!("--pruning-interval={}", NODE_PRUNING_INTERVAL))
+                    .arg("--home")
+                    .arg(&self.data_dir);
+                command
+            }
+            PruneTool::Cosmprund => {
+                let mut command = Command::new(&self.config.cosmprund);
+                command
+                    .arg("prune")
+                    .arg(&db_dir)
+                    .arg(format!("--blocks={}", self.config.keep_blocks))
+                    .arg(format!("--versions={}", self.config.keep_versions));
+                if let Some(backend) = backend {
+                    command.arg(format!("--backend={}", backend.as_str()));
+                }
+                command
+            }
+        };
+        command.args(&self.config.args);
+
+        info!(
+            "Pruning {} to the last {} versions",
+            db_dir.display(),
+            self.config.keep_versions
+        );
+        let status = command.status().with_context(|| {
+            format!(
+                "Failed to run {:?}; is it installed?",
+                command.get_program()
+            )
+        })?;
+        if !status.success() {
+            return Err(anyhow!("Pruning command failed with {}", status));
+        }
+
+        let size_after = dir_size(&db_dir)?;
+        info!(
+            "Pruned data from {:.2} GB to {:.2} GB",
+            size_before as f64 / 1e9,
+            size_after as f64 / 1e9
+        );
+        Ok(())
+    }
+}
+
+/// Total size of the files below a directory
+pub(crate) fn dir_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(total)
+}
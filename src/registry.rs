@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::{info, instrument};
+
+/// Chain metadata published in the Cosmos chain registry's `chain.json`
+#[derive(Debug, Deserialize)]
+pub struct ChainEntry {
+    /// Chain ID, e.g. `osmosis-1`
+    pub chain_id: String,
+
+    /// Name of the chain's daemon binary, e.g. `osmosisd`
+    #[serde(default)]
+    pub daemon_name: Option<String>,
+
+    /// Peers operators publish for the chain
+    #[serde(default)]
+    pub peers: RegistryPeers,
+
+    /// Source and release history of the chain's daemon
+    #[serde(default)]
+    pub codebase: Option<Codebase>,
+}
+
+/// Codebase section of a registry entry
+#[derive(Debug, Deserialize)]
+pub struct Codebase {
+    /// Release currently recommended for running the chain
+    #[serde(default)]
+    pub recommended_version: Option<String>,
+
+    /// Binaries of the recommended release keyed by platform in `os/arch` form
+    #[serde(default)]
+    pub binaries: HashMap<String, String>,
+
+    /// Where the chain's genesis file is published
+    #[serde(default)]
+    pub genesis: Option<RegistryGenesis>,
+
+    /// Versions the chain has run, one per upgrade
+    #[serde(default)]
+    pub versions: Vec<RegistryVersion>,
+}
+
+/// Genesis section of a registry entry's codebase
+#[derive(Debug, Deserialize)]
+pub struct RegistryGenesis {
+    /// URL of the genesis file
+    #[serde(default)]
+    pub genesis_url: Option<String>,
+}
+
+impl Codebase {
+    /// The recommended release and its binaries
+    ///
+    /// Prefers the version entry matching `recommended_version`, then the
+    /// codebase's own binaries, then the latest version that lists any.
+    pub fn recommended_binaries(&self) -> Option<(&str, &HashMap<String, String>)> {
+        let recommended = self.recommended_version.as_deref();
+        let matching = self.versions.iter().rfind(|v| {
+            !v.binaries.is_empty()
+                && recommended.is_some()
+                && v.recommended_version.as_deref() == recommended
+        });
+        if let Some(version) = matching {
+            return Some((recommended.unwrap_or_default(), &version.binaries));
+        }
+        if let (Some(recommended), false) = (recommended, self.binaries.is_empty()) {
+            return Some((recommended, &self.binaries));
+        }
+
+        self.versions
+            .iter()
+            .rfind(|v| !v.binaries.is_empty())
+            .map(|v| {
+                (
+                    v.recommended_version.as_deref().unwrap_or(&v.name),
+                    &v.binaries,
+                )
+            })
+    }
+}
+
+/// A chain version and the height it became active at
+#[derive(Debug, Deserialize)]
+pub struct RegistryVersion {
+    /// Upgrade name
+    pub name: String,
+
+    /// Release recommended for this version
+    #[serde(default)]
+    pub recommended_version: Option<String>,
+
+    /// Height at which the upgrade activated; absent for the genesis version
+    #[serde(default)]
+    pub height: Option<u64>,
+
+    /// Release binaries keyed by platform in `os/arch` form, e.g. `linux/amd64`
+    #[serde(default)]
+    pub binaries: HashMap<String, String>,
+}
+
+/// Seed and persistent peer lists from the registry
+#[derive(Debug, Default, Deserialize)]
+pub struct RegistryPeers {
+    #[serde(default)]
+    pub seeds: Vec<RegistryPeer>,
+
+    #[serde(default)]
+    pub persistent_peers: Vec<RegistryPeer>,
+}
+
+/// A single node from the registry's peer lists
+#[derive(Debug, Deserialize)]
+pub struct RegistryPeer {
+    /// Node ID
+    pub id: String,
+
+    /// `host:port` the node listens on
+    pub address: String,
+}
+
+impl RegistryPeers {
+    /// Seeds in config.toml's comma-separated `id@host:port` form
+    pub fn seeds_list(&self) -> String {
+        join_peers(&self.seeds)
+    }
+
+    /// Persistent peers in config.toml's comma-separated `id@host:port` form
+    pub fn persistent_peers_list(&self) -> String {
+        join_peers(&self.persistent_peers)
+    }
+}
+
+/// Looks up chains in the Cosmos chain registry
+pub struct ChainRegistry {
+    client: Client,
+
+    /// Base URL the registry's chain directories live under
+    base_url: String,
+}
+
+impl ChainRegistry {
+    /// Creates a registry client for the given base URL
+    pub fn new(base_url: &str) -> Self {
+        ChainRegistry {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Fetches the current registry entry for a chain
+    #[instrument(skip(self))]
+    pub async fn fetch(&self, chain: &str) -> Result<ChainEntry> {
+        let url = format!("{}/{}/chain.json", self.base_url, chain);
+        let entry: ChainEntry = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to query chain registry")?
+            .error_for_status()
+            .with_context(|| format!("Chain '{}' not found in the registry", chain))?
+            .json()
+            .await
+            .context("Failed to parse chain registry entry")?;
+
+        info!(
+            "Chain registry lists {} seeds and {} persistent peers for {}",
+            entry.peers.seeds.len(),
+            entry.peers.persistent_peers.len(),
+            entry.chain_id
+        );
+
+        Ok(entry)
+    }
+}
+
+/// Joins peers into a comma-separated `id@address` list
+fn join_peers(peers: &[RegistryPeer]) -> String {
+    peers
+        .iter()
+        .map(|peer| format!("{}@{}", peer.id, peer.address))
+        .collect::<Vec<_>>()
+        .join(",")
+}
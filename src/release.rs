@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{info, instrument, warn};
+
+use crate::checksum;
+
+/// GitHub REST API base URL
+const GITHUB_API: &str = "https://api.github.com";
+
+/// Name fragments identifying each supported operating system in asset names
+const OS_ALIASES: &[(&str, &[&str])] = &[
+    ("linux", &["linux"]),
+    ("macos", &["darwin", "macos", "apple"]),
+    ("windows", &["windows", "win64"]),
+];
+
+/// Name fragments identifying each supported CPU architecture in asset names
+const ARCH_ALIASES: &[(&str, &[&str])] = &[
+    ("x86_64", &["x86_64", "amd64", "x64"]),
+    ("aarch64", &["aarch64", "arm64"]),
+];
+
+/// Suffixes of release assets that are metadata rather than binaries
+const METADATA_SUFFIXES: &[&str] = &[
+    ".sha256", ".sha512", ".asc", ".sig", ".pem", ".sbom", ".json", ".txt", ".md5",
+];
+
+/// A release asset selected for download
+#[derive(Debug, Clone)]
+pub struct ReleaseAsset {
+    /// Asset file name
+    pub name: String,
+
+    /// Direct download URL
+    pub url: String,
+
+    /// SHA256 digest from the release's checksum file, when published
+    pub checksum: Option<String>,
+}
+
+/// Release metadata returned by the GitHub API
+#[derive(Debug, Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    assets: Vec<GithubAsset>,
+}
+
+/// Asset metadata returned by the GitHub API
+#[derive(Debug, Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Resolves node binaries from GitHub Releases
+pub struct GithubReleases {
+    client: Client,
+}
+
+impl Default for GithubReleases {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GithubReleases {
+    /// Creates a new GitHub Releases resolver
+    pub fn new() -> Self {
+        GithubReleases {
+            client: Client::new(),
+        }
+    }
+
+    /// Finds the release asset matching the host platform
+    ///
+    /// # Arguments
+    /// * `repo` - Repository in `owner/name` form
+    /// * `version` - Release tag, or `None` for the latest release
+    /// * `asset_pattern` - Optional regex that overrides platform matching
+    #[instrument(skip(self))]
+    pub async fn resolve(
+        &self,
+        repo: &str,
+        version: Option<&str>,
+        asset_pattern: Option<&str>,
+    ) -> Result<ReleaseAsset> {
+        let release = self.fetch_release(repo, version).await?;
+        info!(
+            "Found release {} with {} assets",
+            release.tag_name,
+            release.assets.len()
+        );
+
+        let asset = match asset_pattern {
+            Some(pattern) => {
+                let re = Regex::new(pattern).context("Invalid release asset pattern")?;
+                release.assets.iter().find(|a| re.is_match(&a.name))
+            }
+            None => select_platform_asset(&release.assets),
+        }
+        .with_context(|| {
+            format!(
+                "No asset in release {} matches {}/{}; set an asset pattern",
+                release.tag_name,
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )
+        })?;
+
+        let checksum = self.find_checksum(&release.assets, &asset.name).await?;
+        if checksum.is_none() {
+            warn!(
+                "Release {} publishes no checksum for {}",
+                release.tag_name, asset.name
+            );
+        }
+
+        Ok(ReleaseAsset {
+            name: asset.name.clone(),
+            url: asset.browser_download_url.clone(),
+            checksum,
+        })
+    }
+
+    /// Fetches release metadata for a tag or the latest release
+    async fn fetch_release(&self, repo: &str, version: Option<&str>) -> Result<GithubRelease> {
+        let url = match version {
+            Some(tag) => format!("{}/repos/{}/releases/tags/{}", GITHUB_API, repo, tag),
+            None => format!("{}/repos/{}/releases/latest", GITHUB_API, repo),
+        };
+
+        let mut request = self
+            .client
+            .get(&url)
+            .header("User-Agent", "snapshot-downloader")
+            .header("Accept", "application/vnd.github+json");
+
+        // An optional token raises the API rate limit for fleets behind one IP
+        if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .context("Failed to query GitHub Releases API")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "GitHub Releases API returned {} for {}",
+                response.status(),
+                url
+            ));
+        }
+
+        response
+            .json::<GithubRelease>()
+            .await
+            .context("Failed to parse GitHub release metadata")
+    }
+
+    /// Looks up the asset's digest in the release's checksum file, if any
+    async fn find_checksum(
+        &self,
+        assets: &[GithubAsset],
+        asset_name: &str,
+    ) -> Result<Option<String>> {
+        let candidates = assets.iter().filter(|a| {
+            let name = a.name.to_lowercase();
+            name.contains("checksum")
+                || name.contains("sha256sum")
+                || name == format!("{}.sha256", asset_name.to_lowercase())
+        });
+
+        for candidate in candidates {
+            let response = self
+                .client
+                .get(&candidate.browser_download_url)
+                .header("User-Agent", "snapshot-downloader")
+                .send()
+                .await
+                .context("Failed to download release checksum file")?;
+
+            if !response.status().is_success() {
+                warn!(
+                    "Failed to download {}: {}",
+                    candidate.name,
+                    response.status()
+                );
+                continue;
+            }
+
+            let body = response
+                .text()
+                .await
+                .context("Failed to read release checksum file")?;
+            if let Some(digest) = checksum::parse_checksum_file(&body, asset_name) {
+                info!("Found checksum for {} in {}", asset_name, candidate.name);
+                return Ok(Some(digest));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Picks the asset whose name mentions the host OS and architecture
+///
+/// When several match, the shortest name wins, which skips variants such as
+/// `-testnet` or `-rocksdb` builds published next to the default one.
+fn select_platform_asset(assets: &[GithubAsset]) -> Option<&GithubAsset> {
+    let os_names = aliases_for(OS_ALIASES, std::env::consts::OS);
+    let arch_names = aliases_for(ARCH_ALIASES, std::env::consts::ARCH);
+
+    assets
+        .iter()
+        .filter(|a| {
+            let name = a.name.to_lowercase();
+            !METADATA_SUFFIXES.iter().any(|s| name.ends_with(s))
+                && os_names.iter().any(|os| name.contains(os))
+                && arch_names.iter().any(|arch| name.contains(arch))
+        })
+        .min_by_key(|a| a.name.len())
+}
+
+/// Returns the name fragments for a platform value, defaulting to the value itself
+fn aliases_for(table: &[(&str, &'static [&'static str])], value: &str) -> Vec<String> {
+    table
+        .iter()
+        .find(|(key, _)| *key == value)
+        .map(|(_, aliases)| aliases.iter().map(|a| a.to_string()).collect())
+        .unwrap_or_else(|| vec![value.to_string()])
+}
@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Machine-readable summary of a completed run
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    /// URL the snapshot was downloaded from
+    pub snapshot_url: String,
+
+    /// How that URL was chosen
+    pub snapshot_source: SnapshotSource,
+
+    /// Local file name of the snapshot archive
+    pub snapshot_file: String,
+
+    /// Block height the snapshot was taken at, when known
+    pub snapshot_height: Option<u64>,
+
+    /// Snapshot creation time in seconds since the Unix epoch, when known
+    pub snapshot_created: Option<u64>,
+
+    /// Where the node binary came from
+    pub binary_source: String,
+
+    /// Node data directory
+    pub data_dir: String,
+
+    /// Completion time in seconds since the Unix epoch
+    pub completed_at: u64,
+}
+
+/// Where the snapshot URL of a run came from
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotSource {
+    /// Configured `snapshot_url`
+    #[default]
+    SnapshotUrl,
+
+    /// Resolved from `snapshot_provider`
+    Provider,
+
+    /// Configured `snapshot_url`, used because the provider failed
+    Fallback,
+}
+
+impl RunReport {
+    /// Stamps the completion time and writes the report as pretty-printed JSON
+    pub fn write<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.completed_at = unix_seconds(SystemTime::now());
+
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize run report")?;
+        std::fs::write(path, json).context("Failed to write run report")?;
+
+        Ok(())
+    }
+}
+
+/// Converts a timestamp to whole seconds since the Unix epoch
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
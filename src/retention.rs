@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, instrument, warn};
+
+use crate::config::RetentionConfig;
+
+/// An archive or extracted directory in the snapshots directory
+struct CacheEntry {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Deletes old archives and extractions from the snapshots directory
+///
+/// Archives (files) and extractions (directories) are each limited to the
+/// `keep_last` most recent entries; afterwards the oldest remaining entries are
+/// removed until the directory fits in `max_cache_size`. Entries in `protected`
+/// (the artifacts of the current run) are never deleted.
+#[instrument(skip(snapshots_dir, policy, protected), fields(dir = %snapshots_dir.as_ref().display()))]
+pub fn apply_retention<P: AsRef<Path>>(
+    snapshots_dir: P,
+    policy: &RetentionConfig,
+    protected: &[PathBuf],
+) -> Result<()> {
+    let (mut files, mut dirs) = list_entries(snapshots_dir.as_ref())?;
+    let is_protected = |entry: &CacheEntry| protected.iter().any(|p| p == &entry.path);
+
+    let mut kept = Vec::new();
+    for group in [&mut files, &mut dirs] {
+        // Newest first, so everything past keep_last is older
+        group.sort_by_key(|entry| std::cmp::Reverse(entry.modified));
+
+        for (index, entry) in group.drain(..).enumerate() {
+            let over_limit = policy.keep_last.is_some_and(|keep| index >= keep);
+            if over_limit && !is_protected(&entry) {
+                remove_entry(&entry)?;
+            } else {
+                kept.push(entry);
+            }
+        }
+    }
+
+    if let Some(max_size) = policy.max_cache_size {
+        kept.sort_by_key(|entry| entry.modified);
+        let mut total: u64 = kept.iter().map(|e| e.size).sum();
+
+        for entry in &kept {
+            if total <= max_size {
+                break;
+            }
+            if is_protected(entry) {
+                continue;
+            }
+            remove_entry(entry)?;
+            total = total.saturating_sub(entry.size);
+        }
+
+        if total > max_size {
+            warn!(
+                "Snapshots directory still uses {:.2} GB after cleanup, above the {:.2} GB limit",
+                total as f64 / 1e9,
+                max_size as f64 / 1e9
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists top-level files and directories with their modification time and size
+fn list_entries(dir: &Path) -> Result<(Vec<CacheEntry>, Vec<CacheEntry>)> {
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+
+    for entry in fs::read_dir(dir).context("Failed to read snapshots directory")? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        let cache_entry = CacheEntry {
+            modified: metadata.modified()?,
+            size: if metadata.is_dir() {
+                dir_size(&path)?
+            } else {
+                metadata.len()
+            },
+            path,
+        };
+
+        if metadata.is_dir() {
+            dirs.push(cache_entry);
+        } else {
+            files.push(cache_entry);
+        }
+    }
+
+    Ok((files, dirs))
+}
+
+/// Computes the total size of a directory tree
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(total)
+}
+
+/// Deletes a cached archive or extracted directory
+fn remove_entry(entry: &CacheEntry) -> Result<()> {
+    info!(
+        "Removing {} ({:.2} MB) from snapshots directory",
+        entry.path.display(),
+        entry.size as f64 / 1_048_576.0
+    );
+
+    if entry.path.is_dir() {
+        fs::remove_dir_all(&entry.path)
+    } else {
+        fs::remove_file(&entry.path)
+    }
+    .with_context(|| format!("Failed to remove {}", entry.path.display()))
+}
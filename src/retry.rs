@@ -0,0 +1,117 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::{RetryConfig, RetryStage};
+
+/// How often and how patiently a failing operation is retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts including the first
+    max_attempts: u32,
+
+    /// Wait before the first retry
+    base_delay: Duration,
+
+    /// Upper bound on the wait between attempts
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    /// The configured policy for a stage, or a single attempt if the stage isn't listed
+    pub fn for_stage(config: &RetryConfig, stage: RetryStage) -> Self {
+        if !config.stages.contains(&stage) {
+            return Self::none();
+        }
+
+        RetryPolicy {
+            max_attempts: config.max_attempts.max(1),
+            base_delay: Duration::from_secs(config.base_delay_secs),
+            max_delay: Duration::from_secs(config.max_delay_secs),
+        }
+    }
+
+    /// Runs `operation` until it succeeds or the attempts are used up
+    ///
+    /// Every failure but the last is logged with the delay before the next
+    /// attempt; the last one is returned.
+    pub async fn run<T, E, F, Fut>(&self, what: &str, operation: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.run_resumable(what, || 0, operation).await
+    }
+
+    /// Runs a resumable `operation` until it succeeds or fails the attempts in a row
+    ///
+    /// `progress` reports how far the operation has got, e.g. the bytes of a
+    /// download on disk. A failed attempt that got further than any before it
+    /// doesn't count against the attempts and the backoff starts over, so a
+    /// long download over a connection that keeps dropping still finishes as
+    /// long as each attempt makes headway.
+    pub async fn run_resumable<T, E, F, Fut, P>(
+        &self,
+        what: &str,
+        progress: P,
+        mut operation: F,
+    ) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        P: Fn() -> u64,
+    {
+        let mut attempt = 1;
+        let mut reached = progress();
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if self.max_attempts > 1 && progress() > reached => {
+                    reached = progress();
+                    attempt = 1;
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{} failed after making progress, resuming in {}s: {:#}",
+                        what,
+                        delay.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) if attempt < self.max_attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{} failed (attempt {}/{}), retrying in {}s: {:#}",
+                        what,
+                        attempt,
+                        self.max_attempts,
+                        delay.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Exponential backoff for the retry following the given attempt
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay)
+    }
+}
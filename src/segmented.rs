@@ -0,0 +1,759 @@
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::{Client, StatusCode};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+use tracing::{debug, info, instrument, warn};
+
+use crate::checksum::{self, ChunkManifest};
+use crate::config::RequestConfig;
+use crate::downloader::build_request;
+use crate::metrics;
+use crate::notify;
+use crate::throttle::Throttle;
+
+/// Connections opened before the first throughput measurement
+const INITIAL_CONNECTIONS: usize = 2;
+
+/// Attempts per segment before the whole download is failed
+const MAX_SEGMENT_ATTEMPTS: u32 = 3;
+
+/// Wait before retrying a failed segment, multiplied by its failed attempts
+const SEGMENT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Overall rate gain an added connection must bring to keep ramping up
+const RAMP_UP_GAIN: f64 = 1.1;
+
+/// Overall rate loss after which a connection is dropped
+const BACK_OFF_LOSS: f64 = 0.8;
+
+/// Share of failed requests in a window above which a connection is dropped
+const MAX_ERROR_RATE: f64 = 0.1;
+
+/// Response data a connection may hold in buffers before it is written out
+const CONNECTION_MEMORY: u64 = 1_000_000;
+
+/// Completed segments of a partially downloaded file
+///
+/// Kept next to the download as `<file>.segments`: a header line with the
+/// file and segment sizes, then the index of every finished segment.
+pub struct SegmentState {
+    /// Path of the state file
+    path: PathBuf,
+
+    /// Total size of the file being downloaded
+    total_size: u64,
+
+    /// Size of every segment but possibly the last
+    segment_size: u64,
+
+    /// Which segments are on disk
+    done: Vec<bool>,
+}
+
+impl SegmentState {
+    /// Loads the state of a download, or derives it from what is already on disk
+    ///
+    /// A state file written for a different size is discarded. Without a state
+    /// file, a complete file counts as done and a shorter one, left by a
+    /// single-stream download, keeps the segments it fully covers.
+    pub fn load(output_path: &Path, total_size: u64, segment_size: u64) -> Result<Self> {
+        let path = state_path(output_path);
+        let count = total_size.div_ceil(segment_size) as usize;
+        let mut state = SegmentState {
+            path,
+            total_size,
+            segment_size,
+            done: vec![false; count],
+        };
+
+        if state.path.exists() {
+            let contents = fs::read_to_string(&state.path)
+                .with_context(|| format!("Failed to read {}", state.path.display()))?;
+            let mut lines = contents.lines();
+            if lines.next() == Some(state.header().as_str()) {
+                for index in lines.filter_map(|line| line.trim().parse::<usize>().ok()) {
+                    if let Some(done) = state.done.get_mut(index) {
+                        *done = true;
+                    }
+                }
+            } else {
+                warn!(
+                    "Segment state {} doesn't match the remote file, restarting download",
+                    state.path.display()
+                );
+            }
+        } else if let Ok(metadata) = fs::metadata(output_path) {
+            let existing = metadata.len();
+            if existing == total_size {
+                state.done.fill(true);
+                return Ok(state);
+            } else if existing < total_size {
+                let covered = (existing / segment_size) as usize;
+                state.done[..covered].fill(true);
+            }
+        }
+
+        state.save()?;
+        Ok(state)
+    }
+
+    /// Re-hashes the segments on disk against a chunk manifest
+    ///
+    /// Segments whose digest doesn't match are marked missing, so only they
+    /// are downloaded again. Returns how many were corrupt.
+    pub fn verify_chunks(&mut self, output_path: &Path, manifest: &ChunkManifest) -> Result<usize> {
+        let mut corrupt = 0;
+        for index in 0..self.done.len() {
+            if !self.done[index] {
+                continue;
+            }
+            let digest = checksum::sha256_range(output_path, self.range(index))
+                .with_context(|| format!("Failed to hash segment {}", index))?;
+            if !manifest.matches(index, &digest) {
+                warn!("Segment {} on disk doesn't match the chunk manifest", index);
+                self.done[index] = false;
+                corrupt += 1;
+            }
+        }
+
+        if corrupt > 0 {
+            self.save()?;
+        }
+        Ok(corrupt)
+    }
+
+    /// Whether every segment is on disk
+    pub fn is_complete(&self) -> bool {
+        self.done.iter().all(|done| *done)
+    }
+
+    /// Byte ranges of the segments still to download
+    fn missing(&self) -> VecDeque<(usize, Range<u64>)> {
+        self.done
+            .iter()
+            .enumerate()
+            .filter(|(_, done)| !**done)
+            .map(|(index, _)| (index, self.range(index)))
+            .collect()
+    }
+
+    /// Number of bytes already on disk
+    pub fn downloaded(&self) -> u64 {
+        self.done
+            .iter()
+            .enumerate()
+            .filter(|(_, done)| **done)
+            .map(|(index, _)| {
+                let range = self.range(index);
+                range.end - range.start
+            })
+            .sum()
+    }
+
+    /// Byte range covered by a segment
+    fn range(&self, index: usize) -> Range<u64> {
+        let start = index as u64 * self.segment_size;
+        start..(start + self.segment_size).min(self.total_size)
+    }
+
+    /// Records a finished segment
+    fn mark_done(&mut self, index: usize) -> Result<()> {
+        self.done[index] = true;
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        writeln!(file, "{}", index)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Rewrites the state file from scratch
+    fn save(&self) -> Result<()> {
+        let mut contents = self.header();
+        contents.push('\n');
+        for (index, _) in self.done.iter().enumerate().filter(|(_, done)| **done) {
+            contents.push_str(&format!("{}\n", index));
+        }
+        fs::write(&self.path, contents)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Removes the state file once the download is complete
+    fn finish(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// First line of the state file, identifying the download it belongs to
+    fn header(&self) -> String {
+        format!("{} {}", self.total_size, self.segment_size)
+    }
+}
+
+/// Number of parallel connections whose buffers fit in `max_memory`
+pub fn connections_within(max_memory: u64) -> usize {
+    (max_memory / CONNECTION_MEMORY).max(1) as usize
+}
+
+/// Path of the segment state file kept next to a download
+pub fn state_path(output_path: &Path) -> PathBuf {
+    let mut name = output_path.as_os_str().to_os_string();
+    name.push(".segments");
+    PathBuf::from(name)
+}
+
+/// Adjusts the number of parallel connections to what the server sustains
+///
+/// Works in windows of as many finished segments as there are connections.
+/// At the end of a window a connection is added while doing so keeps raising
+/// the overall rate, and one is dropped when the rate falls or too many
+/// requests failed. A throttling response halves the connections at once.
+struct ConnectionController {
+    /// Connections currently allowed
+    limit: usize,
+
+    /// Upper bound on connections
+    max: usize,
+
+    /// When the current window started
+    window_started: Instant,
+
+    /// Bytes of the segments finished in the current window
+    window_bytes: u64,
+
+    /// Segments finished in the current window
+    window_segments: usize,
+
+    /// Failed requests in the current window
+    window_errors: usize,
+
+    /// Sum of the rates of the individual connections in the current window
+    window_connection_rates: f64,
+
+    /// Overall rate of the previous window
+    previous_rate: Option<f64>,
+}
+
+impl ConnectionController {
+    /// Creates a controller allowing at most `max` connections
+    fn new(max: usize) -> Self {
+        let max = max.max(1);
+        ConnectionController {
+            limit: INITIAL_CONNECTIONS.min(max),
+            max,
+            window_started: Instant::now(),
+            window_bytes: 0,
+            window_segments: 0,
+            window_errors: 0,
+            window_connection_rates: 0.0,
+            previous_rate: None,
+        }
+    }
+
+    /// Connections currently allowed
+    fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Records a finished segment
+    fn record_success(&mut self, bytes: u64, elapsed: Duration) {
+        self.window_bytes += bytes;
+        self.window_segments += 1;
+        self.window_connection_rates += bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+
+        if self.window_segments >= self.limit {
+            self.adjust();
+        }
+    }
+
+    /// Records a failed request
+    fn record_failure(&mut self, throttled: bool) {
+        if throttled {
+            let limit = (self.limit / 2).max(1);
+            if limit != self.limit {
+                warn!(
+                    "Server is throttling, backing off from {} to {} connections",
+                    self.limit, limit
+                );
+            }
+            self.limit = limit;
+            self.previous_rate = None;
+            self.reset_window();
+        } else {
+            self.window_errors += 1;
+        }
+    }
+
+    /// Picks the connection count for the next window from the one just finished
+    fn adjust(&mut self) {
+        let elapsed = self
+            .window_started
+            .elapsed()
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let rate = self.window_bytes as f64 / elapsed;
+        let connection_rate = self.window_connection_rates / self.window_segments as f64;
+        let error_rate =
+            self.window_errors as f64 / (self.window_segments + self.window_errors) as f64;
+
+        let limit = if error_rate > MAX_ERROR_RATE {
+            self.limit.saturating_sub(1)
+        } else {
+            match self.previous_rate {
+                None => self.limit + 1,
+                Some(previous) if rate > previous * RAMP_UP_GAIN => self.limit + 1,
+                Some(previous) if rate < previous * BACK_OFF_LOSS => self.limit.saturating_sub(1),
+                Some(_) => self.limit,
+            }
+        }
+        .clamp(1, self.max);
+
+        debug!(
+            "{} connections: {:.2} MB/s overall, {:.2} MB/s per connection, {:.0}% errors",
+            self.limit,
+            rate / 1_048_576.0,
+            connection_rate / 1_048_576.0,
+            error_rate * 100.0
+        );
+        if limit != self.limit {
+            info!(
+                "Adjusting download connections from {} to {} ({:.2} MB/s overall)",
+                self.limit,
+                limit,
+                rate / 1_048_576.0
+            );
+        }
+
+        self.limit = limit;
+        self.previous_rate = Some(rate);
+        self.reset_window();
+    }
+
+    /// Starts a new measurement window
+    fn reset_window(&mut self) {
+        self.window_started = Instant::now();
+        self.window_bytes = 0;
+        self.window_segments = 0;
+        self.window_errors = 0;
+        self.window_connection_rates = 0.0;
+    }
+}
+
+/// A failed segment request
+struct SegmentFailure {
+    /// Whether the server asked us to slow down
+    throttled: bool,
+
+    /// What went wrong
+    error: anyhow::Error,
+}
+
+/// Downloads the missing segments of a file over parallel range requests
+///
+/// The file must already exist with its full length. Finished segments are
+/// recorded in `state` as they complete, so a failed download resumes with
+/// only the unfinished ones. With a chunk manifest, whose chunks must be the
+/// segments, each segment is hashed as it streams in and fetched again when
+/// it doesn't match.
+///
+/// With a throttle, all connections together stay within its rate.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(url = %url))]
+pub async fn download(
+    client: &Client,
+    url: &str,
+    output_path: &Path,
+    mut state: SegmentState,
+    max_connections: usize,
+    manifest: Option<&ChunkManifest>,
+    request: Option<&RequestConfig>,
+    throttle: Option<&Arc<Throttle>>,
+) -> Result<()> {
+    let mut pending = state.missing();
+    let mut attempts = vec![0u32; state.done.len()];
+    let mut controller = ConnectionController::new(max_connections);
+    info!(
+        "Downloading {} of {} segments with up to {} connections",
+        pending.len(),
+        state.done.len(),
+        controller.max
+    );
+
+    let progress_bar = ProgressBar::new(state.total_size);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg} ({eta})")?
+            .progress_chars("#>-"),
+    );
+    progress_bar.set_position(state.downloaded());
+
+    let mut tasks = JoinSet::new();
+    loop {
+        while tasks.len() < controller.limit() {
+            let Some((index, range)) = pending.pop_front() else {
+                break;
+            };
+            let delay = SEGMENT_RETRY_DELAY * attempts[index];
+            let client = client.clone();
+            let url = url.to_string();
+            let path = output_path.to_path_buf();
+            let progress_bar = progress_bar.clone();
+            let digest = manifest.map(|manifest| manifest.chunks[index].clone());
+            let request = request.cloned();
+            let throttle = throttle.cloned();
+            tasks.spawn(async move {
+                tokio::time::sleep(delay).await;
+                let started = Instant::now();
+                let result = match build_request(&client, &url, request.as_ref()) {
+                    Ok(request) => {
+                        fetch_segment(
+                            request,
+                            &path,
+                            range.clone(),
+                            digest.as_deref(),
+                            &progress_bar,
+                            throttle.as_deref(),
+                        )
+                        .await
+                    }
+                    Err(error) => Err(SegmentFailure {
+                        throttled: false,
+                        error,
+                    }),
+                };
+                (index, range, started.elapsed(), result)
+            });
+        }
+        progress_bar.set_message(format!("{} connections", tasks.len()));
+
+        let Some(joined) = tasks.join_next().await else {
+            break;
+        };
+        let (index, range, elapsed, result) = joined.context("Segment task panicked")?;
+        match result {
+            Ok(bytes) => {
+                controller.record_success(bytes, elapsed);
+                state.mark_done(index)?;
+                notify::status(&format!(
+                    "Downloaded {} of {} segments",
+                    state.done.iter().filter(|done| **done).count(),
+                    state.done.len()
+                ));
+            }
+            Err(failure) => {
+                controller.record_failure(failure.throttled);
+                attempts[index] += 1;
+                if attempts[index] >= MAX_SEGMENT_ATTEMPTS {
+                    tasks.abort_all();
+                    progress_bar.abandon();
+                    return Err(failure.error.context(format!(
+                        "Segment {} failed {} times",
+                        index, MAX_SEGMENT_ATTEMPTS
+                    )));
+                }
+                warn!("Segment {} failed, retrying: {:#}", index, failure.error);
+                pending.push_front((index, range));
+            }
+        }
+    }
+
+    if !state.is_complete() {
+        return Err(anyhow!("Segmented download ended with missing segments"));
+    }
+    state.finish()?;
+    progress_bar.finish_with_message("done");
+    Ok(())
+}
+
+/// Downloads one segment into its place in the output file
+///
+/// Returns the number of bytes written. Progress made by a failed request is
+/// taken back off the progress bar, since the segment is fetched again whole.
+async fn fetch_segment(
+    request: reqwest::RequestBuilder,
+    output_path: &Path,
+    range: Range<u64>,
+    digest: Option<&str>,
+    progress_bar: &ProgressBar,
+    throttle: Option<&Throttle>,
+) -> std::result::Result<u64, SegmentFailure> {
+    let mut written = 0;
+    let result = write_segment(
+        request,
+        output_path,
+        &range,
+        digest,
+        progress_bar,
+        throttle,
+        &mut written,
+    )
+    .await;
+    if result.is_err() {
+        progress_bar.dec(written);
+    }
+    result.map(|_| written)
+}
+
+/// Streams a range request into the output file, counting the bytes written
+///
+/// When `digest` is given, the segment's SHA256 must match it.
+async fn write_segment(
+    request: reqwest::RequestBuilder,
+    output_path: &Path,
+    range: &Range<u64>,
+    digest: Option<&str>,
+    progress_bar: &ProgressBar,
+    throttle: Option<&Throttle>,
+    written: &mut u64,
+) -> std::result::Result<(), SegmentFailure> {
+    let failure = |error: anyhow::Error| SegmentFailure {
+        throttled: false,
+        error,
+    };
+
+    let response = request
+        .header("Range", format!("bytes={}-{}", range.start, range.end - 1))
+        .send()
+        .await
+        .context("Failed to send range request")
+        .map_err(failure)?;
+
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+        return Err(SegmentFailure {
+            throttled: true,
+            error: anyhow!("Server responded with {}", status),
+        });
+    }
+    if status != StatusCode::PARTIAL_CONTENT {
+        return Err(failure(anyhow!(
+            "Expected 206 Partial Content for a range request, got {}",
+            status
+        )));
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(output_path)
+        .await
+        .context("Failed to open output file")
+        .map_err(failure)?;
+    file.seek(SeekFrom::Start(range.start))
+        .await
+        .context("Failed to seek in output file")
+        .map_err(failure)?;
+
+    let expected = range.end - range.start;
+    let mut hasher = digest.map(|_| Sha256::new());
+    let mut stream = response.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let chunk = item
+            .context("Error while downloading segment")
+            .map_err(failure)?;
+        if *written + chunk.len() as u64 > expected {
+            return Err(failure(anyhow!(
+                "Server sent more than the requested range"
+            )));
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&chunk);
+        }
+        file.write_all(&chunk)
+            .await
+            .context("Error while writing to file")
+            .map_err(failure)?;
+        *written += chunk.len() as u64;
+        progress_bar.inc(chunk.len() as u64);
+        metrics::add_downloaded(chunk.len() as u64);
+        if let Some(throttle) = throttle {
+            throttle.consume_async(chunk.len()).await;
+        }
+    }
+
+    if *written != expected {
+        return Err(failure(anyhow!(
+            "Segment ended after {} of {} bytes",
+            written,
+            expected
+        )));
+    }
+    if let (Some(hasher), Some(digest)) = (hasher, digest) {
+        let actual = format!("{:x}", hasher.finalize());
+        if !actual.eq_ignore_ascii_case(digest) {
+            return Err(failure(anyhow!(
+                "Segment checksum mismatch: expected {}, got {}",
+                digest,
+                actual
+            )));
+        }
+    }
+    file.flush()
+        .await
+        .context("Error while writing to file")
+        .map_err(failure)?;
+    // The segment is recorded as done next, so it must survive a crash first
+    file.sync_data()
+        .await
+        .context("Failed to sync segment to disk")
+        .map_err(failure)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a file of `len` bytes and, when given, a state file beside it
+    fn partial_download(dir: &Path, len: usize, state: Option<&str>) -> PathBuf {
+        let path = dir.join("snap.tar");
+        fs::write(&path, vec![0u8; len]).unwrap();
+        if let Some(state) = state {
+            fs::write(state_path(&path), state).unwrap();
+        }
+        path
+    }
+
+    fn done_segments(state: &SegmentState) -> Vec<usize> {
+        (0..state.done.len()).filter(|i| state.done[*i]).collect()
+    }
+
+    #[test]
+    fn resumes_the_recorded_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = partial_download(dir.path(), 95, Some("95 10\n0\n3\nnot a segment\n42\n"));
+
+        let state = SegmentState::load(&path, 95, 10).unwrap();
+        assert_eq!(done_segments(&state), [0, 3]);
+        assert_eq!(state.downloaded(), 20);
+        assert_eq!(state.range(9), 90..95);
+        let missing: Vec<usize> = state.missing().into_iter().map(|(i, _)| i).collect();
+        assert_eq!(missing, [1, 2, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn restarts_when_the_header_does_not_match() {
+        let dir = tempfile::tempdir().unwrap();
+        for header in ["100 20", "96 10", "garbage"] {
+            let path = partial_download(dir.path(), 95, Some(&format!("{}\n0\n1\n", header)));
+            let state = SegmentState::load(&path, 95, 10).unwrap();
+            assert!(done_segments(&state).is_empty(), "{}", header);
+            assert_eq!(fs::read_to_string(state_path(&path)).unwrap(), "95 10\n");
+        }
+    }
+
+    #[test]
+    fn keeps_the_segments_a_single_stream_download_covered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = partial_download(dir.path(), 35, None);
+
+        let state = SegmentState::load(&path, 95, 10).unwrap();
+        assert_eq!(done_segments(&state), [0, 1, 2]);
+        assert_eq!(state.downloaded(), 30);
+        assert_eq!(
+            fs::read_to_string(state_path(&path)).unwrap(),
+            "95 10\n0\n1\n2\n"
+        );
+    }
+
+    #[test]
+    fn counts_a_complete_file_as_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = partial_download(dir.path(), 95, None);
+        let state = SegmentState::load(&path, 95, 10).unwrap();
+        assert!(state.is_complete());
+        assert!(!state_path(&path).exists());
+
+        // A longer file isn't this download at all
+        let path = partial_download(dir.path(), 120, None);
+        let state = SegmentState::load(&path, 95, 10).unwrap();
+        assert!(done_segments(&state).is_empty());
+    }
+
+    /// Finishes a window of one segment per connection, `bytes` in all, over a second
+    fn finish_window(controller: &mut ConnectionController, bytes: u64) {
+        controller.window_started = Instant::now() - Duration::from_secs(1);
+        let segments = controller.limit();
+        for _ in 0..segments {
+            controller.record_success(bytes / segments as u64, Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn ramps_up_while_the_rate_grows() {
+        let mut controller = ConnectionController::new(4);
+        assert_eq!(controller.limit(), INITIAL_CONNECTIONS);
+
+        // The first window has nothing to compare against, so it tries one more
+        finish_window(&mut controller, 2_000_000);
+        assert_eq!(controller.limit(), 3);
+        finish_window(&mut controller, 3_000_000);
+        assert_eq!(controller.limit(), 4);
+        finish_window(&mut controller, 4_000_000);
+        assert_eq!(controller.limit(), 4, "capped at the maximum");
+
+        assert_eq!(ConnectionController::new(1).limit(), 1);
+        assert_eq!(ConnectionController::new(0).limit(), 1);
+    }
+
+    #[test]
+    fn holds_or_backs_off_when_the_rate_stalls() {
+        let mut controller = ConnectionController::new(8);
+        finish_window(&mut controller, 2_000_000);
+        assert_eq!(controller.limit(), 3);
+
+        // Within the ramp-up gain and the back-off loss nothing changes
+        finish_window(&mut controller, 2_100_000);
+        assert_eq!(controller.limit(), 3);
+        finish_window(&mut controller, 1_200_000);
+        assert_eq!(controller.limit(), 2);
+    }
+
+    #[test]
+    fn backs_off_when_requests_fail() {
+        let mut controller = ConnectionController::new(8);
+        finish_window(&mut controller, 2_000_000);
+        assert_eq!(controller.limit(), 3);
+
+        controller.record_failure(false);
+        finish_window(&mut controller, 6_000_000);
+        assert_eq!(controller.limit(), 2, "one failure in four requests");
+    }
+
+    #[test]
+    fn halves_on_throttling() {
+        let mut controller = ConnectionController::new(16);
+        for window in 1..=6 {
+            finish_window(&mut controller, window * 2_000_000);
+        }
+        let limit = controller.limit();
+        assert!(limit >= 6, "ramped up to {}", limit);
+
+        controller.record_failure(true);
+        assert_eq!(controller.limit(), limit / 2);
+        controller.record_failure(true);
+        assert_eq!(controller.limit(), limit / 4);
+
+        // The measured rate is forgotten, so the next window ramps up again
+        finish_window(&mut controller, 1_000);
+        assert_eq!(controller.limit(), limit / 4 + 1);
+
+        let mut controller = ConnectionController::new(1);
+        controller.record_failure(true);
+        assert_eq!(controller.limit(), 1);
+    }
+}
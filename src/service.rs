@@ -0,0 +1,213 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, instrument};
+
+use crate::config::{CosmosConfig, SystemdConfig};
+use crate::setup;
+
+/// Directory systemd loads administrator-installed units from
+const UNIT_DIR: &str = "/etc/systemd/system";
+
+/// Open file limit of the unit when none is configured, enough for the
+/// databases and peer connections of a busy node
+const DEFAULT_OPEN_FILES_LIMIT: u64 = 65535;
+
+/// A systemd service that starts the node
+pub struct ServiceUnit {
+    /// Unit settings
+    config: SystemdConfig,
+
+    /// Unit name without `.service`
+    name: String,
+
+    /// Program and arguments the service runs
+    exec_start: Vec<String>,
+
+    /// Node home, the service's working directory
+    home: PathBuf,
+
+    /// File with the environment the program needs, e.g. cosmovisor's
+    environment_file: Option<PathBuf>,
+
+    /// Seconds systemd waits before restarting the node
+    restart_delay_secs: u64,
+
+    /// `LimitNOFILE` of the service
+    open_files_limit: u64,
+}
+
+impl ServiceUnit {
+    /// Describes a service running the node's start command with `--home` set
+    ///
+    /// # Arguments
+    /// * `config` - Unit settings
+    /// * `cosmos` - Cosmos node configuration providing the start command
+    /// * `binary_path` - Path to the node binary
+    /// * `home` - Node home directory
+    pub fn new(
+        config: &SystemdConfig,
+        cosmos: &CosmosConfig,
+        binary_path: &Path,
+        home: &Path,
+    ) -> Result<Self> {
+        let binary_path =
+            std::path::absolute(binary_path).context("Failed to resolve binary path")?;
+        let home = std::path::absolute(home).context("Failed to resolve node home")?;
+        let name = match &config.name {
+            Some(name) => name.trim_end_matches(".service").to_string(),
+            None => binary_path
+                .file_name()
+                .context("Failed to determine binary name from cosmos.bin")?
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        let mut exec_start = vec![binary_path.display().to_string()];
+        exec_start.extend(setup::node_args(&cosmos.start_command, &home, true)?);
+
+        Ok(ServiceUnit {
+            config: config.clone(),
+            name,
+            exec_start,
+            home,
+            environment_file: None,
+            restart_delay_secs: 5,
+            open_files_limit: DEFAULT_OPEN_FILES_LIMIT,
+        })
+    }
+
+    /// Runs the node through `cosmovisor run` with the environment in `environment_file`
+    pub fn with_cosmovisor(mut self, path: &str, environment_file: PathBuf) -> Self {
+        self.exec_start[0] = find_executable(path);
+        self.exec_start.insert(1, "run".to_string());
+        self.environment_file = Some(environment_file);
+        self
+    }
+
+    /// Restarts the node after `delay_secs` and raises its open file limit as configured
+    pub fn with_restart_policy(mut self, delay_secs: u64, open_files_limit: Option<u64>) -> Self {
+        self.restart_delay_secs = delay_secs;
+        if let Some(limit) = open_files_limit {
+            self.open_files_limit = limit;
+        }
+        self
+    }
+
+    /// Unit name, e.g. `cronosd.service`
+    pub fn unit_name(&self) -> String {
+        format!("{}.service", self.name)
+    }
+
+    /// Contents of the unit file
+    pub fn render(&self) -> String {
+        let mut service = vec!["Type=simple".to_string()];
+        if let Some(user) = &self.config.user {
+            service.push(format!("User={}", user));
+        }
+        service.push(format!(
+            "WorkingDirectory={}",
+            escape_specifiers(&self.home.display().to_string())
+        ));
+        if let Some(file) = &self.environment_file {
+            service.push(format!(
+                "EnvironmentFile={}",
+                escape_specifiers(&file.display().to_string())
+            ));
+        }
+        let exec_start: Vec<String> = self.exec_start.iter().map(|arg| exec_arg(arg)).collect();
+        service.push(format!("ExecStart={}", exec_start.join(" ")));
+        service.push("Restart=on-failure".to_string());
+        service.push(format!("RestartSec={}", self.restart_delay_secs));
+        service.push(format!("LimitNOFILE={}", self.open_files_limit));
+
+        format!(
+            "[Unit]\nDescription={} node\nAfter=network-online.target\nWants=network-online.target\n\n\
+             [Service]\n{}\n\n\
+             [Install]\nWantedBy=multi-user.target\n",
+            escape_specifiers(&self.name),
+            service.join("\n")
+        )
+    }
+
+    /// Writes the unit file, installing and enabling it when configured
+    ///
+    /// Units that aren't installed go to `output_dir` unless `dir` says
+    /// otherwise; installed ones go to `/etc/systemd/system`.
+    ///
+    /// # Returns
+    /// * `Result<PathBuf>` - Path to the unit file
+    #[instrument(skip(self, output_dir), fields(unit = %self.unit_name()))]
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf> {
+        let install = self.config.install || self.config.enable;
+        let dir = match &self.config.dir {
+            Some(dir) => PathBuf::from(dir),
+            None if install => PathBuf::from(UNIT_DIR),
+            None => output_dir.to_path_buf(),
+        };
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(self.unit_name());
+        fs::write(&path, self.render())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("Wrote systemd unit {}", path.display());
+
+        if install {
+            systemctl(&["daemon-reload"])?;
+        }
+        if self.config.enable {
+            systemctl(&["enable", &self.unit_name()])?;
+            info!("Enabled {}", self.unit_name());
+        }
+        Ok(path)
+    }
+}
+
+/// Runs `systemctl` with the given arguments
+fn systemctl(args: &[&str]) -> Result<()> {
+    let output = Command::new("systemctl")
+        .args(args)
+        .output()
+        .context("Failed to run systemctl")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Absolute path of an executable, searched in `PATH` when given by name
+///
+/// Names that aren't found are kept, for systemd to look up itself.
+fn find_executable(name: &str) -> String {
+    if name.contains('/') {
+        return std::path::absolute(name)
+            .map_or_else(|_| name.to_string(), |path| path.display().to_string());
+    }
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+        .map_or_else(|| name.to_string(), |path| path.display().to_string())
+}
+
+/// Escapes the `%` specifiers systemd expands in unit file values
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Quotes an `ExecStart` argument, which systemd also expands `$` variables in
+fn exec_arg(arg: &str) -> String {
+    let escaped = escape_specifiers(arg).replace('$', "$$");
+    if escaped.is_empty()
+        || escaped.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\\' | '\'' | ';'))
+    {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
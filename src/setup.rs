@@ -1,12 +1,111 @@
-use anyhow::{Context, Result};
-use fs_extra::dir::{copy, CopyOptions};
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
 use std::collections::HashMap;
 use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use tracing::{info, instrument, warn};
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+use tracing::{debug, info, instrument, warn};
 
 use crate::config::CosmosConfig;
+use crate::copy::copy_tree;
+use crate::error::Error;
+
+/// Entries a restored `data` directory can't start without
+const REQUIRED_DATA_ENTRIES: [&str; 3] = ["application.db", "blockstore.db", "state.db"];
+
+/// Entries a restored `data` directory usually has, which the node recreates
+const EXPECTED_DATA_ENTRIES: [&str; 2] = ["cs.wal", "snapshots"];
+
+/// How deep below the data directory to look for misplaced databases
+const MISPLACED_SEARCH_DEPTH: usize = 3;
+
+/// Databases marking the directory that holds a snapshot's data
+const DATA_MARKERS: [&str; 2] = ["application.db", "blockstore.db"];
+
+/// How deep below the staging directory to look for a snapshot's data
+const SNAPSHOT_SEARCH_DEPTH: usize = 4;
+
+/// Directories of a node home next to `data` that snapshots may include,
+/// e.g. the contract code of CosmWasm chains
+const HOME_ENTRIES: [&str; 1] = ["wasm"];
+
+/// File kept among the databases that a snapshot laid out without `data/` may include
+const DATA_FILES: [&str; 1] = ["priv_validator_state.json"];
+
+/// Files of a validator's home a restore must not replace, relative to the home
+///
+/// A snapshot's `priv_validator_state.json` is behind the height the validator
+/// last signed at, so restoring it over the validator's own risks double-signing.
+const VALIDATOR_FILES: [&str; 3] = [
+    "data/priv_validator_state.json",
+    "config/priv_validator_key.json",
+    "config/node_key.json",
+];
+
+/// Genesis file that `init` writes, relative to the home
+///
+/// Its presence means the home was initialized before, and `init` refuses to
+/// run over it.
+const GENESIS_FILE: &str = "config/genesis.json";
+
+/// Magic number opening every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A configured setting whose value in a node's TOML file differs
+#[derive(Debug, Clone)]
+pub struct SettingDrift {
+    /// File the setting belongs in, relative to the node home
+    pub file: &'static str,
+
+    /// Setting name
+    pub key: String,
+
+    /// Value the configuration asks for, rendered as TOML
+    pub expected: String,
+
+    /// Value found in the file, or `None` when the key is absent
+    pub actual: Option<String>,
+}
+
+/// Splits a node command into arguments, appending `--home` when `inject_home` is set
+///
+/// A command that already passes `--home` is left alone, with a warning when
+/// it names a directory other than the node home.
+pub fn node_args(command: &str, home: &Path, inject_home: bool) -> Result<Vec<String>> {
+    let mut args: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+    if !inject_home {
+        return Ok(args);
+    }
+
+    let home = std::path::absolute(home).context("Failed to resolve node home")?;
+    let existing = args
+        .iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.as_str() {
+            "--home" => Some(args.get(i + 1).cloned().unwrap_or_default()),
+            _ => arg.strip_prefix("--home=").map(str::to_string),
+        });
+    match existing {
+        Some(existing) => {
+            if std::path::absolute(&existing).ok().as_deref() != Some(home.as_path()) {
+                warn!(
+                    "Command '{}' sets --home {} but the node home is {}",
+                    command,
+                    existing,
+                    home.display()
+                );
+            }
+        }
+        None => {
+            args.push("--home".to_string());
+            args.push(home.display().to_string());
+        }
+    }
+
+    Ok(args)
+}
 
 /// Handles Cosmos blockchain node setup and configuration
 pub struct CosmosSetup {
@@ -38,98 +137,252 @@ impl CosmosSetup {
     /// Initializes the Cosmos node with the provided configuration
     ///
     /// This will:
-    /// 1. Run the initialization command
+    /// 1. Run the initialization command, unless the home already has a genesis file
     /// 2. Configure app.toml with custom settings
     /// 3. Configure config.toml with custom settings
     #[instrument(skip(self), fields(bin_path = %self.binary_path.display(), data_dir = %self.data_dir.display()))]
-    pub fn init(&self) -> Result<()> {
-        // Run initialization command
-        self.run_init_command()?;
+    pub fn init(&self) -> crate::error::Result<()> {
+        if self.data_dir.join(GENESIS_FILE).is_file() {
+            info!(
+                "{} already exists, skipping the initialization command",
+                GENESIS_FILE
+            );
+        } else {
+            self.run_init_command().map_err(setup_error)?;
+        }
 
         // Apply configurations
-        self.configure_app_toml()?;
-        self.configure_config_toml()?;
+        self.apply_settings()?;
 
         info!("Node initialization completed successfully");
         Ok(())
     }
 
+    /// Writes the configured app.toml and config.toml settings into the home
+    ///
+    /// Used on its own to reconfigure an existing node without restoring it.
+    #[instrument(skip(self), fields(data_dir = %self.data_dir.display()))]
+    pub fn apply_settings(&self) -> crate::error::Result<()> {
+        for (file, settings) in self.file_settings() {
+            self.configure_toml(file, &settings).map_err(setup_error)?;
+        }
+        Ok(())
+    }
+
+    /// Settings for each of the home's TOML files, keyed by path relative to the home
+    ///
+    /// `moniker` goes into config.toml and `chain_id` into client.toml, taking
+    /// precedence over the same keys in `cosmos.config`.
+    fn file_settings(&self) -> [(&'static str, HashMap<String, serde_yaml::Value>); 3] {
+        let mut config = self.config.config.clone();
+        if let Some(moniker) = &self.config.moniker {
+            config.insert("moniker".to_string(), moniker.as_str().into());
+        }
+
+        let mut client = HashMap::new();
+        if let Some(chain_id) = &self.config.chain_id {
+            client.insert("chain-id".to_string(), chain_id.as_str().into());
+        }
+
+        [
+            ("config/app.toml", self.config.app.clone()),
+            ("config/config.toml", config),
+            ("config/client.toml", client),
+        ]
+    }
+
+    /// The init command to run
+    ///
+    /// Without `init_command`, the node is initialized as `init <moniker>`.
+    /// `--chain-id` is appended when `chain_id` is set and the command lacks it.
+    fn init_command(&self) -> String {
+        let mut command = match (&self.config.init_command, &self.config.moniker) {
+            (command, Some(moniker)) if command.trim().is_empty() => format!("init {}", moniker),
+            (command, _) => command.clone(),
+        };
+
+        if let Some(chain_id) = &self.config.chain_id {
+            let has_chain_id = command
+                .split_whitespace()
+                .any(|arg| arg == "--chain-id" || arg.starts_with("--chain-id="));
+            if !has_chain_id {
+                command.push_str(&format!(" --chain-id {}", chain_id));
+            }
+        }
+
+        command
+    }
+
     /// Runs the node initialization command
     #[instrument(skip(self))]
     fn run_init_command(&self) -> Result<()> {
-        info!(
-            "Running initialization command: {}",
-            self.config.init_command
-        );
+        let command = self.init_command();
+        info!("Running initialization command: {}", command);
 
-        let output = Command::new(&self.binary_path)
-            .args(self.config.init_command.split_whitespace())
+        let binary_path =
+            std::path::absolute(&self.binary_path).context("Failed to resolve binary path")?;
+        let output = Command::new(binary_path)
+            .args(node_args(
+                &command,
+                &self.data_dir,
+                self.config.inject_home,
+            )?)
             .current_dir(&self.data_dir)
             .output()
             .context("Failed to execute initialization command")?;
 
-        if !output.status.success() {
-            let error_message = String::from_utf8_lossy(&output.stderr);
+        let succeeded = output.status.success();
+        forward_output("stdout", &output.stdout, succeeded);
+        forward_output("stderr", &output.stderr, succeeded);
+
+        if succeeded {
+            info!("Initialization command executed successfully");
+        } else if self.config.ignore_init_failure {
             warn!(
-                "Initialization command failed with output: {}",
-                error_message
+                "Initialization command failed ({}), continuing as ignore_init_failure is set",
+                output.status
             );
         } else {
-            info!("Initialization command executed successfully");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr
+                .lines()
+                .rev()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or("no error output");
+            return Err(anyhow!(
+                "Initialization command failed ({}): {}",
+                output.status,
+                reason
+            ));
         }
 
         Ok(())
     }
 
-    /// Configures app.toml with the provided settings
-    #[instrument(skip(self), fields(app_toml_path = %self.data_dir.join("config/app.toml").display()))]
-    fn configure_app_toml(&self) -> Result<()> {
-        let app_toml_path = self.data_dir.join("config/app.toml");
+    /// Writes settings into one of the home's TOML files, if it exists
+    #[instrument(skip(self, settings), fields(path = %self.data_dir.join(file).display()))]
+    fn configure_toml(
+        &self,
+        file: &str,
+        settings: &HashMap<String, serde_yaml::Value>,
+    ) -> Result<()> {
+        let path = self.data_dir.join(file);
+        let file_type = file.trim_start_matches("config/");
 
-        // Skip if no app.toml configurations specified
-        if self.config.app.is_empty() {
-            info!("No app.toml configurations specified, skipping");
+        // Skip if no configurations specified for this file
+        if settings.is_empty() {
+            info!("No {} configurations specified, skipping", file_type);
             return Ok(());
         }
 
-        // Check if app.toml exists
-        if !app_toml_path.exists() {
+        // Check if the file exists
+        if !path.exists() {
             warn!(
-                "app.toml not found at path: {}, skipping configuration",
-                app_toml_path.display()
+                "{} not found at path: {}, skipping configuration",
+                file_type,
+                path.display()
             );
             return Ok(());
         }
 
         // Apply configuration changes
-        self.apply_toml_changes(app_toml_path, &self.config.app, "app.toml")
+        self.apply_toml_changes(path, settings, file_type)
+    }
+
+    /// Compares the configured app.toml, config.toml and client.toml settings with the files in the home
+    ///
+    /// Keys are looked up the same way setup writes them, so a setting that
+    /// a run would change shows up as drift. Results are sorted by file and key.
+    #[instrument(skip(self), fields(data_dir = %self.data_dir.display()))]
+    pub fn diff(&self) -> crate::error::Result<Vec<SettingDrift>> {
+        self.find_drift().map_err(setup_error)
+    }
+
+    /// Collects the settings whose value in the home differs from the configured one
+    fn find_drift(&self) -> Result<Vec<SettingDrift>> {
+        let mut drift = Vec::new();
+
+        for (file, settings) in self.file_settings() {
+            if settings.is_empty() {
+                continue;
+            }
+
+            let path = self.data_dir.join(file);
+            let document = read_toml(&path)?;
+
+            for (key, value) in flatten_settings(&settings) {
+                let expected = toml_value(value)
+                    .with_context(|| format!("Invalid value for {}", key))?
+                    .to_string();
+                let actual = find_toml_value(&document, &key).map(|v| v.to_string());
+                if actual.as_deref() != Some(expected.as_str()) {
+                    drift.push(SettingDrift {
+                        file,
+                        key,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        Ok(drift)
     }
 
-    /// Configures config.toml with the provided settings
-    #[instrument(skip(self), fields(config_toml_path = %self.data_dir.join("config/config.toml").display()))]
-    fn configure_config_toml(&self) -> Result<()> {
+    /// Replaces the seeds and persistent peers in config.toml
+    ///
+    /// Empty lists are left untouched so a registry without peers doesn't
+    /// wipe the ones shipped with the node's defaults.
+    #[instrument(skip(self, seeds, persistent_peers))]
+    pub fn configure_peers(&self, seeds: &str, persistent_peers: &str) -> crate::error::Result<()> {
         let config_toml_path = self.data_dir.join("config/config.toml");
+        if !config_toml_path.exists() {
+            warn!(
+                "config.toml not found at path: {}, skipping peer configuration",
+                config_toml_path.display()
+            );
+            return Ok(());
+        }
 
-        // Skip if no config.toml configurations specified
-        if self.config.config.is_empty() {
-            info!("No config.toml configurations specified, skipping");
+        let mut settings = HashMap::new();
+        for (key, list) in [("seeds", seeds), ("persistent_peers", persistent_peers)] {
+            if !list.is_empty() {
+                settings.insert(format!("p2p.{}", key), serde_yaml::Value::from(list));
+            }
+        }
+        if settings.is_empty() {
+            info!("No peers to configure");
             return Ok(());
         }
 
-        // Check if config.toml exists
+        self.apply_toml_changes(config_toml_path, &settings, "config.toml")
+            .map_err(setup_error)
+    }
+
+    /// Sets `db_backend` in config.toml
+    #[instrument(skip(self))]
+    pub fn configure_db_backend(&self, backend: &str) -> crate::error::Result<()> {
+        let config_toml_path = self.data_dir.join("config/config.toml");
         if !config_toml_path.exists() {
             warn!(
-                "config.toml not found at path: {}, skipping configuration",
+                "config.toml not found at path: {}, skipping db_backend configuration",
                 config_toml_path.display()
             );
             return Ok(());
         }
 
-        // Apply configuration changes
-        self.apply_toml_changes(config_toml_path, &self.config.config, "config.toml")
+        let settings =
+            HashMap::from([("db_backend".to_string(), serde_yaml::Value::from(backend))]);
+        self.apply_toml_changes(config_toml_path, &settings, "config.toml")
+            .map_err(setup_error)
     }
 
     /// Applies configuration changes to a TOML file
+    ///
+    /// Keys are dotted paths into the file's tables, e.g. `api.enable` for
+    /// `enable` under `[api]`, and nested mappings stand for tables the same
+    /// way. Missing tables are created, and comments and formatting are kept.
     fn apply_toml_changes(
         &self,
         file_path: PathBuf,
@@ -139,30 +392,20 @@ impl CosmosSetup {
         // Read existing file content
         let content =
             fs::read_to_string(&file_path).context(format!("Failed to read {}", file_type))?;
-
-        let mut updated_content = content.clone();
+        let mut document: DocumentMut = content
+            .parse()
+            .with_context(|| format!("Failed to parse {}", file_type))?;
 
         // Apply each setting
-        for (key, value) in settings {
+        for (key, value) in flatten_settings(settings) {
             info!(key = %key, value = ?value, "Setting {} value", file_type);
-            let value_str = format!("{:?}", value);
-
-            // Create regex for finding the key
-            let pattern = format!("{} = ", key);
-            if updated_content.contains(&pattern) {
-                // Update existing key
-                let re = regex::Regex::new(&format!(r"(?m)^{}\s*=.*$", regex::escape(key)))
-                    .context("Failed to create regex")?;
-                updated_content = re
-                    .replace(&updated_content, &format!("{} = {}", key, value_str))
-                    .to_string();
-            } else {
-                // Add new key
-                updated_content.push_str(&format!("\n{} = {}", key, value_str));
-            }
+            let value = toml_value(value).with_context(|| format!("Invalid value for {}", key))?;
+            set_toml_value(&mut document, &key, value)
+                .with_context(|| format!("Failed to set {} in {}", key, file_type))?;
         }
 
         // Write changes if content was modified
+        let updated_content = document.to_string();
         if content != updated_content {
             fs::write(&file_path, updated_content)
                 .context(format!("Failed to write updated {}", file_type))?;
@@ -175,54 +418,722 @@ impl CosmosSetup {
     }
 }
 
+/// Reads and parses a TOML file, keeping its formatting
+fn read_toml(path: &Path) -> Result<DocumentMut> {
+    fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Flattens nested mappings into dotted keys, sorted by key
+///
+/// `rpc: {laddr: ...}` becomes `rpc.laddr`, so a mapping updates the keys it
+/// lists rather than replacing the whole table.
+fn flatten_settings(
+    settings: &HashMap<String, serde_yaml::Value>,
+) -> Vec<(String, &serde_yaml::Value)> {
+    fn flatten<'a>(
+        key: String,
+        value: &'a serde_yaml::Value,
+        flattened: &mut Vec<(String, &'a serde_yaml::Value)>,
+    ) {
+        match value {
+            serde_yaml::Value::Mapping(entries) => {
+                for (name, value) in entries {
+                    let name = match name {
+                        serde_yaml::Value::String(name) => name.clone(),
+                        other => toml_value(other).map(|v| v.to_string()).unwrap_or_default(),
+                    };
+                    flatten(format!("{}.{}", key, name), value, flattened);
+                }
+            }
+            _ => flattened.push((key, value)),
+        }
+    }
+
+    let mut flattened = Vec::new();
+    for (key, value) in settings {
+        flatten(key.clone(), value, &mut flattened);
+    }
+    flattened.sort_by(|a, b| a.0.cmp(&b.0));
+    flattened
+}
+
+/// Sets the value at a dotted key path, creating missing tables
+///
+/// A replaced value keeps the comments and spacing around it, and a value
+/// that is already set as asked is left as written.
+fn set_toml_value(document: &mut DocumentMut, key: &str, value: Value) -> Result<()> {
+    let parts = key_path(key)?;
+    let (name, tables) = parts.split_last().context("Empty key")?;
+
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for part in tables {
+        let mut implicit = Table::new();
+        implicit.set_implicit(true);
+        table = table
+            .entry(part)
+            .or_insert(Item::Table(implicit))
+            .as_table_like_mut()
+            .with_context(|| format!("{} is not a table", part))?;
+    }
+
+    match table.get_mut(name) {
+        Some(Item::Value(existing)) if normalized(existing).to_string() == value.to_string() => {}
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        Some(Item::None) | None => {
+            table.insert(name, Item::Value(value));
+        }
+        Some(_) => return Err(anyhow::anyhow!("{} is a table, not a value", key)),
+    }
+    Ok(())
+}
+
+/// Finds the value at a dotted key path, normalized for comparison
+///
+/// Quoting, spacing and comments are dropped, so `'x'` and `"x"` compare equal.
+fn find_toml_value(document: &DocumentMut, key: &str) -> Option<Value> {
+    let parts = key_path(key).ok()?;
+    let mut item = document.as_item();
+    for part in parts {
+        item = item.as_table_like()?.get(part)?;
+    }
+    item.as_value().map(normalized)
+}
+
+/// Splits a dotted key into its parts
+fn key_path(key: &str) -> Result<Vec<&str>> {
+    let parts: Vec<&str> = key.split('.').map(str::trim).collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(anyhow::anyhow!("Invalid key {:?}", key));
+    }
+    Ok(parts)
+}
+
+/// Rebuilds a value with default formatting
+fn normalized(value: &Value) -> Value {
+    match value {
+        Value::String(text) => Value::from(text.value().as_str()),
+        Value::Integer(number) => Value::from(*number.value()),
+        Value::Float(number) => Value::from(*number.value()),
+        Value::Boolean(flag) => Value::from(*flag.value()),
+        Value::Datetime(datetime) => Value::from(*datetime.value()),
+        Value::Array(items) => Value::Array(items.iter().map(normalized).collect()),
+        Value::InlineTable(entries) => Value::InlineTable(
+            entries
+                .iter()
+                .map(|(name, value)| (name, normalized(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Logs each line a command printed, as warnings when the command failed
+fn forward_output(stream: &str, output: &[u8], succeeded: bool) {
+    for line in String::from_utf8_lossy(output).lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if succeeded {
+            debug!("init {}: {}", stream, line);
+        } else {
+            warn!("init {}: {}", stream, line);
+        }
+    }
+}
+
+/// Wraps a failed setup step in the typed error
+fn setup_error(source: anyhow::Error) -> Error {
+    Error::Setup(source.into())
+}
+
+/// Converts a YAML scalar or list into a TOML value
+fn toml_value(value: &serde_yaml::Value) -> Result<Value> {
+    Ok(match value {
+        serde_yaml::Value::String(text) => Value::from(text.as_str()),
+        serde_yaml::Value::Bool(flag) => Value::from(*flag),
+        serde_yaml::Value::Number(number) => match number.as_i64() {
+            Some(integer) => Value::from(integer),
+            None => Value::from(number.as_f64().context("Number out of range")?),
+        },
+        serde_yaml::Value::Sequence(items) => Value::Array(
+            items
+                .iter()
+                .map(toml_value)
+                .collect::<Result<toml_edit::Array>>()?,
+        ),
+        other => return Err(anyhow::anyhow!("{:?} has no TOML equivalent", other)),
+    })
+}
+
 /// Moves extracted snapshot data to the node's data directory
 ///
-/// This function finds the extracted snapshot directory and
-/// copies its contents to the specified data directory.
-#[instrument(skip(snapshot_dir, data_dir), fields(from = %snapshot_dir.as_ref().display(), to = %data_dir.as_ref().display()))]
-pub fn move_snapshot<P: AsRef<Path>, Q: AsRef<Path>>(snapshot_dir: P, data_dir: Q) -> Result<()> {
+/// The data is found by the databases in it rather than by position, so
+/// archives that unpack `data/` next to siblings, under a directory named
+/// after the snapshot, or with the databases at the top all restore. The
+/// databases end up in `data/`, and a `wasm` directory among them or beside
+/// their directory is moved to the node home next to `data/`.
+///
+/// Each directory is renamed into place when the node home is on the same
+/// filesystem and has no data there yet. Otherwise it is copied, writing at
+/// most `max_write_rate` bytes per second when set with `jobs` copy
+/// workers, and removed from the staging directory afterwards.
+#[instrument(skip(snapshot_dir, data_dir, max_write_rate, jobs), fields(from = %snapshot_dir.as_ref().display(), to = %data_dir.as_ref().display()))]
+pub fn move_snapshot<P: AsRef<Path>, Q: AsRef<Path>>(
+    snapshot_dir: P,
+    data_dir: Q,
+    max_write_rate: Option<u64>,
+    jobs: Option<usize>,
+) -> Result<()> {
     let snapshot_dir = snapshot_dir.as_ref();
     let data_dir = data_dir.as_ref();
 
     info!("Moving snapshot data to data directory");
 
-    // Find the extracted snapshot directory
-    let snapshot_dirs = find_snapshot_directories(snapshot_dir)?;
+    let Some(data_root) = find_data_root(snapshot_dir) else {
+        let found = list_entries(snapshot_dir);
+        return Err(anyhow!(
+            "No extracted snapshot data found in {}: none of {} within {} levels (found: {})",
+            snapshot_dir.display(),
+            DATA_MARKERS.join(", "),
+            SNAPSHOT_SEARCH_DEPTH,
+            found.join(", ")
+        ));
+    };
+    info!(source = %data_root.display(), "Found snapshot data");
 
-    if snapshot_dirs.is_empty() {
-        return Err(anyhow::anyhow!("No extracted snapshot directory found"));
+    let target = data_dir.join("data");
+    let mut moves = Vec::new();
+    if data_root.file_name().is_some_and(|name| name == "data") {
+        let home_root = data_root.parent().unwrap_or(snapshot_dir);
+        moves.push((data_root.clone(), target));
+        moves.extend(home_entries(home_root, data_dir));
+    } else if data_root == snapshot_dir {
+        // The staging directory also holds the downloads, which stay behind
+        info!("Snapshot databases are at the top of the archive");
+        for entry in fs::read_dir(&data_root)?.filter_map(Result::ok) {
+            let path = entry.path();
+            let name = entry.file_name();
+            if path.is_dir() && HOME_ENTRIES.iter().any(|home| name == *home) {
+                moves.push((path, data_dir.join(name)));
+            } else if path.is_dir() || DATA_FILES.iter().any(|file| name == *file) {
+                moves.push((path, target.join(name)));
+            }
+        }
+    } else {
+        info!(
+            "Snapshot databases are in {}, moving them into data",
+            data_root.display()
+        );
+        // Taken out first, so they don't travel into data/ with the databases
+        moves.extend(home_entries(&data_root, data_dir));
+        moves.extend(home_entries(
+            data_root.parent().unwrap_or(snapshot_dir),
+            data_dir,
+        ));
+        moves.push((data_root.clone(), target));
     }
 
-    // Use the first found directory
-    let snapshot_src = &snapshot_dirs[0];
-    info!(source = %snapshot_src.display(), "Found snapshot directory");
+    for (from, to) in moves {
+        move_entry(&from, &to, max_write_rate, jobs)
+            .with_context(|| format!("Failed to move {} to {}", from.display(), to.display()))?;
+    }
 
-    // Copy with overwrite options
-    let options = create_copy_options();
+    info!("Successfully moved snapshot data to data directory");
+    Ok(())
+}
 
-    copy(snapshot_src, data_dir, &options)
-        .context("Failed to copy snapshot data to data directory")?;
+/// Moves of the [`HOME_ENTRIES`] directories found in `dir` into the node home
+fn home_entries(dir: &Path, home: &Path) -> Vec<(PathBuf, PathBuf)> {
+    HOME_ENTRIES
+        .iter()
+        .map(|name| (dir.join(name), home.join(name)))
+        .filter(|(entry, _)| entry.is_dir())
+        .collect()
+}
 
-    info!("Successfully moved snapshot data to data directory");
+/// Moves a file or directory tree, by renaming it when possible
+///
+/// Renaming needs both sides on one filesystem and nothing at `to` yet but
+/// an empty directory. Otherwise `from` is copied over whatever `to` holds
+/// and removed once the copy is complete.
+fn move_entry(
+    from: &Path,
+    to: &Path,
+    max_write_rate: Option<u64>,
+    jobs: Option<usize>,
+) -> Result<()> {
+    if !to.exists() || (to.is_dir() && is_empty(to)) {
+        if to.is_dir() {
+            fs::remove_dir(to)?;
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::rename(from, to) {
+            Ok(()) => {
+                info!("Renamed {} into place", from.display());
+                return Ok(());
+            }
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                info!(
+                    "{} is on another filesystem than the node home, copying it",
+                    from.display()
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        info!("{} already holds data, copying over it", to.display());
+    }
+
+    if from.is_dir() {
+        copy_tree(from, to, max_write_rate, jobs)?;
+        fs::remove_dir_all(from)?;
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Finds the directory holding a snapshot's databases, closest to `dir` first
+fn find_data_root(dir: &Path) -> Option<PathBuf> {
+    DATA_MARKERS
+        .iter()
+        .filter_map(|marker| find_entry(dir, marker, SNAPSHOT_SEARCH_DEPTH))
+        .filter(|path| path.is_dir())
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .min_by_key(|root| root.components().count())
+}
+
+/// Installs a downloaded node file such as `genesis.json` at `target`
+///
+/// Files published gzipped (`genesis.json.gz`) are recognized by their
+/// content rather than their name and decompressed on the way. The file is
+/// written beside `target` and renamed over it, so an interrupted install
+/// leaves the previous file in place.
+pub fn install_config_file(source: &Path, target: &Path) -> Result<()> {
+    let mut file =
+        fs::File::open(source).with_context(|| format!("Failed to open {}", source.display()))?;
+    let mut magic = [0u8; 2];
+    let gzipped = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    file.seek(SeekFrom::Start(0))?;
+
+    let partial = target.with_extension("partial");
+    let mut output = fs::File::create(&partial)
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+    if gzipped {
+        info!("Decompressing {}", source.display());
+        io::copy(&mut GzDecoder::new(file), &mut output)
+            .with_context(|| format!("Failed to decompress {}", source.display()))?;
+    } else {
+        io::copy(&mut file, &mut output)?;
+    }
+    output.sync_all()?;
+    fs::rename(&partial, target)
+        .with_context(|| format!("Failed to install {}", target.display()))?;
     Ok(())
 }
 
-/// Finds snapshot directories in the specified path
-fn find_snapshot_directories(dir: &Path) -> Result<Vec<PathBuf>> {
-    let entries = fs::read_dir(dir)
-        .context("Failed to read snapshot directory")?
-        .filter_map(Result::ok)
-        .filter(|entry| entry.path().is_dir())
-        .map(|entry| entry.path())
-        .collect::<Vec<_>>();
+/// Copies the validator state and keys found in `home` into `backup`
+///
+/// A file already in `backup` was saved by a restore that didn't finish and
+/// is kept, since the one in the home may have come from the snapshot since.
+pub fn preserve_validator_files(home: &Path, backup: &Path) -> Result<()> {
+    for file in VALIDATOR_FILES {
+        let saved = backup.join(file);
+        if saved.is_file() {
+            info!("Keeping {} saved by an earlier restore", file);
+        } else if home.join(file).is_file() {
+            fs::create_dir_all(saved.parent().unwrap_or(backup))?;
+            fs::copy(home.join(file), &saved)
+                .with_context(|| format!("Failed to back up {}", file))?;
+            info!("Backed up {} before the restore", file);
+        }
+    }
+    Ok(())
+}
 
-    Ok(entries)
+/// Puts the validator files saved by [`preserve_validator_files`] back into `home`
+///
+/// Whatever the snapshot brought in their place is replaced, and `backup` is
+/// removed once every file is back.
+pub fn restore_validator_files(home: &Path, backup: &Path) -> Result<()> {
+    if !backup.is_dir() {
+        return Ok(());
+    }
+    for file in VALIDATOR_FILES {
+        let saved = backup.join(file);
+        if !saved.is_file() {
+            continue;
+        }
+        let target = home.join(file);
+        fs::create_dir_all(target.parent().unwrap_or(home))?;
+        let partial = target.with_extension("partial");
+        fs::copy(&saved, &partial)
+            .and_then(|_| fs::rename(&partial, &target))
+            .with_context(|| format!("Failed to restore {}", file))?;
+        info!("Restored the validator's {}", file);
+    }
+    fs::remove_dir_all(backup).with_context(|| format!("Failed to remove {}", backup.display()))?;
+    Ok(())
 }
 
-/// Creates copy options for directory copying
-fn create_copy_options() -> CopyOptions {
-    let mut options = CopyOptions::new();
-    options.overwrite = true;
-    options.copy_inside = true;
-    options
+/// Checks that the restored `data` directory holds the node's databases
+///
+/// Missing or empty databases are an error naming each problem, with a hint
+/// when they sit elsewhere in the tree because the archive used a different
+/// layout. A missing `cs.wal` or `snapshots` only warrants a warning.
+#[instrument(skip(data_dir), fields(data_dir = %data_dir.display()))]
+pub fn validate_data_dir(data_dir: &Path) -> crate::error::Result<()> {
+    let db_dir = data_dir.join("data");
+
+    let problems: Vec<String> = REQUIRED_DATA_ENTRIES
+        .iter()
+        .filter_map(|name| {
+            let path = db_dir.join(name);
+            if !path.exists() {
+                Some(format!("{} is missing", name))
+            } else if is_empty(&path) {
+                Some(format!("{} is empty", name))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    for name in EXPECTED_DATA_ENTRIES {
+        if !db_dir.join(name).exists() {
+            warn!("{} is not in the snapshot, the node will create it", name);
+        }
+    }
+
+    if problems.is_empty() {
+        info!("Data directory layout looks complete");
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "Restored data in {} doesn't have the expected layout: {}",
+        db_dir.display(),
+        problems.join(", ")
+    );
+    let found = list_entries(&db_dir);
+    if !found.is_empty() {
+        message.push_str(&format!(" (found: {})", found.join(", ")));
+    }
+    if let Some(misplaced) = find_entry(data_dir, "blockstore.db", MISPLACED_SEARCH_DEPTH)
+        .filter(|path| path.parent() != Some(db_dir.as_path()))
+    {
+        message.push_str(&format!(
+            "; blockstore.db was found at {}, so the archive is laid out differently than expected",
+            misplaced.display()
+        ));
+    }
+
+    Err(Error::Setup(message.into()))
+}
+
+/// Whether a file has no content or a directory has no entries
+fn is_empty(path: &Path) -> bool {
+    if path.is_dir() {
+        fs::read_dir(path).map_or(true, |mut entries| entries.next().is_none())
+    } else {
+        fs::metadata(path).map_or(true, |metadata| metadata.len() == 0)
+    }
+}
+
+/// Sorted names of the entries in a directory, empty when it can't be read
+fn list_entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Finds an entry by name, breadth first, up to `depth` levels below `dir`
+fn find_entry(dir: &Path, name: &str, depth: usize) -> Option<PathBuf> {
+    let mut level = vec![dir.to_path_buf()];
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for parent in level {
+            let Ok(entries) = fs::read_dir(&parent) else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if entry.file_name() == name {
+                    return Some(path);
+                }
+                if path.is_dir() {
+                    next.push(path);
+                }
+            }
+        }
+        level = next;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(toml: &str) -> DocumentMut {
+        toml.parse().unwrap()
+    }
+
+    fn set(toml: &str, key: &str, value: impl Into<Value>) -> Result<String> {
+        let mut document = document(toml);
+        set_toml_value(&mut document, key, value.into())?;
+        Ok(document.to_string())
+    }
+
+    #[test]
+    fn replaces_values_keeping_comments() {
+        let toml = "# node\nmoniker = \"node\"\n\n[api]\n# serve the API\nenable = false # off\naddress = \"tcp://0.0.0.0:1317\"\n";
+        assert_eq!(
+            set(toml, "api.enable", true).unwrap(),
+            "# node\nmoniker = \"node\"\n\n[api]\n# serve the API\nenable = true # off\naddress = \"tcp://0.0.0.0:1317\"\n"
+        );
+        assert_eq!(
+            set(toml, "moniker", "validator").unwrap(),
+            "# node\nmoniker = \"validator\"\n\n[api]\n# serve the API\nenable = false # off\naddress = \"tcp://0.0.0.0:1317\"\n"
+        );
+    }
+
+    #[test]
+    fn leaves_equal_values_as_written() {
+        let toml = "moniker = 'node'   # quoted differently\nports = [ 1, 2 ]\n";
+        assert_eq!(set(toml, "moniker", "node").unwrap(), toml);
+
+        let ports: toml_edit::Array = [1i64, 2].into_iter().collect();
+        assert_eq!(set(toml, "ports", ports).unwrap(), toml);
+    }
+
+    #[test]
+    fn creates_missing_tables() {
+        assert_eq!(
+            set("[p2p]\nladdr = \"x\"\n", "p2p.seeds", "a@b:26656").unwrap(),
+            "[p2p]\nladdr = \"x\"\nseeds = \"a@b:26656\"\n"
+        );
+        assert_eq!(
+            set("", "state-sync.snapshot.interval", 1000).unwrap(),
+            "[state-sync.snapshot]\ninterval = 1000\n"
+        );
+        assert_eq!(
+            set("api = { enable = false }\n", "api.swagger", true).unwrap(),
+            "api = { enable = false , swagger = true }\n"
+        );
+    }
+
+    #[test]
+    fn refuses_keys_that_do_not_fit_the_document() {
+        let toml = "moniker = \"node\"\n[api]\nenable = true\n";
+        assert!(set(toml, "moniker.name", "x").is_err());
+        assert!(set(toml, "api", true).is_err());
+        assert!(set(toml, "api..enable", true).is_err());
+        assert!(set(toml, "", true).is_err());
+        assert!(set(toml, ".enable", true).is_err());
+    }
+
+    #[test]
+    fn finds_values_normalized() {
+        let document = document("[api]\nenable = true # on\nname = 'x'\n");
+        assert_eq!(
+            find_toml_value(&document, "api.name").map(|v| v.to_string()),
+            Some("\"x\"".to_string())
+        );
+        assert_eq!(
+            find_toml_value(&document, "api.enable").map(|v| v.to_string()),
+            Some("true".to_string())
+        );
+        assert!(find_toml_value(&document, "api").is_none());
+        assert!(find_toml_value(&document, "api.missing").is_none());
+        assert!(find_toml_value(&document, "api.enable.deeper").is_none());
+    }
+
+    #[test]
+    fn flattens_nested_settings() {
+        let settings: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(
+            "rpc: {laddr: tcp://0.0.0.0:26657, cors: [\"*\"]}\nmoniker: node\nmempool: {size: 5000}\n",
+        )
+        .unwrap();
+        let flattened: Vec<(String, String)> = flatten_settings(&settings)
+            .into_iter()
+            .map(|(key, value)| (key, toml_value(value).unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            flattened,
+            [
+                ("mempool.size", "5000"),
+                ("moniker", "\"node\""),
+                ("rpc.cors", "[\"*\"]"),
+                ("rpc.laddr", "\"tcp://0.0.0.0:26657\""),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+    }
+
+    #[test]
+    fn converts_yaml_scalars_and_lists() {
+        let value = |yaml| toml_value(&serde_yaml::from_str(yaml).unwrap());
+        assert_eq!(value("42").unwrap().to_string(), "42");
+        assert_eq!(value("0.5").unwrap().to_string(), "0.5");
+        assert_eq!(value("'10'").unwrap().to_string(), "\"10\"");
+        assert_eq!(
+            value("[a, 1, true]").unwrap().to_string(),
+            "[\"a\", 1, true]"
+        );
+        assert!(value("null").is_err());
+        assert!(value("[a, ~]").is_err());
+    }
+
+    /// Creates `files`, relative to `root`, with their own path as content
+    fn lay_out(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+    }
+
+    /// Files below `root`, relative to it and sorted
+    fn files_in(root: &Path) -> Vec<String> {
+        fn walk(dir: &Path, root: &Path, files: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).unwrap().map(Result::unwrap) {
+                let path = entry.path();
+                if path.is_dir() {
+                    walk(&path, root, files);
+                } else {
+                    let relative = path.strip_prefix(root).unwrap();
+                    files.push(relative.to_string_lossy().into_owned());
+                }
+            }
+        }
+        let mut files = Vec::new();
+        walk(root, root, &mut files);
+        files.sort();
+        files
+    }
+
+    /// Stages `files` as an extracted snapshot and moves it into a fresh home,
+    /// returning what ends up in the home and what stays staged
+    fn restore(files: &[&str]) -> (Vec<String>, Vec<String>) {
+        let staging = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        lay_out(staging.path(), files);
+        move_snapshot(staging.path(), home.path(), None, None).unwrap();
+        (files_in(home.path()), files_in(staging.path()))
+    }
+
+    #[test]
+    fn restores_a_data_directory_and_its_wasm_sibling() {
+        let (home, staged) = restore(&[
+            "data/application.db/000001.log",
+            "data/blockstore.db/CURRENT",
+            "wasm/wasm/state/code",
+            "config/app.toml",
+        ]);
+        assert_eq!(
+            home,
+            [
+                "data/application.db/000001.log",
+                "data/blockstore.db/CURRENT",
+                "wasm/wasm/state/code",
+            ]
+        );
+        assert_eq!(staged, ["config/app.toml"]);
+    }
+
+    #[test]
+    fn restores_a_data_directory_under_the_snapshot_name() {
+        let (home, staged) = restore(&[
+            "osmosis-123/data/application.db/CURRENT",
+            "osmosis-123/wasm/code",
+        ]);
+        assert_eq!(home, ["data/application.db/CURRENT", "wasm/code"]);
+        assert!(staged.is_empty());
+    }
+
+    #[test]
+    fn restores_databases_at_the_top() {
+        let (home, staged) = restore(&[
+            "application.db/CURRENT",
+            "blockstore.db/CURRENT",
+            "priv_validator_state.json",
+            "wasm/code",
+            "snapshot.tar.lz4",
+        ]);
+        assert_eq!(
+            home,
+            [
+                "data/application.db/CURRENT",
+                "data/blockstore.db/CURRENT",
+                "data/priv_validator_state.json",
+                "wasm/code",
+            ]
+        );
+        assert_eq!(staged, ["snapshot.tar.lz4"]);
+    }
+
+    #[test]
+    fn restores_databases_under_the_snapshot_name() {
+        let (home, staged) = restore(&[
+            "juno-123/application.db/CURRENT",
+            "juno-123/state.db/CURRENT",
+            "juno-123/wasm/code",
+        ]);
+        assert_eq!(
+            home,
+            [
+                "data/application.db/CURRENT",
+                "data/state.db/CURRENT",
+                "wasm/code",
+            ]
+        );
+        assert!(staged.is_empty());
+
+        let (home, _) = restore(&[
+            "snapshot/juno-123/blockstore.db/CURRENT",
+            "snapshot/wasm/code",
+        ]);
+        assert_eq!(home, ["data/blockstore.db/CURRENT", "wasm/code"]);
+    }
+
+    #[test]
+    fn refuses_archives_without_databases() {
+        let staging = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        lay_out(staging.path(), &["config/app.toml", "wasm/code"]);
+        assert!(move_snapshot(staging.path(), home.path(), None, None).is_err());
+        assert!(files_in(home.path()).is_empty());
+    }
+
+    #[test]
+    fn skips_init_over_an_existing_genesis() {
+        let home = tempfile::tempdir().unwrap();
+        let config: CosmosConfig =
+            serde_yaml::from_str("bin: missing-node\ninit_command: init x\nstart_command: start\n")
+                .unwrap();
+        let setup = CosmosSetup::new(&config, home.path(), home.path());
+
+        // The binary doesn't exist, so only a skipped init succeeds
+        assert!(setup.init().is_err());
+        lay_out(home.path(), &[GENESIS_FILE]);
+        setup.init().unwrap();
+    }
 }
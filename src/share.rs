@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{self, HeaderValue};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::Client;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::net::TcpListener;
+use tokio_util::io::ReaderStream;
+use tracing::{info, instrument, warn};
+
+use crate::checksum;
+use crate::config::FleetConfig;
+use crate::extractor::{ArchiveFormat, Extractor};
+use crate::provider::{self, IndexEntry};
+use crate::snapshot_info::SnapshotInfo;
+
+/// Time allowed for a peer to answer whether it has a snapshot
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Path of the generated snapshot index; `/` serves it as well
+const INDEX_PATH: &str = "/index.json";
+
+/// Characters escaped in file names put into peer URLs
+const FILE_NAME_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~');
+
+type Body = BoxBody<Bytes, std::io::Error>;
+
+/// Serves the files of a directory to sibling hosts over HTTP
+///
+/// Files are addressed by name directly below the root; subdirectories and
+/// hidden files are never served. Single byte ranges are supported so that
+/// downloads from a peer resume like any other. The archives in the root
+/// are listed at `/index.json` in the format `snapshot_provider` reads.
+pub struct ShareServer {
+    /// Directory whose files are served
+    root: PathBuf,
+
+    /// Bearer token clients must present, if any
+    token: Option<String>,
+}
+
+impl ShareServer {
+    /// Creates a server for the files in `root`
+    pub fn new(root: &Path, token: Option<&str>) -> Self {
+        ShareServer {
+            root: root.to_path_buf(),
+            token: token.map(str::to_string),
+        }
+    }
+
+    /// Accepts connections on `addr` until the process is stopped
+    ///
+    /// An address without a host, e.g. `:8080`, listens on all interfaces.
+    #[instrument(skip(self), fields(root = %self.root.display()))]
+    pub async fn serve(self, addr: &str) -> Result<()> {
+        let addr = match addr.strip_prefix(':') {
+            Some(port) => format!("0.0.0.0:{}", port),
+            None => addr.to_string(),
+        };
+        let listener = TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        info!(
+            "Serving {} on {}",
+            self.root.display(),
+            listener.local_addr()?
+        );
+
+        let server = Arc::new(self);
+        loop {
+            let (stream, remote) = listener
+                .accept()
+                .await
+                .context("Failed to accept connection")?;
+            let server = server.clone();
+
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(server.handle(request).await) }
+                });
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    info!("Connection from {} ended: {}", remote, e);
+                }
+            });
+        }
+    }
+
+    /// Answers a single request
+    async fn handle(&self, request: Request<Incoming>) -> Response<Body> {
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            return status_response(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        if !self.is_authorized(&request) {
+            let mut response = status_response(StatusCode::UNAUTHORIZED);
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return response;
+        }
+
+        let head_only = request.method() == Method::HEAD;
+        if matches!(request.uri().path(), "/" | INDEX_PATH) {
+            return match self.index_response(head_only) {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!("Failed to list {}: {:#}", self.root.display(), e);
+                    status_response(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            };
+        }
+
+        let Some(path) = self.resolve(request.uri().path()) else {
+            return status_response(StatusCode::NOT_FOUND);
+        };
+        let range = request
+            .headers()
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        match self.file_response(&path, range.as_deref(), head_only).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to serve {}: {:#}", path.display(), e);
+                status_response(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    /// Checks the request's bearer token against the configured one
+    fn is_authorized(&self, request: &Request<Incoming>) -> bool {
+        let Some(expected) = &self.token else {
+            return true;
+        };
+
+        request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.trim(), expected))
+    }
+
+    /// Maps a request path to a regular file directly below the root
+    fn resolve(&self, uri_path: &str) -> Option<PathBuf> {
+        let name = percent_decode_str(uri_path.trim_start_matches('/'))
+            .decode_utf8()
+            .ok()?;
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return None;
+        }
+
+        let path = self.root.join(name.as_ref());
+        path.is_file().then_some(path)
+    }
+
+    /// Builds the JSON index of the archives being served
+    fn index_response(&self, head_only: bool) -> Result<Response<Body>> {
+        let index = serde_json::json!({ "snapshots": self.list_archives()? });
+        let json = Bytes::from(serde_json::to_vec_pretty(&index)?);
+        let length = json.len();
+
+        let body = if head_only {
+            Empty::new().map_err(|never| match never {}).boxed()
+        } else {
+            Full::new(json).map_err(|never| match never {}).boxed()
+        };
+        let mut response = Response::new(body);
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        Ok(response)
+    }
+
+    /// Lists the archives in the root as index entries, highest snapshot first
+    ///
+    /// Heights come from the file names, checksums from `<name>.sha256`
+    /// sidecars, and names containing `pruned` or `archive` are labeled as
+    /// that variant.
+    fn list_archives(&self) -> Result<Vec<IndexEntry>> {
+        let mut entries = Vec::new();
+
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let path = entry.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if name.starts_with('.')
+                || !entry.file_type()?.is_file()
+                || !Extractor::is_supported(&path)
+            {
+                continue;
+            }
+
+            let sha256 = std::fs::read_to_string(self.root.join(format!("{}.sha256", name)))
+                .ok()
+                .and_then(|content| checksum::parse_checksum_file(&content, name));
+            let pruning = provider::pruning_from_name(name);
+
+            entries.push(IndexEntry {
+                url: utf8_percent_encode(name, FILE_NAME_ESCAPES).to_string(),
+                height: SnapshotInfo::from_file_name(name).height,
+                pruning,
+                size: Some(entry.metadata()?.len()),
+                sha256,
+                format: ArchiveFormat::from_file_name(name).map(|f| f.as_str().to_string()),
+                ..Default::default()
+            });
+        }
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.height));
+        Ok(entries)
+    }
+
+    /// Builds the response for a file, honouring a single byte range
+    async fn file_response(
+        &self,
+        path: &Path,
+        range: Option<&str>,
+        head_only: bool,
+    ) -> Result<Response<Body>> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+
+        let (status, start, end) = match range.map_or(ByteRange::Full, |r| parse_range(r, size)) {
+            ByteRange::Full => (StatusCode::OK, 0, size.saturating_sub(1)),
+            ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end),
+            ByteRange::Unsatisfiable => {
+                let mut response = status_response(StatusCode::RANGE_NOT_SATISFIABLE);
+                response.headers_mut().insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes */{}", size))?,
+                );
+                return Ok(response);
+            }
+        };
+        let length = if size == 0 { 0 } else { end - start + 1 };
+
+        let body = if head_only || length == 0 {
+            Empty::new().map_err(|never| match never {}).boxed()
+        } else {
+            file.seek(std::io::SeekFrom::Start(start)).await?;
+            let stream = ReaderStream::new(file.take(length)).map_ok(Frame::data);
+            BodyExt::boxed(StreamBody::new(stream))
+        };
+
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+        let content_type = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(ArchiveFormat::from_file_name)
+            .map_or("application/octet-stream", |format| format.mime_type());
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        if status == StatusCode::PARTIAL_CONTENT {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, size))?,
+            );
+        }
+
+        Ok(response)
+    }
+}
+
+/// Finds a fleet peer that already has the named file
+///
+/// Peers are probed in the configured order and the first one that serves the
+/// file wins; unreachable peers are skipped.
+///
+/// # Returns
+/// * `Option<String>` - URL of the file on the peer
+pub async fn find_peer(fleet: &FleetConfig, file_name: &str) -> Option<String> {
+    let client = Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    for peer in &fleet.peers {
+        let url = peer_file_url(peer, file_name);
+        let mut request = client.get(&url).header("Range", "bytes=0-0");
+        if let Some(token) = &fleet.token {
+            request = request.bearer_auth(token);
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("Fleet peer {} has {}", peer, file_name);
+                return Some(url);
+            }
+            Ok(response) => info!("Fleet peer {} answered {}", peer, response.status()),
+            Err(e) => info!("Fleet peer {} is unreachable: {}", peer, e),
+        }
+    }
+
+    None
+}
+
+/// URL of a file on a peer running `share`
+fn peer_file_url(peer: &str, file_name: &str) -> String {
+    format!(
+        "{}/{}",
+        peer.trim_end_matches('/'),
+        utf8_percent_encode(file_name, FILE_NAME_ESCAPES)
+    )
+}
+
+/// Part of a file selected by a `Range` header
+enum ByteRange {
+    /// The whole file, for absent, malformed or multi-range headers
+    Full,
+
+    /// Inclusive start and end offsets
+    Partial(u64, u64),
+
+    /// A range lying entirely past the end of the file
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=` range against a file of `size` bytes
+fn parse_range(header: &str, size: u64) -> ByteRange {
+    let Some((start, end)) = header
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (size.saturating_sub(suffix), size.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let end = match end {
+                "" => u64::MAX,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return ByteRange::Full,
+                },
+            };
+            (start, end.min(size.saturating_sub(1)))
+        }
+    };
+
+    if size == 0 || start >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(start, end)
+    }
+}
+
+/// Compares two secrets without revealing where they differ through timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// An empty response with the given status
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Empty::new().map_err(|never| match never {}).boxed());
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+use tracing::{info, instrument};
+
+/// Buffer size used when streaming artifacts through a verifier
+const VERIFY_BUFFER_SIZE: usize = 1024 * 1024;
+
+use crate::config::{SignatureConfig, SignatureKind};
+
+/// Verifies detached signatures of downloaded artifacts
+pub struct SignatureVerifier {
+    client: Client,
+}
+
+impl Default for SignatureVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SignatureVerifier {
+    /// Creates a new signature verifier instance
+    pub fn new() -> Self {
+        SignatureVerifier {
+            client: Client::new(),
+        }
+    }
+
+    /// Downloads the detached signature and verifies `artifact_path` against it
+    ///
+    /// Only the keys listed in the configuration are trusted. For GPG they are
+    /// imported into a throwaway keyring so the user's own keyring is never consulted.
+    #[instrument(skip(self, artifact_path, signature), fields(artifact = %artifact_path.as_ref().display(), kind = ?signature.kind))]
+    pub async fn verify<P: AsRef<Path>>(
+        &self,
+        artifact_path: P,
+        signature: &SignatureConfig,
+    ) -> Result<()> {
+        let keyless = signature.kind == SignatureKind::Cosign
+            && signature.certificate_identity.is_some()
+            && signature.certificate_oidc_issuer.is_some();
+        if signature.keys.is_empty() && !keyless {
+            return Err(anyhow!(
+                "No trusted keys configured for signature verification"
+            ));
+        }
+
+        let workdir = TempDir::new().context("Failed to create temporary directory")?;
+        let signature_path = self.fetch_signature(&signature.url, workdir.path()).await?;
+
+        let artifact_path = artifact_path.as_ref().to_path_buf();
+        let signature = signature.clone();
+        tokio::task::spawn_blocking(move || match signature.kind {
+            SignatureKind::Gpg => {
+                verify_gpg(&artifact_path, &signature_path, &signature.keys, &workdir)
+            }
+            SignatureKind::Minisign => {
+                verify_minisign(&artifact_path, &signature_path, &signature.keys)
+            }
+            SignatureKind::Cosign => verify_cosign(&artifact_path, &signature_path, &signature),
+        })
+        .await
+        .context("Signature verification task panicked")?
+    }
+
+    /// Downloads the signature file into the working directory
+    async fn fetch_signature(&self, url: &str, workdir: &Path) -> Result<PathBuf> {
+        info!("Downloading signature from: {}", url);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to send signature request")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to download signature: {}",
+                response.status()
+            ));
+        }
+
+        let bytes = response.bytes().await.context("Failed to read signature")?;
+        let path = workdir.join("artifact.sig");
+        fs::write(&path, &bytes).context("Failed to write signature file")?;
+
+        Ok(path)
+    }
+}
+
+/// Verifies a detached GPG signature using only the given trusted keys
+fn verify_gpg(
+    artifact_path: &Path,
+    signature_path: &Path,
+    keys: &[String],
+    workdir: &TempDir,
+) -> Result<()> {
+    let gnupg_home = workdir.path().join("gnupg");
+    fs::create_dir(&gnupg_home).context("Failed to create temporary keyring directory")?;
+
+    // gpg refuses to trust a homedir that other users can read
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&gnupg_home, fs::Permissions::from_mode(0o700))
+            .context("Failed to restrict temporary keyring permissions")?;
+    }
+
+    for (index, key) in keys.iter().enumerate() {
+        let key_path = resolve_key(key, workdir.path(), index)?;
+        let output = Command::new("gpg")
+            .arg("--batch")
+            .arg("--homedir")
+            .arg(&gnupg_home)
+            .arg("--import")
+            .arg(&key_path)
+            .output()
+            .context("Failed to run gpg; is GnuPG installed?")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to import trusted key {}: {}",
+                key_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+
+    let output = Command::new("gpg")
+        .arg("--batch")
+        .arg("--homedir")
+        .arg(&gnupg_home)
+        .arg("--status-fd")
+        .arg("1")
+        .arg("--verify")
+        .arg(signature_path)
+        .arg(artifact_path)
+        .output()
+        .context("Failed to run gpg; is GnuPG installed?")?;
+
+    // gpg reports a good signature from an imported key with a VALIDSIG status line
+    let status = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !status.lines().any(|l| l.contains("VALIDSIG")) {
+        return Err(anyhow!(
+            "GPG signature verification failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    info!("GPG signature verified");
+    Ok(())
+}
+
+/// Verifies a minisign signature against any of the trusted public keys
+///
+/// Only prehashed signatures (the minisign default) are accepted, since they can
+/// be checked while streaming the artifact instead of loading it into memory.
+fn verify_minisign(artifact_path: &Path, signature_path: &Path, keys: &[String]) -> Result<()> {
+    let signature = minisign_verify::Signature::from_file(signature_path)
+        .map_err(|e| anyhow!("Failed to parse minisign signature: {}", e))?;
+
+    let mut last_error = None;
+    for key in keys {
+        // Keys may be given as a path to a .pub file or as the bare base64 key
+        let public_key = if Path::new(key).is_file() {
+            minisign_verify::PublicKey::from_file(key)
+        } else {
+            minisign_verify::PublicKey::from_base64(key.trim())
+        }
+        .map_err(|e| anyhow!("Failed to parse minisign public key {}: {}", key, e))?;
+
+        let mut verifier = match public_key.verify_stream(&signature) {
+            Ok(verifier) => verifier,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        let mut file = File::open(artifact_path).context("Failed to open artifact")?;
+        let mut buffer = vec![0u8; VERIFY_BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buffer).context("Failed to read artifact")?;
+            if read == 0 {
+                break;
+            }
+            verifier.update(&buffer[..read]);
+        }
+
+        match verifier.finalize() {
+            Ok(()) => {
+                info!("Minisign signature verified");
+                return Ok(());
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(anyhow!(
+        "Minisign signature verification failed: {}",
+        last_error.map_or_else(|| "no usable key".to_string(), |e| e.to_string())
+    ))
+}
+
+/// Verifies a cosign signature with `cosign verify-blob`
+///
+/// Key-based verification uses the configured keys; keyless verification checks
+/// the Sigstore bundle against the expected certificate identity and issuer.
+fn verify_cosign(
+    artifact_path: &Path,
+    signature_path: &Path,
+    signature: &SignatureConfig,
+) -> Result<()> {
+    let mut attempts = Vec::new();
+
+    if signature.keys.is_empty() {
+        let mut command = Command::new("cosign");
+        command
+            .arg("verify-blob")
+            .arg("--bundle")
+            .arg(signature_path)
+            .arg("--certificate-identity")
+            .arg(
+                signature
+                    .certificate_identity
+                    .as_deref()
+                    .unwrap_or_default(),
+            )
+            .arg("--certificate-oidc-issuer")
+            .arg(
+                signature
+                    .certificate_oidc_issuer
+                    .as_deref()
+                    .unwrap_or_default(),
+            )
+            .arg(artifact_path);
+        attempts.push(command);
+    } else {
+        for key in &signature.keys {
+            let mut command = Command::new("cosign");
+            command
+                .arg("verify-blob")
+                .arg("--key")
+                .arg(key)
+                .arg("--signature")
+                .arg(signature_path)
+                .arg(artifact_path);
+            attempts.push(command);
+        }
+    }
+
+    let mut last_error = String::new();
+    for mut command in attempts {
+        let output = command
+            .output()
+            .context("Failed to run cosign; is it installed?")?;
+        if output.status.success() {
+            info!("Cosign signature verified");
+            return Ok(());
+        }
+        last_error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    }
+
+    Err(anyhow!(
+        "Cosign signature verification failed: {}",
+        last_error
+    ))
+}
+
+/// Returns a path to the key, writing inline armored keys to the working directory
+fn resolve_key(key: &str, workdir: &Path, index: usize) -> Result<PathBuf> {
+    if key.trim_start().starts_with("-----BEGIN") {
+        let path = workdir.join(format!("key-{}.asc", index));
+        fs::write(&path, key).context("Failed to write inline key")?;
+        Ok(path)
+    } else {
+        Ok(PathBuf::from(key))
+    }
+}
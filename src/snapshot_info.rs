@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, instrument, warn};
+
+use crate::config::{FreshnessAction, FreshnessConfig};
+use crate::retry::RetryPolicy;
+
+/// Shortest digit run treated as a block height rather than a chain id or version
+const MIN_HEIGHT_DIGITS: usize = 5;
+
+/// Seconds in a day
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Facts about a snapshot derived from its name and provider metadata
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotInfo {
+    /// Block height the snapshot was taken at
+    pub height: Option<u64>,
+
+    /// When the snapshot was created
+    pub created: Option<SystemTime>,
+}
+
+impl SnapshotInfo {
+    /// Parses the height and creation date embedded in a snapshot file name
+    ///
+    /// An eight digit run forming a valid `YYYYMMDD` date is taken as the
+    /// creation date; the longest remaining run of at least five digits is taken
+    /// as the height, e.g. `osmosis_12345678.tar.lz4` or `chain-20250305.tar.lz4`.
+    pub fn from_file_name(file_name: &str) -> Self {
+        let mut info = SnapshotInfo::default();
+
+        for run in file_name
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|run| !run.is_empty())
+        {
+            if info.created.is_none() {
+                if let Some(date) = parse_compact_date(run) {
+                    info.created = Some(date);
+                    continue;
+                }
+            }
+
+            if run.len() >= MIN_HEIGHT_DIGITS {
+                if let Ok(height) = run.parse::<u64>() {
+                    if info.height.is_none_or(|h| height > h) {
+                        info.height = Some(height);
+                    }
+                }
+            }
+        }
+
+        info
+    }
+
+    /// Returns the snapshot age in whole days, if the creation date is known
+    pub fn age_days(&self) -> Option<u64> {
+        self.created
+            .and_then(|created| SystemTime::now().duration_since(created).ok())
+            .map(|age| age.as_secs() / SECS_PER_DAY)
+    }
+}
+
+/// Node status returned by the CometBFT RPC `/status` endpoint
+#[derive(Debug, Deserialize)]
+pub struct StatusResponse {
+    pub result: StatusResult,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusResult {
+    pub sync_info: SyncInfo,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncInfo {
+    pub latest_block_height: String,
+
+    /// Whether the node is still catching up with the chain
+    #[serde(default)]
+    pub catching_up: bool,
+}
+
+/// Checks that a snapshot is recent enough to be worth restoring
+pub struct FreshnessChecker {
+    client: Client,
+
+    /// Retries of failed RPC status queries
+    retry: RetryPolicy,
+}
+
+impl Default for FreshnessChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FreshnessChecker {
+    /// Creates a new freshness checker instance
+    pub fn new() -> Self {
+        FreshnessChecker {
+            client: Client::new(),
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Retries failed RPC status queries according to `policy`
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Validates the snapshot's age and height against the configured limits
+    ///
+    /// The creation date falls back to the server's Last-Modified header when
+    /// the file name carries none. Depending on `action`, stale snapshots are
+    /// reported as a warning or rejected with an error.
+    #[instrument(skip(self, info, config))]
+    pub async fn check(
+        &self,
+        url: &str,
+        info: &mut SnapshotInfo,
+        config: &FreshnessConfig,
+    ) -> Result<()> {
+        if info.created.is_none() {
+            info.created = self.fetch_last_modified(url).await;
+        }
+
+        let mut problems = Vec::new();
+
+        if let Some(max_age_days) = config.max_age_days {
+            match info.age_days() {
+                Some(age) if age > max_age_days => problems.push(format!(
+                    "snapshot is {} days old (limit {} days)",
+                    age, max_age_days
+                )),
+                Some(age) => info!("Snapshot is {} days old", age),
+                None => warn!("Snapshot age is unknown, skipping age check"),
+            }
+        }
+
+        if let (Some(rpc_url), Some(max_lag)) = (&config.rpc_url, config.max_height_lag) {
+            match info.height {
+                Some(height) => {
+                    let chain_height = self
+                        .retry
+                        .run("RPC status query", || self.fetch_chain_height(rpc_url))
+                        .await?;
+                    let lag = chain_height.saturating_sub(height);
+                    if lag > max_lag {
+                        problems.push(format!(
+                            "snapshot height {} is {} blocks behind the chain at {} (limit {})",
+                            height, lag, chain_height, max_lag
+                        ));
+                    } else {
+                        info!(
+                            "Snapshot height {} is {} blocks behind the chain",
+                            height, lag
+                        );
+                    }
+                }
+                None => warn!("Snapshot height is unknown, skipping height check"),
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        let message = problems.join("; ");
+        match config.action {
+            FreshnessAction::Warn => {
+                warn!("Snapshot may be stale: {}", message);
+                Ok(())
+            }
+            FreshnessAction::Reject => Err(anyhow!("Snapshot is stale: {}", message)),
+        }
+    }
+
+    /// Reads the Last-Modified header of the remote file
+    async fn fetch_last_modified(&self, url: &str) -> Option<SystemTime> {
+        let response = self
+            .client
+            .get(url)
+            .header("Range", "bytes=0-0")
+            .send()
+            .await
+            .ok()?;
+
+        response
+            .headers()
+            .get("last-modified")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+    }
+
+    /// Queries the latest block height from a CometBFT RPC endpoint
+    async fn fetch_chain_height(&self, rpc_url: &str) -> Result<u64> {
+        let url = format!("{}/status", rpc_url.trim_end_matches('/'));
+        let status: StatusResponse = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to query RPC status")?
+            .error_for_status()
+            .context("RPC status request failed")?
+            .json()
+            .await
+            .context("Failed to parse RPC status")?;
+
+        status
+            .result
+            .sync_info
+            .latest_block_height
+            .parse()
+            .context("Invalid latest_block_height in RPC status")
+    }
+}
+
+/// Parses an eight digit `YYYYMMDD` run into midnight UTC of that day
+fn parse_compact_date(run: &str) -> Option<SystemTime> {
+    if run.len() != 8 {
+        return None;
+    }
+
+    let year: i64 = run[0..4].parse().ok()?;
+    let month: u32 = run[4..6].parse().ok()?;
+    let day: u32 = run[6..8].parse().ok()?;
+    if !(2000..=2099).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * SECS_PER_DAY))
+}
+
+/// Converts a proleptic Gregorian date to days since the Unix epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+use crate::report::unix_seconds;
+
+/// Name of the state file kept in the output directory
+pub const STATE_FILE: &str = ".snapshot-downloader.json";
+
+/// A pipeline step whose completion survives a crash, in pipeline order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// The snapshot, its increments and the binary are downloaded
+    Downloaded,
+
+    /// The downloads matched their checksums and signatures
+    Verified,
+
+    /// The snapshot is unpacked, into the staging or the data directory
+    Extracted,
+
+    /// The staged snapshot is moved into the data directory
+    Moved,
+
+    /// The node's init command ran
+    Initialized,
+}
+
+impl Step {
+    /// Name of the step as recorded in the state file
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Step::Downloaded => "downloaded",
+            Step::Verified => "verified",
+            Step::Extracted => "extracted",
+            Step::Moved => "moved",
+            Step::Initialized => "initialized",
+        }
+    }
+}
+
+/// An archive the recorded steps were taken for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    /// File name of the archive
+    name: String,
+
+    /// Archive size in bytes; unknown for streamed snapshots
+    size: Option<u64>,
+
+    /// Modification time of the archive in seconds since the Unix epoch
+    modified: Option<u64>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of the archive at `path`, by name alone when it isn't on disk
+    fn of(path: &Path) -> Self {
+        let metadata = fs::metadata(path).ok();
+        Fingerprint {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: metadata.as_ref().map(|m| m.len()),
+            modified: metadata.and_then(|m| m.modified().ok()).map(unix_seconds),
+        }
+    }
+}
+
+/// Contents of the state file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Archives the steps belong to
+    #[serde(default)]
+    archives: Vec<Fingerprint>,
+
+    /// Completed steps, in the order they finished
+    #[serde(default)]
+    completed: Vec<Step>,
+
+    /// When a step last completed, in seconds since the Unix epoch
+    #[serde(default)]
+    updated_at: u64,
+
+    /// File name each URL was last downloaded to, which the server may have chosen
+    #[serde(default)]
+    downloads: BTreeMap<String, String>,
+}
+
+/// Steps completed by earlier runs, so a rerun after a crash skips them
+///
+/// Steps are recorded for one set of archives; binding the state to
+/// different archives, or to the same ones changed on disk, starts over.
+pub struct RunState {
+    /// Path of the state file
+    path: PathBuf,
+
+    /// Recorded state
+    state: State,
+}
+
+impl RunState {
+    /// Loads the state kept in `dir`, starting empty when there is none
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(STATE_FILE);
+        let state = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+                State::default()
+            }),
+            Err(_) => State::default(),
+        };
+        RunState { path, state }
+    }
+
+    /// Ties the recorded steps to the snapshot, increments and binary at `archives`
+    ///
+    /// Steps recorded for other archives are forgotten.
+    pub fn bind(&mut self, archives: &[&Path]) {
+        let fingerprints: Vec<_> = archives.iter().map(|path| Fingerprint::of(path)).collect();
+        if self.state.archives == fingerprints {
+            return;
+        }
+        if !self.state.completed.is_empty() {
+            info!("The archives changed since the last run, not skipping any steps");
+        }
+        self.state = State {
+            archives: fingerprints,
+            downloads: std::mem::take(&mut self.state.downloads),
+            ..State::default()
+        };
+    }
+
+    /// Remembers that `url` was downloaded to `path`, saved with the next step
+    pub fn record_download(&mut self, url: &str, path: &Path) {
+        if let Some(name) = path.file_name() {
+            self.state
+                .downloads
+                .insert(url.to_string(), name.to_string_lossy().into_owned());
+        }
+    }
+
+    /// Name of the file an earlier run downloaded `url` to
+    pub fn downloaded_name(&self, url: &str) -> Option<&str> {
+        self.state.downloads.get(url).map(String::as_str)
+    }
+
+    /// Whether an earlier run completed `step` for the bound archives
+    pub fn is_done(&self, step: Step) -> bool {
+        self.state.completed.contains(&step)
+    }
+
+    /// Records that `step` completed and saves the state
+    pub fn complete(&mut self, step: Step) -> Result<()> {
+        if !self.is_done(step) {
+            self.state.completed.push(step);
+        }
+        self.state.updated_at = unix_seconds(SystemTime::now());
+
+        // Written beside the file and renamed over it, so a crash never leaves half a file
+        let json =
+            serde_json::to_string_pretty(&self.state).context("Failed to serialize run state")?;
+        let partial = self.path.with_extension("json.tmp");
+        fs::write(&partial, json)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        fs::rename(&partial, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Forgets `step` and the steps after it, which built on its output
+    ///
+    /// Initialization is kept, as it only wrote the home's `config` directory.
+    pub fn redo(&mut self, step: Step) {
+        self.state
+            .completed
+            .retain(|done| *done < step || *done == Step::Initialized);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_downloads_across_archive_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("snap shot.tar.lz4");
+        fs::write(&archive, b"snapshot").unwrap();
+
+        let mut state = RunState::load(dir.path());
+        state.record_download("https://host/snap%20shot.tar.lz4", &archive);
+        state.bind(&[&archive]);
+        state.complete(Step::Downloaded).unwrap();
+
+        // New archives forget the steps but not where downloads went
+        let mut state = RunState::load(dir.path());
+        assert!(state.is_done(Step::Downloaded));
+        state.bind(&[&dir.path().join("other.tar")]);
+        assert!(!state.is_done(Step::Downloaded));
+        assert_eq!(
+            state.downloaded_name("https://host/snap%20shot.tar.lz4"),
+            Some("snap shot.tar.lz4")
+        );
+        assert_eq!(state.downloaded_name("https://host/other.tar"), None);
+    }
+}
@@ -0,0 +1,415 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::config::{CosmosConfig, SupervisorConfig};
+use crate::node_log::RotatingLog;
+use crate::notify;
+use crate::setup;
+
+/// Number of recent output lines kept for crash diagnostics
+const TAIL_LINES: usize = 50;
+
+/// How long to keep reading output after the node has exited
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Output patterns that indicate a crash restarting won't fix, with a hint for the operator
+const FATAL_PATTERNS: &[(&str, &str)] = &[
+    (
+        "wrong Block.Header.AppHash",
+        "AppHash mismatch: the binary version doesn't match the one that produced the snapshot at this height",
+    ),
+    (
+        "UPGRADE \"",
+        "an on-chain upgrade is required at this height: install the upgraded binary (or use cosmovisor)",
+    ),
+    (
+        "BINARY UPDATED BEFORE TRIGGER",
+        "the binary is newer than the chain height: use the binary matching the snapshot height",
+    ),
+    (
+        "wrong Block.Header.LastResultsHash",
+        "results hash mismatch: the binary or snapshot is from a different chain version",
+    ),
+    (
+        "resource temporarily unavailable",
+        "the database is locked: another node process may be using this home directory",
+    ),
+    (
+        "too many open files",
+        "the open file limit is too low for this data directory: raise supervisor.open_files_limit",
+    ),
+    (
+        "genesis doc hash in db does not match",
+        "genesis mismatch: config/genesis.json doesn't belong to the restored snapshot",
+    ),
+];
+
+/// How a supervised node run ended
+enum Exit {
+    /// The node process exited on its own
+    Exited(std::process::ExitStatus),
+
+    /// The supervisor was asked to shut down
+    Shutdown,
+}
+
+/// Launches the node and restarts it when it exits, stopping on crash loops
+pub struct NodeSupervisor {
+    /// Path to the node binary
+    binary_path: PathBuf,
+
+    /// Node home directory the binary runs in
+    data_dir: PathBuf,
+
+    /// Command used to start the node
+    start_command: String,
+
+    /// Whether `--home` is appended to the start command
+    inject_home: bool,
+
+    /// Restart policy
+    policy: SupervisorConfig,
+
+    /// Cosmovisor executable and environment, when the node runs under it
+    cosmovisor: Option<(String, Vec<(String, String)>)>,
+}
+
+impl NodeSupervisor {
+    /// Creates a new supervisor for the node described by `config`
+    ///
+    /// # Arguments
+    /// * `config` - Cosmos node configuration providing the binary and start command
+    /// * `policy` - Restart and crash loop detection settings
+    /// * `binary_extract_path` - Path where the node binary is located
+    /// * `data_dir` - Path to the data directory for the node
+    pub fn new(
+        config: &CosmosConfig,
+        policy: &SupervisorConfig,
+        binary_extract_path: &Path,
+        data_dir: &Path,
+    ) -> Self {
+        NodeSupervisor {
+            binary_path: binary_extract_path.join(&config.bin),
+            data_dir: data_dir.to_path_buf(),
+            start_command: config.start_command.clone(),
+            inject_home: config.inject_home,
+            policy: policy.clone(),
+            cosmovisor: None,
+        }
+    }
+
+    /// Launches the node through `cosmovisor run` with the given environment
+    pub fn with_cosmovisor(mut self, path: &str, env: Vec<(String, String)>) -> Self {
+        self.cosmovisor = Some((path.to_string(), env));
+        self
+    }
+
+    /// Runs the node until it is shut down or detected to be crash looping
+    ///
+    /// A crash whose output matches a known fatal pattern stops supervision
+    /// immediately; otherwise `max_rapid_restarts` consecutive exits within
+    /// `min_uptime_secs` of starting are treated as a crash loop.
+    #[instrument(skip(self), fields(bin_path = %self.binary_path.display(), data_dir = %self.data_dir.display()))]
+    pub async fn run(&self) -> Result<()> {
+        let (program, mut args, env) = match &self.cosmovisor {
+            Some((path, env)) => (PathBuf::from(path), vec!["run".to_string()], env.clone()),
+            None => (
+                std::path::absolute(&self.binary_path).context("Failed to resolve binary path")?,
+                Vec::new(),
+                Vec::new(),
+            ),
+        };
+        args.extend(setup::node_args(
+            &self.start_command,
+            &self.data_dir,
+            self.inject_home,
+        )?);
+        if let Some(limit) = self.policy.open_files_limit {
+            raise_open_files_limit(limit)?;
+        }
+        let min_uptime = Duration::from_secs(self.policy.min_uptime_secs);
+        let restart_delay = Duration::from_secs(self.policy.restart_delay_secs);
+        let mut rapid_failures = 0;
+
+        let mut log = RotatingLog::open(&self.data_dir.join("logs"), &self.policy.log)?;
+        info!("Writing node output to {}", log.path().display());
+
+        // Listen for the whole run so a signal during a restart delay isn't lost
+        let shutdown = CancellationToken::new();
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown_signal().await;
+                shutdown.cancel();
+            }
+        });
+
+        loop {
+            info!("Starting node: {}", self.start_command);
+            notify::status("Node running");
+            let started = Instant::now();
+            let mut child = Command::new(&program)
+                .args(&args)
+                .envs(env.iter().cloned())
+                .current_dir(&self.data_dir)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .context("Failed to start node")?;
+
+            let (tail, exit) = self.watch(&mut child, &mut log, &shutdown).await?;
+            let status = match exit {
+                Exit::Shutdown => {
+                    info!("Node stopped");
+                    return Ok(());
+                }
+                Exit::Exited(status) => status,
+            };
+
+            if let Some(hint) = diagnose(&tail) {
+                error!("Node exited with {}: {}", status, hint);
+                return Err(anyhow!(
+                    "Node crashed with a non-recoverable error: {}\nLast output:\n{}",
+                    hint,
+                    tail.iter().cloned().collect::<Vec<_>>().join("\n")
+                ));
+            }
+
+            if started.elapsed() < min_uptime {
+                rapid_failures += 1;
+                warn!(
+                    "Node exited with {} after {}s ({} of {} rapid failures)",
+                    status,
+                    started.elapsed().as_secs(),
+                    rapid_failures,
+                    self.policy.max_rapid_restarts
+                );
+            } else {
+                rapid_failures = 1;
+                warn!("Node exited with {}", status);
+            }
+
+            if rapid_failures >= self.policy.max_rapid_restarts {
+                return Err(anyhow!(
+                    "Node is crash looping: {} consecutive exits within {}s of starting\nLast output:\n{}",
+                    rapid_failures,
+                    self.policy.min_uptime_secs,
+                    tail.iter().cloned().collect::<Vec<_>>().join("\n")
+                ));
+            }
+
+            info!("Restarting node in {}s", restart_delay.as_secs());
+            notify::status(&format!("Node exited with {}, restarting", status));
+            tokio::select! {
+                _ = tokio::time::sleep(restart_delay) => {}
+                _ = shutdown.cancelled() => {
+                    info!("Shutdown requested, not restarting node");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Logs the node's output, keeping its tail, until it exits or a shutdown signal arrives
+    async fn watch(
+        &self,
+        child: &mut Child,
+        log: &mut RotatingLog,
+        shutdown: &CancellationToken,
+    ) -> Result<(VecDeque<String>, Exit)> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_lines(stdout, false, sender.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_lines(stderr, true, sender));
+        }
+
+        let mut tail = VecDeque::with_capacity(TAIL_LINES);
+        let echo = self.policy.log.echo;
+        let mut lines_open = true;
+
+        let exit = loop {
+            tokio::select! {
+                line = receiver.recv(), if lines_open => match line {
+                    Some((is_stderr, line)) => record_line(&mut tail, log, echo, is_stderr, line),
+                    None => lines_open = false,
+                },
+                status = child.wait() => {
+                    break Exit::Exited(status.context("Failed to wait for node")?);
+                }
+                _ = shutdown.cancelled() => {
+                    info!("Shutdown requested, stopping node");
+                    notify::stopping();
+                    terminate(child).await;
+                    break Exit::Shutdown;
+                }
+            }
+        };
+
+        // Collect output still in flight after the exit so diagnostics see it;
+        // the timeout guards against descendants keeping the pipes open
+        if lines_open {
+            let drain = async {
+                while let Some((is_stderr, line)) = receiver.recv().await {
+                    record_line(&mut tail, log, echo, is_stderr, line);
+                }
+            };
+            let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, drain).await;
+        }
+
+        Ok((tail, exit))
+    }
+}
+
+/// Writes a line of node output to the log, optionally echoes it, and appends it to the tail
+fn record_line(
+    tail: &mut VecDeque<String>,
+    log: &mut RotatingLog,
+    echo: bool,
+    is_stderr: bool,
+    line: String,
+) {
+    log.write_line(&line);
+    if echo {
+        if is_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    if tail.len() == TAIL_LINES {
+        tail.pop_front();
+    }
+    tail.push_back(line);
+}
+
+/// Raises this process's open file limit, which the node inherits
+///
+/// The hard limit is raised too when it is lower, which only works with
+/// `CAP_SYS_RESOURCE`; otherwise it has to be raised where the tool is
+/// started, e.g. `LimitNOFILE=` in a systemd unit.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // rlim_t is u64 on Linux but not on every Unix
+fn raise_open_files_limit(limit: u64) -> Result<()> {
+    let mut current = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct we pass it
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut current) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to read the open file limit");
+    }
+    let (soft, hard) = (current.rlim_cur as u64, current.rlim_max as u64);
+    if soft >= limit {
+        info!("Open file limit is {}", soft);
+        return Ok(());
+    }
+
+    let raised = libc::rlimit {
+        rlim_cur: limit as libc::rlim_t,
+        rlim_max: hard.max(limit) as libc::rlim_t,
+    };
+    // SAFETY: setrlimit only reads the struct we pass it
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } != 0 {
+        let error = std::io::Error::last_os_error();
+        if limit > hard {
+            return Err(anyhow!(
+                "Cannot raise the open file limit to {}: the hard limit is {} ({}); raise it with \
+                 `ulimit -Hn` or `LimitNOFILE=` in the systemd unit, or lower \
+                 supervisor.open_files_limit",
+                limit,
+                hard,
+                error
+            ));
+        }
+        return Err(error).context("Failed to raise the open file limit");
+    }
+
+    info!("Raised open file limit from {} to {}", soft, limit);
+    Ok(())
+}
+
+/// File limits can't be changed on this platform
+#[cfg(not(unix))]
+fn raise_open_files_limit(_limit: u64) -> Result<()> {
+    Err(anyhow!(
+        "supervisor.open_files_limit is not supported on this platform"
+    ))
+}
+
+/// Finds a hint for the first fatal pattern in the node's recent output
+fn diagnose(tail: &VecDeque<String>) -> Option<&'static str> {
+    FATAL_PATTERNS
+        .iter()
+        .find(|(pattern, _)| tail.iter().any(|line| line.contains(pattern)))
+        .map(|(_, hint)| *hint)
+}
+
+/// Sends each line read from a child output stream to the channel, tagged with its stream
+async fn forward_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    is_stderr: bool,
+    sender: mpsc::UnboundedSender<(bool, String)>,
+) {
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if sender.send((is_stderr, line)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Completes when the process receives Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Asks the node to stop gracefully, killing it if it doesn't exit in time
+async fn terminate(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: kill only sends a signal to the child we spawned
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+        if tokio::time::timeout(Duration::from_secs(30), child.wait())
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        warn!("Node did not stop within 30s, killing it");
+    }
+
+    let _ = child.kill().await;
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{info, instrument};
+
+use crate::snapshot_info::StatusResponse;
+
+/// RPC endpoint used when config.toml doesn't specify one
+const DEFAULT_RPC_URL: &str = "http://127.0.0.1:26657";
+
+/// Delay between sync status polls
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Waits for a node to finish catching up with the chain
+pub struct SyncWaiter {
+    client: Client,
+
+    /// Base URL of the node's CometBFT RPC
+    rpc_url: String,
+}
+
+impl SyncWaiter {
+    /// Creates a waiter polling the given RPC endpoint
+    pub fn new(rpc_url: &str) -> Self {
+        SyncWaiter {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            rpc_url: rpc_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Creates a waiter for the RPC listen address configured in the node's config.toml
+    pub fn for_data_dir(data_dir: &Path) -> Self {
+        let rpc_url = rpc_url_from_config(&data_dir.join("config").join("config.toml"))
+            .unwrap_or_else(|| DEFAULT_RPC_URL.to_string());
+        Self::new(&rpc_url)
+    }
+
+    /// Polls `/status` until the node reports `catching_up: false`
+    ///
+    /// Heights and the sync rate are logged while waiting. An unreachable RPC
+    /// is retried, since the node takes a while to open it after starting.
+    ///
+    /// # Returns
+    /// * `Result<u64>` - The block height the node was at once synced
+    #[instrument(skip(self), fields(rpc = %self.rpc_url))]
+    pub async fn wait(&self, timeout: Duration) -> Result<u64> {
+        let started = Instant::now();
+        let mut first_height: Option<u64> = None;
+
+        loop {
+            match self.status().await {
+                Ok((height, false)) => {
+                    info!("Node is synced at height {}", height);
+                    return Ok(height);
+                }
+                Ok((height, true)) => {
+                    let base = *first_height.get_or_insert(height);
+                    let elapsed = started.elapsed().as_secs_f64().max(1.0);
+                    info!(
+                        "Node is catching up: height {} ({:.1} blocks/s)",
+                        height,
+                        height.saturating_sub(base) as f64 / elapsed
+                    );
+                }
+                Err(e) => info!("Waiting for node RPC: {:#}", e),
+            }
+
+            if started.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "Node did not finish syncing within {}s",
+                    timeout.as_secs()
+                ));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Fetches the node's latest height and whether it is still catching up
+    async fn status(&self) -> Result<(u64, bool)> {
+        let status: StatusResponse = self
+            .client
+            .get(format!("{}/status", self.rpc_url))
+            .send()
+            .await
+            .context("Failed to query RPC status")?
+            .error_for_status()
+            .context("RPC status request failed")?
+            .json()
+            .await
+            .context("Failed to parse RPC status")?;
+
+        let height = status
+            .result
+            .sync_info
+            .latest_block_height
+            .parse()
+            .context("Invalid latest_block_height in RPC status")?;
+
+        Ok((height, status.result.sync_info.catching_up))
+    }
+}
+
+/// Reads `[rpc] laddr` from a config.toml and turns it into a local HTTP URL
+fn rpc_url_from_config(config_path: &Path) -> Option<String> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let mut in_rpc = false;
+
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_rpc = line == "[rpc]";
+            continue;
+        }
+        if !in_rpc {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim() != "laddr" {
+            continue;
+        }
+
+        let address = value.trim().trim_matches('"');
+        let address = address.strip_prefix("tcp://").unwrap_or(address);
+        if address.starts_with("unix://") {
+            return None;
+        }
+        // A wildcard listen address is reachable on loopback
+        let address = address.replace("0.0.0.0", "127.0.0.1");
+        return Some(format!("http://{}", address));
+    }
+
+    None
+}
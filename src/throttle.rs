@@ -0,0 +1,86 @@
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Unused budget carried over after an idle spell, so short stalls don't
+/// turn into sustained bursts above the limit
+const MAX_BURST: Duration = Duration::from_millis(250);
+
+/// Limits the rate at which bytes pass through one or more streams
+///
+/// Shared between the threads of a stage so the limit applies to the stage
+/// as a whole rather than to each worker.
+pub struct Throttle {
+    /// Bytes per second
+    rate: u64,
+
+    /// When the bytes let through so far will have been paid for
+    paid_until: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// Creates a throttle letting `rate` bytes per second through
+    pub fn new(rate: u64) -> Self {
+        Throttle {
+            rate: rate.max(1),
+            paid_until: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Creates a throttle when a rate is configured
+    pub fn from_rate(rate: Option<u64>) -> Option<Self> {
+        rate.map(Throttle::new)
+    }
+
+    /// Blocks until `bytes` more bytes fit within the rate
+    pub fn consume(&self, bytes: usize) {
+        if let Some(wait) = self.reserve(bytes) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Waits until `bytes` more bytes fit within the rate, without blocking the thread
+    pub async fn consume_async(&self, bytes: usize) {
+        if let Some(wait) = self.reserve(bytes) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Books `bytes` against the rate, returning how long to wait for them
+    fn reserve(&self, bytes: usize) -> Option<Duration> {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.rate as f64);
+        let now = Instant::now();
+        let paid_until = {
+            let mut paid_until = self.paid_until.lock().unwrap();
+            let start = (*paid_until).max(now.checked_sub(MAX_BURST).unwrap_or(now));
+            *paid_until = start + cost;
+            *paid_until
+        };
+
+        paid_until.checked_duration_since(now + MAX_BURST)
+    }
+}
+
+/// A reader whose bytes pass through a [`Throttle`], or unthrottled without one
+pub struct Throttled<'a, R> {
+    inner: R,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, R: Read> Throttled<'a, R> {
+    /// Wraps `inner`, limited by `throttle` when there is one
+    pub fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        Throttled { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(throttle) = self.throttle {
+            throttle.consume(read);
+        }
+        Ok(read)
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Context, Result};
+use indicatif::ProgressBar;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+use tracing::{info, instrument};
+
+use crate::config::{parse_size, TorrentConfig};
+use crate::downloader::{progress_bar, Downloader};
+
+/// Whether a snapshot URL refers to a torrent rather than a plain HTTP download
+pub fn is_torrent_url(url: &str) -> bool {
+    url.starts_with("magnet:")
+        || url
+            .split(['?', '#'])
+            .next()
+            .is_some_and(|path| path.ends_with(".torrent"))
+}
+
+/// Payload name a magnet link carries in its `dn` parameter
+pub fn magnet_name(url: &str) -> Option<String> {
+    if !url.starts_with("magnet:") {
+        return None;
+    }
+    reqwest::Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "dn")
+        .map(|(_, name)| name.into_owned())
+}
+
+/// Downloads and seeds torrents using aria2
+pub struct TorrentDownloader {
+    /// Torrent settings
+    config: TorrentConfig,
+}
+
+/// A completed torrent download
+pub struct TorrentDownload {
+    /// Downloaded payload
+    pub path: PathBuf,
+
+    /// Local .torrent file, used to seed the payload afterwards
+    pub torrent_file: PathBuf,
+}
+
+impl TorrentDownloader {
+    /// Creates a new torrent downloader
+    pub fn new(config: &TorrentConfig) -> Self {
+        TorrentDownloader {
+            config: config.clone(),
+        }
+    }
+
+    /// Downloads the payload of a magnet link or .torrent URL into `output_dir`
+    ///
+    /// The torrent's metadata is fetched first so the payload's name is known;
+    /// the payload itself is downloaded without seeding, with the same progress
+    /// bar as an HTTP download.
+    #[instrument(skip(self, output_dir), fields(dir = %output_dir.display()))]
+    pub async fn download(&self, url: &str, output_dir: &Path) -> Result<TorrentDownload> {
+        let torrent_file = if url.starts_with("magnet:") {
+            self.fetch_metadata(url, output_dir)?
+        } else {
+            Downloader::new()
+                .download(url, output_dir, None)
+                .await
+                .context("Failed to download .torrent file")?
+        };
+
+        let payload = self.payload_name(&torrent_file)?;
+        info!("Downloading torrent payload {}", payload);
+
+        let progress_bar = progress_bar(0)?;
+        self.aria2_tracked(
+            &[
+                &format!("--dir={}", output_dir.display()),
+                "--seed-time=0",
+                "--file-allocation=falloc",
+                &torrent_file.to_string_lossy(),
+            ],
+            &progress_bar,
+        )
+        .context("Torrent download failed")?;
+        progress_bar.finish_with_message(format!("Downloaded {} successfully", payload));
+
+        Ok(TorrentDownload {
+            path: output_dir.join(payload),
+            torrent_file,
+        })
+    }
+
+    /// Whether seeding after completion is configured
+    pub fn seeding_enabled(&self) -> bool {
+        self.config.seed_ratio.is_some() || self.config.seed_time_mins.is_some()
+    }
+
+    /// Starts seeding a downloaded torrent in the background
+    ///
+    /// The seeder outlives this process and stops once the configured ratio
+    /// or time is reached, whichever comes first. Its output goes to
+    /// `seed.log` next to the payload.
+    pub fn start_seeding(&self, download: &TorrentDownload) -> Result<u32> {
+        let dir = download
+            .path
+            .parent()
+            .context("Torrent payload has no parent directory")?;
+        let log = fs::File::create(dir.join("seed.log")).context("Failed to create seed log")?;
+
+        let mut command = Command::new(&self.config.aria2c);
+        command
+            .arg(format!("--dir={}", dir.display()))
+            .arg("--check-integrity=true")
+            .arg("--bt-seed-unverified=false");
+        // aria2 seeds forever unless given a limit, so an unset ratio disables that limit
+        command.arg(format!(
+            "--seed-ratio={}",
+            self.config.seed_ratio.unwrap_or(0.0)
+        ));
+        if let Some(minutes) = self.config.seed_time_mins {
+            command.arg(format!("--seed-time={}", minutes));
+        }
+
+        let child = command
+            .arg(&download.torrent_file)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .with_context(|| format!("Failed to run {}; is it installed?", self.config.aria2c))?;
+
+        Ok(child.id())
+    }
+
+    /// Resolves a magnet link's metadata into a local .torrent file
+    fn fetch_metadata(&self, magnet: &str, output_dir: &Path) -> Result<PathBuf> {
+        info!("Fetching torrent metadata");
+        let started = SystemTime::now();
+
+        self.aria2(&[
+            &format!("--dir={}", output_dir.display()),
+            "--bt-metadata-only=true",
+            "--bt-save-metadata=true",
+            magnet,
+        ])
+        .context("Failed to fetch torrent metadata")?;
+
+        // aria2 names the saved metadata after the info hash
+        fs::read_dir(output_dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "torrent"))
+            .filter_map(|entry| {
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                (modified >= started).then(|| (modified, entry.path()))
+            })
+            .max()
+            .map(|(_, path)| path)
+            .ok_or_else(|| anyhow!("aria2 did not save the torrent metadata"))
+    }
+
+    /// Reads the payload's relative path from a .torrent file
+    ///
+    /// Snapshot torrents carry a single archive; for multi-file torrents the
+    /// first listed file is used.
+    fn payload_name(&self, torrent_file: &Path) -> Result<String> {
+        let listing = self.aria2(&["--show-files=true", &torrent_file.to_string_lossy()])?;
+
+        // Files are listed as `  1|./path/in/torrent`
+        listing
+            .lines()
+            .filter_map(|line| line.trim().split_once('|'))
+            .find(|(index, _)| index.trim().parse::<u32>().is_ok())
+            .map(|(_, path)| path.trim().trim_start_matches("./").to_string())
+            .ok_or_else(|| anyhow!("No files listed in {}", torrent_file.display()))
+    }
+
+    /// Runs aria2c to completion and returns its stdout
+    fn aria2(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new(&self.config.aria2c)
+            .args(["--console-log-level=warn", "--summary-interval=0"])
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to run {}; is it installed?", self.config.aria2c))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "aria2c exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Runs aria2c to completion, following the progress it reports every second
+    fn aria2_tracked(&self, args: &[&str], progress_bar: &ProgressBar) -> Result<()> {
+        let mut child = Command::new(&self.config.aria2c)
+            .args(["--console-log-level=warn", "--summary-interval=1"])
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run {}; is it installed?", self.config.aria2c))?;
+        let stdout = child.stdout.take().context("aria2c has no stdout")?;
+
+        // Messages are kept to explain a failure; the progress summaries aren't
+        let mut messages = Vec::new();
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            match reported_progress(&line) {
+                Some((downloaded, total)) => {
+                    progress_bar.set_length(total);
+                    progress_bar.set_position(downloaded);
+                }
+                None if is_summary_line(&line) => {}
+                None => messages.push(line),
+            }
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            progress_bar.abandon();
+            return Err(anyhow!(
+                "aria2c exited with {}: {}",
+                status,
+                messages.join("\n").trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Bytes downloaded and total of a progress line such as
+/// `[#2089b0 400.0KiB/33.2MiB(1%) CN:1 SD:3 DL:115.7KiB ETA:4m41s]`
+fn reported_progress(line: &str) -> Option<(u64, u64)> {
+    let (_, rest) = line.split_once("[#")?;
+    let sizes = rest.split_whitespace().nth(1)?;
+    let sizes = sizes.split('(').next()?;
+    let (downloaded, total) = sizes.split_once('/')?;
+    Some((parse_size(downloaded).ok()?, parse_size(total).ok()?))
+}
+
+/// Whether a line is part of the frame aria2 prints around its progress summaries
+fn is_summary_line(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty()
+        || line.starts_with("***")
+        || line.starts_with("===")
+        || line.starts_with("---")
+        || line.starts_with("FILE:")
+}
@@ -0,0 +1,132 @@
+"""Writes the age test files used by src/age.rs
+
+An age v1 encoder written from the specification
+(https://age-encryption.org/v1) on Python's `cryptography` package, kept
+apart from the decoder it checks. Run from this directory to regenerate.
+"""
+
+import base64
+import hashlib
+import hmac
+import os
+
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric.x25519 import X25519PrivateKey
+from cryptography.hazmat.primitives.ciphers.aead import ChaCha20Poly1305
+from cryptography.hazmat.primitives.kdf.hkdf import HKDF
+
+CHUNK = 64 * 1024
+CHARSET = "qpzry9x8gf2tvdw0s3jn54khce6mua7l"
+PASSPHRASE = "correct horse battery staple"
+
+
+def b64(data):
+    return base64.b64encode(data).decode().rstrip("=")
+
+
+def hkdf(ikm, salt, info):
+    return HKDF(hashes.SHA256(), 32, salt or None, info).derive(ikm)
+
+
+def bech32(hrp, data):
+    def polymod(values):
+        gen = [0x3B6A57B2, 0x26508E6D, 0x1EA119FA, 0x3D4233DD, 0x2A1462B3]
+        chk = 1
+        for v in values:
+            top = chk >> 25
+            chk = (chk & 0x1FFFFFF) << 5 ^ v
+            for i in range(5):
+                chk ^= gen[i] if (top >> i) & 1 else 0
+        return chk
+
+    acc, bits, values = 0, 0, []
+    for byte in data:
+        acc = (acc << 8) | byte
+        bits += 8
+        while bits >= 5:
+            bits -= 5
+            values.append((acc >> bits) & 31)
+    if bits:
+        values.append((acc << (5 - bits)) & 31)
+    expanded = [ord(c) >> 5 for c in hrp] + [0] + [ord(c) & 31 for c in hrp]
+    check = polymod(expanded + values + [0] * 6) ^ 1
+    values += [(check >> 5 * (5 - i)) & 31 for i in range(6)]
+    return hrp + "1" + "".join(CHARSET[v] for v in values)
+
+
+def stanza(kind, args, body):
+    text = " ".join(["->", kind] + args) + "\n"
+    encoded = b64(body)
+    lines = [encoded[i : i + 64] for i in range(0, len(encoded), 64)]
+    # The last body line is always shorter than a full one
+    if not lines or len(lines[-1]) == 64:
+        lines.append("")
+    return text + "".join(line + "\n" for line in lines)
+
+
+def x25519_stanza(file_key, recipient):
+    ephemeral = X25519PrivateKey.generate()
+    share = ephemeral.public_key().public_bytes_raw()
+    shared = ephemeral.exchange(recipient)
+    key = hkdf(shared, share + recipient.public_bytes_raw(), b"age-encryption.org/v1/X25519")
+    return stanza("X25519", [b64(share)], ChaCha20Poly1305(key).encrypt(bytes(12), file_key, None))
+
+
+def scrypt_stanza(file_key, passphrase, log_n):
+    salt = os.urandom(16)
+    key = hashlib.scrypt(
+        passphrase.encode(),
+        salt=b"age-encryption.org/v1/scrypt" + salt,
+        n=1 << log_n,
+        r=8,
+        p=1,
+        dklen=32,
+    )
+    return stanza("scrypt", [b64(salt), str(log_n)], ChaCha20Poly1305(key).encrypt(bytes(12), file_key, None))
+
+
+def encrypt(payload, stanza_for):
+    file_key = os.urandom(16)
+    header = ("age-encryption.org/v1\n" + stanza_for(file_key) + "---").encode()
+    mac = hmac.new(hkdf(file_key, b"", b"header"), header, hashlib.sha256).digest()
+    out = header + (" " + b64(mac) + "\n").encode()
+
+    nonce = os.urandom(16)
+    aead = ChaCha20Poly1305(hkdf(file_key, nonce, b"payload"))
+    chunks = [payload[i : i + CHUNK] for i in range(0, len(payload), CHUNK)] or [b""]
+    out += nonce
+    for counter, chunk in enumerate(chunks):
+        last = counter == len(chunks) - 1
+        out += aead.encrypt(counter.to_bytes(11, "big") + bytes([last]), chunk, None)
+    return out
+
+
+def identity(name):
+    key = X25519PrivateKey.generate()
+    raw = key.private_bytes(
+        serialization.Encoding.Raw,
+        serialization.PrivateFormat.Raw,
+        serialization.NoEncryption(),
+    )
+    with open(name, "w") as f:
+        f.write("# created: for tests\n" + bech32("age-secret-key-", raw).upper() + "\n")
+    return key.public_key()
+
+
+def write(name, data):
+    with open(name, "wb") as f:
+        f.write(data)
+
+
+recipient = identity("identity.txt")
+identity("other-identity.txt")
+to_recipient = lambda key: x25519_stanza(key, recipient)
+
+# Two chunks, the second one short
+write("x25519.age", encrypt(bytes(i % 251 for i in range(100_000)), to_recipient))
+write("empty.age", encrypt(b"", to_recipient))
+write("exact-chunk.age", encrypt(bytes(i % 251 for i in range(CHUNK)), to_recipient))
+write(
+    "passphrase.age",
+    encrypt(b"snapshot payload\n", lambda key: scrypt_stanza(key, PASSPHRASE, 10)),
+)